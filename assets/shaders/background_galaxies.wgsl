@@ -0,0 +1,154 @@
+#import bevy_pbr::mesh_functions
+#import bevy_pbr::mesh_view_bindings::view
+#import "shaders/noise_functions.wgsl"::octave_noise_3d;
+
+// Duplicated in galaxy_background.rs
+struct BackgroundGalaxyInstance {
+    // xyz = unit direction, w = angular radius in radians
+    direction_size: vec4<f32>,
+    orientation: vec4<f32>,
+    // rgb = colour, w = brightness
+    colour: vec4<f32>,
+    // x = morphology, y = arms / ellipticity, z = pitch / texture layer, w = random phase
+    shape: vec4<f32>,
+}
+
+@group(2) @binding(0) var<storage> instances: array<BackgroundGalaxyInstance>;
+@group(2) @binding(1) var generated_texture: texture_2d_array<f32>;
+@group(2) @binding(2) var generated_sampler: sampler;
+@group(2) @binding(3) var<uniform> brightness: f32;
+
+// Behind every galaxy, the perspective projection has no far plane
+const SHELL_DISTANCE: f32 = 1.0e6;
+// Impostors smaller than this are drawn this size with their light spread out, so they don't flicker
+const MIN_PIXELS: f32 = 2.0;
+// Same as the baked galaxy textures
+const PADDING_COEFFICIENT: f32 = 1.5;
+
+struct Vertex {
+    @builtin(instance_index) instance_index: u32,
+    @location(0) position: vec3<f32>,
+};
+
+struct VertexOutput {
+    @builtin(position) clip_position: vec4<f32>,
+    // In units of the galaxy radius
+    @location(0) uv: vec2<f32>,
+    @location(1) @interpolate(flat) tag: u32,
+    @location(2) flux_scale: f32,
+};
+
+fn rotate(q: vec4<f32>, v: vec3<f32>) -> vec3<f32> {
+    let t = 2.0 * cross(q.xyz, v);
+    return v + q.w * t + cross(q.xyz, t);
+}
+
+@vertex
+fn vertex(vertex: Vertex) -> VertexOutput {
+    let tag = mesh_functions::get_tag(vertex.instance_index);
+    let galaxy = instances[tag];
+
+    let camera_right = view.world_from_view[0].xyz;
+    let camera_up = view.world_from_view[1].xyz;
+
+    // Angle covered by one pixel at the centre of the screen
+    let pixel_angle = 2.0 / (view.clip_from_view[1][1] * view.viewport.w);
+    let size = galaxy.direction_size.w;
+    let quad_size = max(size, pixel_angle * MIN_PIXELS);
+
+    let centre = view.world_position + galaxy.direction_size.xyz * SHELL_DISTANCE;
+    let offset = (camera_right * vertex.position.x + camera_up * vertex.position.y) * quad_size * SHELL_DISTANCE;
+
+    var out: VertexOutput;
+    out.clip_position = view.clip_from_world * vec4<f32>(centre + offset, 1.0);
+    out.uv = vertex.position.xy;
+    out.tag = tag;
+    out.flux_scale = (size * size) / (quad_size * quad_size);
+    return out;
+}
+
+fn bulge(r: f32) -> f32 {
+    return 1.5 * exp(-r * r / 0.006);
+}
+
+fn elliptical(uv: vec2<f32>, galaxy: BackgroundGalaxyInstance) -> f32 {
+    let c = cos(galaxy.shape.w);
+    let s = sin(galaxy.shape.w);
+    let p = vec2<f32>(c * uv.x - s * uv.y, s * uv.x + c * uv.y);
+    let r = length(vec2<f32>(p.x, p.y / galaxy.shape.y));
+    // de Vaucouleurs profile
+    let effective_radius = 0.25;
+    return min(exp(-7.67 * (pow(r / effective_radius, 0.25) - 1.0)) * 0.05, 4.0);
+}
+
+fn spiral(disk: vec2<f32>, galaxy: BackgroundGalaxyInstance) -> f32 {
+    let r = max(length(disk), 1e-4);
+    let theta = atan2(disk.y, disk.x);
+    let arms = galaxy.shape.y;
+    let pitch = galaxy.shape.z;
+    let arm = 0.5 + 0.5 * cos(arms * (theta - log(r) / tan(pitch)) + galaxy.shape.w);
+    return exp(-r / 0.25) * (0.3 + 0.7 * arm * arm);
+}
+
+fn irregular(disk: vec2<f32>, galaxy: BackgroundGalaxyInstance) -> f32 {
+    let r = length(disk);
+    let clumps = saturate(octave_noise_3d(3, 0.5, 3.0, vec3<f32>(disk, galaxy.shape.w)) * 0.8 + 0.5);
+    return exp(-r / 0.35) * clumps * clumps * 1.5;
+}
+
+fn generated(disk: vec2<f32>, galaxy: BackgroundGalaxyInstance) -> vec3<f32> {
+    let uv = disk / (2.0 * PADDING_COEFFICIENT) + 0.5;
+    let xz_sample = textureSampleLevel(generated_texture, generated_sampler, uv, i32(galaxy.shape.z), 0.0);
+
+    // x = disk, y = dust, z = stars, a fixed palette since these aren't any configured galaxy
+    let disk_col = vec3<f32>(0.4, 0.6, 1.0);
+    let stars_col = vec3<f32>(1.0, 0.95, 0.85);
+    let dust_col = vec3<f32>(0.6, 0.4, 0.2);
+    let col = disk_col * xz_sample.x + stars_col * xz_sample.z * 0.5;
+    return col * 10.0 * (1.0 - clamp(xz_sample.y * 10.0, 0.0, 0.8) * dust_col);
+}
+
+@fragment
+fn fragment(in: VertexOutput) -> @location(0) vec4<f32> {
+    let galaxy = instances[in.tag];
+    let view_dir = galaxy.direction_size.xyz;
+
+    // Point on the billboard, projected along the line of sight onto the galaxy's disk
+    let p = view.world_from_view[0].xyz * in.uv.x + view.world_from_view[1].xyz * in.uv.y;
+    let normal = rotate(galaxy.orientation, vec3<f32>(0.0, 1.0, 0.0));
+    let cos_i = dot(view_dir, normal);
+    let safe_cos_i = select(-1.0, 1.0, cos_i >= 0.0) * max(abs(cos_i), 0.05);
+    let q = p - view_dir * dot(p, normal) / safe_cos_i;
+    let disk = vec2<f32>(
+        dot(q, rotate(galaxy.orientation, vec3<f32>(1.0, 0.0, 0.0))),
+        dot(q, rotate(galaxy.orientation, vec3<f32>(0.0, 0.0, 1.0))),
+    );
+    // A thin disk seen edge on has a longer path through it
+    let path_length = 1.0 / max(abs(cos_i), 0.1);
+
+    var col = galaxy.colour.rgb;
+    var intensity = 0.0;
+    switch i32(galaxy.shape.x) {
+        case 0: {
+            intensity = elliptical(in.uv, galaxy);
+        }
+        case 1: {
+            intensity = spiral(disk, galaxy) * path_length * 0.3 + bulge(length(in.uv));
+        }
+        case 2: {
+            intensity = irregular(disk, galaxy) * path_length * 0.3;
+        }
+        default: {
+            col *= generated(disk, galaxy) * path_length * 0.3;
+            intensity = 1.0;
+            col += galaxy.colour.rgb * bulge(length(in.uv));
+        }
+    }
+
+    // Fade out towards the quad edges
+    let edge = 1.0 - smoothstep(0.8, 1.0, length(in.uv));
+    let flux = intensity * edge * galaxy.colour.w * brightness * in.flux_scale;
+
+    // Additive blending
+    return vec4<f32>(col * flux, 0.0);
+}
@@ -0,0 +1,37 @@
+#import bevy_pbr::mesh_view_bindings::view
+#import "shaders/noise_functions.wgsl"::octave_noise_3d;
+
+@group(2) @binding(0) var<uniform> intensity: f32;
+
+// Just inside the background galaxy shell
+const SHELL_DISTANCE: f32 = 0.9e6;
+
+struct Vertex {
+    @location(0) position: vec3<f32>,
+};
+
+struct VertexOutput {
+    @builtin(position) clip_position: vec4<f32>,
+    @location(0) direction: vec3<f32>,
+};
+
+@vertex
+fn vertex(vertex: Vertex) -> VertexOutput {
+    var out: VertexOutput;
+    out.clip_position = view.clip_from_world * vec4<f32>(view.world_position + vertex.position * SHELL_DISTANCE, 1.0);
+    out.direction = vertex.position;
+    return out;
+}
+
+@fragment
+fn fragment(in: VertexOutput) -> @location(0) vec4<f32> {
+    let dir = normalize(in.direction);
+
+    // Unresolved galaxies add up to a faint, slightly patchy glow
+    let large_scale = octave_noise_3d(3, 0.5, 1.5, dir) * 0.5 + 0.5;
+    let small_scale = octave_noise_3d(2, 0.5, 24.0, dir) * 0.5 + 0.5;
+    let glow = vec3<f32>(0.55, 0.5, 0.65) * (0.6 + 0.4 * large_scale) * (0.8 + 0.2 * small_scale);
+
+    // Additive blending
+    return vec4<f32>(glow * 0.002 * intensity, 0.0);
+}
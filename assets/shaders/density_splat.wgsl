@@ -0,0 +1,71 @@
+#import bevy_pbr::mesh_view_bindings::view
+#import bevy_pbr::mesh_functions::get_world_from_local
+#import bevy_pbr::prepass_io::Vertex
+
+// Duplicated in density_splat.rs
+struct DensitySplatParams {
+    bounds_min: vec4<f32>,
+    bounds_size: vec4<f32>,
+    disk_color: vec4<f32>,
+    intensity: f32,
+    raymarch_steps: f32,
+    inverse_cell_volume: f32,
+}
+
+@group(2) @binding(0) var<uniform> params: DensitySplatParams;
+@group(2) @binding(1) var density_texture: texture_3d<f32>;
+@group(2) @binding(2) var density_sampler: sampler;
+
+struct VertexOutput {
+    @builtin(position) position: vec4<f32>,
+    @location(0) world_position: vec3<f32>,
+}
+
+@vertex
+fn vertex(vertex: Vertex) -> VertexOutput {
+    let model = get_world_from_local(vertex.instance_index);
+    let world_pos = model * vec4<f32>(vertex.position, 1.0);
+
+    var out: VertexOutput;
+    out.position = view.clip_from_world * world_pos;
+    out.world_position = world_pos.xyz;
+    return out;
+}
+
+@fragment
+fn fragment(in: VertexOutput) -> @location(0) vec4<f32> {
+    let origin = view.world_position;
+    let ray_dir = normalize(in.world_position - origin);
+
+    // Slab intersection with the grid bounds, starting at the camera if it's inside
+    let bounds_min = params.bounds_min.xyz;
+    let bounds_max = bounds_min + params.bounds_size.xyz;
+    let inv_dir = 1.0 / ray_dir;
+    let t0 = (bounds_min - origin) * inv_dir;
+    let t1 = (bounds_max - origin) * inv_dir;
+    let t_near = max(max(min(t0.x, t1.x), min(t0.y, t1.y)), min(t0.z, t1.z));
+    let t_far = min(min(max(t0.x, t1.x), max(t0.y, t1.y)), max(t0.z, t1.z));
+    let t_start = max(t_near, 0.0);
+    if t_far <= t_start {
+        discard;
+    }
+
+    let steps = max(params.raymarch_steps, 1.0);
+    let dt = (t_far - t_start) / steps;
+
+    // Disk colour of the merging galaxies, shifting warmer where stars pile up
+    let sparse_col = params.disk_color.rgb;
+    let dense_col = vec3<f32>(1.0, 0.9, 0.45);
+
+    var col = vec3<f32>(0.0);
+    for (var i = 0.0; i < steps; i += 1.0) {
+        let p = origin + ray_dir * (t_start + (i + 0.5) * dt);
+        let uvw = (p - bounds_min) / params.bounds_size.xyz;
+        let count = textureSampleLevel(density_texture, density_sampler, uvw, 0.0).x;
+        let density = count * params.inverse_cell_volume;
+        col += mix(sparse_col, dense_col, saturate(density * 0.01)) * density * dt;
+    }
+
+    // Additive blending
+    return vec4<f32>(col * params.intensity * 1e-3, 0.0);
+}
@@ -8,7 +8,11 @@
 
 @compute @workgroup_size(64, 1, 1)
 fn cache_extinction(@builtin(global_invocation_id) invocation_id: vec3<u32>, @builtin(num_workgroups) num_workgroups: vec3<u32>) {
-    let index = invocation_id.x;
+    // Rows of num_workgroups.x workgroups, the last one is partly past the end
+    let index = invocation_id.x + invocation_id.y * num_workgroups.x * 64u;
+    if index >= arrayLength(&positions_input) {
+        return;
+    }
 
     var col = (colours_input[index].rgb);
     let len = length(col);
@@ -21,11 +25,12 @@ fn cache_extinction(@builtin(global_invocation_id) invocation_id: vec3<u32>, @bu
     let STEPS = 128;
     let step : vec3<f32> = (end-start)/f32(STEPS);
     let step_size : f32 = length(step);
+    let view_dir : vec3<f32> = step / max(step_size, 0.0001);
     let exposure = 0.1;
 
     for(var i =0; i<STEPS; i++) {
         let pos: vec3<f32> = start + f32(i) * step;
-        col = ray_step(pos, col, step_size * exposure);
+        col = ray_step(pos, view_dir, col, step_size * exposure);
     }
 
 
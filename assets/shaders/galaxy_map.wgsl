@@ -0,0 +1,35 @@
+#import bevy_pbr::forward_io::VertexOutput
+
+struct GalaxyMapParams {
+    radius : f32,
+    padding_coefficient : f32,
+    texture_dimension : f32,
+    opacity : f32,
+    intensity : f32,
+    disk_color : vec3<f32>,
+}
+
+@group(2) @binding(0) var<uniform> map_params: GalaxyMapParams;
+@group(2) @binding(1) var galaxy_xz_texture: texture_2d<f32>;
+@group(2) @binding(2) var galaxy_xz_sampler: sampler;
+
+// The plane spans the padded texture, so its uv is pos_to_uv in intensity_shared.wgsl without the texel offset
+// Sampling in mesh space keeps the map on the galaxy when it's moved or tilted
+fn map_uv(mesh_uv : vec2<f32>) -> vec2<f32> {
+    return mesh_uv + vec2<f32>(0.5,0.5)/map_params.texture_dimension;
+}
+
+@fragment
+fn fragment(in: VertexOutput) -> @location(0) vec4<f32> {
+    let xz_sample = textureSample(galaxy_xz_texture, galaxy_xz_sampler, map_uv(in.uv));
+
+    // x = disk, y = dust, z = stars
+    let disk_col = map_params.disk_color;
+    let stars_col = vec3<f32>(1.0,0.95,0.85);
+    let dust_col = vec3<f32>(0.6,0.4,0.2);
+
+    var col = disk_col * xz_sample.x + stars_col * xz_sample.z * 0.5;
+    col = col * map_params.intensity * (1.0 - clamp(xz_sample.y * map_params.intensity, 0.0, 0.8) * dust_col);
+
+    return vec4<f32>(col, map_params.opacity);
+}
@@ -1,6 +1,70 @@
 const pi = radians(180.0);
 
-#import "shaders/noise_functions.wgsl"::{ridge_noise,octave_noise_3d};
+#import "shaders/noise_functions.wgsl"::Perlin3D;
+
+#ifdef RUNTIME_NOISE
+fn lattice_noise(p : vec3<f32>) -> f32 {
+    return Perlin3D(p);
+}
+#else
+// One period of Perlin3D, baked by noise_texture.rs
+@group(2) @binding(11) var noise_texture: texture_3d<f32>;
+@group(2) @binding(12) var noise_sampler: sampler;
+
+// Perlin3D's hash domain
+const NOISE_PERIOD : f32 = 69.0;
+
+fn lattice_noise(p : vec3<f32>) -> f32 {
+    let size = vec3<f32>(textureDimensions(noise_texture));
+    // Explicit level, the raymarch loops aren't uniform control flow
+    return textureSampleLevel(noise_texture, noise_sampler, p / NOISE_PERIOD + 0.5 / size, 0.0).r;
+}
+#endif
+
+// Same as octave_noise_3d and ridge_noise in noise_functions.wgsl, over the baked noise unless RUNTIME_NOISE is set
+fn galaxy_octave_noise(octaves: i32, persistence : f32, scale : f32, pos : vec3<f32> ) -> f32 {
+    var sum = 0.0;
+    var frequency = scale;
+    var amplitude = 1.0;
+
+    var amp_sum = 0.0;
+    for(var i =0; i < octaves; i++) {
+        sum += lattice_noise(pos * frequency) * amplitude;
+
+        frequency *= 2.0;
+        amp_sum += amplitude;
+        amplitude *= persistence;
+    }
+
+    return sum / amp_sum;
+}
+
+fn galaxy_ridge_noise( in_pos : vec3<f32>,in_frequency : f32,octaves : i32, lacunarity : f32, offset : f32, gain : f32) -> f32 {
+    var value = 0.0;
+    var weight = 1.0;
+
+    let w = - 0.05f;
+    var freq = in_frequency;
+
+    var p = in_pos;
+    for(var i =0; i < octaves; i++) {
+        var signal = lattice_noise(p+ vec3<f32>(f32(i)) * 0.72354);
+
+        signal = abs(signal);
+        signal = offset - signal;
+        signal *= signal;
+
+        signal *= weight;
+
+        weight = saturate(signal * gain);
+
+        value += signal * pow(freq,w);
+
+        p = p * lacunarity;
+        freq *= lacunarity;
+    }
+    return (value * 1.25) - 1.0;
+}
 
 // Returns position rotated by the provided winding angle and scaled to the unit galaxy
 fn get_twirled_unit_pos(p : vec3<f32>, winding_angle : f32) -> vec3<f32> {
@@ -10,12 +74,12 @@ fn get_twirled_unit_pos(p : vec3<f32>, winding_angle : f32) -> vec3<f32> {
 
 fn disk_noise(p : vec3<f32>, winding_angle : f32, octaves : i32) -> f32 {
     let r = get_twirled_unit_pos(p,winding_angle);
-    return octave_noise_3d(octaves,disk_params.noise_persistence,disk_params.noise_scale, r);    
+    return galaxy_octave_noise(octaves,disk_params.noise_persistence,disk_params.noise_scale, r);    
 }
 
 fn dust_noise(p : vec3<f32>, winding_angle : f32, octaves : i32) -> f32 {
     let pr = get_twirled_unit_pos(p, winding_angle);
-    return max(0.0,ridge_noise(pr * dust_params.noise_scale, dust_params.noise_persistence,octaves,2.5,dust_params.noise_offset, dust_params.noise_tilt));
+    return max(0.0,galaxy_ridge_noise(pr * dust_params.noise_scale, dust_params.noise_persistence,octaves,2.5,dust_params.noise_offset, dust_params.noise_tilt));
 }
 
 // END Noise utilities
@@ -31,11 +95,26 @@ struct GalaxyParams {
     exposure : f32,
     raymarch_steps : f32,
     texture_dimension : f32,
+    dust_albedo : f32,
+    dust_anisotropy : f32,
+    scattering_intensity : f32,
+    max_step_scale : f32,
 }
 struct BulgeParams {
     strength : f32,
     radius : f32, // width
     intensity_mod : f32,
+    sersic_index : f32,
+    // Axes over the major axis
+    axis_ratio_y : f32,
+    axis_ratio_z : f32,
+    boxiness : f32,
+    peanut : f32,
+    // In the xz plane
+    major_axis : vec2<f32>,
+    // Linear RGB, the outer colour's w is the gradient radius in galaxy radii, zero for a flat colour
+    color : vec4<f32>,
+    outer_color : vec4<f32>,
 }
 struct ComponentParams {
     strength : f32,
@@ -50,6 +129,8 @@ struct ComponentParams {
     noise_tilt : f32,
     noise_persistence : f32,
     noise_octaves : f32,
+    color : vec4<f32>,
+    outer_color : vec4<f32>,
 }
 
 #ifdef COMPUTE_BINDINGS
@@ -111,9 +192,6 @@ fn get_disk_intensity(p : vec3<f32>, winding_angle : f32, base_intensity : f32)
 
     var p2 = 0.5;
     let octaves = i32(disk_params.noise_octaves);
-#ifdef DIAGNOSTIC
-    return f32(octaves) / 10.0;
-#else
     if octaves > 0 {
         p2 = abs(disk_noise(p, winding_angle, octaves));
     }
@@ -123,7 +201,6 @@ fn get_disk_intensity(p : vec3<f32>, winding_angle : f32, base_intensity : f32)
     p2 += disk_params.noise_offset;
     
     return base_intensity * p2 * disk_params.strength;
-#endif
 }
 
 fn get_dust_intensity(p : vec3<f32>, winding_angle : f32, base_intensity : f32) -> f32 {
@@ -133,9 +210,6 @@ fn get_dust_intensity(p : vec3<f32>, winding_angle : f32, base_intensity : f32)
 
     var p2 = 0.5;
     let octaves = i32(dust_params.noise_octaves);
-#ifdef DIAGNOSTIC
-    return f32(octaves) / 10.0;
-#else
     if octaves > 0 {
         p2 = dust_noise(p, winding_angle, octaves);
     }
@@ -147,7 +221,6 @@ fn get_dust_intensity(p : vec3<f32>, winding_angle : f32, base_intensity : f32)
 
     let s : f32 = 0.01;
     return base_intensity * p2 * s * dust_params.strength;
-#endif
 }
 
 fn get_dust_intensity_ridged(p : vec3<f32>, winding_angle : f32, base_intensity : f32) -> f32 {
@@ -157,26 +230,83 @@ fn get_dust_intensity_ridged(p : vec3<f32>, winding_angle : f32, base_intensity
 
     var p2 = 0.5;
     let octaves = i32(dust_params.noise_octaves);
-#ifdef DIAGNOSTIC
-    return f32(octaves) / 10.0;
-#else
     if octaves > 0 {
         p2 = dust_noise(p,winding_angle,octaves);
     }
 
     let s : f32 = 0.01;
     return p2 * base_intensity * s * dust_params.strength;
-#endif
 }
 
+// Generalised radius of p, its distance from the centre for a spherical bulge
+// Mirrored by BulgeShape::radius
+fn bulge_radius(p : vec3<f32>) -> f32 {
+    let axis = bulge_params.major_axis;
+    let x = p.x * axis.x + p.z * axis.y;
+    let z = -p.x * axis.y + p.z * axis.x;
+    let peanut = 1.0 + bulge_params.peanut * x * x / max(dot(p, p), 1e-6);
+    let q = abs(vec3<f32>(x, p.y / (bulge_params.axis_ratio_y * peanut), z / bulge_params.axis_ratio_z));
+    let m = max(q.x, max(q.y, q.z));
+    if m <= 0.0 {
+        return 0.0;
+    }
+    // Normalised by the largest component so the powers can't overflow
+    let c = bulge_params.boxiness;
+    let qn = max(q / m, vec3<f32>(1e-6));
+    return m * pow(pow(qn.x, c) + pow(qn.y, c) + pow(qn.z, c), 1.0 / c);
+}
+
+// Mirrored by bulge_density in bulge.rs
 fn get_bulge_intensity(p : vec3<f32>) -> f32 {
     let rho_0: f32 = bulge_params.strength;
-    let rad : f32 = (length(p)/galaxy.radius+0.01)*bulge_params.radius + 0.01;
-    var i : f32 = rho_0 * (pow(rad,-0.855)*exp(-pow(rad,1.0/4.0f)) - 0.05f);
+    let n = bulge_params.sersic_index;
+    let rad : f32 = (bulge_radius(p)/galaxy.radius+0.01)*bulge_params.radius + 0.01;
+    // Deprojected Sersic profile, the inner slope is a fit to Prugniel & Simien's
+    var i : f32 = rho_0 * (pow(rad,-(1.0 - 0.58 / n))*exp(-pow(rad,1.0/n)) - 0.05f);
     return max(0.0,i);
 }
 
-fn ray_step(p: vec3<f32>, in_col : vec3<f32>, stepsize : f32) -> vec3<f32> {
+// Fraction of light scattered per steradian, cos_theta is between the light's direction of travel before and after
+// Mirrored in dust_scattering.rs
+fn henyey_greenstein(cos_theta : f32, g : f32) -> f32 {
+    let g2 = g * g;
+    let denom = 1.0 + g2 - 2.0 * g * cos_theta;
+    return (1.0 - g2) / (4.0 * pi * denom * sqrt(denom));
+}
+
+// Light reaching the dust at p, before any colour is applied
+// x = bulge light through the phase function, y = isotropic disk light
+fn dust_incident_light(p : vec3<f32>, view_dir : vec3<f32>, disk_xz : f32) -> vec2<f32> {
+    let r = length(p) / galaxy.radius;
+    let from_centre = p / max(length(p), 0.0001);
+    let phase = henyey_greenstein(dot(from_centre, view_dir), galaxy.dust_anisotropy);
+    // Softened so the dust right at the centre doesn't blow up
+    let central_light = bulge_params.strength / (r * r + 0.01);
+    let disk_light = disk_xz * disk_params.strength / (4.0 * pi);
+    return vec2<f32>(central_light * phase, disk_light);
+}
+
+// Bulge and disk light scattered by the dust at p toward the camera
+// The bulge is approximated by a point source at the centre, the local disk light as isotropic
+// Mirrored in dust_scattering.rs
+fn dust_in_scattering(p : vec3<f32>, view_dir : vec3<f32>, dust_intensity : f32, disk_xz : f32, bulge_col : vec3<f32>, disk_col : vec3<f32>, dust_col : vec3<f32>) -> vec3<f32> {
+    let light = dust_incident_light(p, view_dir, disk_xz);
+    let incident = bulge_col * light.x + disk_col * light.y;
+    // Dust scatters the blue it absorbs, so scattered light is bluer and transmitted light redder
+    return incident * dust_col * dust_intensity * galaxy.dust_albedo * galaxy.scattering_intensity * galaxy.exposure;
+}
+
+// Dust and emission found by a single raymarch step, already multiplied by the step size
+struct StepSample {
+    dust : f32,
+    disk : f32,
+    bulge : f32,
+    // Unmodulated disk density, lights the dust
+    disk_xz : f32,
+}
+
+fn sample_step(p : vec3<f32>, stepsize : f32) -> StepSample {
+    var out : StepSample;
 
     let d : f32 = length(p.xz) / galaxy.radius;
     let uv : vec2<f32> = pos_to_uv(p.xz);
@@ -192,30 +322,282 @@ fn ray_step(p: vec3<f32>, in_col : vec3<f32>, stepsize : f32) -> vec3<f32> {
 
     let dust_xz = reconstruct_intensity(p, xz_sample.y, dust_params.y_thickness);
     let dust_winding_angle : f32 = base_winding * dust_params.winding_factor;
-    let dust_intensity : f32 = get_dust_intensity(p, dust_winding_angle, dust_xz) * stepsize;
-    // yellow absorption spectra = appears red
-    let dust_col = vec3<f32>(0.4,0.6,1.0);
-    let extinction : vec3<f32> = exp(-dust_intensity * dust_col );
+    out.dust = get_dust_intensity(p, dust_winding_angle, dust_xz) * stepsize;
 
-#ifdef EXTINCTION_ONLY
-    return in_col * extinction;
-#else
-    let disk_xz: f32  = reconstruct_intensity(p, xz_sample.x, disk_params.y_thickness);
+#ifndef EXTINCTION_ONLY
+    out.disk_xz = reconstruct_intensity(p, xz_sample.x, disk_params.y_thickness);
     let disk_winding_angle : f32 = base_winding * disk_params.winding_factor;//-disk_sample.y;
+    out.disk = get_disk_intensity(p, disk_winding_angle, out.disk_xz) * stepsize;
+    out.bulge = get_bulge_intensity(p) * bulge_params.intensity_mod * stepsize * galaxy.exposure * 0.1;
+#endif
+    return out;
+}
 
-    //  blue
-    let disk_col = vec3<f32>(0.4,0.6,1.0);
-    let disk_intensity : f32 = get_disk_intensity(p, disk_winding_angle, disk_xz) * stepsize;
-    
-    let bulge_intensity = get_bulge_intensity(p) * stepsize * galaxy.exposure * 0.1;
-    // yellow
-    let bulge_col = vec3<f32>(1.,0.9,0.45);
+// Light a raymarch step adds, already dimmed by the step's own dust, and how much of the light behind it gets through
+struct StepLight {
+    emitted : vec3<f32>,
+    transmittance : vec3<f32>,
+}
+
+// Mirrored by ComponentColor::at
+fn radial_color(color : vec4<f32>, outer_color : vec4<f32>, r : f32) -> vec3<f32> {
+    if outer_color.w <= 0.0 {
+        return color.rgb;
+    }
+    return mix(color.rgb, outer_color.rgb, saturate(r / outer_color.w));
+}
+
+// view_dir is the direction from p toward the camera
+fn step_light(p: vec3<f32>, view_dir : vec3<f32>, stepsize : f32) -> StepLight {
+    let s = sample_step(p, stepsize);
+    var out : StepLight;
 
-#ifdef DIAGNOSTIC
-    return in_col + stepsize * galaxy.exposure * vec3<f32>((disk_intensity + dust_intensity), 0.0, 0.0);
+    // The disk and dust follow the plane, the bulge is spherical
+    let d = length(p.xz) / galaxy.radius;
+    // blue absorption = appears red
+    let dust_col = radial_color(dust_params.color, dust_params.outer_color, d);
+    out.transmittance = exp(-s.dust * dust_col );
+
+#ifdef EXTINCTION_ONLY
+    out.emitted = vec3<f32>(0.0);
+#else
+    let disk_col = radial_color(disk_params.color, disk_params.outer_color, d);
+    let bulge_col = radial_color(bulge_params.color, bulge_params.outer_color, length(p) / galaxy.radius);
+
+#ifdef DUST_SCATTERING
+    let scattered = dust_in_scattering(p, view_dir, s.dust, s.disk_xz, bulge_col, disk_col, dust_col);
 #else
-    let col = in_col + disk_col * disk_intensity * galaxy.exposure + bulge_col * bulge_intensity;
-    return col * extinction;
+    let scattered = vec3<f32>(0.0);
 #endif
+    out.emitted = (disk_col * s.disk * galaxy.exposure + bulge_col * s.bulge + scattered) * out.transmittance;
+#endif
+    return out;
+}
+
+// Back to front, in_col is the light from behind p
+fn ray_step(p: vec3<f32>, view_dir : vec3<f32>, in_col : vec3<f32>, stepsize : f32) -> vec3<f32> {
+    let light = step_light(p, view_dir, stepsize);
+    return in_col * light.transmittance + light.emitted;
+}
+
+#ifndef COMPUTE_BINDINGS
+// Eight wavelength bins, see spectrum.rs for their wavelengths
+// Split over two vec4s so the per bin maths stays vectorised
+struct Spectrum {
+    lo : vec4<f32>,
+    hi : vec4<f32>,
+}
+
+// Duplicated in shader_types.rs
+struct SpectralParams {
+    // Dust optical depth per bin, relative to the B band
+    extinction_lo : vec4<f32>,
+    extinction_hi : vec4<f32>,
+    disk_lo : vec4<f32>,
+    disk_hi : vec4<f32>,
+    bulge_lo : vec4<f32>,
+    bulge_hi : vec4<f32>,
+    // Linear sRGB contribution of each bin, through the selected filter
+    response : array<vec4<f32>, 8>,
+}
+
+@group(2) @binding(8) var<uniform> spectral: SpectralParams;
+
+struct SpectralStepLight {
+    emitted : Spectrum,
+    transmittance : Spectrum,
+}
+
+// Same as step_light, with dust reddening and emission following their spectra rather than fixed colours
+fn spectral_step_light(p: vec3<f32>, view_dir : vec3<f32>, stepsize : f32) -> SpectralStepLight {
+    let s = sample_step(p, stepsize);
+
+    var lo = spectral.disk_lo * s.disk * galaxy.exposure + spectral.bulge_lo * s.bulge;
+    var hi = spectral.disk_hi * s.disk * galaxy.exposure + spectral.bulge_hi * s.bulge;
+#ifdef DUST_SCATTERING
+    let light = dust_incident_light(p, view_dir, s.disk_xz);
+    let scattering = s.dust * galaxy.dust_albedo * galaxy.scattering_intensity * galaxy.exposure;
+    lo += (spectral.bulge_lo * light.x + spectral.disk_lo * light.y) * spectral.extinction_lo * scattering;
+    hi += (spectral.bulge_hi * light.x + spectral.disk_hi * light.y) * spectral.extinction_hi * scattering;
+#endif
+    let transmittance = Spectrum(exp(-s.dust * spectral.extinction_lo), exp(-s.dust * spectral.extinction_hi));
+    return SpectralStepLight(Spectrum(lo * transmittance.lo, hi * transmittance.hi), transmittance);
+}
+
+fn ray_step_spectral(p: vec3<f32>, view_dir : vec3<f32>, in_spec : Spectrum, stepsize : f32) -> Spectrum {
+    let light = spectral_step_light(p, view_dir, stepsize);
+    return Spectrum(
+        in_spec.lo * light.transmittance.lo + light.emitted.lo,
+        in_spec.hi * light.transmittance.hi + light.emitted.hi,
+    );
+}
+
+fn spectrum_to_rgb(spectrum : Spectrum) -> vec3<f32> {
+    var col = vec3<f32>(0.0);
+    for (var i = 0; i < 4; i++) {
+        col += spectral.response[i].rgb * spectrum.lo[i];
+        col += spectral.response[i + 4].rgb * spectrum.hi[i];
+    }
+    return max(col, vec3<f32>(0.0));
+}
+
+// Duplicated in shader_types.rs
+struct VisualizationParams {
+    // Colormap polynomial in sRGB, lowest order first
+    colormap : array<vec4<f32>, 7>,
+    mode : f32,
+    range : f32,
+    log_scale : f32,
+    stars_y_thickness : f32,
+}
+
+@group(2) @binding(9) var<uniform> visualization: VisualizationParams;
+
+// Matching VisualizationMode::shader_id
+const VIS_DISK_DENSITY : i32 = 0;
+const VIS_DUST_DENSITY : i32 = 1;
+const VIS_BULGE_DENSITY : i32 = 2;
+const VIS_STAR_DENSITY : i32 = 3;
+const VIS_COLUMN_DENSITY : i32 = 4;
+const VIS_DUST_OPTICAL_DEPTH : i32 = 5;
+const VIS_WINDING_ANGLE : i32 = 6;
+const VIS_RAYMARCH_COST : i32 = 7;
+
+// Dust optical depth past which less than 1% of the light behind gets through
+const SATURATION_DEPTH : f32 = 4.605;
+
+// Densities at a point, without the step size or exposure the raymarch applies
+struct DensitySample {
+    disk : f32,
+    dust : f32,
+    bulge : f32,
+    stars : f32,
+    // Of the disk, in radians
+    winding : f32,
+}
+
+fn sample_densities(p : vec3<f32>) -> DensitySample {
+    var out : DensitySample;
+
+    let d : f32 = length(p.xz) / galaxy.radius;
+    let xz_sample : vec4<f32> = textureSample(galaxy_xz_texture, galaxy_xz_sampler, pos_to_uv(p.xz));
+    let base_winding : f32 = -lookup_winding(d);
+
+    out.winding = base_winding * disk_params.winding_factor;
+    out.disk = get_disk_intensity(p, out.winding, reconstruct_intensity(p, xz_sample.x, disk_params.y_thickness));
+    let dust_xz = reconstruct_intensity(p, xz_sample.y, dust_params.y_thickness);
+    out.dust = get_dust_intensity(p, base_winding * dust_params.winding_factor, dust_xz);
+    out.bulge = get_bulge_intensity(p);
+    out.stars = reconstruct_intensity(p, xz_sample.z, visualization.stars_y_thickness);
+    return out;
+}
+
+fn colormap(t : f32) -> vec3<f32> {
+    let c = visualization.colormap;
+    let srgb = c[0].rgb + t * (c[1].rgb + t * (c[2].rgb + t * (c[3].rgb + t * (c[4].rgb + t * (c[5].rgb + t * c[6].rgb)))));
+    // The render target is linear
+    return pow(saturate(srgb), vec3<f32>(2.2));
+}
+
+// Marches front to back between t_near and t_far, reducing the selected quantity along the ray to a colour
+fn visualize_ray(ro : vec3<f32>, rd : vec3<f32>, t_near : f32, t_far : f32) -> vec3<f32> {
+    let mode = i32(visualization.mode);
+    let steps = galaxy.raymarch_steps;
+    let dt = (t_far - t_near) / steps;
+
+    var peak = 0.0;
+    var column = 0.0;
+    var optical_depth = 0.0;
+    var winding_sum = 0.0;
+    var weight_sum = 0.0;
+    var saturated_at = steps;
+    for (var i = 0; i < i32(steps); i++) {
+        let s = sample_densities(ro + rd * (t_near + (f32(i) + 0.5) * dt));
+        if mode == VIS_DISK_DENSITY {
+            peak = max(peak, s.disk);
+        } else if mode == VIS_DUST_DENSITY {
+            peak = max(peak, s.dust);
+        } else if mode == VIS_BULGE_DENSITY {
+            peak = max(peak, s.bulge);
+        } else if mode == VIS_STAR_DENSITY {
+            peak = max(peak, s.stars);
+        }
+        column += (s.disk + s.bulge) * dt;
+        // Same scale the raymarch gives the dust
+        optical_depth += s.dust * dt * 0.1;
+        winding_sum += s.winding * s.disk;
+        weight_sum += s.disk;
+        if optical_depth > SATURATION_DEPTH && saturated_at == steps {
+            saturated_at = f32(i + 1);
+        }
+    }
+
+    var value = peak;
+    if mode == VIS_COLUMN_DENSITY {
+        value = column;
+    } else if mode == VIS_DUST_OPTICAL_DEPTH {
+        value = optical_depth;
+    } else if mode == VIS_WINDING_ANGLE {
+        // In turns, weighted toward where the disk is brightest
+        value = abs(winding_sum) / max(weight_sum, 0.0001) / (2.0 * pi);
+    } else if mode == VIS_RAYMARCH_COST {
+        value = saturated_at / steps;
+    }
+    // Empty space stays black rather than the bottom of the colormap
+    if value <= 0.0 {
+        return vec3<f32>(0.0);
+    }
+
+    var t = saturate(value / visualization.range);
+    if visualization.log_scale > 0.5 {
+        t = log(1.0 + 99.0 * t) / log(100.0);
+    }
+    return colormap(t);
+}
+
+// Maximum density of each cell, finest level first, see occupancy.rs
+@group(2) @binding(10) var occupancy_texture: texture_3d<f32>;
+
+// Mirrored in occupancy.rs
+const OCCUPANCY_THRESHOLD : f32 = 0.0005;
+const SPARSE_DENSITY : f32 = 0.05;
+
+// Distance along rd out of the largest empty occupancy cell around p, zero if p's finest cell is occupied
+// Mirrored in occupancy.rs
+fn empty_space_exit(p : vec3<f32>, rd : vec3<f32>) -> f32 {
+    let extent = galaxy.radius * galaxy.padding_coefficient;
+    for (var level = i32(textureNumLevels(occupancy_texture)) - 1; level >= 0; level--) {
+        let dims = vec3<f32>(textureDimensions(occupancy_texture, level));
+        let cell_size = 2.0 * extent / dims;
+        let index = clamp(floor((p + extent) / cell_size), vec3<f32>(0.0), dims - 1.0);
+        if textureLoad(occupancy_texture, vec3<i32>(index), level).r >= OCCUPANCY_THRESHOLD {
+            continue;
+        }
+        return cell_exit(p, rd, level);
+    }
+    return 0.0;
+}
+
+// Distance along rd out of the occupancy cell containing p
+// Mirrored in occupancy.rs
+fn cell_exit(p : vec3<f32>, rd : vec3<f32>, level : i32) -> f32 {
+    let extent = galaxy.radius * galaxy.padding_coefficient;
+    let dims = vec3<f32>(textureDimensions(occupancy_texture, level));
+    let cell_size = 2.0 * extent / dims;
+    let index = clamp(floor((p + extent) / cell_size), vec3<f32>(0.0), dims - 1.0);
+    let bounds = index * cell_size - extent + select(vec3<f32>(0.0), cell_size, rd > vec3<f32>(0.0));
+    let exits = select(vec3<f32>(1e30), (bounds - p) / rd, abs(rd) > vec3<f32>(1e-6));
+    // Nudged over the boundary so the next lookup lands in the next cell
+    return max(min(exits.x, min(exits.y, exits.z)), 0.0) + extent * 1e-4;
+}
+
+// Multiplier on the base step size at p, longer where the volume is sparse
+// Mirrored in occupancy.rs
+fn adaptive_step_scale(p : vec3<f32>) -> f32 {
+    let extent = galaxy.radius * galaxy.padding_coefficient;
+    let dims = vec3<f32>(textureDimensions(occupancy_texture, 0));
+    let index = clamp(floor((p + extent) / (2.0 * extent) * dims), vec3<f32>(0.0), dims - 1.0);
+    let occupancy = textureLoad(occupancy_texture, vec3<i32>(index), 0).r;
+    return clamp(sqrt(SPARSE_DENSITY / max(occupancy, 1e-6)), 1.0, max(galaxy.max_step_scale, 1.0));
+}
 #endif
-}
\ No newline at end of file
@@ -0,0 +1,152 @@
+#import bevy_pbr::mesh_functions
+#import bevy_pbr::mesh_view_bindings::view
+#import "shaders/noise_functions.wgsl"::octave_noise_3d;
+
+// Duplicated in nebulae.rs
+struct NebulaInstance {
+    // rgb = colour, w = brightness
+    colour: vec4<f32>,
+    orientation: vec4<f32>,
+    // x = kind, y = noise offset, z = shell radius, w = elongation
+    shape: vec4<f32>,
+}
+
+struct NebulaParams {
+    extinction_offset: u32,
+    brightness: f32,
+    raymarch_steps: f32,
+    use_extinction: f32,
+}
+
+@group(2) @binding(0) var<storage> nebulae: array<NebulaInstance>;
+@group(2) @binding(1) var<storage> extinction: array<vec4<f32>>;
+@group(2) @binding(2) var<uniform> params: NebulaParams;
+
+// What the extinction compute shader stores for a white source with no dust, after its brightness compression
+// (log(1 + sqrt(3)) * 4 + 1) / sqrt(3)
+const EXTINCTION_REFERENCE: f32 = 2.8984;
+
+// Secondary line colours
+const OIII_COL: vec3<f32> = vec3<f32>(0.3, 0.85, 0.8);
+const SHOCK_COL: vec3<f32> = vec3<f32>(0.45, 0.6, 1.0);
+
+struct Vertex {
+    @builtin(instance_index) instance_index: u32,
+    @location(0) position: vec3<f32>,
+};
+
+struct VertexOutput {
+    @builtin(position) clip_position: vec4<f32>,
+    // In the nebula's space, where it fills the unit sphere
+    @location(0) camera_origin: vec3<f32>,
+    @location(1) ray_dir: vec3<f32>,
+    @location(2) @interpolate(flat) tag: u32,
+    @location(3) @interpolate(flat) transmittance: vec3<f32>,
+};
+
+fn rotate(q: vec4<f32>, v: vec3<f32>) -> vec3<f32> {
+    let t = 2.0 * cross(q.xyz, v);
+    return v + q.w * t + cross(q.xyz, t);
+}
+
+@vertex
+fn vertex(vertex: Vertex) -> VertexOutput {
+    let tag = mesh_functions::get_tag(vertex.instance_index);
+    let model = mesh_functions::get_world_from_local(vertex.instance_index);
+
+    // Uniformly scaled, so the inverse rotation and scale is the transpose over the squared scale
+    let world_from_local = mat3x3<f32>(model[0].xyz, model[1].xyz, model[2].xyz);
+    let local_from_world = transpose(world_from_local) / dot(model[0].xyz, model[0].xyz);
+    let local_camera = local_from_world * (view.world_position - model[3].xyz);
+
+    // Same for the whole nebula, evaluated once at its centre
+    var transmittance = vec3<f32>(1.0);
+    let index = params.extinction_offset + tag;
+    if params.use_extinction > 0.5 && index < arrayLength(&extinction) {
+        transmittance = extinction[index].rgb / EXTINCTION_REFERENCE;
+    }
+
+    var out: VertexOutput;
+    out.clip_position = view.clip_from_world * model * vec4<f32>(vertex.position, 1.0);
+    out.camera_origin = local_camera;
+    out.ray_dir = vertex.position - local_camera;
+    out.tag = tag;
+    out.transmittance = transmittance;
+    return out;
+}
+
+// Star forming region, clumpy and brightest in the middle
+fn emission_nebula(p: vec3<f32>, nebula: NebulaInstance) -> vec3<f32> {
+    let r = length(p);
+    let clumps = octave_noise_3d(4, 0.55, 2.5, p + nebula.shape.y) * 1.2 + 0.4 - r * 0.6;
+    let density = saturate(clumps) * (1.0 - smoothstep(0.3, 1.0, r));
+    // Ionised oxygen close to the hot stars in the middle
+    let col = mix(OIII_COL, nebula.colour.rgb, smoothstep(0.0, 0.6, r));
+    return col * density * density;
+}
+
+// Ejected shell of a dying star, often elongated
+fn planetary_nebula(p: vec3<f32>, nebula: NebulaInstance) -> vec3<f32> {
+    let shell_radius = nebula.shape.z;
+    let r = length(p * vec3<f32>(1.0, nebula.shape.w, 1.0));
+    let shell = exp(-pow((r - shell_radius) / 0.1, 2.0));
+    let inner = exp(-r * r / 0.05) * 0.3;
+    let texture = 0.6 + 0.4 * octave_noise_3d(3, 0.5, 4.0, p + nebula.shape.y);
+    // Red rim around a teal shell
+    let col = mix(nebula.colour.rgb, vec3<f32>(1.0, 0.35, 0.4), smoothstep(shell_radius - 0.1, shell_radius + 0.1, r));
+    return col * (shell * texture + inner);
+}
+
+// Thin filamentary shell swept up by the blast
+fn supernova_remnant(p: vec3<f32>, nebula: NebulaInstance) -> vec3<f32> {
+    let shell_radius = nebula.shape.z;
+    let r = length(p);
+    let shell = exp(-pow((r - shell_radius) / 0.12, 2.0));
+    let n = octave_noise_3d(4, 0.5, 3.0, p + nebula.shape.y);
+    let filaments = pow(1.0 - abs(n), 6.0);
+    let col = mix(nebula.colour.rgb, SHOCK_COL, saturate(n * 2.0 + 0.5));
+    return col * shell * filaments * 2.0;
+}
+
+fn emission(p: vec3<f32>, nebula: NebulaInstance) -> vec3<f32> {
+    let kind = i32(nebula.shape.x);
+    if kind == 1 {
+        return planetary_nebula(p, nebula);
+    } else if kind == 2 {
+        return supernova_remnant(p, nebula);
+    }
+    return emission_nebula(p, nebula);
+}
+
+@fragment
+fn fragment(in: VertexOutput) -> @location(0) vec4<f32> {
+    let nebula = nebulae[in.tag];
+
+    // Into the nebula's own orientation
+    let inverse_orientation = vec4<f32>(-nebula.orientation.xyz, nebula.orientation.w);
+    let ro = rotate(inverse_orientation, in.camera_origin);
+    let rd = rotate(inverse_orientation, normalize(in.ray_dir));
+
+    // Unit sphere intersection
+    let b = dot(ro, rd);
+    let h = b * b - (dot(ro, ro) - 1.0);
+    if h < 0.0 {
+        discard;
+    }
+    let t_start = max(-b - sqrt(h), 0.0);
+    let t_end = -b + sqrt(h);
+    if t_end <= t_start {
+        discard;
+    }
+
+    let steps = max(params.raymarch_steps, 1.0);
+    let dt = (t_end - t_start) / steps;
+    var col = vec3<f32>(0.0);
+    for (var i = 0.0; i < steps; i += 1.0) {
+        col += emission(ro + rd * (t_start + (i + 0.5) * dt), nebula) * dt;
+    }
+
+    let brightness = nebula.colour.w * params.brightness * 2.0;
+    // Additive blending
+    return vec4<f32>(col * brightness * in.transmittance, 0.0);
+}
@@ -0,0 +1,149 @@
+#import bevy_pbr::mesh_view_bindings::{view, globals}
+#import bevy_pbr::mesh_functions::get_world_from_local
+#import bevy_pbr::prepass_io::Vertex
+#import "shaders/noise_functions.wgsl"::octave_noise_3d;
+
+// Duplicated in nucleus.rs
+struct NucleusParams {
+    half_size: vec4<f32>,
+    disk_radius: f32,
+    disk_brightness: f32,
+    jet_length: f32,
+    jet_opening: f32,
+    jet_brightness: f32,
+    jet_noise_scale: f32,
+    jet_speed: f32,
+}
+
+@group(2) @binding(0) var<uniform> params: NucleusParams;
+
+const JET_STEPS: i32 = 64;
+// Inner edge of the accretion disk, relative to its radius
+const DISK_INNER: f32 = 0.15;
+// Orbital speed at the inner edge, as a fraction of c, for the doppler beaming
+const DISK_BETA: f32 = 0.3;
+
+struct VertexOutput {
+    @builtin(position) position: vec4<f32>,
+    @location(0) camera_origin: vec3<f32>,
+    @location(1) ray_dir: vec3<f32>,
+}
+
+@vertex
+fn vertex(vertex: Vertex) -> VertexOutput {
+    let model = get_world_from_local(vertex.instance_index);
+    let local_pos = vertex.position * params.half_size.xyz;
+    let world_pos = model * vec4<f32>(local_pos, 1.0);
+
+    // Marched in the galaxy's local space, so the jets follow its tilt
+    let local_from_world_rot = transpose(mat3x3<f32>(model[0].xyz, model[1].xyz, model[2].xyz));
+    let local_camera = local_from_world_rot * (view.world_position - model[3].xyz);
+
+    var out: VertexOutput;
+    out.position = view.clip_from_world * world_pos;
+    out.camera_origin = local_camera;
+    out.ray_dir = local_pos - local_camera;
+    return out;
+}
+
+// Thin disk temperature profile, normalised to peak at 1
+fn disk_emission(r: f32) -> f32 {
+    let r_in = params.disk_radius * DISK_INNER;
+    if r < r_in || r > params.disk_radius {
+        return 0.0;
+    }
+    let x = r_in / r;
+    let edge_fade = 1.0 - smoothstep(0.7, 1.0, r / params.disk_radius);
+    return x * x * x * (1.0 - sqrt(x)) / 0.0567 * edge_fade;
+}
+
+fn accretion_disk(ro: vec3<f32>, rd: vec3<f32>, t_start: f32, t_end: f32) -> vec3<f32> {
+    // Infinitely thin, intersected with the y = 0 plane
+    if abs(rd.y) < 1e-5 {
+        return vec3<f32>(0.0);
+    }
+    let t = -ro.y / rd.y;
+    if t < t_start || t > t_end {
+        return vec3<f32>(0.0);
+    }
+    let p = ro + rd * t;
+    let r = length(p.xz);
+    let emission = disk_emission(r);
+    if emission <= 0.0 {
+        return vec3<f32>(0.0);
+    }
+
+    // The side orbiting towards the camera is brighter
+    let tangent = normalize(vec3<f32>(-p.z, 0.0, p.x));
+    let beta = DISK_BETA * sqrt(params.disk_radius * DISK_INNER / r);
+    let beaming = pow(1.0 - beta * dot(tangent, -rd), -3.0);
+
+    // Hotter towards the middle, blue-white to orange
+    let col = mix(vec3<f32>(1.0, 0.55, 0.25), vec3<f32>(0.8, 0.88, 1.0), sqrt(emission));
+    // Seen edge on the path through the disk is longer
+    let path_length = min(1.0 / abs(rd.y), 10.0);
+    return col * emission * beaming * path_length * params.disk_brightness * 5.0;
+}
+
+// Unresolved glow of the innermost disk, from the ray's closest approach to the centre
+fn core_glow(ro: vec3<f32>, rd: vec3<f32>) -> vec3<f32> {
+    let closest = ro - rd * dot(ro, rd);
+    let b = length(closest) / (params.disk_radius * DISK_INNER * 2.0);
+    return vec3<f32>(0.85, 0.9, 1.0) * exp(-b * b) * params.disk_brightness * 10.0;
+}
+
+fn jet_emission(p: vec3<f32>) -> f32 {
+    let h = abs(p.y);
+    if h > params.jet_length {
+        return 0.0;
+    }
+    let width = max(h * params.jet_opening, params.disk_radius * 0.1);
+    let rho = length(p.xz) / width;
+    let profile = exp(-rho * rho);
+    if profile < 0.01 {
+        return 0.0;
+    }
+
+    let along = h / params.jet_length;
+    let fade = exp(-3.0 * along) * (1.0 - smoothstep(0.7, 1.0, along));
+    // Knots travelling outwards
+    let q = vec3<f32>(p.x, h - globals.time * params.jet_speed, p.z) / params.jet_length;
+    let knots = saturate(octave_noise_3d(4, 0.5, params.jet_noise_scale, q) * 0.8 + 0.6);
+    // Spread over the cone's width, so the brightness along the jet doesn't depend on the opening angle
+    return profile * fade * knots / width;
+}
+
+fn jets(ro: vec3<f32>, rd: vec3<f32>, t_start: f32, t_end: f32) -> vec3<f32> {
+    if params.jet_brightness <= 0.0 {
+        return vec3<f32>(0.0);
+    }
+    // Synchrotron blue
+    let jet_col = vec3<f32>(0.55, 0.7, 1.0);
+    let dt = (t_end - t_start) / f32(JET_STEPS);
+    var intensity = 0.0;
+    for (var i = 0; i < JET_STEPS; i++) {
+        intensity += jet_emission(ro + rd * (t_start + (f32(i) + 0.5) * dt)) * dt;
+    }
+    return jet_col * intensity * params.jet_brightness * 2.0;
+}
+
+@fragment
+fn fragment(in: VertexOutput) -> @location(0) vec4<f32> {
+    let ro = in.camera_origin;
+    let rd = normalize(in.ray_dir);
+
+    let inv_dir = 1.0 / rd;
+    let t0 = (-params.half_size.xyz - ro) * inv_dir;
+    let t1 = (params.half_size.xyz - ro) * inv_dir;
+    let t_near = max(max(min(t0.x, t1.x), min(t0.y, t1.y)), min(t0.z, t1.z));
+    let t_far = min(min(max(t0.x, t1.x), max(t0.y, t1.y)), max(t0.z, t1.z));
+    let t_start = max(t_near, 0.0);
+    if t_far <= t_start {
+        discard;
+    }
+
+    let col = accretion_disk(ro, rd, t_start, t_far) + core_glow(ro, rd) + jets(ro, rd, t_start, t_far);
+
+    // Additive blending
+    return vec4<f32>(col, 0.0);
+}
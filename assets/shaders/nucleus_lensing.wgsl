@@ -0,0 +1,35 @@
+#import bevy_core_pipeline::fullscreen_vertex_shader::FullscreenVertexOutput
+
+// Duplicated in nucleus.rs
+struct NucleusLensing {
+    centre: vec2<f32>,
+    einstein_radius: f32,
+    aspect: f32,
+    shadow_radius: f32,
+}
+
+@group(0) @binding(0) var screen_texture: texture_2d<f32>;
+@group(0) @binding(1) var screen_sampler: sampler;
+@group(0) @binding(2) var<uniform> lensing: NucleusLensing;
+
+@fragment
+fn fragment(in: FullscreenVertexOutput) -> @location(0) vec4<f32> {
+    let aspect = vec2<f32>(lensing.aspect, 1.0);
+    // In units of the screen height, so the lens stays round
+    let offset = (in.uv - lensing.centre) * aspect;
+    let r = max(length(offset), 1e-6);
+    let einstein_radius = lensing.einstein_radius;
+
+    if r < einstein_radius * lensing.shadow_radius {
+        return vec4<f32>(0.0, 0.0, 0.0, 1.0);
+    }
+
+    // Point lens, light seen at r comes from r - θE² / r
+    // Inside the Einstein ring that's the mirrored image from the far side
+    // Faded out further away so the whole screen isn't warped
+    let fade = 1.0 - smoothstep(8.0 * einstein_radius, 12.0 * einstein_radius, r);
+    let source = offset * (1.0 - fade * einstein_radius * einstein_radius / (r * r));
+    let uv = lensing.centre + source / aspect;
+
+    return textureSampleLevel(screen_texture, screen_sampler, uv, 0.0);
+}
@@ -4,7 +4,7 @@
 #import bevy_pbr::prepass_io::Vertex
 
 
-#import "shaders/intensity_shared.wgsl"::{galaxy, ray_step, get_xz_intensity};
+#import "shaders/intensity_shared.wgsl"::{galaxy, ray_step, get_xz_intensity, Spectrum, ray_step_spectral, spectrum_to_rgb, visualize_ray, step_light, spectral_step_light, empty_space_exit, adaptive_step_scale};
 
 // see https://github.com/kulkalkul/bevy_mod_billboard/blob/main/src/shader/billboard.wgsl
 
@@ -31,10 +31,14 @@ fn vertex(vertex: Vertex) -> VertexOutput {
 
     let scaled_vertex = vertex.position * scaled_r;
 
+    // The raymarch happens in the galaxy's local space, so it can be placed and tilted anywhere
+    let local_from_world_rot = transpose(mat3x3<f32>(model[0].xyz, model[1].xyz, model[2].xyz));
+    let local_camera = local_from_world_rot * (view.world_position - model[3].xyz);
+
     var out: VertexOutput;
     out.position = position;
-    out.camera_origin = view.world_position;
-    out.ray_dir = world_pos.xyz - view.world_position;
+    out.camera_origin = local_camera;
+    out.ray_dir = scaled_vertex - local_camera;
 
     return out;
 }
@@ -53,19 +57,134 @@ fn sphIntersect( ro : vec3<f32> , rd : vec3<f32> ,  r : f32 ) -> vec2<f32>
 }
 
 fn march(ro : vec3<f32>, rd : vec3<f32>, near_offset : f32, far_offset : f32) -> vec3<f32> {    
+#ifdef SPECTRAL
+    var spectrum = Spectrum(vec4<f32>(0.0), vec4<f32>(0.0));
+#else
     var col = vec3<f32>(0.0,0.0,0.0);
+#endif
     let STEPS = galaxy.raymarch_steps;
     let exposure = 0.1;
 
+    // Camera is inside the volume
+    // The steps are spread quadratically so the detail close to the camera isn't lost
+    if near_offset <= 0.0 {
+        return march_inside(ro, rd, far_offset);
+    }
+
     // we trace backwards from the far point
     let step_size = abs(near_offset-far_offset) / STEPS;
     let start = ro + rd * (far_offset + jitter(rd.xy + rd.zz) * step_size * 5.0);
     let end = ro + rd * near_offset;
     for(var i =0; i<i32(STEPS); i++) {
         let p = start - step_size * f32(i) * rd;
-        col = ray_step(p,col,step_size * exposure);
+#ifdef SPECTRAL
+        spectrum = ray_step_spectral(p, -rd, spectrum, step_size * exposure);
+#else
+        col = ray_step(p, -rd, col,step_size * exposure);
+#endif
     }
+#ifdef SPECTRAL
+    return spectrum_to_rgb(spectrum);
+#else
     return col;    
+#endif
+}
+
+fn march_inside(ro : vec3<f32>, rd : vec3<f32>, far_offset : f32) -> vec3<f32> {
+#ifdef SPECTRAL
+    var spectrum = Spectrum(vec4<f32>(0.0), vec4<f32>(0.0));
+#else
+    var col = vec3<f32>(0.0,0.0,0.0);
+#endif
+    let STEPS = galaxy.raymarch_steps;
+    let exposure = 0.1;
+    let j = jitter(rd.xy + rd.zz);
+
+    // we trace backwards from the far point
+    for(var i =0; i<i32(STEPS); i++) {
+        let s0 = 1.0 - (f32(i) - j) / STEPS;
+        let s1 = 1.0 - (f32(i) + 1.0 - j) / STEPS;
+        let t0 = s0 * s0 * far_offset;
+        let t1 = s1 * s1 * far_offset;
+#ifdef SPECTRAL
+        spectrum = ray_step_spectral(ro + rd * t0, -rd, spectrum, (t0 - t1) * exposure);
+#else
+        col = ray_step(ro + rd * t0, -rd, col, (t0 - t1) * exposure);
+#endif
+    }
+#ifdef SPECTRAL
+    return spectrum_to_rgb(spectrum);
+#else
+    return col;
+#endif
+}
+
+// Mirrored in occupancy.rs
+const SATURATED_TRANSMITTANCE : f32 = 0.01;
+
+// Front to back, jumping over the cells of the occupancy grid the density never reaches and lengthening the steps
+// where it's sparse, until the dust lets almost nothing through
+// Mirrored by march_adaptive in occupancy.rs
+fn march_skipping(ro : vec3<f32>, rd : vec3<f32>, near_offset : f32, far_offset : f32) -> vec3<f32> {
+#ifdef SPECTRAL
+    var spectrum = Spectrum(vec4<f32>(0.0), vec4<f32>(0.0));
+    var transmittance = Spectrum(vec4<f32>(1.0), vec4<f32>(1.0));
+#else
+    var col = vec3<f32>(0.0,0.0,0.0);
+    var transmittance = vec3<f32>(1.0);
+#endif
+    let STEPS = galaxy.raymarch_steps;
+    let exposure = 0.1;
+    let j = jitter(rd.xy + rd.zz);
+    let base_step = (far_offset - near_offset) / STEPS;
+
+    var t = near_offset;
+    // Skips count too, so a ray grazing many cells can't run away
+    for (var i = 0; i < i32(STEPS) * 2; i++) {
+        if t >= far_offset {
+            break;
+        }
+        let p = ro + rd * t;
+        let skip = empty_space_exit(p, rd);
+        if skip > 0.0 {
+            t += skip;
+            continue;
+        }
+
+        var step_size = base_step * adaptive_step_scale(p);
+        // A long step mustn't run on into a denser cell and weight its density by the sparse one's length
+        step_size = min(step_size, max(cell_exit(p, rd, 0), base_step));
+        // Camera is inside the volume, keep the quadratic spacing of march_inside close to it
+        if near_offset <= 0.0 {
+            step_size = min(step_size, base_step * max(2.0 * sqrt(t / far_offset), 0.01));
+        }
+        step_size = min(step_size, far_offset - t);
+
+        let sample_pos = ro + rd * (t + step_size * j);
+#ifdef SPECTRAL
+        let light = spectral_step_light(sample_pos, -rd, step_size * exposure);
+        spectrum.lo += transmittance.lo * light.emitted.lo;
+        spectrum.hi += transmittance.hi * light.emitted.hi;
+        transmittance.lo *= light.transmittance.lo;
+        transmittance.hi *= light.transmittance.hi;
+        let remaining = max(max(max(transmittance.lo.x, transmittance.lo.y), max(transmittance.lo.z, transmittance.lo.w)),
+            max(max(transmittance.hi.x, transmittance.hi.y), max(transmittance.hi.z, transmittance.hi.w)));
+#else
+        let light = step_light(sample_pos, -rd, step_size * exposure);
+        col += transmittance * light.emitted;
+        transmittance *= light.transmittance;
+        let remaining = max(transmittance.x, max(transmittance.y, transmittance.z));
+#endif
+        if remaining < SATURATED_TRANSMITTANCE {
+            break;
+        }
+        t += step_size;
+    }
+#ifdef SPECTRAL
+    return spectrum_to_rgb(spectrum);
+#else
+    return col;
+#endif
 }
 
 fn jitter(p : vec2<f32>) -> f32 {
@@ -88,6 +207,15 @@ fn fragment(
     let near = max(0.0,t.x);
     let far = t.y;
 
+#ifdef VISUALIZATION
+    let a = visualize_ray(ro, rd, near, far);
+#else
+#ifdef EMPTY_SPACE_SKIPPING
+    let a = march_skipping(ro, rd, near, far);
+#else
     let a = march(mesh.camera_origin, normalize(mesh.ray_dir), near,far);
-    return vec4<f32>(a,1.0);        
+#endif
+#endif
+    // Additive blending, zero alpha so galaxies behind aren't covered
+    return vec4<f32>(a,0.0);        
 }
\ No newline at end of file
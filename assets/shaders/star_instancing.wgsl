@@ -1,17 +1,51 @@
-#import bevy_pbr::{
-    mesh_functions,
-    view_transformations::position_world_to_clip
+#import bevy_render::view::View
+
+@group(0) @binding(0) var<uniform> view: View;
+
+// Duplicated in star_instancing.rs
+struct StarBatchParams {
+    world_from_local: mat4x4<f32>,
+    point_scale: f32,
+    supersampling_offset_scale: f32,
+}
+@group(1) @binding(0) var<uniform> batch: StarBatchParams;
+
+// Duplicated in star_instancing.rs
+struct StarPsfParams {
+    threshold: f32,
+    size: f32,
+    brightness: f32,
+    core_scale: f32,
+    halo_strength: f32,
+    halo_radius: f32,
+    spike_strength: f32,
+    spike_length: f32,
+    spike_lines: f32,
+    rotation: f32,
 }
-#import bevy_pbr::mesh_functions::{get_world_from_local, mesh_position_local_to_clip}
-#import bevy_pbr::view_transformations::position_world_to_clip;
-#import bevy_pbr::mesh_view_bindings::view
-@group(2) @binding(0) var<storage> extinction_output: array<vec4<f32>>;
-@group(2) @binding(1) var<uniform> supersampling_offset_scale: f32;
+@group(1) @binding(1) var<uniform> psf: StarPsfParams;
+// Galaxy local positions, w is 0 for stars that aren't placed yet
+@group(1) @binding(2) var<storage> star_positions: array<vec4<f32>>;
+@group(1) @binding(3) var<storage> extinction_output: array<vec4<f32>>;
+
+const pi = radians(180.0);
+// Diffraction scales with wavelength, relative to red at 650nm
+const WAVELENGTH_RATIO = vec3<f32>(1.0, 0.83, 0.68);
+
+// Two triangles per star
+const QUAD_CORNERS = array<vec2<f32>, 6>(
+    vec2<f32>(-1.0, -1.0),
+    vec2<f32>(1.0, -1.0),
+    vec2<f32>(1.0, 1.0),
+    vec2<f32>(-1.0, -1.0),
+    vec2<f32>(1.0, 1.0),
+    vec2<f32>(-1.0, 1.0)
+);
 
 
 struct Vertex {
     @builtin(instance_index) instance_index: u32,
-    @location(0) position: vec3<f32>,
+    @builtin(vertex_index) vertex_index: u32,
 };
 
 struct VertexOutput {
@@ -19,6 +53,8 @@ struct VertexOutput {
     @location(0) world_position: vec4<f32>,
     @location(1) color: vec4<f32>,
     @location(2) uv : vec2<f32>,
+    // 1 for the stars bright enough for the full PSF
+    @location(3) @interpolate(flat) use_psf : f32,
 };
 
 @vertex
@@ -26,9 +62,17 @@ fn vertex(vertex: Vertex) -> VertexOutput {
     let billboard_margin_scale = 4.0;
     let minor_stars_scale_factor = 0.1;
 
-    // retrieve colour based on instance tag
-    let tag = mesh_functions::get_tag(vertex.instance_index);
-    let in_color = extinction_output[tag].rgb;
+    var out : VertexOutput;
+    let star_position = star_positions[vertex.instance_index];
+    if star_position.w == 0.0 {
+        // Every corner on the same point outside the clip volume, so nothing is rasterised
+        out.clip_position = vec4<f32>(2.0, 2.0, 2.0, 1.0);
+        return out;
+    }
+    let corner = QUAD_CORNERS[vertex.vertex_index];
+
+    // the instance is the star index
+    let in_color = extinction_output[vertex.instance_index].rgb;
 
     var scale_factor =  (in_color.x+in_color.y+in_color.z) * minor_stars_scale_factor * billboard_margin_scale;
     var alpha = 1.0;
@@ -36,15 +80,25 @@ fn vertex(vertex: Vertex) -> VertexOutput {
         alpha = scale_factor/1.0;
         scale_factor = 1.0;
     }
+    scale_factor *= batch.point_scale;
+
+    // Only the brightest stars after extinction pay for the PSF, on a larger billboard to fit the spikes
+    let use_psf = in_color.x + in_color.y + in_color.z > psf.threshold;
+    var uv_scale = billboard_margin_scale;
+    if use_psf {
+        scale_factor *= psf.size;
+        uv_scale *= psf.size;
+    }
 
     let camera_right = normalize(vec3<f32>(view.clip_from_world[0].x, view.clip_from_world[1].x, view.clip_from_world[2].x));    
     let camera_up = normalize(vec3<f32>(view.clip_from_world[0].y, view.clip_from_world[1].y, view.clip_from_world[2].y));
 
-    var out : VertexOutput;
-    out.world_position = get_world_from_local(vertex.instance_index) * vec4<f32>((camera_right * vertex.position.x + camera_up * vertex.position.y ) * scale_factor,1.0);
+    let centre = batch.world_from_local * vec4<f32>(star_position.xyz, 1.0);
+    out.world_position = vec4<f32>(centre.xyz + (camera_right * corner.x + camera_up * corner.y) * scale_factor, 1.0);
     out.clip_position = view.clip_from_world * vec4<f32>(out.world_position.xyz, 1.0);
-    out.uv = vertex.position.xy * billboard_margin_scale;
+    out.uv = corner * uv_scale;
     out.color = vec4<f32>(in_color,alpha);
+    out.use_psf = select(0.0, 1.0, use_psf);
 
     return out;
 }
@@ -71,6 +125,49 @@ fn draw_star(pos : vec2<f32>, star_color : vec3<f32>, I : f32) -> vec3<f32> {
     return col;
 }
 
+// Accurate to about 1% over the range the PSF uses
+fn bessel_j1(x : f32) -> f32 {
+    if x < 3.0 {
+        let x2 = x * x;
+        return x * 0.5 * (1.0 - x2 / 8.0 + x2 * x2 / 192.0 - x2 * x2 * x2 / 9216.0 + x2 * x2 * x2 * x2 / 737280.0);
+    }
+    let chi = x - 0.75 * pi;
+    return sqrt(2.0 / (pi * x)) * (cos(chi) - 3.0 / (8.0 * x) * sin(chi));
+}
+
+// Diffraction pattern of a circular aperture, 1 at the centre
+fn airy(x : f32) -> f32 {
+    if x < 0.0001 {
+        return 1.0;
+    }
+    let a = 2.0 * bessel_j1(x) / x;
+    return a * a;
+}
+
+// Airy core, a scattered light halo and diffraction spikes from the secondary mirror's support vanes
+fn draw_star_psf(pos : vec2<f32>, star_color : vec3<f32>, I : f32) -> vec3<f32> {
+    let d = length(pos);
+
+    let x = d * psf.core_scale / WAVELENGTH_RATIO;
+    let core = vec3<f32>(airy(x.r), airy(x.g), airy(x.b));
+
+    let h = d / psf.halo_radius;
+    let halo = psf.halo_strength / pow(1.0 + h * h, 1.5);
+
+    var spikes = vec3<f32>(0.0);
+    let lines = i32(psf.spike_lines);
+    for (var k = 0; k < lines; k += 1) {
+        let angle = psf.rotation + f32(k) * pi / f32(lines);
+        let dir = vec2<f32>(cos(angle), sin(angle));
+        let along = abs(dot(pos, dir));
+        let across = dot(pos, vec2<f32>(-dir.y, dir.x));
+        let falloff = 1.0 / (1.0 + along / (psf.spike_length * WAVELENGTH_RATIO));
+        spikes += exp(-across * across * 400.0) * falloff * falloff;
+    }
+
+    return star_color * I * psf.brightness * (core + halo + spikes * psf.spike_strength);
+}
+
 const weights_4 = array<vec2<f32>,4>(
     vec2<f32>(1.0/8.0,3.0/8.0),
     vec2<f32>(3.0/8.0,-1.0/8.0),
@@ -90,18 +187,25 @@ const weights_8 = array<vec2<f32>,8>(
 
 @fragment
 fn fragment(in: VertexOutput) -> @location(0) vec4<f32> {
-    let dpdx = dpdx(in.uv) * supersampling_offset_scale;//vec2(dpdx(in.uv),dpdy(in.uv));
-    let dpdy = dpdy(in.uv) * supersampling_offset_scale;
+    let dpdx = dpdx(in.uv) * batch.supersampling_offset_scale;//vec2(dpdx(in.uv),dpdy(in.uv));
+    let dpdy = dpdy(in.uv) * batch.supersampling_offset_scale;
 
     let intensity =  in.color.a / 256.0;//.02*exp(-15.*rnd(1));
 
     var starcol = vec3<f32>(0.0);
-    for(var i =0; i<8; i+=1) {
-        starcol     += draw_star(in.uv + dpdx * weights_8[i].x + dpdy * weights_8[i].y, in.color.rgb, intensity);
+    if in.use_psf > 0.5 {
+        // Smoother than the plain star, so fewer samples do
+        for(var i =0; i<4; i+=1) {
+            starcol += draw_star_psf(in.uv + dpdx * weights_4[i].x + dpdy * weights_4[i].y, in.color.rgb, intensity);
+        }
+        starcol = in.color.a * starcol / 4.0;
+    } else {
+        for(var i =0; i<8; i+=1) {
+            starcol     += draw_star(in.uv + dpdx * weights_8[i].x + dpdy * weights_8[i].y, in.color.rgb, intensity);
+        }
+        starcol = in.color.a * starcol / 8.0;
     }
 
-    starcol = in.color.a * starcol / 8.0;
-
     let a = (starcol.x+starcol.y+starcol.z)/3.0;
 
     return vec4<f32>(starcol,a);
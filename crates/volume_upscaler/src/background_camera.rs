@@ -15,11 +15,12 @@ use bevy::{
 
 use super::background_upscale::BackgroundUpscaleSettings;
 
-const UPSCALE_FACTOR: i32 = 4;
+/// The background is rendered at 1/UPSCALE_FACTOR of the target resolution along each axis
+pub const UPSCALE_FACTOR: i32 = 4;
 pub const BACKGROUND_RENDER_LAYER: usize = 999;
 
 /// The target camera
-#[derive(Component)]
+#[derive(Component, Default)]
 #[require(BackgroundUpscaleSettings)]
 pub struct BackgroundCamera;
 
@@ -30,6 +31,7 @@ struct BackgroundChildCamera;
 
 /// This is the lowres background texture
 /// Component on the parent camera, render target of the child camera
+/// Setting `reset` discards the accumulated history on the next frame
 #[derive(Component, Default, Clone, Reflect)]
 #[reflect(Component, Default, Clone)]
 pub struct BackgroundImageOutput {
@@ -72,16 +74,27 @@ impl Plugin for BackgroundCameraPlugin {
 }
 
 /// This could be implemented more robustly
+#[allow(clippy::type_complexity)]
 fn cleanup(
     mut commands: Commands,
-    mut q_child: Query<(Entity, &mut Camera, &ChildOf), With<BackgroundChildCamera>>,
-    q_parent: Query<&Camera, (With<BackgroundCamera>, Without<BackgroundChildCamera>)>,
+    mut q_child: Query<
+        (Entity, &mut Camera, &mut Projection, &ChildOf),
+        With<BackgroundChildCamera>,
+    >,
+    q_parent: Query<
+        (&Camera, Ref<Projection>),
+        (With<BackgroundCamera>, Without<BackgroundChildCamera>),
+    >,
 ) {
-    // if parent is alive, copy their HDR settings
+    // if parent is alive, copy their HDR settings, sub view (for tiled rendering) and projection
     // otherwise clean up the stray child
-    for (entity, mut camera, child_of) in q_child.iter_mut() {
-        if let Ok(parent_camera) = q_parent.get(child_of.parent()) {
+    for (entity, mut camera, mut projection, child_of) in q_child.iter_mut() {
+        if let Ok((parent_camera, parent_projection)) = q_parent.get(child_of.parent()) {
             camera.hdr = parent_camera.hdr;
+            camera.sub_camera_view = parent_camera.sub_camera_view;
+            if parent_projection.is_changed() || projection.is_added() {
+                *projection = parent_projection.clone();
+            }
         } else {
             commands.entity(entity).despawn();
         }
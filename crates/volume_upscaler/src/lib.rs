@@ -3,7 +3,10 @@ mod background_camera;
 mod background_upscale;
 pub mod prelude;
 
-pub use background_camera::{BACKGROUND_RENDER_LAYER, BackgroundCamera, background_render_layer};
+pub use background_camera::{
+    BACKGROUND_RENDER_LAYER, BackgroundCamera, BackgroundImageOutput, UPSCALE_FACTOR,
+    background_render_layer,
+};
 
 pub struct BackgroundRenderingPlugin;
 
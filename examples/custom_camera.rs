@@ -0,0 +1,27 @@
+use bevy::prelude::*;
+use galaxy_tracer::{graphics::GalaxyCamera, GalaxyPlugin};
+
+// The galaxy embedded in an app with its own camera and no egui
+fn main() {
+    App::new()
+        .add_plugins(DefaultPlugins)
+        .add_plugins(GalaxyPlugin::default().without_ui().with_custom_camera())
+        .insert_resource(ClearColor(Color::BLACK))
+        .add_systems(Startup, setup)
+        .add_systems(Update, orbit)
+        .run();
+}
+
+fn setup(mut commands: Commands) {
+    commands.spawn((
+        Camera3d::default(),
+        Transform::from_xyz(0.0, 8000.0, -20000.0).looking_at(Vec3::ZERO, Vec3::Y),
+        GalaxyCamera,
+    ));
+}
+
+fn orbit(time: Res<Time>, mut camera: Query<&mut Transform, With<GalaxyCamera>>) {
+    for mut transform in &mut camera {
+        transform.rotate_around(Vec3::ZERO, Quat::from_rotation_y(time.delta_secs() * 0.05));
+    }
+}
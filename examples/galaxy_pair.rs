@@ -0,0 +1,36 @@
+use bevy::prelude::*;
+use galaxy_tracer::{
+    galaxy::{GalaxyConfig, PrimaryGalaxy},
+    GalaxyPlugin,
+};
+use std::f32::consts::FRAC_PI_3;
+
+// Two galaxies side by side, the second one smaller and tilted
+// Its seed only changes the star names and nebula placement, the stars are sampled afresh for every galaxy anyway
+fn main() {
+    App::new()
+        .add_plugins(DefaultPlugins)
+        .add_plugins(GalaxyPlugin::default().without_default_galaxy())
+        .add_systems(Startup, setup)
+        .run();
+}
+
+fn setup(mut commands: Commands) {
+    let config = GalaxyConfig::default();
+    let offset = config.radius * 2.5;
+
+    commands.spawn((
+        config.clone(),
+        Transform::from_xyz(-offset, 0.0, 0.0),
+        PrimaryGalaxy,
+    ));
+    commands.spawn((
+        GalaxyConfig {
+            seed: config.seed + 1,
+            radius: config.radius * 0.6,
+            ..config
+        },
+        Transform::from_xyz(offset, config.radius * 0.3, 0.0)
+            .with_rotation(Quat::from_rotation_x(FRAC_PI_3)),
+    ));
+}
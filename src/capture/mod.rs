@@ -0,0 +1,18 @@
+use bevy::prelude::*;
+
+mod poster;
+mod recording;
+mod skybox;
+
+/// Offline rendering: image sequences and high resolution stills
+pub struct CapturePlugin;
+
+impl Plugin for CapturePlugin {
+    fn build(&self, app: &mut App) {
+        app.add_plugins((
+            recording::RecordingPlugin,
+            poster::PosterPlugin,
+            skybox::SkyboxExportPlugin,
+        ));
+    }
+}
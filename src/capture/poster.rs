@@ -0,0 +1,330 @@
+use crate::ui::CameraMain;
+use bevy::{
+    prelude::*,
+    render::{
+        camera::{RenderTarget, SubCameraView},
+        render_asset::RenderAssetUsages,
+        render_resource::{Extent3d, TextureDimension, TextureFormat, TextureUsages},
+        view::screenshot::{Screenshot, ScreenshotCaptured},
+    },
+    tasks::IoTaskPool,
+    time::TimeUpdateStrategy,
+    window::WindowRef,
+};
+use bevy_egui::{egui, EguiContexts};
+use std::time::Duration;
+use volume_upscaler::BackgroundImageOutput;
+
+/// Poster quality stills larger than the window
+///
+/// The view is split into tiles which are rendered one after another with an off-centre sub-view of the full frustum.
+/// Time is frozen while a tile is held on screen, so the temporal upscaler accumulates every jittered subpixel
+/// and the volume is captured at full resolution rather than the blurry single-frame upscale.
+pub struct PosterPlugin;
+
+impl Plugin for PosterPlugin {
+    fn build(&self, app: &mut App) {
+        app.insert_resource(PosterSettings::default())
+            .insert_resource(PosterState::default())
+            .add_systems(Update, (poster_ui, update_poster).chain());
+    }
+}
+
+/// Upscaler history is reset for this many frames after moving to a new tile,
+/// the background child camera picks up the new sub-view a frame late
+const RESET_FRAMES: u32 = 2;
+
+#[derive(Resource, Clone, PartialEq)]
+pub struct PosterSettings {
+    pub width: u32,
+    pub height: u32,
+    /// Edge length of the square tiles, rounded to a multiple of the upscale factor
+    pub tile_size: u32,
+    /// Frames each tile is held for before capturing, at least one full jitter cycle
+    pub converge_frames: u32,
+    pub output_path: String,
+}
+
+impl Default for PosterSettings {
+    fn default() -> Self {
+        Self {
+            width: 7680,
+            height: 4320,
+            tile_size: 2048,
+            converge_frames: 32,
+            output_path: "poster.png".into(),
+        }
+    }
+}
+
+#[derive(Resource, Default)]
+struct PosterState {
+    active: bool,
+    tiles: Vec<URect>,
+    current: usize,
+    wait: u32,
+    target: Option<Handle<Image>>,
+    /// Tiles are copied in here as their readback arrives
+    stitched: Option<Image>,
+    received: usize,
+    output_path: String,
+}
+
+fn create_tile_image(size: u32) -> Image {
+    let mut image = Image::new_fill(
+        Extent3d {
+            width: size,
+            height: size,
+            depth_or_array_layers: 1,
+        },
+        TextureDimension::D2,
+        &[0, 0, 0, 255],
+        TextureFormat::Rgba8UnormSrgb,
+        RenderAssetUsages::default(),
+    );
+    image.texture_descriptor.usage = TextureUsages::TEXTURE_BINDING
+        | TextureUsages::COPY_SRC
+        | TextureUsages::COPY_DST
+        | TextureUsages::RENDER_ATTACHMENT;
+    image
+}
+
+fn poster_tiles(width: u32, height: u32, tile_size: u32) -> Vec<URect> {
+    let mut tiles = vec![];
+    for y in (0..height).step_by(tile_size as usize) {
+        for x in (0..width).step_by(tile_size as usize) {
+            tiles.push(URect::new(x, y, x + tile_size, y + tile_size));
+        }
+    }
+    tiles
+}
+
+fn tile_view(settings_size: UVec2, tile: URect) -> SubCameraView {
+    SubCameraView {
+        full_size: settings_size,
+        offset: tile.min.as_vec2(),
+        size: tile.size(),
+    }
+}
+
+fn start_poster(
+    commands: &mut Commands,
+    settings: &PosterSettings,
+    state: &mut PosterState,
+    camera: &mut Camera,
+    images: &mut Assets<Image>,
+) {
+    if !matches!(camera.target, RenderTarget::Window(WindowRef::Primary)) {
+        warn!("Camera is already rendering offscreen, poster not started");
+        return;
+    }
+
+    // The upscaler renders the volume at a fraction of the resolution, keep the tiles divisible by it
+    let factor = volume_upscaler::UPSCALE_FACTOR as u32;
+    let tile_size = (settings.tile_size.max(64) / factor) * factor;
+    let target = images.add(create_tile_image(tile_size));
+    camera.target = RenderTarget::Image(target.clone().into());
+
+    let mut stitched = Image::new_fill(
+        Extent3d {
+            width: settings.width,
+            height: settings.height,
+            depth_or_array_layers: 1,
+        },
+        TextureDimension::D2,
+        &[0, 0, 0, 255],
+        TextureFormat::Rgba8UnormSrgb,
+        RenderAssetUsages::MAIN_WORLD,
+    );
+    stitched.texture_descriptor.usage = TextureUsages::COPY_DST;
+
+    *state = PosterState {
+        active: true,
+        tiles: poster_tiles(settings.width, settings.height, tile_size),
+        current: 0,
+        wait: settings.converge_frames.max(RESET_FRAMES + 16),
+        target: Some(target),
+        stitched: Some(stitched),
+        received: 0,
+        output_path: settings.output_path.clone(),
+    };
+    camera.sub_camera_view = Some(tile_view(
+        uvec2(settings.width, settings.height),
+        state.tiles[0],
+    ));
+
+    commands.insert_resource(TimeUpdateStrategy::ManualDuration(Duration::ZERO));
+    info!(
+        "Rendering {}x{} poster in {} tiles",
+        settings.width,
+        settings.height,
+        state.tiles.len()
+    );
+}
+
+fn finish_poster(commands: &mut Commands, state: &mut PosterState, camera: &mut Camera) {
+    camera.target = RenderTarget::default();
+    camera.sub_camera_view = None;
+    state.active = false;
+    state.target = None;
+    commands.insert_resource(TimeUpdateStrategy::Automatic);
+}
+
+fn receive_tile(tile: URect) -> impl FnMut(Trigger<ScreenshotCaptured>, ResMut<PosterState>) {
+    move |trigger, mut state| {
+        let captured = &trigger.event().0;
+        let Some(stitched) = state.stitched.as_mut() else {
+            return;
+        };
+        let (Some(src), Some(dst)) = (captured.data.as_ref(), stitched.data.as_mut()) else {
+            return;
+        };
+
+        let full_width = stitched.texture_descriptor.size.width;
+        let full_height = stitched.texture_descriptor.size.height;
+        let tile_width = captured.width();
+        // Tiles on the right and bottom edges overhang the poster
+        let copy_width = tile.width().min(full_width - tile.min.x) as usize;
+        let copy_height = tile.height().min(full_height - tile.min.y);
+
+        for row in 0..copy_height {
+            let src_start = (row * tile_width) as usize * 4;
+            let dst_start = ((tile.min.y + row) * full_width + tile.min.x) as usize * 4;
+            dst[dst_start..dst_start + copy_width * 4]
+                .copy_from_slice(&src[src_start..src_start + copy_width * 4]);
+        }
+
+        state.received += 1;
+        if state.received < state.tiles.len() {
+            return;
+        }
+
+        let Some(stitched) = state.stitched.take() else {
+            return;
+        };
+        let path = state.output_path.clone();
+        IoTaskPool::get()
+            .spawn(async move {
+                match stitched.try_into_dynamic() {
+                    Ok(dynamic) => match dynamic.into_rgba8().save(&path) {
+                        Ok(()) => info!("Poster saved to {path}"),
+                        Err(err) => error!("Failed to save poster {path}: {err}"),
+                    },
+                    Err(err) => error!("Cannot convert poster: {err:?}"),
+                }
+            })
+            .detach();
+    }
+}
+
+fn update_poster(
+    mut commands: Commands,
+    settings: Res<PosterSettings>,
+    mut state: ResMut<PosterState>,
+    mut camera: Query<(&mut Camera, &mut BackgroundImageOutput), With<CameraMain>>,
+) {
+    if !state.active {
+        return;
+    }
+    let Ok((mut camera, mut background)) = camera.single_mut() else {
+        return;
+    };
+
+    let converge_frames = settings.converge_frames.max(RESET_FRAMES + 16);
+    if state.wait > converge_frames - RESET_FRAMES {
+        background.reset = true;
+    }
+    if state.wait > 0 {
+        state.wait -= 1;
+        return;
+    }
+
+    let (Some(target), Some(&tile)) = (state.target.clone(), state.tiles.get(state.current)) else {
+        return;
+    };
+    commands
+        .spawn(Screenshot::image(target))
+        .observe(receive_tile(tile));
+
+    state.current += 1;
+    if let Some(&next) = state.tiles.get(state.current) {
+        camera.sub_camera_view = Some(tile_view(uvec2(settings.width, settings.height), next));
+        state.wait = converge_frames;
+    } else {
+        finish_poster(&mut commands, &mut state, &mut camera);
+    }
+}
+
+fn poster_ui(
+    mut commands: Commands,
+    mut contexts: EguiContexts,
+    keys: Res<ButtonInput<KeyCode>>,
+    mut settings: ResMut<PosterSettings>,
+    mut state: ResMut<PosterState>,
+    mut camera: Query<&mut Camera, With<CameraMain>>,
+    mut images: ResMut<Assets<Image>>,
+) {
+    let ctx = contexts.ctx_mut();
+    let mut new_settings = settings.clone();
+    let mut start = keys.just_pressed(KeyCode::F12);
+
+    egui::Window::new("Poster")
+        .default_open(false)
+        .resizable(false)
+        .show(ctx, |ui| {
+            ui.add_enabled_ui(!state.active, |ui| {
+                ui.horizontal(|ui| {
+                    ui.add(egui::DragValue::new(&mut new_settings.width).range(64..=32768));
+                    ui.label("x");
+                    ui.add(egui::DragValue::new(&mut new_settings.height).range(64..=32768));
+                });
+                ui.horizontal(|ui| {
+                    for (label, width, height) in
+                        [("4K", 3840, 2160), ("8K", 7680, 4320), ("16K", 15360, 8640)]
+                    {
+                        if ui.button(label).clicked() {
+                            new_settings.width = width;
+                            new_settings.height = height;
+                        }
+                    }
+                });
+                ui.add(
+                    egui::Slider::new(&mut new_settings.tile_size, 256..=4096).text("Tile Size"),
+                );
+                ui.add(
+                    egui::Slider::new(&mut new_settings.converge_frames, 18..=128)
+                        .text("Converge Frames"),
+                );
+                ui.horizontal(|ui| {
+                    ui.label("Output");
+                    ui.text_edit_singleline(&mut new_settings.output_path);
+                });
+            });
+
+            if state.active {
+                ui.label(format!(
+                    "Tile {} / {}",
+                    state.current + 1,
+                    state.tiles.len()
+                ));
+            } else {
+                start |= ui.button("Render (F12)").clicked();
+            }
+        });
+
+    if new_settings != *settings {
+        *settings = new_settings;
+    }
+
+    if start && !state.active {
+        if let Ok(mut camera) = camera.single_mut() {
+            start_poster(
+                &mut commands,
+                &settings,
+                &mut state,
+                &mut camera,
+                &mut images,
+            );
+        }
+    }
+}
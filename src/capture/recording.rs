@@ -0,0 +1,398 @@
+use crate::ui::{CameraBookmarks, CameraMain, CameraPath};
+use bevy::{
+    core_pipeline::tonemapping::Tonemapping,
+    prelude::*,
+    render::{
+        camera::RenderTarget,
+        render_asset::RenderAssetUsages,
+        render_resource::{Extent3d, TextureDimension, TextureFormat, TextureUsages},
+        view::screenshot::{save_to_disk, Screenshot, ScreenshotCaptured},
+    },
+    time::TimeUpdateStrategy,
+    window::{PrimaryWindow, WindowRef},
+};
+use bevy_egui::{egui, EguiContexts};
+use std::time::Duration;
+
+/// Offline frame sequence recording
+///
+/// While recording, simulated time advances by exactly one frame interval per rendered frame,
+/// so the output is smooth regardless of how long each frame actually takes to render.
+/// The main camera renders to an offscreen image (optionally larger than the window) which is captured every frame.
+pub struct RecordingPlugin;
+
+impl Plugin for RecordingPlugin {
+    fn build(&self, app: &mut App) {
+        app.insert_resource(RecordingSettings::default())
+            .insert_resource(RecordingState::default())
+            // Before camera_control_system places the camera in PostUpdate
+            .add_systems(Update, (recording_ui, update_recording).chain());
+    }
+}
+
+// Frames rendered with frozen time before capturing starts, so the temporal upscaler history converges
+const WARMUP_FRAMES: u32 = 16;
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum FrameFormat {
+    Png,
+    /// Linear HDR, rendered without tonemapping to a 32-bit float target
+    Exr,
+}
+
+impl FrameFormat {
+    fn extension(&self) -> &'static str {
+        match self {
+            FrameFormat::Png => "png",
+            FrameFormat::Exr => "exr",
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum RecordingDriver {
+    /// Plays the camera path from the start, recording stops at the end of the path
+    CameraPath,
+    /// Rotates the orbit camera around its target
+    Turntable { degrees_per_second: f32 },
+}
+
+#[derive(Resource, Clone, PartialEq)]
+pub struct RecordingSettings {
+    pub fps: u32,
+    /// Output resolution relative to the window
+    pub resolution_scale: f32,
+    pub format: FrameFormat,
+    pub output_dir: String,
+    pub driver: RecordingDriver,
+    /// Only used by the turntable driver, camera path recordings last as long as the path
+    pub duration: f32,
+}
+
+impl Default for RecordingSettings {
+    fn default() -> Self {
+        Self {
+            fps: 30,
+            resolution_scale: 1.0,
+            format: FrameFormat::Png,
+            output_dir: "recording".into(),
+            driver: RecordingDriver::Turntable {
+                degrees_per_second: 12.0,
+            },
+            duration: 10.0,
+        }
+    }
+}
+
+#[derive(Resource, Default)]
+struct RecordingState {
+    active: bool,
+    warmup: u32,
+    frame: u32,
+    total_frames: u32,
+    target: Option<Handle<Image>>,
+    start_yaw: f32,
+    /// Restored once the recording is done
+    previous_hdr: bool,
+    previous_tonemapping: Tonemapping,
+}
+
+fn create_target_image(width: u32, height: u32, format: FrameFormat) -> Image {
+    let (texture_format, pixel) = match format {
+        FrameFormat::Png => (TextureFormat::Bgra8UnormSrgb, vec![0u8; 4]),
+        FrameFormat::Exr => (TextureFormat::Rgba32Float, vec![0u8; 16]),
+    };
+    let mut image = Image::new_fill(
+        Extent3d {
+            width,
+            height,
+            depth_or_array_layers: 1,
+        },
+        TextureDimension::D2,
+        &pixel,
+        texture_format,
+        RenderAssetUsages::default(),
+    );
+    image.texture_descriptor.usage = TextureUsages::TEXTURE_BINDING
+        | TextureUsages::COPY_SRC
+        | TextureUsages::COPY_DST
+        | TextureUsages::RENDER_ATTACHMENT;
+    image
+}
+
+fn start_recording(
+    commands: &mut Commands,
+    settings: &RecordingSettings,
+    state: &mut RecordingState,
+    window: &Window,
+    camera: (&mut Camera, &mut Tonemapping, &CameraMain),
+    path: &mut CameraPath,
+    images: &mut Assets<Image>,
+) {
+    if !matches!(camera.0.target, RenderTarget::Window(WindowRef::Primary)) {
+        warn!("Camera is already rendering offscreen, recording not started");
+        return;
+    }
+    if let Err(err) = std::fs::create_dir_all(&settings.output_dir) {
+        error!("Failed to create recording directory: {err}");
+        return;
+    }
+
+    let duration = match settings.driver {
+        RecordingDriver::CameraPath => {
+            path.time = 0.0;
+            path.looping = false;
+            // Sampled frame by frame by the recording instead
+            path.playing = false;
+            path.duration()
+        }
+        RecordingDriver::Turntable { .. } => settings.duration,
+    };
+
+    let width = (window.physical_width() as f32 * settings.resolution_scale) as u32;
+    let height = (window.physical_height() as f32 * settings.resolution_scale) as u32;
+    let target = images.add(create_target_image(
+        width.max(1),
+        height.max(1),
+        settings.format,
+    ));
+
+    let (camera, tonemapping, camera_main) = camera;
+    *state = RecordingState {
+        active: true,
+        warmup: WARMUP_FRAMES,
+        frame: 0,
+        total_frames: (duration * settings.fps as f32).ceil() as u32,
+        target: Some(target.clone()),
+        start_yaw: camera_main.yaw(),
+        previous_hdr: camera.hdr,
+        previous_tonemapping: *tonemapping,
+    };
+
+    camera.target = RenderTarget::Image(target.into());
+    if settings.format == FrameFormat::Exr {
+        // Linear radiance straight into the float target, the exposure is held while capturing
+        camera.hdr = true;
+        *tonemapping = Tonemapping::None;
+    }
+
+    // Frozen until warmup is done
+    commands.insert_resource(TimeUpdateStrategy::ManualDuration(Duration::ZERO));
+    info!(
+        "Recording {} frames at {width}x{height} to {}",
+        state.total_frames, settings.output_dir
+    );
+}
+
+fn stop_recording(
+    commands: &mut Commands,
+    settings: &RecordingSettings,
+    state: &mut RecordingState,
+    camera: (&mut Camera, &mut Tonemapping, &mut CameraMain),
+) {
+    let (camera, tonemapping, camera_main) = camera;
+    camera.target = RenderTarget::default();
+    camera.hdr = state.previous_hdr;
+    *tonemapping = state.previous_tonemapping;
+    if let RecordingDriver::Turntable { .. } = settings.driver {
+        camera_main.set_yaw(state.start_yaw);
+    }
+    state.active = false;
+    state.target = None;
+    commands.insert_resource(TimeUpdateStrategy::Automatic);
+    info!("Recording finished after {} frames", state.frame);
+}
+
+fn save_exr(path: String) -> impl FnMut(Trigger<ScreenshotCaptured>) {
+    move |trigger| {
+        let image = trigger.event().0.clone();
+        match image.try_into_dynamic() {
+            Ok(dynamic) => {
+                if let Err(err) = dynamic.into_rgba32f().save(&path) {
+                    error!("Failed to save frame {path}: {err}");
+                }
+            }
+            Err(err) => error!("Cannot convert frame {path}: {err:?}"),
+        }
+    }
+}
+
+fn update_recording(
+    mut commands: Commands,
+    settings: Res<RecordingSettings>,
+    mut state: ResMut<RecordingState>,
+    mut path: ResMut<CameraPath>,
+    bookmarks: Res<CameraBookmarks>,
+    mut camera: Query<(&mut Camera, &mut Tonemapping, &mut CameraMain)>,
+) {
+    if !state.active {
+        return;
+    }
+    let Ok((mut camera, mut tonemapping, mut camera_main)) = camera.single_mut() else {
+        return;
+    };
+
+    if state.frame >= state.total_frames {
+        stop_recording(
+            &mut commands,
+            &settings,
+            &mut state,
+            (&mut camera, &mut tonemapping, &mut camera_main),
+        );
+        return;
+    }
+
+    // Both drivers are sampled from the frame number, so warmup converges on frame 0 and frame 0 is t = 0
+    let t = state.frame as f32 / settings.fps.max(1) as f32;
+    match settings.driver {
+        RecordingDriver::Turntable { degrees_per_second } => {
+            camera_main.set_yaw(state.start_yaw + (degrees_per_second * t).to_radians());
+        }
+        RecordingDriver::CameraPath => {
+            path.time = t;
+            if let Some(sample) = path.sample(&bookmarks, t) {
+                camera_main.apply_bookmark(&sample);
+            }
+        }
+    }
+
+    if state.warmup > 0 {
+        state.warmup -= 1;
+        if state.warmup == 0 {
+            let frame_time = Duration::from_secs_f64(1.0 / settings.fps.max(1) as f64);
+            commands.insert_resource(TimeUpdateStrategy::ManualDuration(frame_time));
+        }
+        return;
+    }
+
+    let Some(target) = state.target.clone() else {
+        return;
+    };
+    let file = format!(
+        "{}/frame_{:05}.{}",
+        settings.output_dir,
+        state.frame,
+        settings.format.extension()
+    );
+    match settings.format {
+        FrameFormat::Png => {
+            commands
+                .spawn(Screenshot::image(target))
+                .observe(save_to_disk(file));
+        }
+        FrameFormat::Exr => {
+            commands
+                .spawn(Screenshot::image(target))
+                .observe(save_exr(file));
+        }
+    }
+    state.frame += 1;
+}
+
+#[allow(clippy::too_many_arguments)]
+fn recording_ui(
+    mut commands: Commands,
+    mut contexts: EguiContexts,
+    mut settings: ResMut<RecordingSettings>,
+    mut state: ResMut<RecordingState>,
+    mut path: ResMut<CameraPath>,
+    bookmarks: Res<CameraBookmarks>,
+    window: Query<&Window, With<PrimaryWindow>>,
+    mut camera: Query<(&mut Camera, &mut Tonemapping, &mut CameraMain)>,
+    mut images: ResMut<Assets<Image>>,
+) {
+    let ctx = contexts.ctx_mut();
+    let mut new_settings = settings.clone();
+    let mut start = false;
+    let mut stop = false;
+
+    egui::Window::new("Recording")
+        .default_open(false)
+        .resizable(false)
+        .show(ctx, |ui| {
+            ui.add_enabled_ui(!state.active, |ui| {
+                ui.add(egui::Slider::new(&mut new_settings.fps, 1..=120).text("FPS"));
+                ui.add(
+                    egui::Slider::new(&mut new_settings.resolution_scale, 0.25..=4.0)
+                        .text("Resolution Scale"),
+                );
+                ui.horizontal(|ui| {
+                    ui.radio_value(&mut new_settings.format, FrameFormat::Png, "PNG");
+                    ui.radio_value(&mut new_settings.format, FrameFormat::Exr, "EXR");
+                });
+                ui.horizontal(|ui| {
+                    ui.label("Output");
+                    ui.text_edit_singleline(&mut new_settings.output_dir);
+                });
+
+                let is_path = new_settings.driver == RecordingDriver::CameraPath;
+                ui.horizontal(|ui| {
+                    if ui.radio(is_path, "Camera Path").clicked() {
+                        new_settings.driver = RecordingDriver::CameraPath;
+                    }
+                    if ui.radio(!is_path, "Turntable").clicked() && is_path {
+                        new_settings.driver = RecordingDriver::Turntable {
+                            degrees_per_second: 12.0,
+                        };
+                    }
+                });
+                match &mut new_settings.driver {
+                    RecordingDriver::CameraPath => {
+                        ui.label(format!(
+                            "{} keyframes, {:.1} s",
+                            path.keyframes
+                                .iter()
+                                .filter(|k| bookmarks.get(&k.bookmark).is_some())
+                                .count(),
+                            path.duration()
+                        ));
+                    }
+                    RecordingDriver::Turntable { degrees_per_second } => {
+                        ui.add(
+                            egui::Slider::new(degrees_per_second, -90.0..=90.0)
+                                .text("Degrees per Second"),
+                        );
+                        ui.add(
+                            egui::Slider::new(&mut new_settings.duration, 1.0..=120.0)
+                                .text("Duration"),
+                        );
+                    }
+                }
+            });
+
+            if state.active {
+                ui.label(format!("Frame {} / {}", state.frame, state.total_frames));
+                stop = ui.button("Stop").clicked();
+            } else {
+                start = ui.button("Record").clicked();
+            }
+        });
+
+    if new_settings != *settings {
+        *settings = new_settings;
+    }
+
+    let (Ok(window), Ok((mut camera, mut tonemapping, mut camera_main))) =
+        (window.single(), camera.single_mut())
+    else {
+        return;
+    };
+    if start {
+        start_recording(
+            &mut commands,
+            &settings,
+            &mut state,
+            window,
+            (&mut camera, &mut tonemapping, &camera_main),
+            &mut path,
+            &mut images,
+        );
+    } else if stop {
+        stop_recording(
+            &mut commands,
+            &settings,
+            &mut state,
+            (&mut camera, &mut tonemapping, &mut camera_main),
+        );
+    }
+}
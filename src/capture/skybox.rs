@@ -0,0 +1,437 @@
+use crate::graphics::ExtinctionOrigin;
+use crate::prelude::*;
+use crate::ui::CameraMain;
+use bevy::{
+    core_pipeline::tonemapping::Tonemapping,
+    prelude::*,
+    render::{
+        camera::RenderTarget,
+        render_asset::RenderAssetUsages,
+        render_resource::{Extent3d, TextureDimension, TextureFormat, TextureUsages},
+        view::screenshot::{Screenshot, ScreenshotCaptured},
+    },
+    tasks::IoTaskPool,
+    time::TimeUpdateStrategy,
+    window::WindowRef,
+};
+use bevy_egui::{egui, EguiContexts};
+use rayon::prelude::*;
+use std::f32::consts::{FRAC_PI_2, PI, TAU};
+use std::time::Duration;
+use volume_upscaler::BackgroundImageOutput;
+
+/// Skybox export: six cube faces rendered from an arbitrary point in the galaxy
+///
+/// The faces are written as a vertical strip cubemap (+X, -X, +Y, -Y, +Z, -Z) and resampled into an
+/// equirectangular panorama, both as linear HDR. Tonemapping is disabled for the capture.
+/// Like Bevy's skybox, the cubemap is left-handed: cubemap direction (x, y, z) is world direction (x, y, -z).
+pub struct SkyboxExportPlugin;
+
+impl Plugin for SkyboxExportPlugin {
+    fn build(&self, app: &mut App) {
+        app.insert_resource(SkyboxSettings::default())
+            .insert_resource(SkyboxState::default())
+            .add_systems(Update, (skybox_ui, update_skybox).chain());
+    }
+}
+
+// The upscaler's background camera copies the face projection a frame late, so this must be at least 2
+const RESET_FRAMES: u32 = 2;
+// One full jitter cycle of the upscaler plus the reset frames
+const MIN_CONVERGE_FRAMES: u32 = RESET_FRAMES + 16;
+
+/// World space (forward, up) of each face, in cubemap layer order
+const FACES: [(Vec3, Vec3); 6] = [
+    (Vec3::X, Vec3::Y),
+    (Vec3::NEG_X, Vec3::Y),
+    (Vec3::Y, Vec3::Z),
+    (Vec3::NEG_Y, Vec3::NEG_Z),
+    (Vec3::NEG_Z, Vec3::Y),
+    (Vec3::Z, Vec3::Y),
+];
+
+#[derive(Resource, Clone, PartialEq)]
+pub struct SkyboxSettings {
+    /// World position the faces are rendered from
+    pub origin: Vec3,
+    pub face_size: u32,
+    /// The panorama is half as tall as it is wide
+    pub panorama_width: u32,
+    pub converge_frames: u32,
+    /// Output files are `{name}_cubemap.hdr` and `{name}_equirect.hdr`
+    pub output_name: String,
+}
+
+impl Default for SkyboxSettings {
+    fn default() -> Self {
+        Self {
+            origin: Vec3::ZERO,
+            face_size: 1024,
+            panorama_width: 4096,
+            converge_frames: 32,
+            output_name: "skybox".into(),
+        }
+    }
+}
+
+#[derive(Resource, Default)]
+struct SkyboxState {
+    active: bool,
+    face: usize,
+    wait: u32,
+    target: Option<Handle<Image>>,
+    /// Linear RGB per face, filled in as the readbacks arrive
+    faces: Vec<Option<Vec<Vec3>>>,
+    settings: SkyboxSettings,
+    /// Restored once the capture is done
+    previous_hdr: bool,
+    previous_tonemapping: Tonemapping,
+}
+
+fn create_face_image(size: u32) -> Image {
+    let mut image = Image::new_fill(
+        Extent3d {
+            width: size,
+            height: size,
+            depth_or_array_layers: 1,
+        },
+        TextureDimension::D2,
+        &[0; 16],
+        TextureFormat::Rgba32Float,
+        RenderAssetUsages::default(),
+    );
+    image.texture_descriptor.usage = TextureUsages::TEXTURE_BINDING
+        | TextureUsages::COPY_SRC
+        | TextureUsages::COPY_DST
+        | TextureUsages::RENDER_ATTACHMENT;
+    image
+}
+
+fn face_transform(origin: Vec3, face: usize) -> Transform {
+    let (forward, up) = FACES[face];
+    Transform::from_translation(origin).looking_to(forward, up)
+}
+
+/// Face index and texel coordinates in 0..1 for a world direction, inverse of [`FACES`]
+fn cube_face_uv(dir: Vec3) -> (usize, Vec2) {
+    // to the left-handed cubemap space
+    let d = vec3(dir.x, dir.y, -dir.z);
+    let a = d.abs();
+    let (face, ma, sc, tc) = if a.x >= a.y && a.x >= a.z {
+        if d.x > 0.0 {
+            (0, a.x, -d.z, -d.y)
+        } else {
+            (1, a.x, d.z, -d.y)
+        }
+    } else if a.y >= a.z {
+        if d.y > 0.0 {
+            (2, a.y, d.x, d.z)
+        } else {
+            (3, a.y, d.x, -d.z)
+        }
+    } else if d.z > 0.0 {
+        (4, a.z, d.x, -d.y)
+    } else {
+        (5, a.z, -d.x, -d.y)
+    };
+    (face, (vec2(sc, tc) / ma + 1.0) * 0.5)
+}
+
+fn sample_face(face: &[Vec3], size: u32, uv: Vec2) -> Vec3 {
+    let p = (uv * size as f32 - 0.5).clamp(Vec2::ZERO, Vec2::splat(size as f32 - 1.0));
+    let p0 = p.floor().as_uvec2();
+    let p1 = (p0 + 1).min(UVec2::splat(size - 1));
+    let f = p - p.floor();
+    let texel = |x: u32, y: u32| face[(y * size + x) as usize];
+    let top = texel(p0.x, p0.y).lerp(texel(p1.x, p0.y), f.x);
+    let bottom = texel(p0.x, p1.y).lerp(texel(p1.x, p1.y), f.x);
+    top.lerp(bottom, f.y)
+}
+
+/// Longitude 0 looks down -Z, the panorama centre matches the default camera forward
+fn equirect_direction(uv: Vec2) -> Vec3 {
+    let lon = uv.x * TAU - PI;
+    let lat = FRAC_PI_2 - uv.y * PI;
+    vec3(lon.sin() * lat.cos(), lat.sin(), -lon.cos() * lat.cos())
+}
+
+fn rgb_image(width: u32, height: u32, pixels: &[Vec3]) -> Image {
+    let data = pixels
+        .iter()
+        .flat_map(|c| c.extend(1.0).to_array())
+        .flat_map(f32::to_le_bytes)
+        .collect();
+    Image::new(
+        Extent3d {
+            width,
+            height,
+            depth_or_array_layers: 1,
+        },
+        TextureDimension::D2,
+        data,
+        TextureFormat::Rgba32Float,
+        RenderAssetUsages::MAIN_WORLD,
+    )
+}
+
+fn save_hdr(image: Image, path: String) {
+    match image.try_into_dynamic() {
+        Ok(dynamic) => match dynamic.into_rgb32f().save(&path) {
+            Ok(()) => info!("Saved {path}"),
+            Err(err) => error!("Failed to save {path}: {err}"),
+        },
+        Err(err) => error!("Cannot convert {path}: {err:?}"),
+    }
+}
+
+fn write_skybox(faces: Vec<Vec<Vec3>>, settings: SkyboxSettings) {
+    let size = settings.face_size;
+
+    let strip: Vec<Vec3> = faces.iter().flatten().copied().collect();
+    save_hdr(
+        rgb_image(size, size * 6, &strip),
+        format!("{}_cubemap.hdr", settings.output_name),
+    );
+
+    let width = settings.panorama_width;
+    let height = width / 2;
+    let panorama: Vec<Vec3> = (0..height)
+        .into_par_iter()
+        .flat_map_iter(|y| {
+            let faces = &faces;
+            (0..width).map(move |x| {
+                let uv = (vec2(x as f32, y as f32) + 0.5) / vec2(width as f32, height as f32);
+                let (face, face_uv) = cube_face_uv(equirect_direction(uv));
+                sample_face(&faces[face], size, face_uv)
+            })
+        })
+        .collect();
+    save_hdr(
+        rgb_image(width, height, &panorama),
+        format!("{}_equirect.hdr", settings.output_name),
+    );
+}
+
+fn receive_face(face: usize) -> impl FnMut(Trigger<ScreenshotCaptured>, ResMut<SkyboxState>) {
+    move |trigger, mut state| {
+        let Some(data) = trigger.event().0.data.as_ref() else {
+            return;
+        };
+        let texels = data
+            .chunks_exact(16)
+            .map(|texel| {
+                let channel =
+                    |i: usize| f32::from_le_bytes(texel[i * 4..i * 4 + 4].try_into().unwrap());
+                vec3(channel(0), channel(1), channel(2))
+            })
+            .collect();
+        state.faces[face] = Some(texels);
+
+        if state.faces.iter().any(Option::is_none) {
+            return;
+        }
+        let faces = std::mem::take(&mut state.faces)
+            .into_iter()
+            .flatten()
+            .collect();
+        let settings = state.settings.clone();
+        IoTaskPool::get()
+            .spawn(async move { write_skybox(faces, settings) })
+            .detach();
+    }
+}
+
+fn start_skybox(
+    commands: &mut Commands,
+    settings: &SkyboxSettings,
+    state: &mut SkyboxState,
+    camera: (&mut Camera, &mut Tonemapping, &mut CameraMain),
+    extinction_origin: &mut ExtinctionOrigin,
+    images: &mut Assets<Image>,
+) {
+    let (camera, tonemapping, camera_main) = camera;
+    if !matches!(camera.target, RenderTarget::Window(WindowRef::Primary)) {
+        warn!("Camera is already rendering offscreen, skybox export not started");
+        return;
+    }
+
+    // The upscaler renders the volume at a fraction of the resolution, keep the faces divisible by it
+    let factor = volume_upscaler::UPSCALE_FACTOR as u32;
+    let face_size = (settings.face_size.max(64) / factor) * factor;
+    let target = images.add(create_face_image(face_size));
+
+    *state = SkyboxState {
+        active: true,
+        face: 0,
+        wait: settings.converge_frames.max(MIN_CONVERGE_FRAMES),
+        target: Some(target.clone()),
+        faces: vec![None; FACES.len()],
+        settings: SkyboxSettings {
+            face_size,
+            ..settings.clone()
+        },
+        previous_hdr: camera.hdr,
+        previous_tonemapping: *tonemapping,
+    };
+
+    // Linear radiance straight into the float target
+    camera.target = RenderTarget::Image(target.into());
+    camera.hdr = true;
+    *tonemapping = Tonemapping::None;
+    camera_main.set_capture_view(face_transform(settings.origin, 0), FRAC_PI_2);
+    extinction_origin.0 = Some(settings.origin);
+
+    commands.insert_resource(TimeUpdateStrategy::ManualDuration(Duration::ZERO));
+    info!("Exporting skybox from {}", settings.origin);
+}
+
+fn finish_skybox(
+    commands: &mut Commands,
+    state: &mut SkyboxState,
+    camera: (&mut Camera, &mut Tonemapping, &mut CameraMain),
+    extinction_origin: &mut ExtinctionOrigin,
+) {
+    let (camera, tonemapping, camera_main) = camera;
+    camera.target = RenderTarget::default();
+    camera.hdr = state.previous_hdr;
+    *tonemapping = state.previous_tonemapping;
+    camera_main.clear_capture_view();
+    extinction_origin.0 = None;
+    state.active = false;
+    state.target = None;
+    commands.insert_resource(TimeUpdateStrategy::Automatic);
+}
+
+fn update_skybox(
+    mut commands: Commands,
+    mut state: ResMut<SkyboxState>,
+    mut extinction_origin: ResMut<ExtinctionOrigin>,
+    mut camera: Query<(
+        &mut Camera,
+        &mut Tonemapping,
+        &mut CameraMain,
+        &mut BackgroundImageOutput,
+    )>,
+) {
+    if !state.active {
+        return;
+    }
+    let Ok((mut camera, mut tonemapping, mut camera_main, mut background)) = camera.single_mut()
+    else {
+        return;
+    };
+
+    let converge_frames = state.settings.converge_frames.max(MIN_CONVERGE_FRAMES);
+    if state.wait > converge_frames - RESET_FRAMES {
+        background.reset = true;
+    }
+    if state.wait > 0 {
+        state.wait -= 1;
+        return;
+    }
+
+    let Some(target) = state.target.clone() else {
+        return;
+    };
+    commands
+        .spawn(Screenshot::image(target))
+        .observe(receive_face(state.face));
+
+    state.face += 1;
+    if state.face < FACES.len() {
+        camera_main.set_capture_view(face_transform(state.settings.origin, state.face), FRAC_PI_2);
+        state.wait = converge_frames;
+    } else {
+        finish_skybox(
+            &mut commands,
+            &mut state,
+            (&mut camera, &mut tonemapping, &mut camera_main),
+            &mut extinction_origin,
+        );
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
+fn skybox_ui(
+    mut commands: Commands,
+    mut contexts: EguiContexts,
+    mut settings: ResMut<SkyboxSettings>,
+    mut state: ResMut<SkyboxState>,
+    mut extinction_origin: ResMut<ExtinctionOrigin>,
+    mut camera: Query<(&mut Camera, &mut Tonemapping, &mut CameraMain, &Transform)>,
+    galaxy: Query<(&StarCatalogue, &GlobalTransform), With<PrimaryGalaxy>>,
+    mut images: ResMut<Assets<Image>>,
+    mut star_index: Local<u32>,
+) {
+    let Ok((mut camera, mut tonemapping, mut camera_main, camera_transform)) = camera.single_mut()
+    else {
+        return;
+    };
+    let ctx = contexts.ctx_mut();
+    let mut new_settings = settings.clone();
+    let mut start = false;
+
+    egui::Window::new("Skybox")
+        .default_open(false)
+        .resizable(false)
+        .show(ctx, |ui| {
+            ui.add_enabled_ui(!state.active, |ui| {
+                ui.horizontal(|ui| {
+                    ui.label("Origin");
+                    ui.add(egui::DragValue::new(&mut new_settings.origin.x).speed(10.0));
+                    ui.add(egui::DragValue::new(&mut new_settings.origin.y).speed(10.0));
+                    ui.add(egui::DragValue::new(&mut new_settings.origin.z).speed(10.0));
+                });
+                ui.horizontal(|ui| {
+                    if ui.button("Camera").clicked() {
+                        new_settings.origin = camera_transform.translation;
+                    }
+                    ui.add(egui::DragValue::new(&mut *star_index).prefix("Star #"));
+                    if ui.button("Star").clicked() {
+                        // Star indices are per galaxy, pick from the primary one
+                        if let Ok((catalogue, transform)) = galaxy.single() {
+                            if let Some(position) = catalogue.position(*star_index) {
+                                new_settings.origin = transform.transform_point(position);
+                            }
+                        }
+                    }
+                });
+                ui.add(
+                    egui::Slider::new(&mut new_settings.face_size, 256..=4096).text("Face Size"),
+                );
+                ui.add(
+                    egui::Slider::new(&mut new_settings.panorama_width, 512..=16384)
+                        .text("Panorama Width"),
+                );
+                ui.add(
+                    egui::Slider::new(&mut new_settings.converge_frames, MIN_CONVERGE_FRAMES..=128)
+                        .text("Converge Frames"),
+                );
+                ui.horizontal(|ui| {
+                    ui.label("Output");
+                    ui.text_edit_singleline(&mut new_settings.output_name);
+                });
+            });
+
+            if state.active {
+                ui.label(format!("Face {} / {}", state.face + 1, FACES.len()));
+            } else {
+                start = ui.button("Export").clicked();
+            }
+        });
+
+    if new_settings != *settings {
+        *settings = new_settings;
+    }
+
+    if start {
+        start_skybox(
+            &mut commands,
+            &settings,
+            &mut state,
+            (&mut camera, &mut tonemapping, &mut camera_main),
+            &mut extinction_origin,
+            &mut images,
+        );
+    }
+}
@@ -0,0 +1,259 @@
+use crate::prelude::*;
+use bevy::prelude::*;
+use rand::prelude::*;
+
+/// Shape of a galaxy's bulge
+///
+/// The density is constant on generalised ellipsoids around the centre, the default is a sphere following
+/// de Vaucouleurs' law.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct BulgeShape {
+    /// Sérsic index, 4 is de Vaucouleurs' law, 1 an exponential, lower is more concentrated toward a flat core
+    pub sersic_index: f32,
+    /// Vertical axis over the major axis, below 1 flattens the bulge
+    pub axis_ratio_y: f32,
+    /// Minor axis in the disk plane over the major axis, below 1 makes the bulge triaxial
+    pub axis_ratio_z: f32,
+    /// Exponent of the generalised ellipsoid, 2 is an ellipsoid and higher is boxier
+    pub boxiness: f32,
+    /// Thickens the bulge toward the ends of its major axis, so it looks like a peanut seen edge on
+    pub peanut: f32,
+    /// Angle of the major axis in the disk plane, in degrees
+    pub position_angle: f32,
+}
+
+impl Default for BulgeShape {
+    fn default() -> Self {
+        Self {
+            sersic_index: 4.0,
+            axis_ratio_y: 1.0,
+            axis_ratio_z: 1.0,
+            boxiness: 2.0,
+            peanut: 0.0,
+            position_angle: 0.0,
+        }
+    }
+}
+
+impl BulgeShape {
+    /// Unit vector of the major axis in the xz plane
+    pub fn major_axis(&self) -> Vec2 {
+        Vec2::from_angle(self.position_angle.to_radians())
+    }
+
+    /// Generalised radius of `p`, its distance from the centre for a spherical bulge
+    /// Same as `bulge_radius` in intensity_shared.wgsl
+    pub fn radius(&self, p: Vec3) -> f32 {
+        let axis = self.major_axis();
+        let x = p.x * axis.x + p.z * axis.y;
+        let z = -p.x * axis.y + p.z * axis.x;
+        let peanut = 1.0 + self.peanut * x * x / p.length_squared().max(1e-6);
+        let q = vec3(x, p.y / (self.axis_ratio_y * peanut), z / self.axis_ratio_z).abs();
+        let m = q.max_element();
+        if m <= 0.0 {
+            return 0.0;
+        }
+        // Normalised by the largest component so the powers can't overflow
+        let c = self.boxiness.max(2.0);
+        let qn = (q / m).max(Vec3::splat(1e-6));
+        m * (qn.x.powf(c) + qn.y.powf(c) + qn.z.powf(c)).powf(1.0 / c)
+    }
+
+    /// Lower bound of `radius(p) / |p|` over every direction
+    ///
+    /// The c-norm of three components is at least 3^(1/c - 1/2) times their length, and each axis is stretched by at
+    /// most its largest ratio.
+    pub fn min_radius_scale(&self) -> f32 {
+        let c = self.boxiness.max(2.0);
+        let stretch = 1.0f32
+            .max(self.axis_ratio_z)
+            .max(self.axis_ratio_y * (1.0 + self.peanut.max(0.0)));
+        3.0f32.powf(1.0 / c - 0.5) / stretch
+    }
+
+    /// Maps a point from the frame where the axis ratios and position angle are undone back to galaxy space
+    /// An ellipsoidal bulge is a sphere in that frame
+    fn ellipsoid_to_galaxy(&self, q: Vec3) -> Vec3 {
+        let axis = self.major_axis();
+        let x = q.x;
+        let z = q.z * self.axis_ratio_z;
+        vec3(
+            x * axis.x - z * axis.y,
+            q.y * self.axis_ratio_y,
+            x * axis.y + z * axis.x,
+        )
+    }
+}
+
+/// Bulge density at generalised radius `m`
+pub fn bulge_density_at_radius(config: &GalaxyConfig, m: f32) -> f32 {
+    let n = config.bulge_shape.sersic_index.max(0.1);
+    let rad = (m / config.radius + 0.01) * config.bulge_radius + 0.01;
+    // Deprojected Sérsic profile, the inner slope 1 - 0.58 / n is a fit to Prugniel & Simien's
+    let slope = 1.0 - 0.58 / n;
+    (config.bulge_strength * (rad.powf(-slope) * (-rad.powf(1.0 / n)).exp() - 0.05)).max(0.0)
+}
+
+/// `get_bulge_intensity` in intensity_shared.wgsl
+pub fn bulge_density(config: &GalaxyConfig, p: Vec3) -> f32 {
+    bulge_density_at_radius(config, config.bulge_shape.radius(p))
+}
+
+/// Draws positions distributed like the bulge density
+///
+/// The generalised radius is drawn from a tabulated distribution and a direction from the unit sphere of the
+/// ellipsoid frame, which the axis ratios map onto the shape. Boxiness and the peanut bend the shape away from
+/// an ellipsoid, so directions are then rejected in proportion to the volume the shape covers along them.
+pub struct BulgeSampler {
+    shape: BulgeShape,
+    /// Cumulative mass inside the outer edge of each bin
+    cdf: Vec<f32>,
+    bin_width: f32,
+    /// Lower bound of `radius` over unit vectors of the ellipsoid frame, 1 for an ellipsoid
+    min_scale: f32,
+    /// Integral of `radius`^-3 over the unit sphere of the ellipsoid frame
+    angular_integral: f32,
+}
+
+impl BulgeSampler {
+    const BINS: usize = 512;
+    const DIRECTIONS: usize = 4096;
+    /// Acceptance only gets low with both boxiness and peanut near their maximum
+    const MAX_DIRECTION_TRIES: usize = 256;
+
+    /// None if the bulge is empty
+    pub fn new(config: &GalaxyConfig) -> Option<Self> {
+        // The bulge only reaches past the disk with an unusually large strength
+        let extent = config.radius * 1.5;
+        let bin_width = extent / Self::BINS as f32;
+
+        let mut cdf = Vec::with_capacity(Self::BINS);
+        let mut total = 0.0;
+        let mut previous = 0.0;
+        for i in 1..=Self::BINS {
+            let m = i as f32 * bin_width;
+            let shell = m * m * bulge_density_at_radius(config, m);
+            total += 0.5 * (previous + shell) * bin_width;
+            previous = shell;
+            cdf.push(total);
+        }
+        if total <= 0.0 {
+            return None;
+        }
+
+        // Fibonacci sphere, evenly spread directions
+        let shape = config.bulge_shape;
+        let golden_angle = std::f32::consts::PI * (3.0 - 5.0f32.sqrt());
+        let mut min_scale = f32::MAX;
+        let mut reach_cubed = 0.0;
+        for i in 0..Self::DIRECTIONS {
+            let y = 1.0 - 2.0 * (i as f32 + 0.5) / Self::DIRECTIONS as f32;
+            let r = (1.0 - y * y).sqrt();
+            let angle = golden_angle * i as f32;
+            let scale = shape
+                .radius(shape.ellipsoid_to_galaxy(vec3(r * angle.cos(), y, r * angle.sin())))
+                .max(1e-6);
+            min_scale = min_scale.min(scale);
+            reach_cubed += scale.powi(-3);
+        }
+        // The sampled minimum with some slack for the gaps between directions, never below the analytic bound
+        // - The c-norm is at least 3^(1/c - 1/2) times the length
+        // - The peanut shrinks one component by at most 1 + peanut
+        let c = shape.boxiness.max(2.0);
+        let analytic_min = 3.0f32.powf(1.0 / c - 0.5) / (1.0 + shape.peanut.max(0.0));
+        let min_scale = (min_scale * 0.95).max(analytic_min).min(1.0);
+
+        Some(Self {
+            shape,
+            cdf,
+            bin_width,
+            min_scale,
+            angular_integral: 4.0 * std::f32::consts::PI * reach_cubed / Self::DIRECTIONS as f32,
+        })
+    }
+
+    /// Integrated density, the radial mass times the volume of the unit shape over the unit sphere's
+    pub fn mass(&self) -> f32 {
+        // The ellipsoid frame is stretched by the axis ratios
+        let stretch = self.shape.axis_ratio_y * self.shape.axis_ratio_z;
+        self.cdf[self.cdf.len() - 1] * self.angular_integral * stretch
+    }
+
+    pub fn sample(&self, rng: &mut impl Rng) -> Vec3 {
+        let target = rng.random::<f32>() * self.cdf[self.cdf.len() - 1];
+        let bin = self.cdf.partition_point(|&mass| mass < target);
+        let below = if bin == 0 { 0.0 } else { self.cdf[bin - 1] };
+        let fraction = (target - below) / (self.cdf[bin] - below).max(1e-12);
+        let m = (bin as f32 + fraction) * self.bin_width;
+
+        // The unit shape reaches 1 / scale along u, its volume there grows with the cube of that
+        let mut direction = Vec3::X;
+        let mut scale = 1.0;
+        for _ in 0..Self::MAX_DIRECTION_TRIES {
+            direction = sample_unit_sphere(rng);
+            scale = self
+                .shape
+                .radius(self.shape.ellipsoid_to_galaxy(direction))
+                .max(1e-6);
+            if rng.random::<f32>() * scale.powi(3) < self.min_scale.powi(3) {
+                break;
+            }
+        }
+        self.shape.ellipsoid_to_galaxy(direction * m / scale)
+    }
+}
+
+fn sample_unit_sphere(rng: &mut impl Rng) -> Vec3 {
+    let y = rng.random_range(-1.0..1.0f32);
+    let angle = rng.random_range(0.0..std::f32::consts::TAU);
+    let r = (1.0 - y * y).sqrt();
+    vec3(r * angle.cos(), y, r * angle.sin())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::rngs::StdRng;
+
+    #[test]
+    fn default_shape_is_de_vaucouleurs() {
+        let config = GalaxyConfig::default();
+        for p in [
+            vec3(0.0, 0.0, 0.0),
+            vec3(1.0, 2.0, -3.0),
+            vec3(0.05, 0.01, 0.02) * config.radius,
+            vec3(-0.3, 0.1, 0.2) * config.radius,
+        ] {
+            // The law before the bulge shape was configurable
+            let rad = (p.length() / config.radius + 0.01) * config.bulge_radius + 0.01;
+            let expected = (config.bulge_strength
+                * (rad.powf(-0.855) * (-rad.powf(0.25)).exp() - 0.05))
+                .max(0.0);
+            let density = bulge_density(&config, p);
+            assert!(
+                (density - expected).abs() <= expected.abs() * 1e-4 + 1e-6,
+                "{p}: {density} != {expected}"
+            );
+        }
+    }
+
+    #[test]
+    fn flattened_samples_follow_axis_ratios() {
+        let config = GalaxyConfig {
+            bulge_shape: BulgeShape {
+                axis_ratio_y: 0.1,
+                axis_ratio_z: 0.1,
+                ..default()
+            },
+            ..default()
+        };
+        let sampler = BulgeSampler::new(&config).unwrap();
+        let mut rng = StdRng::seed_from_u64(1);
+        let squared: Vec3 = (0..20_000)
+            .map(|_| sampler.sample(&mut rng).powf(2.0))
+            .sum();
+        let rms_ratio = (squared / squared.x).powf(0.5);
+        assert!((rms_ratio.y - 0.1).abs() < 0.02, "{rms_ratio}");
+        assert!((rms_ratio.z - 0.1).abs() < 0.02, "{rms_ratio}");
+    }
+}
@@ -0,0 +1,113 @@
+use crate::prelude::*;
+use bevy::prelude::*;
+use std::f32::consts::PI;
+
+/// Fraction of light scattered per steradian, `cos_theta` is between the light's direction of travel before and after
+/// Same as `henyey_greenstein` in intensity_shared.wgsl
+pub fn henyey_greenstein(cos_theta: f32, g: f32) -> f32 {
+    let g2 = g * g;
+    let denom = 1.0 + g2 - 2.0 * g * cos_theta;
+    (1.0 - g2) / (4.0 * PI * denom * denom.sqrt())
+}
+
+/// Reference for `dust_in_scattering` in intensity_shared.wgsl, to check the shader against
+///
+/// `p` is in the galaxy's local space and `view_dir` points from it toward the camera.
+/// `dust_intensity` (already multiplied by the step size) and `disk_xz` are the values `ray_step` computes at `p`.
+pub fn dust_in_scattering(
+    galaxy_config: &GalaxyConfig,
+    galaxy_render_settings: &GalaxyRenderConfig,
+    p: Vec3,
+    view_dir: Vec3,
+    dust_intensity: f32,
+    disk_xz: f32,
+) -> Vec3 {
+    // Same colours as step_light
+    let d = p.xz().length() / galaxy_config.radius;
+    let dust_col = galaxy_config.dust_params.color.at(d);
+    let disk_col = galaxy_config.disk_params.color.at(d);
+    let r = p.length() / galaxy_config.radius;
+    let bulge_col = galaxy_config.bulge_color.at(r);
+
+    let from_centre = p / p.length().max(0.0001);
+    let phase = henyey_greenstein(
+        from_centre.dot(view_dir),
+        galaxy_render_settings.dust_anisotropy,
+    );
+    let central_light = galaxy_config.bulge_strength / (r * r + 0.01);
+    // The shader's disk strength is zeroed when the component is disabled
+    let disk_strength = if galaxy_config.disk_params.enabled {
+        galaxy_config.disk_params.strength
+    } else {
+        0.0
+    };
+    let disk_light = disk_xz * disk_strength / (4.0 * PI);
+    let incident = bulge_col * central_light * phase + disk_col * disk_light;
+    incident
+        * dust_col
+        * dust_intensity
+        * galaxy_render_settings.dust_albedo
+        * galaxy_render_settings.scattering_intensity
+        * galaxy_render_settings.exposure
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn henyey_greenstein_is_normalised() {
+        const STEPS: usize = 100_000;
+        let step = 2.0 / STEPS as f32;
+        for g in [-0.5, 0.0, 0.3, 0.6, 0.8] {
+            // Over the sphere, dΩ = 2π dcosθ
+            let sum: f32 = (0..STEPS)
+                .map(|i| henyey_greenstein((i as f32 + 0.5) * step - 1.0, g))
+                .sum();
+            let integral = sum * step * 2.0 * PI;
+            assert!((integral - 1.0).abs() < 1e-3, "g = {g}: {integral}");
+        }
+    }
+
+    #[test]
+    fn henyey_greenstein_is_isotropic_at_zero() {
+        for cos_theta in [-1.0, -0.3, 0.0, 0.5, 1.0] {
+            let phase = henyey_greenstein(cos_theta, 0.0);
+            assert!((phase - 1.0 / (4.0 * PI)).abs() < 1e-6);
+        }
+    }
+
+    #[test]
+    fn back_lit_dust_scatters_more() {
+        let config = GalaxyConfig::default();
+        let render_settings = GalaxyRenderConfig {
+            dust_anisotropy: 0.6,
+            ..default()
+        };
+        let p = vec3(0.3, 0.0, 0.2) * config.radius;
+        let from_centre = p.normalize();
+        let scattered = |view_dir| {
+            dust_in_scattering(&config, &render_settings, p, view_dir, 1.0, 0.0).element_sum()
+        };
+        assert!(scattered(from_centre) > scattered(-from_centre));
+    }
+
+    #[test]
+    fn no_scattering_without_albedo_or_intensity() {
+        let config = GalaxyConfig::default();
+        let p = vec3(0.3, 0.0, 0.2) * config.radius;
+        for render_settings in [
+            GalaxyRenderConfig {
+                dust_albedo: 0.0,
+                ..default()
+            },
+            GalaxyRenderConfig {
+                scattering_intensity: 0.0,
+                ..default()
+            },
+        ] {
+            let scattered = dust_in_scattering(&config, &render_settings, p, Vec3::Y, 1.0, 0.5);
+            assert_eq!(scattered, Vec3::ZERO);
+        }
+    }
+}
@@ -2,7 +2,8 @@ use crate::prelude::*;
 use bevy::prelude::*;
 use std::f32::consts::PI;
 
-fn smoothstep(edge0: f32, edge1: f32, x: f32) -> f32 {
+/// Hermite step from 0 at `edge0` to 1 at `edge1`, like the WGSL builtin
+pub fn smoothstep(edge0: f32, edge1: f32, x: f32) -> f32 {
     let s = ((x - edge0) / (edge1 - edge0)).clamp(0.0, 1.0);
     s * s * (3.0 - 2.0 * s)
 }
@@ -39,6 +40,15 @@ impl GalaxyComponentDensity<'_> {
         //let t= atan(exp(1.0/r) / wb) * 2.0 * wn;
     }
 
+    /// Returns the position of an arm's spine at the given radial distance (scaled to the unit galaxy)
+    pub fn arm_position(&self, arm_id: usize, radial_distance: f32) -> Vec2 {
+        let winding = self.rad_winding(radial_distance);
+        let angle = self.galaxy.arm_offsets[arm_id]
+            - winding
+            - self.component.angular_offset.to_radians();
+        vec2(angle.sin(), angle.cos()) * radial_distance * self.galaxy.radius
+    }
+
     fn find_theta_difference(&self, t1: f32, t2: f32) -> f32 {
         let diff: f32 = (t1 - t2).abs() / PI;
         let normalized_diff: f32 = ((diff + 1.0) % 2.0) - 1.0;
@@ -74,7 +84,12 @@ impl GalaxyComponentDensity<'_> {
         self.xz_density(p.xz()) * self.get_height_modulation(p.y)
     }
 
-    pub fn xz_density(&self, p: Vec2) -> f32 {
+    /// Density with the arms smeared out, the profile the arms modulate
+    pub fn xyz_envelope(&self, p: Vec3) -> f32 {
+        self.xz_envelope(p.xz()) * self.get_height_modulation(p.y)
+    }
+
+    pub fn xz_envelope(&self, p: Vec2) -> f32 {
         let r0 = self.component.radial_extent;
         let inner = self.component.radial_dropoff; // central falloff parameter
 
@@ -84,10 +99,16 @@ impl GalaxyComponentDensity<'_> {
         let central_falloff = (smoothstep(0.0, 1.0 * inner, d)).powi(4);
         let r = self.get_radial_intensity(d, r0);
 
+        central_falloff * r
+    }
+
+    pub fn xz_density(&self, p: Vec2) -> f32 {
+        let d = p.length() / self.galaxy.radius; // distance to galactic central axis
+
         // I think the component winding_factor is only meant to apply to noise?
         let winding = self.rad_winding(d); // * self.component.winding_factor;
         let arm_mod = self.arms_modifier(winding, p);
 
-        central_falloff * arm_mod * r
+        self.xz_envelope(p) * arm_mod
     }
 }
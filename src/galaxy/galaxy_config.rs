@@ -1,20 +1,54 @@
 use bevy::prelude::*;
 use bevy::render::extract_resource::{ExtractResource, ExtractResourcePlugin};
 
+use super::{BulgeShape, Colormap, ReddeningLaw, SpectralFilter, StarBudget, VisualizationMode};
+
 #[derive(Resource, Clone, PartialEq, ExtractResource)]
 pub struct GalaxyRenderConfig {
     pub raymarch_steps: u32,
+    /// Skips the parts of the volume the occupancy grid marks empty, takes longer steps where it's sparse and stops
+    /// once the dust is opaque
+    pub empty_space_skipping: bool,
+    /// Longest step in sparse regions, as a multiple of the fixed step
+    pub max_step_scale: f32,
+    /// Samples a baked tiling texture for each noise octave instead of evaluating `Perlin3D`
+    /// - Trilinear filtering replaces Perlin's C2 blend between texels, so the noise looks softer and blockier
+    /// - At 4 texels per cell the R16F texture is 276³, about 42 MB of VRAM
+    pub baked_noise: bool,
+    /// Resolution of the baked noise, the texture is 69 times this along each side
+    pub noise_texels_per_cell: u32,
     pub draw_volume_to_background: bool,
     pub texture_dimension: u32,
-    pub diagnostic_mode: bool,
+    /// False colour view of the volume instead of the normal raymarch
+    pub visualization: VisualizationMode,
+    pub colormap: Colormap,
+    /// Value at the top of the colormap
+    pub visualization_range: f32,
+    pub visualization_log_scale: bool,
     pub draw_stars_to_background: bool,
     pub padding_coeff: f32,
     pub exposure: f32,
+    /// Single scattering of bulge and disk light by the dust, costs a phase function per raymarch step
+    pub dust_scattering: bool,
+    /// Fraction of the light hitting dust that is scattered rather than absorbed
+    pub dust_albedo: f32,
+    /// Henyey-Greenstein asymmetry, positive scatters forwards so back-lit dust glows
+    pub dust_anisotropy: f32,
+    pub scattering_intensity: f32,
+    /// Carries a spectrum through the raymarch instead of RGB, so dust reddens and populations are coloured
+    /// physically
+    pub spectral: bool,
+    pub spectral_filter: SpectralFilter,
+    pub reddening_law: ReddeningLaw,
 }
 
-#[derive(Resource, Clone, PartialEq, ExtractResource)]
+/// Shape of a single galaxy, every entity with this component is rendered as a galaxy
+/// The entity's transform places the galaxy, only translation and rotation are supported (use `radius` to resize)
+#[derive(Component, Clone, PartialEq)]
+#[require(Transform, Visibility)]
 pub struct GalaxyConfig {
     pub generation: i32,
+    pub seed: u64,
 
     pub radius: f32,
     pub n_arms: i32,
@@ -29,9 +63,14 @@ pub struct GalaxyConfig {
 
     pub bulge_strength: f32,
     pub bulge_radius: f32,
+    /// Brightness of the bulge's light relative to its density
     pub bulge_intensity: f32,
+    pub bulge_color: ComponentColor,
+    pub bulge_shape: BulgeShape,
 
     pub stars_per_arm: i32,
+    /// How the spawned stars are shared between the bulge, disk, arms and halo
+    pub star_budget: StarBudget,
 
     pub disk_params: ComponentConfig,
     pub dust_params: ComponentConfig,
@@ -77,6 +116,46 @@ pub struct ComponentConfig {
     pub noise_persistence: f32,
     pub noise_octaves: u32,
     pub noise_enabled: bool,
+    /// Emission colour for the disk, the colour absorbed for the dust, unused by the stars
+    pub color: ComponentColor,
+}
+
+/// Colour of a component in the volume, optionally blending to a second colour away from the centre
+/// Ignored by the spectral raymarch, which takes its colours from the population spectra
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct ComponentColor {
+    /// Linear RGB, at the centre when the gradient is on
+    pub inner: Vec3,
+    pub outer: Vec3,
+    pub radial_gradient: bool,
+    /// Distance the outer colour is reached at, in galaxy radii
+    pub gradient_radius: f32,
+}
+
+impl ComponentColor {
+    pub const fn flat(color: Vec3) -> Self {
+        Self {
+            inner: color,
+            outer: color,
+            radial_gradient: false,
+            gradient_radius: 1.0,
+        }
+    }
+
+    /// Colour at `r` galaxy radii from the centre, same as `radial_color` in intensity_shared.wgsl
+    pub fn at(&self, r: f32) -> Vec3 {
+        if !self.radial_gradient || self.gradient_radius <= 0.0 {
+            return self.inner;
+        }
+        self.inner.lerp(self.outer, (r / self.gradient_radius).clamp(0.0, 1.0))
+    }
+}
+
+impl Default for ComponentColor {
+    fn default() -> Self {
+        // Blue, for the dust this absorbs blue the most so it appears red
+        Self::flat(vec3(0.4, 0.6, 1.0))
+    }
 }
 
 impl Default for ComponentConfig {
@@ -97,6 +176,7 @@ impl Default for ComponentConfig {
             noise_persistence: 1.0,
             noise_octaves: 5,
             noise_enabled: true,
+            color: ComponentColor::default(),
         }
     }
 }
@@ -118,6 +198,7 @@ impl ComponentConfig {
         noise_persistence: 0.1,
         noise_octaves: 0,
         noise_enabled: false,
+        color: ComponentColor::flat(Vec3::ZERO),
     };
     pub const MAX: Self = Self {
         component_type: ComponentType::Disk,
@@ -135,23 +216,44 @@ impl ComponentConfig {
         noise_persistence: 2.0,
         noise_octaves: 10,
         noise_enabled: true,
+        color: ComponentColor::flat(Vec3::ONE),
     };
 }
+/// The galaxy edited by the UI and used by the single-galaxy tools (camera limits, map, labels, territories)
+/// If no galaxy is marked, the first one found is promoted
+#[derive(Component)]
+pub struct PrimaryGalaxy;
+
 pub struct GalaxyConfigPlugin;
 
 impl Plugin for GalaxyConfigPlugin {
     fn build(&self, app: &mut App) {
-        app.insert_resource(GalaxyConfig::default())
-            .insert_resource(GalaxyRenderConfig::default())
-            .add_systems(Update,update_generation)
-            .add_plugins(ExtractResourcePlugin::<GalaxyConfig>::default())
+        app.insert_resource(GalaxyRenderConfig::default())
+            .add_systems(Update, (update_generation, ensure_primary_galaxy))
             .add_plugins(ExtractResourcePlugin::<GalaxyRenderConfig>::default());
     }
 }
 
-fn update_generation(mut galaxy_config : ResMut<GalaxyConfig>) {
-    if galaxy_config.is_changed() {
-        galaxy_config.generation+=1;
+/// Spawns a galaxy with the default config at the origin
+pub fn spawn_default_galaxy(mut commands: Commands) {
+    commands.spawn((GalaxyConfig::default(), PrimaryGalaxy));
+}
+
+fn update_generation(mut galaxies: Query<&mut GalaxyConfig, Changed<GalaxyConfig>>) {
+    for mut galaxy_config in &mut galaxies {
+        galaxy_config.generation += 1;
+    }
+}
+
+fn ensure_primary_galaxy(
+    mut commands: Commands,
+    primary: Query<(), With<PrimaryGalaxy>>,
+    galaxies: Query<Entity, With<GalaxyConfig>>,
+) {
+    if primary.is_empty() {
+        if let Some(entity) = galaxies.iter().next() {
+            commands.entity(entity).insert(PrimaryGalaxy);
+        }
     }
 }
 
@@ -164,14 +266,29 @@ pub struct ArmConfig {
 impl Default for GalaxyRenderConfig {
     fn default() -> Self {
         Self {
-            diagnostic_mode: false,
+            visualization: VisualizationMode::Off,
+            colormap: Colormap::Viridis,
+            visualization_range: 1.0,
+            visualization_log_scale: false,
             draw_volume_to_background: true,
             raymarch_steps: 128,
+            empty_space_skipping: true,
+            max_step_scale: 4.0,
+            // Opt in, it changes the look of the noise
+            baked_noise: false,
+            noise_texels_per_cell: 4,
             texture_dimension: 512,
             // Tends to look extremely bad in motion
             draw_stars_to_background: false,
             exposure: 0.01,
             padding_coeff: 1.5,
+            dust_scattering: true,
+            dust_albedo: 0.6,
+            dust_anisotropy: 0.6,
+            scattering_intensity: 0.1,
+            spectral: false,
+            spectral_filter: SpectralFilter::Visible,
+            reddening_law: ReddeningLaw::Cardelli,
         }
     }
 }
@@ -180,11 +297,18 @@ impl Default for GalaxyConfig {
     fn default() -> Self {
         Self {
             generation: 1,
+            seed: 0,
             bulge_strength: 100.0,
             bulge_radius: 9.0,
             bulge_intensity: 1.0,
+            bulge_shape: BulgeShape::default(),
+            bulge_color: ComponentColor {
+                gradient_radius: 0.2,
+                ..ComponentColor::flat(vec3(1.0, 0.9, 0.45))
+            },
             radius: 500.0, // in parsecs
             stars_per_arm: 10000,
+            star_budget: StarBudget::default(),
             spacing: 40.0,
             n_arms: 3,
             arm_configs: [
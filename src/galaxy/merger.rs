@@ -0,0 +1,399 @@
+use super::{StarCount, StarSpawningControl};
+use crate::prelude::*;
+use bevy::prelude::*;
+use rayon::prelude::*;
+use std::f32::consts::TAU;
+
+/// Restricted three-body simulation of two interacting galaxies
+///
+/// Each galaxy's mass is a softened point (Plummer sphere) at its centre, the two centres orbit each other
+/// and every star is a massless test particle pulled by both. This is the Toomre & Toomre setup:
+/// cheap enough for every spawned star, and it produces the tidal tails and bridges of real encounters.
+/// The galaxies' entities follow their centres, stars stay in their galaxy's catalogue and are moved in its local space.
+pub struct MergerPlugin;
+
+impl Plugin for MergerPlugin {
+    fn build(&self, app: &mut App) {
+        app.insert_resource(MergerConfig::default())
+            .insert_resource(MergerSimulation::default())
+            .add_systems(Update, (handle_merger_request, step_merger).chain());
+    }
+}
+
+#[derive(Resource, Clone, PartialEq)]
+pub struct MergerConfig {
+    /// Orbital period of a star at the primary galaxy's radius, in simulated seconds
+    pub orbital_period: f32,
+    /// Mass of the secondary galaxy relative to the primary
+    pub mass_ratio: f32,
+    /// Relative speed of the galaxies at the start, 1 is a parabolic (just unbound) encounter
+    pub approach_speed: f32,
+    /// Angle between the approach velocity and the line joining the galaxies, in degrees
+    /// 0 is a head-on collision, larger angles give a wider pericentre
+    pub approach_angle: f32,
+    /// Softening length of each galaxy's potential, relative to its radius
+    pub core_softening: f32,
+    /// Drag on the galaxies' relative motion while they overlap, lets them actually merge
+    pub dynamical_friction: f32,
+    /// Stars orbit against their galaxy's approach instead of with it
+    pub retrograde: [bool; 2],
+    pub time_step: f32,
+    /// Simulated seconds per real second
+    pub speed: f32,
+}
+
+impl Default for MergerConfig {
+    fn default() -> Self {
+        Self {
+            orbital_period: 60.0,
+            mass_ratio: 0.5,
+            approach_speed: 1.0,
+            approach_angle: 20.0,
+            core_softening: 0.15,
+            dynamical_friction: 0.5,
+            retrograde: [false, false],
+            time_step: 0.05,
+            speed: 4.0,
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum MergerRequest {
+    Start,
+    Stop,
+}
+
+struct Core {
+    galaxy: Entity,
+    generation: i32,
+    rotation: Quat,
+    start: Vec3,
+    position: Vec3,
+    velocity: Vec3,
+    /// Gravitational constant times mass
+    gm: f32,
+    softening: f32,
+}
+
+struct Particle {
+    /// Index in its galaxy's star catalogue
+    index: u32,
+    core: usize,
+    position: Vec3,
+    velocity: Vec3,
+}
+
+#[derive(Resource, Default)]
+pub struct MergerSimulation {
+    pub running: bool,
+    request: Option<MergerRequest>,
+    cores: Vec<Core>,
+    particles: Vec<Particle>,
+    time: f32,
+    accumulator: f32,
+}
+
+impl MergerSimulation {
+    pub fn request(&mut self, request: MergerRequest) {
+        self.request = Some(request);
+    }
+
+    pub fn is_active(&self) -> bool {
+        !self.cores.is_empty()
+    }
+
+    /// Simulated time since the start
+    pub fn time(&self) -> f32 {
+        self.time
+    }
+
+    /// The galaxies taking part, primary first
+    pub fn galaxies(&self) -> impl Iterator<Item = Entity> + '_ {
+        self.cores.iter().map(|core| core.galaxy)
+    }
+
+    /// World space positions of every simulated star
+    pub fn star_positions(&self) -> impl Iterator<Item = Vec3> + '_ {
+        self.particles.iter().map(|particle| particle.position)
+    }
+}
+
+/// Plummer sphere acceleration toward `centre`
+fn plummer_acceleration(position: Vec3, centre: Vec3, gm: f32, softening: f32) -> Vec3 {
+    let d = centre - position;
+    let r2 = d.length_squared() + softening * softening;
+    d * (gm / (r2 * r2.sqrt()))
+}
+
+fn core_accelerations(cores: &[Core], config: &MergerConfig) -> [Vec3; 2] {
+    let (a, b) = (&cores[0], &cores[1]);
+    let softening = (a.softening * a.softening + b.softening * b.softening).sqrt();
+    let mut acceleration = [
+        plummer_acceleration(a.position, b.position, b.gm, softening),
+        plummer_acceleration(b.position, a.position, a.gm, softening),
+    ];
+
+    // Crude dynamical friction, only while the halos overlap
+    let separation = a.position.distance(b.position);
+    let overlap = (-(separation / (4.0 * softening)).powi(2)).exp();
+    let relative_velocity = a.velocity - b.velocity;
+    let total = a.gm + b.gm;
+    acceleration[0] -= relative_velocity * config.dynamical_friction * overlap * b.gm / total;
+    acceleration[1] += relative_velocity * config.dynamical_friction * overlap * a.gm / total;
+    acceleration
+}
+
+fn particle_acceleration(position: Vec3, cores: &[Core]) -> Vec3 {
+    cores
+        .iter()
+        .map(|core| plummer_acceleration(position, core.position, core.gm, core.softening))
+        .sum()
+}
+
+/// Kick-drift-kick leapfrog, symplectic so orbits don't spiral in or out over long runs
+fn leapfrog_step(simulation: &mut MergerSimulation, config: &MergerConfig, dt: f32) {
+    let half = dt * 0.5;
+
+    let acceleration = core_accelerations(&simulation.cores, config);
+    for (core, acceleration) in simulation.cores.iter_mut().zip(acceleration) {
+        core.velocity += acceleration * half;
+    }
+    let cores = &simulation.cores;
+    simulation.particles.par_iter_mut().for_each(|particle| {
+        particle.velocity += particle_acceleration(particle.position, cores) * half;
+        particle.position += particle.velocity * dt;
+    });
+    for core in simulation.cores.iter_mut() {
+        core.position += core.velocity * dt;
+    }
+
+    let acceleration = core_accelerations(&simulation.cores, config);
+    for (core, acceleration) in simulation.cores.iter_mut().zip(acceleration) {
+        core.velocity += acceleration * half;
+    }
+    let cores = &simulation.cores;
+    simulation.particles.par_iter_mut().for_each(|particle| {
+        particle.velocity += particle_acceleration(particle.position, cores) * half;
+    });
+    simulation.time += dt;
+}
+
+/// Velocity of a circular orbit around a single Plummer sphere
+fn circular_speed(r: f32, gm: f32, softening: f32) -> f32 {
+    let r2 = r * r + softening * softening;
+    (gm * r * r / (r2 * r2.sqrt())).sqrt()
+}
+
+#[allow(clippy::type_complexity)]
+fn start_merger(
+    commands: &mut Commands,
+    simulation: &mut MergerSimulation,
+    config: &MergerConfig,
+    galaxies: &Query<(
+        Entity,
+        &GalaxyConfig,
+        &GlobalTransform,
+        &StarCount,
+        &StarCatalogue,
+        Has<PrimaryGalaxy>,
+    )>,
+) {
+    let mut pair: Vec<_> = galaxies.iter().collect();
+    // Primary first, then the first other galaxy
+    pair.sort_by_key(|(.., primary)| !primary);
+    if pair.len() < 2 {
+        warn!("A merger needs two galaxies");
+        return;
+    }
+    pair.truncate(2);
+
+    let primary_radius = pair[0].1.radius;
+    let gm_primary = (TAU / config.orbital_period).powi(2) * primary_radius.powi(3);
+
+    let mut cores: Vec<Core> = pair
+        .iter()
+        .enumerate()
+        .map(|(i, (galaxy, galaxy_config, transform, ..))| Core {
+            galaxy: *galaxy,
+            generation: galaxy_config.generation,
+            rotation: transform.rotation(),
+            start: transform.translation(),
+            position: transform.translation(),
+            velocity: Vec3::ZERO,
+            gm: if i == 0 {
+                gm_primary
+            } else {
+                gm_primary * config.mass_ratio
+            },
+            softening: galaxy_config.radius * config.core_softening,
+        })
+        .collect();
+
+    let separation = cores[1].position - cores[0].position;
+    let distance = separation.length();
+    if distance < 1.0 {
+        warn!("The merging galaxies need to start apart");
+        return;
+    }
+
+    // Approach velocity in the centre of mass frame
+    let direction = -separation / distance;
+    let side = direction.cross(Vec3::Y).try_normalize().unwrap_or(Vec3::X);
+    let angle = config.approach_angle.to_radians();
+    let total_gm = cores[0].gm + cores[1].gm;
+    let escape_speed = (2.0 * total_gm / distance).sqrt();
+    let relative_velocity =
+        (direction * angle.cos() + side * angle.sin()) * escape_speed * config.approach_speed;
+    cores[0].velocity = -relative_velocity * cores[1].gm / total_gm;
+    cores[1].velocity = relative_velocity * cores[0].gm / total_gm;
+
+    let mut particles = vec![];
+    for (core_index, (galaxy, _, transform, star_count, catalogue, _)) in pair.iter().enumerate() {
+        let core = &cores[core_index];
+        let normal = transform.rotation() * Vec3::Y;
+        let spin = if config.retrograde[core_index] {
+            -1.0
+        } else {
+            1.0
+        };
+
+        if catalogue.len() < star_count.count {
+            warn!("Galaxy {galaxy} is still spawning stars, merger not started");
+            return;
+        }
+        for (index, star_position) in catalogue.positions.iter().enumerate() {
+            let offset = transform.rotation() * *star_position;
+            let r = offset.length();
+            let tangent = normal.cross(offset).normalize_or_zero() * spin;
+            particles.push(Particle {
+                index: index as u32,
+                core: core_index,
+                position: core.position + offset,
+                velocity: core.velocity + tangent * circular_speed(r, core.gm, core.softening),
+            });
+        }
+    }
+
+    for core in &cores {
+        commands.entity(core.galaxy).insert(Merging);
+    }
+    info!(
+        "Starting merger with {} stars, relative speed {:.0} pc/s",
+        particles.len(),
+        relative_velocity.length()
+    );
+    *simulation = MergerSimulation {
+        running: true,
+        request: None,
+        cores,
+        particles,
+        time: 0.0,
+        accumulator: 0.0,
+    };
+}
+
+/// Marks galaxies whose stars are driven by the merger simulation
+/// Their analytic volume no longer matches the stars, so it is replaced by a density splat
+#[derive(Component)]
+pub struct Merging;
+
+/// Puts the galaxies back where they started and respawns their stars
+fn stop_merger(
+    commands: &mut Commands,
+    simulation: &mut MergerSimulation,
+    galaxies: &mut Query<(&mut Transform, &mut StarSpawningControl), With<GalaxyConfig>>,
+) {
+    for core in &simulation.cores {
+        commands.entity(core.galaxy).remove::<Merging>();
+        if let Ok((mut transform, mut spawning)) = galaxies.get_mut(core.galaxy) {
+            transform.translation = core.start;
+            spawning.reset();
+        }
+    }
+    *simulation = MergerSimulation::default();
+}
+
+#[allow(clippy::type_complexity)]
+fn handle_merger_request(
+    mut commands: Commands,
+    mut simulation: ResMut<MergerSimulation>,
+    config: Res<MergerConfig>,
+    galaxies: Query<(
+        Entity,
+        &GalaxyConfig,
+        &GlobalTransform,
+        &StarCount,
+        &StarCatalogue,
+        Has<PrimaryGalaxy>,
+    )>,
+    mut galaxy_transforms: Query<(&mut Transform, &mut StarSpawningControl), With<GalaxyConfig>>,
+) {
+    // A galaxy that was edited or removed respawned its stars, the simulation no longer applies
+    let invalidated = simulation.cores.iter().any(|core| {
+        !galaxies
+            .get(core.galaxy)
+            .is_ok_and(|(_, config, ..)| config.generation == core.generation)
+    });
+    if invalidated {
+        stop_merger(&mut commands, &mut simulation, &mut galaxy_transforms);
+    }
+
+    match simulation.request.take() {
+        Some(MergerRequest::Start) => {
+            if simulation.is_active() {
+                stop_merger(&mut commands, &mut simulation, &mut galaxy_transforms);
+            }
+            start_merger(&mut commands, &mut simulation, &config, &galaxies);
+        }
+        Some(MergerRequest::Stop) => {
+            stop_merger(&mut commands, &mut simulation, &mut galaxy_transforms);
+        }
+        None => {}
+    }
+}
+
+fn step_merger(
+    time: Res<Time>,
+    config: Res<MergerConfig>,
+    mut simulation: ResMut<MergerSimulation>,
+    mut galaxies: Query<(&mut Transform, &mut StarCatalogue), With<GalaxyConfig>>,
+) {
+    if !simulation.is_active() || !simulation.running {
+        return;
+    }
+
+    // Fixed steps keep the integration stable regardless of frame rate
+    const MAX_STEPS_PER_FRAME: u32 = 8;
+    let dt = config.time_step.max(1e-4);
+    simulation.accumulator += time.delta_secs() * config.speed;
+    let mut steps = 0;
+    while simulation.accumulator >= dt && steps < MAX_STEPS_PER_FRAME {
+        leapfrog_step(&mut simulation, &config, dt);
+        simulation.accumulator -= dt;
+        steps += 1;
+    }
+    simulation.accumulator = simulation.accumulator.min(dt);
+    if steps == 0 {
+        return;
+    }
+
+    for core in &simulation.cores {
+        if let Ok((mut transform, _)) = galaxies.get_mut(core.galaxy) {
+            transform.translation = core.position;
+        }
+    }
+    // Stars stay in their galaxy's catalogue, so they are written back in its local space
+    for (core_index, core) in simulation.cores.iter().enumerate() {
+        let Ok((_, mut catalogue)) = galaxies.get_mut(core.galaxy) else {
+            continue;
+        };
+        let inverse_rotation = core.rotation.inverse();
+        let positions = catalogue.positions_mut();
+        for particle in simulation.particles.iter().filter(|p| p.core == core_index) {
+            if let Some(position) = positions.get_mut(particle.index as usize) {
+                *position = inverse_rotation * (particle.position - core.position);
+            }
+        }
+    }
+}
@@ -1,17 +1,61 @@
 use bevy::prelude::*;
 
+mod bulge;
+mod dust_scattering;
 mod galaxy_component_density;
 mod galaxy_config;
+mod merger;
+mod naming;
+mod noise;
+mod occupancy;
 mod spawn_stars;
+mod spectrum;
+mod star_mixture;
+mod territory;
+mod visualization;
 
-pub use spawn_stars::{SpawnStarsPlugin, Star};
+pub use merger::{MergerConfig, MergerPlugin, MergerRequest, MergerSimulation, Merging};
+pub use spawn_stars::{
+    star_color, SpawnStarsPlugin, Star, StarCatalogue, StarSelection, StarSpawningControl,
+};
+pub use territory::{PartitionMode, Sector, Territories, TerritoryConfig, TerritoryPlugin};
+pub use visualization::{Colormap, VisualizationMode};
 
-pub use galaxy_component_density::GalaxyComponentDensity;
+pub use bulge::{bulge_density, bulge_density_at_radius, BulgeSampler, BulgeShape};
+pub use dust_scattering::{dust_in_scattering, henyey_greenstein};
+pub use galaxy_component_density::{smoothstep, GalaxyComponentDensity};
+pub use star_mixture::{
+    allocate_stars, population_masses, StarBudget, StarSampler, StellarPopulation,
+};
+pub use spectrum::{
+    blackbody, bulge_spectrum, disk_spectrum, ReddeningLaw, SpectralFilter, SPECTRAL_BINS,
+};
+pub use naming::{catalogue_designation, NameGenerator};
+pub use noise::{octave_noise_3d, perlin_3d, ridge_noise, NOISE_PERIOD};
+pub use occupancy::{OccupancyGrid, OCCUPANCY_LEVELS, OCCUPANCY_THRESHOLD};
 pub use galaxy_config::{
-    ArmConfig, ComponentConfig, ComponentType, GalaxyConfig, GalaxyConfigPlugin, GalaxyRenderConfig,
+    spawn_default_galaxy, ArmConfig, ComponentColor, ComponentConfig, ComponentType, GalaxyConfig,
+    GalaxyConfigPlugin, GalaxyRenderConfig, PrimaryGalaxy,
 };
 
-#[derive(Resource)]
+/// Number of stars a galaxy spawns, on the galaxy entity
+#[derive(Component, Default)]
 pub struct StarCount {
     pub count: usize,
+    /// Stars in each population, indexed by `StellarPopulation::index`, the stars are ordered by population
+    pub populations: [usize; 4],
+}
+
+impl StarCount {
+    /// Population of the star with the given index
+    pub fn population_of(&self, index: usize) -> StellarPopulation {
+        let mut end = 0;
+        for population in StellarPopulation::ALL {
+            end += self.populations[population.index()];
+            if index < end {
+                return population;
+            }
+        }
+        StellarPopulation::Arms
+    }
 }
@@ -0,0 +1,247 @@
+use bevy::prelude::*;
+use rand::prelude::*;
+use rand::rngs::SmallRng;
+use std::f32::consts::PI;
+
+/// Syllabified training names for the Markov chain
+/// - Mostly traditional star names, which gives the output a vaguely "astronomical" flavour
+const NAME_CORPUS: &[&str] = &[
+    "al-de-ba-ran",
+    "be-tel-geu-se",
+    "ri-gel",
+    "ve-ga",
+    "an-ta-res",
+    "ca-pel-la",
+    "si-ri-us",
+    "pro-cy-on",
+    "ar-ctu-rus",
+    "pol-lux",
+    "de-neb",
+    "al-tair",
+    "mi-ra",
+    "spi-ca",
+    "re-gu-lus",
+    "ca-no-pus",
+    "a-cher-nar",
+    "fo-mal-haut",
+    "mir-fak",
+    "al-ni-lam",
+    "al-ni-tak",
+    "bel-la-trix",
+    "el-nath",
+    "mi-zar",
+    "du-bhe",
+    "me-rak",
+    "al-ka-id",
+    "thu-ban",
+    "ko-chab",
+    "al-gol",
+    "ha-mal",
+    "men-kar",
+    "zau-rak",
+    "sa-dal-mel-ik",
+    "ach-ird",
+    "nun-ki",
+    "ka-phe-ra",
+    "sa-bik",
+    "ta-ra-zed",
+    "ve-ra-nis",
+    "sol-ta-ri",
+    "mar-kab",
+    "sche-at",
+    "al-phe-ratz",
+    "mi-ra-ch",
+    "ti-ra-nis",
+    "or-ri-on",
+    "an-dro-me-da",
+    "cas-si-o-pe-ia",
+    "ce-phe-us",
+];
+
+const MIN_SYLLABLES: usize = 2;
+const MAX_SYLLABLES: usize = 4;
+/// Dead ends allowed before a short name is accepted, so a degenerate corpus can't loop forever
+const MAX_RESTARTS: usize = 16;
+
+/// Rings and slices used for catalogue designations
+pub const DESIGNATION_RINGS: u32 = 4;
+pub const DESIGNATION_SLICES: u32 = 12;
+
+/// Deterministic name generator for stars, clusters and galactic regions
+///
+/// Names come from a first order syllable Markov chain trained on [`NAME_CORPUS`]
+/// Every name is seeded from the galaxy seed plus an object id, so the same star always gets the same name
+#[derive(Resource)]
+pub struct NameGenerator {
+    syllables: Vec<String>,
+    // transitions[0] is the start state, transitions[i + 1] follows syllables[i]
+    // None marks the end of a name
+    transitions: Vec<Vec<Option<usize>>>,
+}
+
+#[derive(Clone, Copy)]
+enum NameKind {
+    Star = 1,
+    Cluster = 2,
+    Region = 3,
+}
+
+impl Default for NameGenerator {
+    fn default() -> Self {
+        Self::from_corpus(NAME_CORPUS)
+    }
+}
+
+impl NameGenerator {
+    pub fn from_corpus(corpus: &[&str]) -> Self {
+        let mut syllables: Vec<String> = Vec::new();
+        let mut transitions: Vec<Vec<Option<usize>>> = vec![Vec::new()];
+
+        // Interned in insertion order, so the chain doesn't depend on hashmap iteration order
+        let mut intern = |syllable: &str, transitions: &mut Vec<Vec<Option<usize>>>| {
+            if let Some(i) = syllables.iter().position(|s| s == syllable) {
+                i
+            } else {
+                syllables.push(syllable.to_string());
+                transitions.push(Vec::new());
+                syllables.len() - 1
+            }
+        };
+
+        for name in corpus {
+            let mut state = 0;
+            for syllable in name.split('-') {
+                let id = intern(syllable, &mut transitions);
+                transitions[state].push(Some(id));
+                state = id + 1;
+            }
+            transitions[state].push(None);
+        }
+
+        Self {
+            syllables,
+            transitions,
+        }
+    }
+
+    fn rng(galaxy_seed: u64, kind: NameKind, id: u64) -> SmallRng {
+        SmallRng::seed_from_u64(splitmix64(
+            galaxy_seed ^ splitmix64(id.wrapping_mul(8).wrapping_add(kind as u64)),
+        ))
+    }
+
+    fn generate(&self, rng: &mut SmallRng) -> String {
+        let mut name = String::new();
+        let mut state = 0;
+        let mut count = 0;
+        let mut restarts = 0;
+
+        while count < MAX_SYLLABLES {
+            let options = &self.transitions[state];
+            // Resample a few times if the chain wants to end too early
+            let mut next = *options.choose(rng).unwrap_or(&None);
+            for _ in 0..4 {
+                if next.is_some() || count >= MIN_SYLLABLES {
+                    break;
+                }
+                next = *options.choose(rng).unwrap_or(&None);
+            }
+
+            let Some(id) = next else {
+                if count >= MIN_SYLLABLES || restarts >= MAX_RESTARTS {
+                    break;
+                }
+                // Dead end before the minimum length, restart from the start state
+                restarts += 1;
+                state = 0;
+                continue;
+            };
+
+            name.push_str(&self.syllables[id]);
+            state = id + 1;
+            count += 1;
+        }
+
+        capitalize(&name)
+    }
+
+    pub fn star_name(&self, galaxy_seed: u64, star_index: u32) -> String {
+        self.generate(&mut Self::rng(
+            galaxy_seed,
+            NameKind::Star,
+            star_index as u64,
+        ))
+    }
+
+    pub fn cluster_name(&self, galaxy_seed: u64, cluster_id: u64) -> String {
+        let name = self.generate(&mut Self::rng(galaxy_seed, NameKind::Cluster, cluster_id));
+        format!("{name} Cluster")
+    }
+
+    pub fn region_name(&self, galaxy_seed: u64, region_id: u64) -> String {
+        let mut rng = Self::rng(galaxy_seed, NameKind::Region, region_id);
+        let name = self.generate(&mut rng);
+        let suffix = ["Arm", "Reach", "Expanse", "Drift", "Spur"]
+            .choose(&mut rng)
+            .unwrap();
+        format!("{name} {suffix}")
+    }
+}
+
+/// Catalogue style designation, eg. "S2-07 1423"
+/// - Sectors are a polar grid of [`DESIGNATION_RINGS`] rings and [`DESIGNATION_SLICES`] angular slices
+pub fn catalogue_designation(pos: Vec3, galaxy_radius: f32, number: u32) -> String {
+    let (ring, slice) = designation_sector(pos.xz(), galaxy_radius);
+    format!("S{ring}-{slice:02} {number}")
+}
+
+pub fn designation_sector(p: Vec2, galaxy_radius: f32) -> (u32, u32) {
+    let ring = ((p.length() / galaxy_radius) * DESIGNATION_RINGS as f32) as u32;
+    let angle = f32::atan2(p.y, p.x).rem_euclid(2.0 * PI);
+    let slice = (angle / (2.0 * PI) * DESIGNATION_SLICES as f32) as u32;
+    (
+        ring.min(DESIGNATION_RINGS - 1),
+        slice.min(DESIGNATION_SLICES - 1),
+    )
+}
+
+fn capitalize(s: &str) -> String {
+    let mut chars = s.chars();
+    match chars.next() {
+        Some(first) => first.to_uppercase().chain(chars).collect(),
+        None => String::new(),
+    }
+}
+
+fn splitmix64(x: u64) -> u64 {
+    let mut z = x.wrapping_add(0x9E3779B97F4A7C15);
+    z = (z ^ (z >> 30)).wrapping_mul(0xBF58476D1CE4E5B9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94D049BB133111EB);
+    z ^ (z >> 31)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn names_are_deterministic() {
+        let a = NameGenerator::default();
+        let b = NameGenerator::default();
+        for id in 0..64 {
+            assert_eq!(a.star_name(42, id), b.star_name(42, id));
+            assert_eq!(a.cluster_name(42, id as u64), b.cluster_name(42, id as u64));
+            assert_eq!(a.region_name(42, id as u64), b.region_name(42, id as u64));
+        }
+        // Another galaxy seed gives another set of names
+        assert!((0..64).any(|id| a.star_name(42, id) != a.star_name(43, id)));
+    }
+
+    #[test]
+    fn degenerate_corpus_terminates() {
+        assert_eq!(NameGenerator::from_corpus(&[]).star_name(1, 1), "");
+        // Every name ends after one syllable, below the minimum
+        let generator = NameGenerator::from_corpus(&["ka", "ri"]);
+        assert!(!generator.star_name(1, 1).is_empty());
+    }
+}
@@ -0,0 +1,184 @@
+use bevy::prelude::*;
+
+// Port of noise_functions.wgsl, operation for operation so the baked noise matches the shader's
+// Vector maths stays per component, glam's signum and fract differ from WGSL's sign and fract
+
+/// `Perlin3D` repeats every this many lattice cells along each axis, the hash domain is truncated to it
+pub const NOISE_PERIOD: u32 = 69;
+
+fn sign(v: Vec4) -> Vec4 {
+    let sign = |x: f32| {
+        if x > 0.0 {
+            1.0
+        } else if x < 0.0 {
+            -1.0
+        } else {
+            0.0
+        }
+    };
+    vec4(sign(v.x), sign(v.y), sign(v.z), sign(v.w))
+}
+
+fn fract(v: Vec4) -> Vec4 {
+    v - v.floor()
+}
+
+fn interpolation_c2_3d(x: Vec3) -> Vec3 {
+    x * x * x * (x * (x * 6.0 - 15.0) + 10.0)
+}
+
+/// Random number for each of the 8 corners of a cell, the 4 at the lower z then the 4 at the higher
+fn fast32_hash_3d(gridcell: Vec3) -> (Vec4, Vec4) {
+    let offset = vec2(50.0, 161.0);
+    let domain: f32 = 69.0;
+    let some_large_float: f32 = 635.298_7;
+    let zinc: f32 = 48.500_39;
+
+    // truncate the domain
+    let gridcell = gridcell - (gridcell * (1.0 / domain)).floor() * domain;
+    let edge = Vec3::splat(domain - 1.5);
+    let gridcell_inc1 =
+        Vec3::select(edge.cmpge(gridcell), Vec3::ONE, Vec3::ZERO) * (gridcell + 1.0);
+
+    // calculate the noise
+    let mut p = vec4(gridcell.x, gridcell.y, gridcell_inc1.x, gridcell_inc1.y)
+        + vec4(offset.x, offset.y, offset.x, offset.y);
+    p *= p;
+    p = vec4(p.x, p.z, p.x, p.z) * vec4(p.y, p.y, p.w, p.w);
+
+    let highz =
+        Vec2::ONE / (Vec2::splat(some_large_float) + vec2(gridcell.z, gridcell_inc1.z) * zinc);
+    (fract(p * highz.x), fract(p * highz.y))
+}
+
+/// `Perlin3D` in noise_functions.wgsl, in -1..1
+pub fn perlin_3d(p: Vec3) -> f32 {
+    // establish our grid cell and unit position
+    let pi = p.floor();
+    let pf = p - pi;
+    let pf_min1 = pf - 1.0;
+
+    let (mut hash_lowz, mut hash_highz) = fast32_hash_3d(pi);
+
+    // "improved" noise using 8 corner gradients
+    hash_lowz -= 0.5;
+    let grad_results_0_0 = vec4(pf.x, pf_min1.x, pf.x, pf_min1.x) * sign(hash_lowz);
+    hash_lowz = hash_lowz.abs() - 0.25;
+    let grad_results_0_1 = vec4(pf.y, pf.y, pf_min1.y, pf_min1.y) * sign(hash_lowz);
+    let grad_results_0_2 = Vec4::splat(pf.z) * sign(hash_lowz.abs() - 0.125);
+    let grad_results_0 = grad_results_0_0 + grad_results_0_1 + grad_results_0_2;
+
+    hash_highz -= 0.5;
+    let grad_results_1_0 = vec4(pf.x, pf_min1.x, pf.x, pf_min1.x) * sign(hash_highz);
+    hash_highz = hash_highz.abs() - 0.25;
+    let grad_results_1_1 = vec4(pf.y, pf.y, pf_min1.y, pf_min1.y) * sign(hash_highz);
+    let grad_results_1_2 = Vec4::splat(pf_min1.z) * sign(hash_highz.abs() - 0.125);
+    let grad_results_1 = grad_results_1_0 + grad_results_1_1 + grad_results_1_2;
+
+    // blend the gradients and return
+    let blend = interpolation_c2_3d(pf);
+    // mix as WGSL defines it
+    let res0 = grad_results_0 * (1.0 - blend.z) + grad_results_1 * blend.z;
+    let blend2 = vec4(blend.x, blend.y, 1.0 - blend.x, 1.0 - blend.y);
+    let weights =
+        vec4(blend2.z, blend2.x, blend2.z, blend2.x) * vec4(blend2.w, blend2.w, blend2.y, blend2.y);
+    res0.dot(weights) * (2.0 / 3.0)
+}
+
+/// `ridge_noise` in noise_functions.wgsl
+pub fn ridge_noise(
+    in_pos: Vec3,
+    in_frequency: f32,
+    octaves: i32,
+    lacunarity: f32,
+    offset: f32,
+    gain: f32,
+) -> f32 {
+    let mut value = 0.0;
+    let mut weight = 1.0;
+    let w = -0.05f32;
+    let mut freq = in_frequency;
+
+    let mut p = in_pos;
+    for i in 0..octaves {
+        let mut signal = perlin_3d(p + Vec3::splat(i as f32) * 0.72354);
+        signal = signal.abs();
+        signal = offset - signal;
+        signal *= signal;
+        signal *= weight;
+
+        weight = (signal * gain).clamp(0.0, 1.0);
+        value += signal * freq.powf(w);
+
+        p *= lacunarity;
+        freq *= lacunarity;
+    }
+    value * 1.25 - 1.0
+}
+
+/// `octave_noise_3d` in noise_functions.wgsl
+pub fn octave_noise_3d(octaves: i32, persistence: f32, scale: f32, pos: Vec3) -> f32 {
+    let mut sum = 0.0;
+    let mut frequency = scale;
+    let mut amplitude = 1.0;
+
+    let mut amp_sum = 0.0;
+    for _ in 0..octaves {
+        sum += perlin_3d(pos * frequency) * amplitude;
+
+        frequency *= 2.0;
+        amp_sum += amplitude;
+        amplitude *= persistence;
+    }
+    sum / amp_sum
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn zero_on_lattice_points() {
+        for p in [
+            vec3(0.0, 0.0, 0.0),
+            vec3(3.0, -7.0, 12.0),
+            vec3(68.0, 1.0, -69.0),
+        ] {
+            assert_eq!(perlin_3d(p), 0.0, "{p}");
+        }
+    }
+
+    #[test]
+    fn repeats_every_period() {
+        // Dyadic fractions, so adding the period doesn't round the position within its cell
+        let period = NOISE_PERIOD as f32;
+        for p in [
+            vec3(0.25, 1.625, 2.375),
+            vec3(10.5, -3.25, 7.125),
+            vec3(-20.375, 40.75, 0.5),
+        ] {
+            for axis in [Vec3::X, Vec3::Y, Vec3::Z] {
+                assert_eq!(perlin_3d(p), perlin_3d(p + axis * period), "{p} {axis}");
+            }
+        }
+    }
+
+    #[test]
+    fn matches_shader() {
+        // Read back from a one-off wgpu 24 compute shader writing `Perlin3D(points[id.x].xyz)` for these points, with
+        // noise_functions.wgsl included as is, run on llvmpipe through the GL backend
+        for (p, expected) in [
+            (vec3(0.25, 1.625, 2.375), 0.044765126),
+            (vec3(10.5, -3.25, 7.125), -0.12494354),
+            (vec3(-20.375, 40.75, 0.5), 0.08752881),
+            (vec3(33.3, 12.7, -5.9), -0.046599712),
+            (vec3(0.1, 0.2, 0.3), -0.0483064),
+        ] {
+            let value = perlin_3d(p);
+            assert!(
+                (value - expected).abs() < 1e-6,
+                "{p}: {value} != {expected}"
+            );
+        }
+    }
+}
@@ -0,0 +1,423 @@
+use crate::prelude::*;
+use bevy::prelude::*;
+use rayon::prelude::*;
+
+/// Cells of the finest occupancy level along x and z, and along y
+pub const OCCUPANCY_XZ_CELLS: u32 = 64;
+pub const OCCUPANCY_Y_CELLS: u32 = 32;
+/// Each level halves the cells of the one before
+pub const OCCUPANCY_LEVELS: u32 = 3;
+
+/// Below this the shader's disk and dust intensity functions return zero, so cells under it can be skipped
+/// without changing the image
+pub const OCCUPANCY_THRESHOLD: f32 = 0.0005;
+
+/// Occupancy at or above this is marched at the base step size, sparser cells get longer steps
+pub const SPARSE_DENSITY: f32 = 0.05;
+
+/// Conservative maximum density of the volume over a coarse grid, for empty space skipping
+///
+/// The grid is a cube around the galaxy's padded sphere. Each cell holds the largest disk, dust or bulge base
+/// intensity anywhere inside it, and each level's cells the maximum of the eight they cover on the level below.
+/// Noise only ever scales the base intensity, so it's ignored here.
+pub struct OccupancyGrid {
+    /// Half the side of the cube
+    pub extent: f32,
+    /// Finest first, x fastest then y then z
+    pub levels: Vec<Vec<f32>>,
+}
+
+impl OccupancyGrid {
+    pub fn dimensions(level: u32) -> UVec3 {
+        uvec3(
+            OCCUPANCY_XZ_CELLS >> level,
+            OCCUPANCY_Y_CELLS >> level,
+            OCCUPANCY_XZ_CELLS >> level,
+        )
+    }
+
+    pub fn new(config: &GalaxyConfig, render_settings: &GalaxyRenderConfig) -> Self {
+        let extent = config.radius * render_settings.padding_coeff;
+        let dims = Self::dimensions(0);
+        let texture_dimension = render_settings.texture_dimension.next_power_of_two();
+
+        let disk_painter = GalaxyComponentDensity::new(config, &config.disk_params);
+        let dust_painter = GalaxyComponentDensity::new(config, &config.dust_params);
+        let disk_enabled = config.disk_params.enabled && config.disk_params.strength != 0.0;
+        let dust_enabled = config.dust_params.enabled && config.dust_params.strength != 0.0;
+
+        // Largest xz density of each column, over every texel of the baked texture the GPU could interpolate from
+        let texel = 2.0 * extent / texture_dimension as f32;
+        let cell = 2.0 * extent / dims.x as f32;
+        let texel_range = |min: f32| {
+            let first = (((min + extent) / texel).floor() as i32 - 1).max(0);
+            let last = (((min + cell + extent) / texel).ceil() as i32 + 1)
+                .min(texture_dimension as i32 - 1);
+            first..=last
+        };
+        let columns: Vec<(f32, f32)> = (0..dims.x * dims.z)
+            .into_par_iter()
+            .map(|i| {
+                let min = vec2((i % dims.x) as f32, (i / dims.x) as f32) * cell - extent;
+                let mut disk_max = 0.0f32;
+                let mut dust_max = 0.0f32;
+                for z in texel_range(min.y) {
+                    for x in texel_range(min.x) {
+                        let p = vec2(x as f32, z as f32) * texel - extent;
+                        if disk_enabled {
+                            disk_max = disk_max.max(disk_painter.xz_density(p));
+                        }
+                        if dust_enabled {
+                            dust_max = dust_max.max(dust_painter.xz_density(p));
+                        }
+                    }
+                }
+                (disk_max, dust_max)
+            })
+            .collect();
+
+        let cell_size = 2.0 * extent / dims.as_vec3();
+        let finest: Vec<f32> = (0..dims.x * dims.y * dims.z)
+            .into_par_iter()
+            .map(|i| {
+                let index = uvec3(i % dims.x, (i / dims.x) % dims.y, i / (dims.x * dims.y));
+                let min = index.as_vec3() * cell_size - extent;
+                let max = min + cell_size;
+                // Closest point of the cell to the plane and to the centre, where the density peaks
+                let nearest = Vec3::ZERO.clamp(min, max);
+
+                let (disk_xz, dust_xz) = columns[(index.x + index.z * dims.x) as usize];
+                let disk = disk_xz
+                    * height_modulation(nearest.y, config.disk_params.y_thickness * config.radius);
+                let dust = dust_xz
+                    * height_modulation(nearest.y, config.dust_params.y_thickness * config.radius);
+                // Lower bound on the bulge's generalised radius in the cell, so an upper bound on its density
+                let bulge = bulge_density_at_radius(
+                    config,
+                    nearest.length() * config.bulge_shape.min_radius_scale(),
+                );
+                disk.max(dust).max(bulge)
+            })
+            .collect();
+
+        let mut levels = vec![finest];
+        for level in 1..OCCUPANCY_LEVELS {
+            let dims = Self::dimensions(level);
+            let below = &levels[level as usize - 1];
+            let below_dims = Self::dimensions(level - 1);
+            let coarse = (0..dims.x * dims.y * dims.z)
+                .map(|i| {
+                    let index = uvec3(i % dims.x, (i / dims.x) % dims.y, i / (dims.x * dims.y));
+                    let mut max = 0.0f32;
+                    for child in 0..8 {
+                        let c = index * 2 + uvec3(child & 1, (child >> 1) & 1, child >> 2);
+                        max = max.max(
+                            below[(c.x + c.y * below_dims.x + c.z * below_dims.x * below_dims.y)
+                                as usize],
+                        );
+                    }
+                    max
+                })
+                .collect();
+            levels.push(coarse);
+        }
+
+        Self { extent, levels }
+    }
+
+    fn cell(&self, level: u32, p: Vec3) -> (UVec3, Vec3) {
+        let dims = Self::dimensions(level);
+        let cell_size = 2.0 * self.extent / dims.as_vec3();
+        let index = ((p + self.extent) / cell_size)
+            .floor()
+            .clamp(Vec3::ZERO, dims.as_vec3() - 1.0);
+        (index.as_uvec3(), cell_size)
+    }
+
+    /// Occupancy of the cell containing `p`
+    pub fn value(&self, level: u32, p: Vec3) -> f32 {
+        let dims = Self::dimensions(level);
+        let (index, _) = self.cell(level, p);
+        self.levels[level as usize]
+            [(index.x + index.y * dims.x + index.z * dims.x * dims.y) as usize]
+    }
+
+    /// Distance along `rd` out of the largest empty cell containing `p`, zero if its finest cell is occupied
+    /// Same as `empty_space_exit` in intensity_shared.wgsl
+    pub fn empty_space_exit(&self, p: Vec3, rd: Vec3) -> f32 {
+        for level in (0..OCCUPANCY_LEVELS).rev() {
+            if self.value(level, p) >= OCCUPANCY_THRESHOLD {
+                continue;
+            }
+            return self.cell_exit(level, p, rd);
+        }
+        0.0
+    }
+
+    /// Distance along `rd` out of the cell containing `p`
+    /// Same as `cell_exit` in intensity_shared.wgsl
+    pub fn cell_exit(&self, level: u32, p: Vec3, rd: Vec3) -> f32 {
+        let (index, cell_size) = self.cell(level, p);
+        let cell_min = index.as_vec3() * cell_size - self.extent;
+        let bounds = cell_min + Vec3::select(rd.cmpgt(Vec3::ZERO), cell_size, Vec3::ZERO);
+        let t = Vec3::select(
+            rd.abs().cmpgt(Vec3::splat(1e-6)),
+            (bounds - p) / rd,
+            Vec3::splat(1e30),
+        );
+        // Nudged over the boundary so the next lookup lands in the next cell
+        t.min_element().max(0.0) + self.extent * 1e-4
+    }
+
+    /// Multiplier on the base step size at `p`, longer where the volume is sparse
+    /// Same as `adaptive_step_scale` in intensity_shared.wgsl
+    pub fn step_scale(&self, p: Vec3, max_step_scale: f32) -> f32 {
+        (SPARSE_DENSITY / self.value(0, p).max(1e-6))
+            .sqrt()
+            .clamp(1.0, max_step_scale.max(1.0))
+    }
+}
+
+/// `get_height_modulation` in intensity_shared.wgsl, unlike the CPU density it has no cut off
+fn height_modulation(height: f32, thickness: f32) -> f32 {
+    let val = 1.0 / (height / thickness).abs().cosh();
+    val * val
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Raymarching stops once no channel lets more than this through, as in `march_skipping`
+    const SATURATED_TRANSMITTANCE: f32 = 0.01;
+
+    /// Result of [`compare_raymarch`]
+    #[derive(Debug, Clone, Copy)]
+    struct RaymarchComparison {
+        /// Largest per channel difference, relative to the brightest fixed step pixel
+        max_error: f32,
+        /// Density evaluations over all rays
+        fixed_steps: usize,
+        adaptive_steps: usize,
+    }
+
+    /// The volume with noise disabled, evaluated the way `step_light` does
+    struct ReferenceVolume<'a> {
+        config: &'a GalaxyConfig,
+        render_settings: &'a GalaxyRenderConfig,
+        disk: GalaxyComponentDensity<'a>,
+        dust: GalaxyComponentDensity<'a>,
+    }
+
+    impl ReferenceVolume<'_> {
+        /// Light added by a step, already dimmed by its own dust, and the step's transmittance
+        fn step_light(&self, p: Vec3, stepsize: f32) -> (Vec3, Vec3) {
+            let d = p.xz().length() / self.config.radius;
+            let dust_col = self.config.dust_params.color.at(d);
+            let disk_col = self.config.disk_params.color.at(d);
+            let bulge_col = self.config.bulge_color.at(p.length() / self.config.radius);
+            let disk_params = &self.config.disk_params;
+            let dust_params = &self.config.dust_params;
+
+            // The noise free branches of get_disk_intensity and get_dust_intensity
+            let disk_xz = self.disk.xz_density(p.xz())
+                * height_modulation(p.y, disk_params.y_thickness * self.config.radius);
+            let disk = if disk_xz < OCCUPANCY_THRESHOLD || !disk_params.enabled {
+                0.0
+            } else {
+                disk_xz
+                    * (0.5f32.powf(disk_params.noise_tilt) + disk_params.noise_offset)
+                    * disk_params.strength
+            };
+            let dust_xz = self.dust.xz_density(p.xz())
+                * height_modulation(p.y, dust_params.y_thickness * self.config.radius);
+            let dust = if dust_xz < OCCUPANCY_THRESHOLD || !dust_params.enabled {
+                0.0
+            } else {
+                let p2 = (0.5 - dust_params.noise_offset).max(0.0);
+                dust_xz
+                    * (5.0 * p2).powf(dust_params.noise_tilt).clamp(-10.0, 10.0)
+                    * 0.01
+                    * dust_params.strength
+            };
+            let bulge = bulge_density(self.config, p) * self.config.bulge_intensity;
+
+            let exposure = self.render_settings.exposure;
+            let extinction = (-dust * stepsize * dust_col).exp();
+            let emitted = disk_col * disk * stepsize * exposure
+                + bulge_col * bulge * stepsize * exposure * 0.1;
+            (emitted * extinction, extinction)
+        }
+    }
+
+    /// Sphere intersection as in shader_galaxy_volume.wgsl
+    fn sphere_intersect(ro: Vec3, rd: Vec3, r: f32) -> Option<(f32, f32)> {
+        let b = ro.dot(rd);
+        let h = b * b - (ro.dot(ro) - r * r);
+        (h >= 0.0).then(|| (-b - h.sqrt(), -b + h.sqrt()))
+    }
+
+    /// `march` and `march_inside` in shader_galaxy_volume.wgsl, without jitter
+    fn march_fixed(
+        volume: &ReferenceVolume,
+        ro: Vec3,
+        rd: Vec3,
+        near: f32,
+        far: f32,
+    ) -> (Vec3, usize) {
+        let steps = volume.render_settings.raymarch_steps;
+        let exposure = 0.1;
+        let mut col = Vec3::ZERO;
+        for i in 0..steps {
+            let (p, stepsize) = if near <= 0.0 {
+                let s0 = 1.0 - i as f32 / steps as f32;
+                let s1 = 1.0 - (i + 1) as f32 / steps as f32;
+                (ro + rd * s0 * s0 * far, (s0 * s0 - s1 * s1) * far)
+            } else {
+                let step_size = (far - near) / steps as f32;
+                (ro + rd * (far - step_size * i as f32), step_size)
+            };
+            let (emitted, transmittance) = volume.step_light(p, stepsize * exposure);
+            col = col * transmittance + emitted;
+        }
+        (col, steps as usize)
+    }
+
+    /// `march_skipping` in shader_galaxy_volume.wgsl, without jitter
+    fn march_adaptive(
+        volume: &ReferenceVolume,
+        grid: &OccupancyGrid,
+        ro: Vec3,
+        rd: Vec3,
+        near: f32,
+        far: f32,
+    ) -> (Vec3, usize) {
+        let steps = volume.render_settings.raymarch_steps as f32;
+        let exposure = 0.1;
+        let base_step = (far - near.max(0.0)) / steps;
+        let mut t = near.max(0.0);
+        let mut col = Vec3::ZERO;
+        let mut transmittance = Vec3::ONE;
+        let mut evaluated = 0;
+        for _ in 0..(steps as usize * 2) {
+            if t >= far {
+                break;
+            }
+            let p = ro + rd * t;
+            let skip = grid.empty_space_exit(p, rd);
+            if skip > 0.0 {
+                t += skip;
+                continue;
+            }
+            let mut step = base_step * grid.step_scale(p, volume.render_settings.max_step_scale);
+            // A long step mustn't run on into a denser cell and weight its density by the sparse one's length
+            step = step.min(grid.cell_exit(0, p, rd).max(base_step));
+            if near <= 0.0 {
+                step = step.min(base_step * (2.0 * (t / far).sqrt()).max(0.01));
+            }
+            step = step.min(far - t);
+
+            let (emitted, step_transmittance) =
+                volume.step_light(p + rd * step * 0.5, step * exposure);
+            evaluated += 1;
+            col += transmittance * emitted;
+            transmittance *= step_transmittance;
+            if transmittance.max_element() < SATURATED_TRANSMITTANCE {
+                break;
+            }
+            t += step;
+        }
+        (col, evaluated)
+    }
+
+    /// Renders a `resolution` squared image of the galaxy from `camera` with both the fixed step raymarch and the
+    /// empty space skipping one, on the CPU, and compares them
+    fn compare_raymarch(
+        config: &GalaxyConfig,
+        render_settings: &GalaxyRenderConfig,
+        camera: Transform,
+        vertical_fov: f32,
+        resolution: u32,
+    ) -> RaymarchComparison {
+        let grid = OccupancyGrid::new(config, render_settings);
+        let volume = ReferenceVolume {
+            config,
+            render_settings,
+            disk: GalaxyComponentDensity::new(config, &config.disk_params),
+            dust: GalaxyComponentDensity::new(config, &config.dust_params),
+        };
+
+        let half_height = (vertical_fov * 0.5).tan();
+        let results: Vec<(Vec3, usize, Vec3, usize)> = (0..resolution * resolution)
+            .into_par_iter()
+            .filter_map(|i| {
+                let uv = (vec2((i % resolution) as f32, (i / resolution) as f32) + 0.5)
+                    / resolution as f32
+                    * 2.0
+                    - 1.0;
+                let rd = (camera.rotation * vec3(uv.x * half_height, -uv.y * half_height, -1.0))
+                    .normalize();
+                let (near, far) = sphere_intersect(camera.translation, rd, grid.extent)?;
+                if far <= 0.0 {
+                    return None;
+                }
+                let (fixed, fixed_steps) = march_fixed(&volume, camera.translation, rd, near, far);
+                let (adaptive, adaptive_steps) =
+                    march_adaptive(&volume, &grid, camera.translation, rd, near, far);
+                Some((fixed, fixed_steps, adaptive, adaptive_steps))
+            })
+            .collect();
+
+        let brightest = results
+            .iter()
+            .map(|(fixed, ..)| fixed.max_element())
+            .fold(1e-12, f32::max);
+        let errors: Vec<f32> = results
+            .iter()
+            .map(|(fixed, _, adaptive, _)| (*fixed - *adaptive).abs().max_element() / brightest)
+            .collect();
+        RaymarchComparison {
+            max_error: errors.iter().copied().fold(0.0, f32::max),
+            fixed_steps: results.iter().map(|r| r.1).sum(),
+            adaptive_steps: results.iter().map(|r| r.3).sum(),
+        }
+    }
+
+    /// The empty space skipping march must match the fixed step one to within a couple of percent of the
+    /// brightest pixel
+    fn assert_matches(camera: Transform) -> RaymarchComparison {
+        let config = GalaxyConfig::default();
+        let render_settings = GalaxyRenderConfig::default();
+        let camera = camera.looking_at(Vec3::ZERO, Vec3::Y);
+        let result = compare_raymarch(&config, &render_settings, camera, 1.0, 32);
+        assert!(result.max_error < 0.02, "{result:?}");
+        assert!(result.adaptive_steps > 0, "{result:?}");
+        result
+    }
+
+    #[test]
+    fn face_on() {
+        let r = GalaxyConfig::default().radius;
+        let result = assert_matches(Transform::from_xyz(0.0, r * 3.0, 0.0));
+        // Most of the view is the empty space above and below the disk
+        assert!(result.adaptive_steps < result.fixed_steps, "{result:?}");
+    }
+
+    #[test]
+    fn inclined() {
+        let r = GalaxyConfig::default().radius;
+        assert_matches(Transform::from_xyz(0.0, r * 1.5, r * 2.5));
+    }
+
+    #[test]
+    fn edge_on() {
+        let r = GalaxyConfig::default().radius;
+        assert_matches(Transform::from_xyz(0.0, 0.0, r * 3.0));
+    }
+
+    #[test]
+    fn inside_the_disk() {
+        let r = GalaxyConfig::default().radius;
+        assert_matches(Transform::from_xyz(r * 0.6, r * 0.02, 0.0));
+    }
+}
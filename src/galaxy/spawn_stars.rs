@@ -8,102 +8,238 @@ pub struct SpawnStarsPlugin;
 
 impl Plugin for SpawnStarsPlugin {
     fn build(&self, app: &mut App) {
-        app.insert_resource(StarSpawningControl {
-            generation: -1,
-            stars_left_to_place: 0,
-            next_star_index: 0,
-        })
-        .insert_resource(StarCount { count: 0 })
-        .add_systems(Update, manage_star_instances);
+        app.register_required_components::<GalaxyConfig, StarSpawningControl>()
+            .register_required_components::<GalaxyConfig, StarCount>()
+            .register_required_components::<GalaxyConfig, StarCatalogue>()
+            .register_required_components::<GalaxyConfig, StarSelection>()
+            .add_systems(Update, (manage_star_instances, sync_selected_stars).chain());
     }
 }
 
-#[derive(Resource)]
+/// Per galaxy spawning progress
+#[derive(Component)]
 pub struct StarSpawningControl {
     generation: i32,
     stars_left_to_place: i32,
-    next_star_index: u32,
 }
 
+impl StarSpawningControl {
+    /// Clears the galaxy's stars and spawns them again from its config
+    pub fn reset(&mut self) {
+        self.generation = -1;
+    }
+}
+
+impl Default for StarSpawningControl {
+    fn default() -> Self {
+        Self {
+            generation: -1,
+            stars_left_to_place: 0,
+        }
+    }
+}
+
+/// Every star of a galaxy, on the galaxy entity and indexed by star index
+/// Positions are in the galaxy's local space, the stars are drawn straight from this without any entities
+#[derive(Component, Default)]
+pub struct StarCatalogue {
+    pub positions: Vec<Vec3>,
+    pub masses: Vec<f32>,
+    /// [`star_color`] of each mass, worked out once when the star is placed
+    pub colors: Vec<Vec3>,
+    moves: u32,
+}
+
+impl StarCatalogue {
+    /// Stars placed so far
+    pub fn len(&self) -> usize {
+        self.positions.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.positions.is_empty()
+    }
+
+    pub fn position(&self, index: u32) -> Option<Vec3> {
+        self.positions.get(index as usize).copied()
+    }
+
+    /// For moving stars that are already placed, so consumers know to re-read every position
+    pub fn positions_mut(&mut self) -> &mut [Vec3] {
+        self.moves = self.moves.wrapping_add(1);
+        &mut self.positions
+    }
+
+    /// Bumped whenever placed stars are moved, placing new stars leaves it alone
+    pub fn moves(&self) -> u32 {
+        self.moves
+    }
+
+    fn clear(&mut self) {
+        self.positions.clear();
+        self.masses.clear();
+        self.colors.clear();
+    }
+}
+
+/// Stars of a galaxy that also get a [`Star`] entity, for anything that needs to attach components to them
+#[derive(Component, Default)]
+pub struct StarSelection {
+    pub indices: Vec<u32>,
+}
+
+/// A selected star, a child of its galaxy with its transform relative to it
+/// Kept in sync with the galaxy's [`StarCatalogue`]
 #[derive(Component)]
 pub struct Star {
+    /// Index within the galaxy
     pub index: u32,
     mass: f32,
 }
 
 impl Star {
-    fn temperature(&self) -> f32 {
-        self.mass.powf(0.625) * 5772.0
+    pub fn color(&self) -> Vec3 {
+        star_color(self.mass)
     }
-    fn simple_planck(temperature: f32) -> Vec3 {
-        let mut res: Vec3 = Vec3::ZERO;
-        let m = 1.0;
-        for i in 0..3 {
-            // +=.1 if you want to better sample the spectrum.
-            let f = 1. + 0.5 * i as f32;
-            res[i as usize] += 10.0 / m * (f * f * f) / (f32::exp(19.0e3 * f / temperature) - 1.);
-            // Planck law
-        }
+}
 
-        //res = res / res.max_element();
-        res
-    }
+fn temperature(mass: f32) -> f32 {
+    mass.powf(0.625) * 5772.0
+}
 
-    pub fn color(&self) -> Vec3 {
-        Self::simple_planck(self.temperature())
+fn simple_planck(temperature: f32) -> Vec3 {
+    let mut res: Vec3 = Vec3::ZERO;
+    let m = 1.0;
+    for i in 0..3 {
+        // +=.1 if you want to better sample the spectrum.
+        let f = 1. + 0.5 * i as f32;
+        res[i as usize] += 10.0 / m * (f * f * f) / (f32::exp(19.0e3 * f / temperature) - 1.);
+        // Planck law
     }
+
+    //res = res / res.max_element();
+    res
+}
+
+/// Blackbody colour of a star of `mass` solar masses
+pub fn star_color(mass: f32) -> Vec3 {
+    simple_planck(temperature(mass))
 }
 
-/// Spawns or despawns star instances
-/// Spawns in fairly small batches to avoid stutter when galaxy config changes
+/// Fills the star catalogues
+/// Spawns in batches to avoid stutter when galaxy config changes
 /// - Might be a flag active during game loading that causes the spawn to run to finish
 fn manage_star_instances(
-    mut commands: Commands,
-    mut star_count: ResMut<StarCount>,
-    galaxy_config: Res<GalaxyConfig>,
-    existing_star_query: Query<Entity, With<Star>>,
-    mut star_instancing: ResMut<StarSpawningControl>,
+    mut galaxies: Query<(
+        &GalaxyConfig,
+        &mut StarCount,
+        &mut StarCatalogue,
+        &mut StarSpawningControl,
+    )>,
 ) {
-    const BATCH_SIZE: i32 = 4096;
-
-    if star_instancing.generation != galaxy_config.generation {
-        // cleanup existing stars
-        for entity in &existing_star_query {
-            commands.entity(entity).despawn();
+    for (galaxy_config, mut star_count, mut catalogue, mut star_instancing) in &mut galaxies {
+        if star_instancing.generation != galaxy_config.generation {
+            // cleanup existing stars
+            catalogue.clear();
+            // update params
+            star_instancing.generation = galaxy_config.generation;
+            star_count.count = (galaxy_config.stars_per_arm * galaxy_config.n_arms) as usize;
+            star_count.populations = super::allocate_stars(galaxy_config, star_count.count);
+            star_instancing.stars_left_to_place = star_count.count as i32;
+        }
+        // Only borrowed mutably when placing stars, the extinction cache reuploads the catalogue when it changes
+        if galaxy_config.stars_params.enabled && star_instancing.stars_left_to_place > 0 {
+            spawn_star_batch(
+                galaxy_config,
+                &star_count,
+                &mut catalogue,
+                &mut star_instancing,
+            );
         }
-        // update params
-        star_instancing.generation = galaxy_config.generation;
-        star_count.count = (galaxy_config.stars_per_arm * galaxy_config.n_arms) as usize;
-        star_instancing.stars_left_to_place = star_count.count as i32;
-        star_instancing.next_star_index = 0;
-    }
-    if !galaxy_config.stars_params.enabled {
-        return;
     }
+}
+
+fn spawn_star_batch(
+    galaxy_config: &GalaxyConfig,
+    star_count: &StarCount,
+    catalogue: &mut StarCatalogue,
+    star_instancing: &mut StarSpawningControl,
+) {
+    // No entities per star, so the batches can be much larger
+    const BATCH_SIZE: i32 = 32768;
+
     // Spawn stars for the current batch
-    if star_instancing.stars_left_to_place > 0 {
-        let batch_size = star_instancing.stars_left_to_place.min(BATCH_SIZE);
+    let batch_size = star_instancing.stars_left_to_place.min(BATCH_SIZE);
 
-        let mut star_samples = vec![(Vec3::ZERO, 0.0); batch_size as usize];
-        star_samples.par_iter_mut().for_each(|sample| {
+    let sampler = super::StarSampler::new(galaxy_config);
+    let first_index = catalogue.len();
+    let mut star_samples = vec![(Vec3::ZERO, 0.0); batch_size as usize];
+    star_samples
+        .par_iter_mut()
+        .enumerate()
+        .for_each(|(i, sample)| {
             let mut rng = rand::rng();
-            *sample = (
-                sample_star_pos(&galaxy_config, &mut rng),
-                random_star_mass(&mut rng),
-            );
+            let population = star_count.population_of(first_index + i);
+            let pos = sampler.sample(population, &mut rng);
+            *sample = (pos, random_star_mass(&mut rng));
         });
 
-        for star in star_samples {
+    for (position, mass) in star_samples {
+        catalogue.positions.push(position);
+        catalogue.masses.push(mass);
+        catalogue.colors.push(star_color(mass));
+    }
+    star_instancing.stars_left_to_place -= batch_size;
+}
+
+/// Spawns and despawns the [`Star`] entities of the selected stars, and moves them with the catalogue
+#[allow(clippy::type_complexity)]
+fn sync_selected_stars(
+    mut commands: Commands,
+    galaxies: Query<
+        (Entity, &StarCatalogue, &StarSelection),
+        Or<(Changed<StarCatalogue>, Changed<StarSelection>)>,
+    >,
+    mut stars: Query<(Entity, &mut Star, &ChildOf, &mut Transform)>,
+) {
+    for (galaxy, catalogue, selection) in &galaxies {
+        let mut existing = vec![];
+        for (entity, mut star, child_of, mut transform) in &mut stars {
+            if child_of.parent() != galaxy {
+                continue;
+            }
+            match catalogue.position(star.index) {
+                Some(position) if selection.indices.contains(&star.index) => {
+                    transform.set_if_neq(Transform::from_translation(position));
+                    // A respawn may have put a different star at the same index
+                    let mass = catalogue.masses[star.index as usize];
+                    if star.mass != mass {
+                        star.mass = mass;
+                    }
+                    existing.push(star.index);
+                }
+                // Deselected, or removed by a respawn
+                _ => commands.entity(entity).despawn(),
+            }
+        }
+
+        for &index in &selection.indices {
+            let Some(position) = catalogue.position(index) else {
+                continue;
+            };
+            if existing.contains(&index) {
+                continue;
+            }
+            existing.push(index);
             commands.spawn((
-                Transform::from_translation(star.0),
+                Transform::from_translation(position),
                 Star {
-                    index: star_instancing.next_star_index,
-                    mass: star.1,
+                    index,
+                    mass: catalogue.masses[index as usize],
                 },
+                ChildOf(galaxy),
             ));
-            star_instancing.next_star_index += 1;
         }
-        star_instancing.stars_left_to_place -= batch_size;
     }
 }
 
@@ -124,41 +260,3 @@ fn random_star_mass(rng: &mut ThreadRng) -> f32 {
         .clone();
     rng.random_range(range)
 }
-
-fn sample_unit_circle(rng: &mut ThreadRng) -> Vec2 {
-    let length = rng.random::<f32>().sqrt();
-    let angle = std::f32::consts::PI * rng.random_range(0.0..2.0);
-
-    vec2(angle.cos(), angle.sin()) * length
-}
-
-fn sample_pos(rng: &mut ThreadRng, radius: f32) -> Vec3 {
-    let circle_sample = sample_unit_circle(rng) * radius;
-    let height_sample: f32 = rng.random_range(-2.0..2.0);
-
-    //height_sample /= height_sample.abs().sqrt();
-
-    vec3(circle_sample.x, height_sample, circle_sample.y) * 2.0
-}
-
-fn sample_star_pos(galaxy_config: &GalaxyConfig, rng: &mut ThreadRng) -> Vec3 {
-    let arm_painter =
-        super::GalaxyComponentDensity::new(galaxy_config, &galaxy_config.stars_params);
-
-    let current_pos = sample_pos(rng, galaxy_config.radius);
-    let mut best = current_pos;
-    let weight = arm_painter.xyz_density(current_pos);
-    let mut weight_sum = weight;
-
-    for _ in 0..256 {
-        let current_pos = sample_pos(rng, galaxy_config.radius);
-        let weight = arm_painter.xyz_density(current_pos) + 0.0001;
-        weight_sum += weight;
-
-        if rng.random::<f32>() < weight / weight_sum {
-            best = current_pos;
-        }
-    }
-
-    best
-}
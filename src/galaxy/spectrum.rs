@@ -0,0 +1,235 @@
+use bevy::prelude::*;
+
+/// Centre wavelengths of the bins the spectral raymarch carries, in nm
+///
+/// Far and near UV, five across the visible (the sixth on H-alpha) and the near infrared H band.
+pub const SPECTRAL_BINS: [f32; 8] = [160.0, 260.0, 420.0, 480.0, 540.0, 600.0, 656.3, 1600.0];
+
+/// The B band, dust strength is the optical depth at this wavelength
+const REFERENCE_WAVELENGTH: f32 = 445.0;
+
+/// Second radiation constant hc/k, in nm K
+const C2: f32 = 1.4388e7;
+
+/// Wavelength dependence of dust extinction
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub enum ReddeningLaw {
+    /// Milky Way dust, Cardelli, Clayton & Mathis (1989) with R_V = 3.1
+    #[default]
+    Cardelli,
+    /// Starburst galaxies, Calzetti et al. (2000) with R_V = 4.05, greyer and without the 2175Å bump
+    Calzetti,
+}
+
+impl ReddeningLaw {
+    /// A(λ)/A(V) at a wavelength in nm
+    pub fn extinction(&self, wavelength: f32) -> f32 {
+        match self {
+            Self::Cardelli => cardelli(1000.0 / wavelength, 3.1),
+            Self::Calzetti => calzetti(wavelength / 1000.0, 4.05) / 4.05,
+        }
+    }
+
+    /// Extinction at each bin, relative to the B band so the dust strength means the same in both modes
+    pub fn extinction_curve(&self) -> [f32; 8] {
+        let reference = self.extinction(REFERENCE_WAVELENGTH);
+        SPECTRAL_BINS.map(|wavelength| self.extinction(wavelength) / reference)
+    }
+}
+
+/// `x` is the inverse wavelength in µm⁻¹, valid from 0.3 to 10
+fn cardelli(x: f32, r_v: f32) -> f32 {
+    let (a, b) = if x < 1.1 {
+        (0.574 * x.powf(1.61), -0.527 * x.powf(1.61))
+    } else if x < 3.3 {
+        let y = x - 1.82;
+        let a = 1.0 + 0.17699 * y - 0.50447 * y.powi(2) - 0.02427 * y.powi(3)
+            + 0.72085 * y.powi(4)
+            + 0.01979 * y.powi(5)
+            - 0.77530 * y.powi(6)
+            + 0.32999 * y.powi(7);
+        let b = 1.41338 * y + 2.28305 * y.powi(2) + 1.07233 * y.powi(3)
+            - 5.38434 * y.powi(4)
+            - 0.62251 * y.powi(5)
+            + 5.30260 * y.powi(6)
+            - 2.09002 * y.powi(7);
+        (a, b)
+    } else {
+        let (fa, fb) = if x > 5.9 {
+            let y = x - 5.9;
+            (
+                -0.04473 * y.powi(2) - 0.009779 * y.powi(3),
+                0.2130 * y.powi(2) + 0.1207 * y.powi(3),
+            )
+        } else {
+            (0.0, 0.0)
+        };
+        (
+            1.752 - 0.316 * x - 0.104 / ((x - 4.67).powi(2) + 0.341) + fa,
+            -3.090 + 1.825 * x + 1.206 / ((x - 4.62).powi(2) + 0.263) + fb,
+        )
+    };
+    a + b / r_v
+}
+
+/// k(λ) with the wavelength in µm, valid from 0.12 to 2.2
+fn calzetti(wavelength: f32, r_v: f32) -> f32 {
+    let x = 1.0 / wavelength;
+    if wavelength < 0.63 {
+        2.659 * (-2.156 + 1.509 * x - 0.198 * x * x + 0.011 * x * x * x) + r_v
+    } else {
+        2.659 * (-1.857 + 1.040 * x) + r_v
+    }
+}
+
+/// Planck's law up to a constant factor, wavelength in nm
+pub fn blackbody(wavelength: f32, temperature: f32) -> f32 {
+    wavelength.powi(-5) / ((C2 / (wavelength * temperature)).exp() - 1.0)
+}
+
+/// Blackbody at each bin, normalised to 1 at 540nm
+fn blackbody_spectrum(temperature: f32) -> [f32; 8] {
+    let reference = blackbody(540.0, temperature);
+    SPECTRAL_BINS.map(|wavelength| blackbody(wavelength, temperature) / reference)
+}
+
+/// Old, cool population of the bulge, dominated by K giants
+pub fn bulge_spectrum() -> [f32; 8] {
+    blackbody_spectrum(4000.0)
+}
+
+/// Young population of the disk, hot OB stars plus the H-alpha line of the HII regions around them
+pub fn disk_spectrum() -> [f32; 8] {
+    let mut spectrum = blackbody_spectrum(15000.0);
+    spectrum[6] += 1.5;
+    spectrum
+}
+
+/// What the spectrum is viewed through, mapping each bin to a displayed colour
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub enum SpectralFilter {
+    /// True colour, only the visible bins
+    #[default]
+    Visible,
+    /// Near infrared in false colour, sees through the dust to the old stars
+    Infrared,
+    /// Narrowband on the H-alpha line, picks out star forming regions
+    HAlpha,
+    /// Near and far UV in false colour, only the young stars
+    Ultraviolet,
+}
+
+impl SpectralFilter {
+    pub const ALL: [Self; 4] = [
+        Self::Visible,
+        Self::Infrared,
+        Self::HAlpha,
+        Self::Ultraviolet,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Visible => "Visible",
+            Self::Infrared => "Infrared",
+            Self::HAlpha => "H-alpha",
+            Self::Ultraviolet => "Ultraviolet",
+        }
+    }
+
+    /// Linear sRGB contribution of each bin
+    pub fn response(&self) -> [Vec3; 8] {
+        match self {
+            // CIE colour matching functions integrated over each bin and taken to sRGB, normalised so a flat
+            // spectrum is white
+            Self::Visible => [
+                Vec3::ZERO,
+                Vec3::ZERO,
+                vec3(0.10, 0.0, 0.60),
+                vec3(0.0, 0.20, 0.40),
+                vec3(0.05, 0.55, 0.0),
+                vec3(0.45, 0.25, 0.0),
+                vec3(0.40, 0.0, 0.0),
+                Vec3::ZERO,
+            ],
+            Self::Infrared => {
+                let mut response = [Vec3::ZERO; 8];
+                response[6] = vec3(0.3, 0.1, 0.05);
+                response[7] = vec3(2.0, 1.5, 1.1);
+                response
+            }
+            Self::HAlpha => {
+                let mut response = [Vec3::ZERO; 8];
+                response[6] = vec3(1.0, 0.3, 0.3);
+                response
+            }
+            Self::Ultraviolet => {
+                let mut response = [Vec3::ZERO; 8];
+                response[0] = vec3(0.15, 0.1, 0.3);
+                response[1] = vec3(0.1, 0.15, 0.3);
+                response
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn cardelli_is_normalised_to_v() {
+        let law = ReddeningLaw::Cardelli;
+        assert!((law.extinction(550.0) - 1.0).abs() < 0.01);
+        // A(B)/A(V) for R_V = 3.1
+        assert!((law.extinction(REFERENCE_WAVELENGTH) - 1.31).abs() < 0.02);
+    }
+
+    #[test]
+    fn calzetti_is_normalised_to_v() {
+        assert!((calzetti(0.55, 4.05) / 4.05 - 1.0).abs() < 0.01);
+        assert!((ReddeningLaw::Calzetti.extinction(550.0) - 1.0).abs() < 0.01);
+    }
+
+    #[test]
+    fn extinction_curve_is_one_at_b_band() {
+        // 445nm falls between the 420 and 480nm bins
+        let t = (REFERENCE_WAVELENGTH - SPECTRAL_BINS[2]) / (SPECTRAL_BINS[3] - SPECTRAL_BINS[2]);
+        for law in [ReddeningLaw::Cardelli, ReddeningLaw::Calzetti] {
+            let curve = law.extinction_curve();
+            let at_reference = curve[2].lerp(curve[3], t);
+            assert!((at_reference - 1.0).abs() < 0.01, "{law:?}: {at_reference}");
+            // Dust always dims blue more than red
+            assert!(curve.windows(2).all(|pair| pair[0] > pair[1]), "{law:?}");
+        }
+    }
+
+    #[test]
+    fn flat_spectrum_is_white() {
+        let white: Vec3 = SpectralFilter::Visible.response().iter().sum();
+        assert!(white.abs_diff_eq(Vec3::ONE, 1e-5), "{white}");
+    }
+
+    #[test]
+    fn blackbody_peaks_at_wien_wavelength() {
+        for temperature in [4000.0, 6000.0, 15000.0] {
+            let peak = (100..3000)
+                .map(|wavelength| wavelength as f32)
+                .max_by(|a, b| blackbody(*a, temperature).total_cmp(&blackbody(*b, temperature)))
+                .unwrap();
+            let wien = 2.898e6 / temperature;
+            assert!(
+                (peak - wien).abs() < 2.0,
+                "{temperature}K: {peak} != {wien}"
+            );
+        }
+    }
+
+    #[test]
+    fn disk_is_bluer_than_bulge() {
+        let (disk, bulge) = (disk_spectrum(), bulge_spectrum());
+        assert_eq!(disk[4], 1.0);
+        assert_eq!(bulge[4], 1.0);
+        assert!(disk[2] > bulge[2]);
+        assert!(disk[5] < bulge[5]);
+    }
+}
@@ -0,0 +1,286 @@
+use crate::prelude::*;
+use bevy::prelude::*;
+use rand::prelude::*;
+
+/// Populations the spawned stars are drawn from
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum StellarPopulation {
+    Bulge,
+    /// The smooth disk between the arms
+    Disk,
+    Arms,
+    /// Sparse and spherical, reaching past the disk
+    Halo,
+}
+
+impl StellarPopulation {
+    pub const ALL: [Self; 4] = [Self::Bulge, Self::Disk, Self::Arms, Self::Halo];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Bulge => "Bulge",
+            Self::Disk => "Disk",
+            Self::Arms => "Arms",
+            Self::Halo => "Halo",
+        }
+    }
+
+    pub fn index(&self) -> usize {
+        *self as usize
+    }
+}
+
+/// The stellar halo holds this fraction of the disk's mass, about the Milky Way's
+const HALO_MASS_FRACTION: f32 = 0.02;
+
+/// Star budget of each population, as a multiplier on its share of the galaxy's light
+/// At 1 for all of them the stars follow the light of the volume
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct StarBudget {
+    pub bulge: f32,
+    pub disk: f32,
+    pub arms: f32,
+    pub halo: f32,
+}
+
+impl Default for StarBudget {
+    fn default() -> Self {
+        Self {
+            bulge: 1.0,
+            // The volume has almost no light between the arms
+            disk: 0.3,
+            arms: 1.0,
+            halo: 1.0,
+        }
+    }
+}
+
+impl StarBudget {
+    pub fn get_mut(&mut self, population: StellarPopulation) -> &mut f32 {
+        match population {
+            StellarPopulation::Bulge => &mut self.bulge,
+            StellarPopulation::Disk => &mut self.disk,
+            StellarPopulation::Arms => &mut self.arms,
+            StellarPopulation::Halo => &mut self.halo,
+        }
+    }
+
+    pub fn get(&self, population: StellarPopulation) -> f32 {
+        match population {
+            StellarPopulation::Bulge => self.bulge,
+            StellarPopulation::Disk => self.disk,
+            StellarPopulation::Arms => self.arms,
+            StellarPopulation::Halo => self.halo,
+        }
+    }
+}
+
+/// Integrated light of each population, in the units the volume is rendered with, indexed by
+/// [`StellarPopulation::index`]
+///
+/// The disk and arms are measured on the disk component, which is what the volume draws, the way `step_light`
+/// evaluates it without noise. Stars are still placed along the star component's arms.
+pub fn population_masses(config: &GalaxyConfig) -> [f32; 4] {
+    let disk_params = &config.disk_params;
+    let disk = GalaxyComponentDensity::new(config, disk_params);
+
+    // The volume's sech² height profile has no cut off
+    let height_integral = 2.0 * disk_params.y_thickness * config.radius;
+    // The shader's noise factor with the noise at its midpoint
+    let noise_scale = 0.5f32.powf(disk_params.noise_tilt) + disk_params.noise_offset;
+    let strength = if disk_params.enabled {
+        disk_params.strength * noise_scale.max(0.0)
+    } else {
+        0.0
+    };
+
+    // Midpoint rule over the disk, the radial profile is smooth enough for a coarse grid
+    const CELLS: usize = 128;
+    let extent = config.radius * 1.5;
+    let cell = 2.0 * extent / CELLS as f32;
+    let mut envelope = 0.0;
+    let mut arms = 0.0;
+    for i in 0..CELLS * CELLS {
+        let p = (vec2((i % CELLS) as f32, (i / CELLS) as f32) + 0.5) * cell - extent;
+        envelope += disk.xz_envelope(p);
+        arms += disk.xz_density(p);
+    }
+    let to_light = cell * cell * height_integral * strength;
+    let envelope = envelope * to_light;
+    let arms = arms * to_light;
+
+    // Same scaling the raymarch gives the bulge light
+    let bulge =
+        BulgeSampler::new(config).map_or(0.0, |bulge| bulge.mass()) * config.bulge_intensity * 0.1;
+
+    let mut masses = [0.0; 4];
+    masses[StellarPopulation::Bulge.index()] = bulge;
+    masses[StellarPopulation::Disk.index()] = (envelope - arms).max(0.0);
+    masses[StellarPopulation::Arms.index()] = arms;
+    masses[StellarPopulation::Halo.index()] = envelope * HALO_MASS_FRACTION;
+    masses
+}
+
+/// Splits `total` stars between the populations in proportion to their budgeted masses
+pub fn allocate_stars(config: &GalaxyConfig, total: usize) -> [usize; 4] {
+    let masses = population_masses(config);
+    let weights = StellarPopulation::ALL
+        .map(|population| masses[population.index()] * config.star_budget.get(population).max(0.0));
+    let weight_sum: f32 = weights.iter().sum();
+
+    let mut counts = [0; 4];
+    if weight_sum <= 0.0 || !weight_sum.is_finite() {
+        counts[StellarPopulation::Arms.index()] = total;
+        return counts;
+    }
+
+    // Largest remainder, so the counts add up to the total
+    let shares = weights.map(|weight| weight / weight_sum * total as f32);
+    for (count, share) in counts.iter_mut().zip(shares) {
+        *count = share.floor() as usize;
+    }
+    let mut by_remainder = [0, 1, 2, 3];
+    by_remainder.sort_by(|a, b| {
+        (shares[*b] - shares[*b].floor()).total_cmp(&(shares[*a] - shares[*a].floor()))
+    });
+    let assigned: usize = counts.iter().sum();
+    for i in by_remainder
+        .iter()
+        .cycle()
+        .take(total.saturating_sub(assigned))
+    {
+        counts[*i] += 1;
+    }
+    counts
+}
+
+/// Draws star positions from each population
+pub struct StarSampler<'a> {
+    config: &'a GalaxyConfig,
+    stars: GalaxyComponentDensity<'a>,
+    bulge: Option<BulgeSampler>,
+}
+
+impl<'a> StarSampler<'a> {
+    pub fn new(config: &'a GalaxyConfig) -> Self {
+        Self {
+            config,
+            stars: GalaxyComponentDensity::new(config, &config.stars_params),
+            bulge: BulgeSampler::new(config),
+        }
+    }
+
+    pub fn sample(&self, population: StellarPopulation, rng: &mut ThreadRng) -> Vec3 {
+        match population {
+            StellarPopulation::Bulge => match &self.bulge {
+                Some(bulge) => bulge.sample(rng),
+                None => self.sample_arms(rng),
+            },
+            StellarPopulation::Disk => resample(rng, self.config.radius, sample_pos, |p| {
+                (self.stars.xyz_envelope(p) - self.stars.xyz_density(p)).max(0.0)
+            }),
+            StellarPopulation::Arms => self.sample_arms(rng),
+            StellarPopulation::Halo => resample(rng, self.config.radius, sample_sphere, |p| {
+                (p.length() / self.config.radius + 0.1).powf(-3.5)
+            }),
+        }
+    }
+
+    fn sample_arms(&self, rng: &mut ThreadRng) -> Vec3 {
+        resample(rng, self.config.radius, sample_pos, |p| {
+            self.stars.xyz_density(p)
+        })
+    }
+}
+
+fn sample_unit_circle(rng: &mut ThreadRng) -> Vec2 {
+    let length = rng.random::<f32>().sqrt();
+    let angle = std::f32::consts::PI * rng.random_range(0.0..2.0);
+
+    vec2(angle.cos(), angle.sin()) * length
+}
+
+fn sample_pos(rng: &mut ThreadRng, radius: f32) -> Vec3 {
+    let circle_sample = sample_unit_circle(rng) * radius;
+    let height_sample: f32 = rng.random_range(-2.0..2.0);
+
+    //height_sample /= height_sample.abs().sqrt();
+
+    vec3(circle_sample.x, height_sample, circle_sample.y) * 2.0
+}
+
+/// Uniform in a sphere of 1.5 galaxy radii
+fn sample_sphere(rng: &mut ThreadRng, radius: f32) -> Vec3 {
+    let y = rng.random_range(-1.0..1.0f32);
+    let angle = rng.random_range(0.0..std::f32::consts::TAU);
+    let r = (1.0 - y * y).sqrt();
+    vec3(r * angle.cos(), y, r * angle.sin()) * rng.random::<f32>().cbrt() * radius * 1.5
+}
+
+/// Picks one of 257 proposals with probability proportional to its weight
+fn resample(
+    rng: &mut ThreadRng,
+    radius: f32,
+    propose: fn(&mut ThreadRng, f32) -> Vec3,
+    weight: impl Fn(Vec3) -> f32,
+) -> Vec3 {
+    let current_pos = propose(rng, radius);
+    let mut best = current_pos;
+    let mut weight_sum = weight(current_pos);
+
+    for _ in 0..256 {
+        let current_pos = propose(rng, radius);
+        let weight = weight(current_pos) + 0.0001;
+        weight_sum += weight;
+
+        if rng.random::<f32>() < weight / weight_sum {
+            best = current_pos;
+        }
+    }
+
+    best
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn allocation_sums_to_total() {
+        let config = GalaxyConfig::default();
+        for total in [0, 1, 7, 1000, 123_457] {
+            assert_eq!(allocate_stars(&config, total).iter().sum::<usize>(), total);
+        }
+    }
+
+    #[test]
+    fn allocation_tracks_budgets() {
+        const TOTAL: usize = 1_000_000;
+        let masses = population_masses(&GalaxyConfig::default());
+        for star_budget in [
+            StarBudget::default(),
+            StarBudget {
+                bulge: 1.0,
+                disk: 0.0,
+                arms: 2.0,
+                halo: 5.0,
+            },
+        ] {
+            let config = GalaxyConfig {
+                star_budget,
+                ..default()
+            };
+            let counts = allocate_stars(&config, TOTAL);
+
+            let weights = StellarPopulation::ALL
+                .map(|population| masses[population.index()] * star_budget.get(population));
+            let weight_sum: f32 = weights.iter().sum();
+            for population in StellarPopulation::ALL {
+                let expected = weights[population.index()] / weight_sum * TOTAL as f32;
+                let count = counts[population.index()] as f32;
+                // Largest remainder rounding, plus f32 error on a million stars
+                assert!((count - expected).abs() <= 2.0, "{counts:?} {weights:?}");
+            }
+        }
+    }
+}
@@ -0,0 +1,297 @@
+use super::StarCount;
+use crate::prelude::*;
+use bevy::prelude::*;
+use std::f32::consts::PI;
+
+/// Segments used to approximate the galaxy rim (and arcs in the polar grid)
+const RIM_SEGMENTS: usize = 96;
+
+pub struct TerritoryPlugin;
+
+impl Plugin for TerritoryPlugin {
+    fn build(&self, app: &mut App) {
+        app.insert_resource(TerritoryConfig::default())
+            .insert_resource(Territories::default())
+            .add_systems(Update, update_territories);
+    }
+}
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum PartitionMode {
+    /// Rings by radius and slices by angle
+    Polar { rings: u32, slices: u32 },
+    /// Voronoi regions around seed stars
+    Voronoi { seeds: u32 },
+}
+
+#[derive(Resource, Clone, PartialEq)]
+pub struct TerritoryConfig {
+    pub mode: PartitionMode,
+    /// Explicit Voronoi seed stars (by `Star::index`), if empty they are picked evenly from the star list
+    pub seed_stars: Vec<u32>,
+    pub show_overlay: bool,
+    pub overlay_opacity: f32,
+}
+
+impl Default for TerritoryConfig {
+    fn default() -> Self {
+        Self {
+            mode: PartitionMode::Polar {
+                rings: 4,
+                slices: 12,
+            },
+            seed_stars: Vec::new(),
+            show_overlay: false,
+            overlay_opacity: 0.15,
+        }
+    }
+}
+
+pub struct Sector {
+    pub id: usize,
+    /// Closed boundary on the galactic (xz) plane
+    pub boundary: Vec<Vec2>,
+    /// Area in square parsecs
+    pub area: f32,
+    /// `Star::index` of every star inside the sector
+    pub members: Vec<u32>,
+    /// Faction id, used by the overlay to colour the sector
+    pub owner: Option<u32>,
+}
+
+impl Sector {
+    pub fn centroid(&self) -> Vec2 {
+        self.boundary.iter().copied().sum::<Vec2>() / self.boundary.len().max(1) as f32
+    }
+}
+
+#[derive(Resource, Default)]
+pub struct Territories {
+    sectors: Vec<Sector>,
+    mode: Option<PartitionMode>,
+    galaxy_radius: f32,
+    seed_positions: Vec<Vec2>,
+    generation: i32,
+    /// Stars in the catalogue when the membership was last updated
+    placed_stars: usize,
+    layout_version: u32,
+}
+
+impl Territories {
+    pub fn sectors(&self) -> &[Sector] {
+        &self.sectors
+    }
+
+    pub fn sector(&self, id: usize) -> Option<&Sector> {
+        self.sectors.get(id)
+    }
+
+    pub fn set_owner(&mut self, id: usize, owner: Option<u32>) {
+        if let Some(sector) = self.sectors.get_mut(id) {
+            sector.owner = owner;
+            self.layout_version = self.layout_version.wrapping_add(1);
+        }
+    }
+
+    /// Changes whenever sector boundaries or owners change, but not on membership updates
+    pub fn layout_version(&self) -> u32 {
+        self.layout_version
+    }
+
+    /// Returns the sector containing a point on the galactic plane, if it's inside the galaxy
+    pub fn sector_at(&self, p: Vec2) -> Option<usize> {
+        if p.length() > self.galaxy_radius {
+            return None;
+        }
+        match self.mode? {
+            PartitionMode::Polar { rings, slices } if rings == 0 || slices == 0 => None,
+            PartitionMode::Polar { rings, slices } => {
+                let ring = ((p.length() / self.galaxy_radius * rings as f32) as u32).min(rings - 1);
+                let angle = f32::atan2(p.y, p.x).rem_euclid(2.0 * PI);
+                let slice = ((angle / (2.0 * PI) * slices as f32) as u32).min(slices - 1);
+                Some((ring * slices + slice) as usize)
+            }
+            PartitionMode::Voronoi { .. } => self
+                .seed_positions
+                .iter()
+                .enumerate()
+                .min_by(|a, b| a.1.distance_squared(p).total_cmp(&b.1.distance_squared(p)))
+                .map(|(i, _)| i),
+        }
+    }
+
+    fn rebuild_sectors(&mut self, config: &TerritoryConfig, galaxy_radius: f32) {
+        self.galaxy_radius = galaxy_radius;
+        self.mode = Some(config.mode);
+        self.layout_version = self.layout_version.wrapping_add(1);
+
+        let boundaries = match config.mode {
+            PartitionMode::Polar { rings, slices } => {
+                polar_boundaries(rings, slices, galaxy_radius)
+            }
+            PartitionMode::Voronoi { .. } => {
+                voronoi_boundaries(&self.seed_positions, galaxy_radius)
+            }
+        };
+
+        // Keep faction ownership across rebuilds as long as the layout is the same size
+        let owners: Vec<Option<u32>> = self.sectors.iter().map(|s| s.owner).collect();
+        let keep_owners = owners.len() == boundaries.len();
+
+        self.sectors = boundaries
+            .into_iter()
+            .enumerate()
+            .map(|(id, boundary)| Sector {
+                id,
+                area: polygon_area(&boundary),
+                boundary,
+                members: Vec::new(),
+                owner: if keep_owners { owners[id] } else { None },
+            })
+            .collect();
+    }
+}
+
+fn polar_boundaries(rings: u32, slices: u32, galaxy_radius: f32) -> Vec<Vec<Vec2>> {
+    let arc_segments = (RIM_SEGMENTS / slices.max(1) as usize).max(2);
+    let mut boundaries = Vec::new();
+
+    for ring in 0..rings {
+        let r0 = ring as f32 / rings as f32 * galaxy_radius;
+        let r1 = (ring + 1) as f32 / rings as f32 * galaxy_radius;
+        for slice in 0..slices {
+            let a0 = slice as f32 / slices as f32 * 2.0 * PI;
+            let a1 = (slice + 1) as f32 / slices as f32 * 2.0 * PI;
+            let arc = |r: f32, i: usize| {
+                let a = f32::lerp(a0, a1, i as f32 / arc_segments as f32);
+                vec2(a.cos(), a.sin()) * r
+            };
+
+            let mut boundary: Vec<Vec2> = (0..=arc_segments).map(|i| arc(r1, i)).collect();
+            if ring == 0 {
+                boundary.push(Vec2::ZERO);
+            } else {
+                boundary.extend((0..=arc_segments).rev().map(|i| arc(r0, i)));
+            }
+            boundaries.push(boundary);
+        }
+    }
+    boundaries
+}
+
+/// Each cell is the galaxy rim clipped by the bisector half-planes of every other seed
+fn voronoi_boundaries(seeds: &[Vec2], galaxy_radius: f32) -> Vec<Vec<Vec2>> {
+    let rim: Vec<Vec2> = (0..RIM_SEGMENTS)
+        .map(|i| {
+            let a = i as f32 / RIM_SEGMENTS as f32 * 2.0 * PI;
+            vec2(a.cos(), a.sin()) * galaxy_radius
+        })
+        .collect();
+
+    seeds
+        .iter()
+        .enumerate()
+        .map(|(i, seed)| {
+            let mut cell = rim.clone();
+            for (j, other) in seeds.iter().enumerate() {
+                if i == j || seed == other {
+                    continue;
+                }
+                let normal = *other - *seed;
+                let midpoint = (*seed + *other) * 0.5;
+                cell = clip_half_plane(&cell, midpoint, normal);
+            }
+            cell
+        })
+        .collect()
+}
+
+/// Sutherland-Hodgman clip, keeps the side of the line facing away from `normal`
+fn clip_half_plane(polygon: &[Vec2], point: Vec2, normal: Vec2) -> Vec<Vec2> {
+    let side = |p: Vec2| (p - point).dot(normal);
+    let mut out = Vec::with_capacity(polygon.len());
+
+    for i in 0..polygon.len() {
+        let a = polygon[i];
+        let b = polygon[(i + 1) % polygon.len()];
+        let (sa, sb) = (side(a), side(b));
+        if sa <= 0.0 {
+            out.push(a);
+        }
+        if (sa <= 0.0) != (sb <= 0.0) {
+            out.push(a.lerp(b, sa / (sa - sb)));
+        }
+    }
+    out
+}
+
+fn polygon_area(polygon: &[Vec2]) -> f32 {
+    let mut sum = 0.0;
+    for i in 0..polygon.len() {
+        sum += polygon[i].perp_dot(polygon[(i + 1) % polygon.len()]);
+    }
+    (sum * 0.5).abs()
+}
+
+/// Rebuilds sector layout when the config changes, and star membership while stars are being spawned
+/// Territories partition the primary galaxy, in its local space
+fn update_territories(
+    config: Res<TerritoryConfig>,
+    galaxy: Query<(
+        &GalaxyConfig,
+        &StarCount,
+        &StarCatalogue,
+        Ref<PrimaryGalaxy>,
+    )>,
+    mut territories: ResMut<Territories>,
+) {
+    let Ok((galaxy_config, star_count, catalogue, primary)) = galaxy.single() else {
+        return;
+    };
+    let mut layout_changed = config.is_changed()
+        || primary.is_added()
+        || territories.generation != galaxy_config.generation;
+    // Stars moved by the merger keep the membership they had when placed
+    if !layout_changed && territories.placed_stars == catalogue.len() {
+        return;
+    }
+    let first_new = territories.placed_stars;
+    territories.generation = galaxy_config.generation;
+    territories.placed_stars = catalogue.len();
+
+    if let PartitionMode::Voronoi { seeds } = config.mode {
+        let seed_indices: Vec<u32> = if config.seed_stars.is_empty() {
+            let count = star_count.count.max(1) as u32;
+            (0..seeds).map(|i| i * count / seeds.max(1)).collect()
+        } else {
+            config.seed_stars.clone()
+        };
+        let seed_positions: Vec<Vec2> = seed_indices
+            .iter()
+            .filter_map(|index| catalogue.position(*index).map(|position| position.xz()))
+            .collect();
+
+        if seed_positions != territories.seed_positions {
+            territories.seed_positions = seed_positions;
+            layout_changed = true;
+        }
+    }
+    if layout_changed {
+        territories.rebuild_sectors(&config, galaxy_config.radius);
+    }
+
+    // Only the stars placed since last time need a sector unless the sectors changed
+    let first_new = if layout_changed {
+        for sector in territories.sectors.iter_mut() {
+            sector.members.clear();
+        }
+        0
+    } else {
+        first_new
+    };
+    for (index, position) in catalogue.positions.iter().enumerate().skip(first_new) {
+        if let Some(id) = territories.sector_at(position.xz()) {
+            territories.sectors[id].members.push(index as u32);
+        }
+    }
+}
@@ -0,0 +1,151 @@
+use bevy::prelude::*;
+
+/// False colour views of the volume, replacing the normal raymarch
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub enum VisualizationMode {
+    /// Normal rendering
+    #[default]
+    Off,
+    /// Peak density of each component along the view ray
+    DiskDensity,
+    DustDensity,
+    BulgeDensity,
+    /// Peak density of the distribution the stars are spawned from
+    StarDensity,
+    /// Disk and bulge density integrated along the view ray
+    ColumnDensity,
+    /// Optical depth of the dust in front of the far side of the volume, in the B band
+    DustOpticalDepth,
+    /// Disk winding angle in turns, weighted by the disk density along the view ray
+    WindingAngle,
+    /// Fraction of the raymarch steps taken before the dust lets less than 1% of the light through
+    RaymarchCost,
+}
+
+impl VisualizationMode {
+    pub const ALL: [Self; 9] = [
+        Self::Off,
+        Self::DiskDensity,
+        Self::DustDensity,
+        Self::BulgeDensity,
+        Self::StarDensity,
+        Self::ColumnDensity,
+        Self::DustOpticalDepth,
+        Self::WindingAngle,
+        Self::RaymarchCost,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Off => "Off",
+            Self::DiskDensity => "Disk Density",
+            Self::DustDensity => "Dust Density",
+            Self::BulgeDensity => "Bulge Density",
+            Self::StarDensity => "Star Density",
+            Self::ColumnDensity => "Column Density",
+            Self::DustOpticalDepth => "Dust Optical Depth",
+            Self::WindingAngle => "Winding Angle",
+            Self::RaymarchCost => "Raymarch Cost",
+        }
+    }
+
+    /// Matching the `VIS_` constants in intensity_shared.wgsl
+    pub fn shader_id(&self) -> f32 {
+        match self {
+            Self::Off | Self::DiskDensity => 0.0,
+            Self::DustDensity => 1.0,
+            Self::BulgeDensity => 2.0,
+            Self::StarDensity => 3.0,
+            Self::ColumnDensity => 4.0,
+            Self::DustOpticalDepth => 5.0,
+            Self::WindingAngle => 6.0,
+            Self::RaymarchCost => 7.0,
+        }
+    }
+
+    /// Value mapped to the top of the colormap, roughly the maximum of the default galaxy
+    pub fn default_range(&self) -> f32 {
+        match self {
+            Self::Off | Self::DiskDensity | Self::StarDensity | Self::RaymarchCost => 1.0,
+            Self::DustDensity => 0.05,
+            Self::BulgeDensity => 50.0,
+            Self::ColumnDensity => 200.0,
+            Self::DustOpticalDepth => 5.0,
+            Self::WindingAngle => 2.0,
+        }
+    }
+}
+
+/// Perceptually uniform colormaps, as polynomial fits to the matplotlib originals
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub enum Colormap {
+    #[default]
+    Viridis,
+    Inferno,
+    Magma,
+    Plasma,
+}
+
+impl Colormap {
+    pub const ALL: [Self; 4] = [Self::Viridis, Self::Inferno, Self::Magma, Self::Plasma];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Viridis => "Viridis",
+            Self::Inferno => "Inferno",
+            Self::Magma => "Magma",
+            Self::Plasma => "Plasma",
+        }
+    }
+
+    /// Sixth order polynomial in sRGB, lowest order first
+    pub fn coefficients(&self) -> [Vec3; 7] {
+        match self {
+            Self::Viridis => [
+                vec3(0.277_727_3, 0.005_407_344_5, 0.334_099_8),
+                vec3(0.105_093_04, 1.404_613_5, 1.384_590_1),
+                vec3(-0.330_861_8, 0.214_847_56, 0.095_095_16),
+                vec3(-4.634_230_6, -5.799_101, -19.332_441),
+                vec3(6.228_27, 14.179_933, 56.690_55),
+                vec3(4.776_385, -13.745_145, -65.353_03),
+                vec3(-5.435_456, 4.645_852_6, 26.312_435),
+            ],
+            Self::Inferno => [
+                vec3(0.000_218_940_37, 0.001_651_004_6, -0.019_480_899),
+                vec3(0.106_513_42, 0.563_956_4, 3.932_712_4),
+                vec3(11.602_493, -3.972_854, -15.942_394),
+                vec3(-41.703_995, 17.436_4, 44.354_145),
+                vec3(77.162_94, -33.402_36, -81.807_31),
+                vec3(-71.319_43, 32.626_064, 73.209_52),
+                vec3(25.131_126, -12.242_669, -23.070_325),
+            ],
+            Self::Magma => [
+                vec3(-0.002_136_485, -0.000_749_655_05, -0.005_386_128),
+                vec3(0.251_660_54, 0.677_523_2, 2.494_026_6),
+                vec3(8.353_717, -3.577_719_5, 0.314_467_9),
+                vec3(-27.668_733, 14.264_731, -13.649_213),
+                vec3(52.176_14, -27.943_606, 12.944_169),
+                vec3(-50.768_524, 29.046_583, 4.234_153),
+                vec3(18.655_705, -11.489_773, -5.601_961_5),
+            ],
+            Self::Plasma => [
+                vec3(0.058_732_344, 0.023_336_709, 0.543_340_2),
+                vec3(2.176_514_6, 0.238_383_42, 0.753_960_4),
+                vec3(-2.689_460_5, -7.455_851, 3.110_8),
+                vec3(6.130_348, 42.346_188, -28.518_854),
+                vec3(-11.107_436, -82.666_31, 60.139_847),
+                vec3(10.023_066, 71.413_62, -54.072_186),
+                vec3(-3.658_713_8, -22.931_534, 18.191_908),
+            ],
+        }
+    }
+
+    /// sRGB colour at `t` in 0..1, the same as `colormap` in intensity_shared.wgsl before it goes linear
+    pub fn sample(&self, t: f32) -> Vec3 {
+        self.coefficients()
+            .iter()
+            .rev()
+            .fold(Vec3::ZERO, |acc, c| acc * t + *c)
+            .clamp(Vec3::ZERO, Vec3::ONE)
+    }
+}
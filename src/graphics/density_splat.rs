@@ -0,0 +1,287 @@
+use super::volume_render_layers;
+use crate::prelude::*;
+use crate::ui::CameraMain;
+use bevy::{
+    pbr::{MaterialPipeline, MaterialPipelineKey},
+    prelude::*,
+    reflect::TypePath,
+    render::{
+        mesh::MeshVertexBufferLayoutRef,
+        render_asset::RenderAssetUsages,
+        render_resource::{
+            AsBindGroup, Extent3d, Face, RenderPipelineDescriptor, ShaderRef, ShaderType,
+            SpecializedMeshPipelineError, TextureDimension, TextureFormat,
+        },
+        view::NoFrustumCulling,
+    },
+};
+use rayon::prelude::*;
+
+const SHADER_ASSET_PATH: &str = "shaders/density_splat.wgsl";
+
+/// Volume rendering of the merger simulation's stars
+///
+/// The analytic volume is evaluated from the galaxy config and can't follow stars flung into tidal tails,
+/// so while galaxies are merging their stars are splatted into a 3D density grid that is raymarched instead.
+pub struct DensitySplatPlugin;
+
+impl Plugin for DensitySplatPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_plugins(MaterialPlugin::<DensitySplatMaterial>::default())
+            .insert_resource(DensitySplatConfig::default())
+            .add_systems(Startup, setup_density_splat)
+            .add_systems(PostUpdate, update_density_splat);
+    }
+}
+
+#[derive(Resource, Clone, PartialEq)]
+pub struct DensitySplatConfig {
+    pub enabled: bool,
+    /// Grid cells along each axis
+    pub resolution: u32,
+    pub intensity: f32,
+    pub raymarch_steps: u32,
+}
+
+impl Default for DensitySplatConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            resolution: 96,
+            intensity: 10.0,
+            raymarch_steps: 96,
+        }
+    }
+}
+
+#[derive(ShaderType, Clone, Copy, Debug, Default, PartialEq)]
+struct DensitySplatParams {
+    bounds_min: Vec4,
+    bounds_size: Vec4,
+    /// rgb = disk colour of the merging galaxies
+    disk_color: Vec4,
+    intensity: f32,
+    raymarch_steps: f32,
+    inverse_cell_volume: f32,
+}
+
+#[derive(Asset, TypePath, AsBindGroup, Debug, Clone, Default)]
+struct DensitySplatMaterial {
+    #[uniform(0)]
+    params: DensitySplatParams,
+    #[texture(1, dimension = "3d")]
+    #[sampler(2)]
+    density: Option<Handle<Image>>,
+}
+
+impl Material for DensitySplatMaterial {
+    fn vertex_shader() -> ShaderRef {
+        SHADER_ASSET_PATH.into()
+    }
+    fn fragment_shader() -> ShaderRef {
+        SHADER_ASSET_PATH.into()
+    }
+    fn alpha_mode(&self) -> AlphaMode {
+        AlphaMode::Add
+    }
+
+    // Back faces, so the volume is still drawn with the camera inside it
+    fn specialize(
+        _pipeline: &MaterialPipeline<Self>,
+        descriptor: &mut RenderPipelineDescriptor,
+        _layout: &MeshVertexBufferLayoutRef,
+        _key: MaterialPipelineKey<Self>,
+    ) -> Result<(), SpecializedMeshPipelineError> {
+        descriptor.primitive.cull_mode = Some(Face::Front);
+        Ok(())
+    }
+}
+
+#[derive(Component)]
+struct DensitySplat;
+
+fn setup_density_splat(
+    mut commands: Commands,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut materials: ResMut<Assets<DensitySplatMaterial>>,
+) {
+    commands.spawn((
+        Mesh3d(meshes.add(Cuboid::new(1.0, 1.0, 1.0))),
+        MeshMaterial3d(materials.add(DensitySplatMaterial::default())),
+        Transform::IDENTITY,
+        Visibility::Hidden,
+        DensitySplat,
+        NoFrustumCulling,
+    ));
+}
+
+/// Cloud-in-cell splat, each star is shared between the 8 nearest cells so the grid stays smooth as it moves
+/// Cells hold star counts, the shader divides by the cell volume
+fn splat_density(
+    positions: &[Vec3],
+    bounds_min: Vec3,
+    bounds_size: Vec3,
+    resolution: u32,
+) -> Vec<f32> {
+    let n = resolution as usize;
+
+    // Cell centres sit at half integers
+    let cells: Vec<Vec3> = positions
+        .par_iter()
+        .map(|position| (*position - bounds_min) / bounds_size * resolution as f32 - 0.5)
+        .collect();
+    // Bucketed by the lower z cell (offset by one, it can be -1), so each slice only visits the stars touching it
+    let mut slabs: Vec<Vec<Vec3>> = vec![Vec::new(); n + 1];
+    for p in cells {
+        let slab = p.z.floor() as i32 + 1;
+        if (0..=n as i32).contains(&slab) {
+            slabs[slab as usize].push(p);
+        }
+    }
+
+    let mut grid = vec![0.0f32; n * n * n];
+    grid.par_chunks_mut(n * n)
+        .enumerate()
+        .for_each(|(z, slice)| {
+            // Stars whose lower corner is in the slice below reach up into this one, and vice versa
+            for (slab, upper) in [(z, true), (z + 1, false)] {
+                for p in &slabs[slab] {
+                    let base = p.floor();
+                    let f = *p - base;
+                    let base = base.as_ivec3();
+                    let wz = if upper { f.z } else { 1.0 - f.z };
+                    for corner in 0..4 {
+                        let offset = ivec2(corner & 1, corner >> 1);
+                        let cell = base.xy() + offset;
+                        if cell.min_element() < 0 || cell.max_element() >= resolution as i32 {
+                            continue;
+                        }
+                        let o = offset.as_vec2();
+                        let w = o * f.xy() + (Vec2::ONE - o) * (Vec2::ONE - f.xy());
+                        slice[cell.y as usize * n + cell.x as usize] += w.element_product() * wz;
+                    }
+                }
+            }
+        });
+    grid
+}
+
+#[allow(clippy::too_many_arguments)]
+fn update_density_splat(
+    simulation: Res<MergerSimulation>,
+    config: Res<DensitySplatConfig>,
+    galaxy_render_settings: Res<GalaxyRenderConfig>,
+    galaxies: Query<&GalaxyConfig, With<Merging>>,
+    camera: Query<&CameraMain>,
+    mut commands: Commands,
+    mut splat: Query<
+        (
+            Entity,
+            &mut Transform,
+            &mut Visibility,
+            &MeshMaterial3d<DensitySplatMaterial>,
+        ),
+        With<DensitySplat>,
+    >,
+    mut materials: ResMut<Assets<DensitySplatMaterial>>,
+    mut images: ResMut<Assets<Image>>,
+    mut splatted_time: Local<Option<f32>>,
+) {
+    let Ok((entity, mut transform, mut visibility, material)) = splat.single_mut() else {
+        return;
+    };
+    // Drawn alongside the analytic volume it replaces
+    if galaxy_render_settings.is_changed() {
+        commands
+            .entity(entity)
+            .insert(volume_render_layers(&galaxy_render_settings));
+    }
+    let map_view = camera
+        .single()
+        .is_ok_and(|camera| camera.map_blend() >= 1.0);
+    let shown = config.enabled && simulation.is_active() && !map_view;
+    visibility.set_if_neq(if shown {
+        Visibility::Inherited
+    } else {
+        Visibility::Hidden
+    });
+    if !shown {
+        *splatted_time = None;
+        return;
+    }
+    // All the merging stars share one grid, so their disk colours are averaged
+    let merging = galaxies.iter().count().max(1) as f32;
+    let disk_color = (galaxies
+        .iter()
+        .map(|galaxy| galaxy.disk_params.color.inner)
+        .sum::<Vec3>()
+        / merging)
+        .extend(0.0);
+    // Stars only move when the simulation steps, which is never while it's paused
+    if *splatted_time == Some(simulation.time()) && !config.is_changed() {
+        let recoloured = materials
+            .get(&material.0)
+            .is_some_and(|mat| mat.params.disk_color != disk_color);
+        if recoloured {
+            if let Some(mat) = materials.get_mut(&material.0) {
+                mat.params.disk_color = disk_color;
+            }
+        }
+        return;
+    }
+    *splatted_time = Some(simulation.time());
+
+    let positions: Vec<Vec3> = simulation.star_positions().collect();
+    let Some(first) = positions.first() else {
+        return;
+    };
+    let (min, max) = positions
+        .iter()
+        .fold((*first, *first), |(min, max), p| (min.min(*p), max.max(*p)));
+    // A little margin so the outermost stars aren't cut off by the box
+    let centre = (min + max) * 0.5;
+    let size = ((max - min) * 1.1).max(Vec3::splat(1.0));
+    let bounds_min = centre - size * 0.5;
+
+    let resolution = config.resolution.clamp(8, 256);
+    let grid = splat_density(&positions, bounds_min, size, resolution);
+    // 32 bit float textures aren't filterable everywhere
+    let data: Vec<u8> = grid
+        .iter()
+        .flat_map(|count| (*count as f16).to_le_bytes())
+        .collect();
+
+    let image = Image::new(
+        Extent3d {
+            width: resolution,
+            height: resolution,
+            depth_or_array_layers: resolution,
+        },
+        TextureDimension::D3,
+        data,
+        TextureFormat::R16Float,
+        RenderAssetUsages::RENDER_WORLD,
+    );
+
+    *transform = Transform::from_translation(centre).with_scale(size);
+
+    let Some(mat) = materials.get_mut(&material.0) else {
+        return;
+    };
+    mat.params = DensitySplatParams {
+        bounds_min: bounds_min.extend(0.0),
+        bounds_size: size.extend(0.0),
+        disk_color,
+        intensity: config.intensity,
+        raymarch_steps: config.raymarch_steps as f32,
+        inverse_cell_volume: 1.0 / (size / resolution as f32).element_product(),
+    };
+    match mat
+        .density
+        .as_ref()
+        .and_then(|handle| images.get_mut(handle))
+    {
+        Some(existing) => *existing = image,
+        None => mat.density = Some(images.add(image)),
+    }
+}
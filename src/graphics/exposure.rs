@@ -0,0 +1,126 @@
+use super::GalaxyCamera;
+use bevy::{
+    core_pipeline::{
+        auto_exposure::{AutoExposure, AutoExposureCompensationCurve, AutoExposurePlugin},
+        tonemapping::Tonemapping,
+    },
+    math::cubic_splines::LinearSpline,
+    prelude::*,
+    render::camera::RenderTarget,
+};
+
+/// Exposure and tonemapping of every [`GalaxyCamera`]
+///
+/// Auto exposure meters a luminance histogram of the composited frame, so the exposure follows the camera from
+/// the whole galaxy down to a dim patch of outer disk. `GalaxyRenderConfig::exposure` stays as a fixed gain on the
+/// volume on top of it.
+/// While an offline capture renders the camera to an image the metered exposure is held, so poster tiles,
+/// skybox faces and recorded frames all share the exposure from when the capture started.
+pub struct ExposurePlugin;
+
+impl Plugin for ExposurePlugin {
+    fn build(&self, app: &mut App) {
+        if !app.is_plugin_added::<AutoExposurePlugin>() {
+            app.add_plugins(AutoExposurePlugin);
+        }
+        app.insert_resource(ExposureConfig::default())
+            .add_systems(Update, apply_exposure);
+    }
+}
+
+#[derive(Resource, Clone, PartialEq)]
+pub struct ExposureConfig {
+    /// Needs HDR, which is kept on while this is enabled, so the H toggle does nothing
+    pub auto_exposure: bool,
+    /// In EV per second
+    pub adaptation_speed: f32,
+    /// Added to the metered exposure, in EV, positive brightens
+    pub compensation: f32,
+    /// Range the metered exposure is clamped to, in EV
+    pub min_ev: f32,
+    pub max_ev: f32,
+    pub tonemapping: Tonemapping,
+}
+
+impl Default for ExposureConfig {
+    fn default() -> Self {
+        Self {
+            auto_exposure: true,
+            adaptation_speed: 2.0,
+            compensation: 0.0,
+            min_ev: -10.0,
+            max_ev: 6.0,
+            // Holds on to hue in the saturated bulge core and rolls off slowly enough to keep the faint disk
+            tonemapping: Tonemapping::AgX,
+        }
+    }
+}
+
+/// Tonemappers suited to the range between the bulge core and the outer disk, with display names
+pub const TONEMAPPERS: [(Tonemapping, &str); 6] = [
+    (Tonemapping::AgX, "AgX"),
+    (Tonemapping::TonyMcMapface, "Tony McMapface"),
+    (Tonemapping::BlenderFilmic, "Blender Filmic"),
+    (Tonemapping::AcesFitted, "ACES"),
+    (Tonemapping::ReinhardLuminance, "Reinhard (Luminance)"),
+    (Tonemapping::None, "None"),
+];
+
+#[allow(clippy::type_complexity)]
+fn apply_exposure(
+    mut commands: Commands,
+    config: Res<ExposureConfig>,
+    mut cameras: Query<
+        (Entity, &mut Camera, &mut Tonemapping, Option<&AutoExposure>),
+        With<GalaxyCamera>,
+    >,
+    mut curves: ResMut<Assets<AutoExposureCompensationCurve>>,
+    mut curve: Local<Option<Handle<AutoExposureCompensationCurve>>>,
+) {
+    if config.is_changed() {
+        // Flat, the same compensation whatever the metered exposure
+        let compensation = config.compensation.clamp(-8.0, 8.0);
+        let max_ev = config.max_ev.max(config.min_ev + 1.0);
+        let flat = AutoExposureCompensationCurve::from_curve(LinearSpline::new([
+            vec2(config.min_ev, compensation),
+            vec2(max_ev, compensation),
+        ]))
+        .ok();
+        *curve = flat.map(|flat| curves.add(flat));
+    }
+
+    for (entity, mut camera, mut tonemapping, auto_exposure) in &mut cameras {
+        if config.is_changed() || camera.is_added() {
+            tonemapping.set_if_neq(config.tonemapping);
+        }
+
+        if !config.auto_exposure {
+            if auto_exposure.is_some() {
+                commands.entity(entity).remove::<AutoExposure>();
+            }
+            continue;
+        }
+        // The histogram is taken from the HDR target
+        if !camera.hdr {
+            camera.hdr = true;
+        }
+        // Captures render to an image, the exposure must not drift between their tiles or frames
+        let capturing = !matches!(camera.target, RenderTarget::Window(_));
+        let speed = if capturing {
+            0.0
+        } else {
+            config.adaptation_speed
+        };
+        if config.is_changed() || auto_exposure.is_none_or(|auto| auto.speed_brighten != speed) {
+            commands.entity(entity).insert(AutoExposure {
+                range: config.min_ev..=config.max_ev.max(config.min_ev + 1.0),
+                // Most of the frame is empty sky, so the darker half of the histogram is ignored
+                filter: 0.5..=0.98,
+                speed_brighten: speed,
+                speed_darken: speed,
+                compensation_curve: curve.clone().unwrap_or_default(),
+                ..default()
+            });
+        }
+    }
+}
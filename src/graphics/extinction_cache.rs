@@ -1,7 +1,7 @@
-use super::{galaxy_texture::GalaxyTexture, shader_types::*, StarInstanceMarker};
+use super::{galaxy_texture::GalaxyTexture, shader_types::*, GalaxyCamera};
 use crate::prelude::*;
-use crate::ui::CameraMain;
 use bevy::{
+    math::Affine3A,
     prelude::*,
     render::{
         extract_resource::{ExtractResource, ExtractResourcePlugin},
@@ -11,13 +11,17 @@ use bevy::{
         renderer::{RenderContext, RenderDevice, RenderQueue},
         storage::{GpuShaderStorageBuffer, ShaderStorageBuffer},
         texture::GpuImage,
-        Render, RenderApp, RenderSet,
+        view::ExtractedView,
+        Extract, ExtractSchedule, Render, RenderApp, RenderSet,
     },
 };
-use std::borrow::Cow;
+use rayon::prelude::*;
+use std::{borrow::Cow, ops::Range};
 
 const SHADER_ASSET_PATH: &str = "shaders/extinction_cache_compute.wgsl";
 const WORKGROUP_SIZE: u32 = 64;
+/// Limit on the workgroups of a dispatch along each axis
+const MAX_WORKGROUPS_PER_DIMENSION: u32 = 65535;
 ///
 /// This is a strategy to complement point-rendering/PSF rendering (ie. stars)
 ///
@@ -31,7 +35,7 @@ const WORKGROUP_SIZE: u32 = 64;
 /// ---- > I think this one is particularly neat because even a noticeably slow response will look more like a stylisation decision than a real visual flaw
 ///
 /// API
-/// -> ExtinctionCache resource holds the output texture (I guess 2 textures that alternate by frame)
+/// -> ExtinctionCache component on each galaxy holds the output buffer (I guess 2 textures that alternate by frame)
 /// -> VolumeStar or VolumeStarList are marker components
 /// --> Providing the texture lookup offsets:
 /// --- Could either be done by adding a new component, or using an Option/marker value in the source component
@@ -42,18 +46,24 @@ struct ExtinctionCacheLabel;
 
 impl Plugin for ExtinctionCachePlugin {
     fn build(&self, app: &mut App) {
-        app.add_plugins(ExtractResourcePlugin::<ExtinctionCache>::default())
-            .add_systems(Startup, init_cache_resource)
-            .add_systems(Update, update_positions);
+        app.register_required_components::<GalaxyConfig, ExtinctionCache>()
+            .insert_resource(ExtinctionOrigin::default())
+            .add_plugins(ExtractResourcePlugin::<ExtinctionOrigin>::default())
+            .add_systems(Update, (init_cache_buffers, update_positions).chain());
 
         let render_app = app.sub_app_mut(RenderApp);
         render_app
+            .init_resource::<ExtractedExtinctionCaches>()
             .init_resource::<ExtinctionCacheGalaxyUniforms>()
+            .add_systems(ExtractSchedule, extract_extinction_caches)
             .add_systems(
                 Render,
                 prepare_bind_group.in_set(RenderSet::PrepareBindGroups),
             )
-            .add_systems(Render, prepare_uniforms.in_set(RenderSet::PrepareResources));
+            .add_systems(
+                Render,
+                (prepare_uniforms, write_input_ranges).in_set(RenderSet::PrepareResources),
+            );
 
         let mut render_graph = render_app.world_mut().resource_mut::<RenderGraph>();
         render_graph.add_node(ExtinctionCacheLabel, ExtinctionCacheNode::default());
@@ -65,65 +75,179 @@ impl Plugin for ExtinctionCachePlugin {
     }
 }
 
-#[derive(Resource, Default, Clone, ExtractResource)]
+/// Per galaxy star extinction, on the galaxy entity
+/// Star positions are in the galaxy's local space and only the galaxy's own dust is considered
+#[derive(Component, Default, Clone)]
 pub struct ExtinctionCache {
     pub output_buffer: Handle<ShaderStorageBuffer>,
     pub required_size: usize,
+    /// Extra points evaluated for a white source, stored in the output after the stars (from `required_size`)
+    pub extra_positions: Vec<Vec3>,
+    /// Star positions in the galaxy's local space followed by the extra points, w is 0 for stars not placed yet
+    pub positions_buffer: Handle<ShaderStorageBuffer>,
     positions: Vec<Vec4>,
     colours: Vec<Vec4>,
-    positions_buffer: Handle<ShaderStorageBuffer>,
     colours_buffer: Handle<ShaderStorageBuffer>,
     size: usize,
+    /// Stars in the input buffers, and the catalogue moves they are up to date with
+    uploaded_stars: usize,
+    uploaded_moves: u32,
+    /// Part of the input buffers that changed this frame, written to the GPU without reuploading the rest
+    written: Option<Range<usize>>,
 }
 
-fn update_positions(
-    mut extinction_cache: ResMut<ExtinctionCache>,
+/// World space point the extinction of every galaxy is evaluated toward, the [`GalaxyCamera`] when `None`
+#[derive(Resource, Default, Clone, ExtractResource)]
+pub struct ExtinctionOrigin(pub Option<Vec3>);
+
+fn init_cache_buffers(
+    mut caches: Query<&mut ExtinctionCache, Added<ExtinctionCache>>,
     mut buffers: ResMut<Assets<ShaderStorageBuffer>>,
-    query: Query<(&Transform, &Star), Added<StarInstanceMarker>>,
 ) {
-    if extinction_cache.size != extinction_cache.required_size {
-        let size = extinction_cache.required_size;
-        extinction_cache.size = size;
-        extinction_cache.positions.resize(size, Vec4::ZERO);
-        extinction_cache.colours.resize(size, Vec4::ZERO);
-
-        if let Some(buffer) = buffers.get_mut(&extinction_cache.output_buffer) {
-            buffer.set_data(vec![Vec4::ZERO; size]);
-        }
+    for mut extinction_cache in &mut caches {
+        extinction_cache.output_buffer = buffers.add(ShaderStorageBuffer::from(Vec::<Vec4>::new()));
+        extinction_cache.positions_buffer =
+            buffers.add(ShaderStorageBuffer::from(Vec::<Vec4>::new()));
+        extinction_cache.colours_buffer =
+            buffers.add(ShaderStorageBuffer::from(Vec::<Vec4>::new()));
     }
+}
 
-    if query.is_empty() {
-        return;
-    }
+fn update_positions(
+    mut caches: Query<(&mut ExtinctionCache, &StarCount, Ref<StarCatalogue>)>,
+    mut buffers: ResMut<Assets<ShaderStorageBuffer>>,
+) {
+    for (mut extinction_cache, star_count, catalogue) in &mut caches {
+        // Already extracted last frame
+        extinction_cache.bypass_change_detection().written = None;
+        if extinction_cache.required_size != star_count.count {
+            extinction_cache.required_size = star_count.count;
+        }
+        // The star count or the extra points were changed elsewhere
+        let changed = extinction_cache.is_changed();
+        // Stars only change while they are spawned or something like the merger simulation drives them
+        if !changed && !catalogue.is_changed() {
+            continue;
+        }
+        let extinction_cache = &mut *extinction_cache;
+        let required_size = extinction_cache.required_size;
+        let size = required_size + extinction_cache.extra_positions.len();
+        let placed = catalogue.len().min(required_size);
+        // A respawn empties the catalogue, the old stars have to be cleared from the buffers too
+        let full = extinction_cache.size != size || placed < extinction_cache.uploaded_stars;
+        if full {
+            extinction_cache.size = size;
+            extinction_cache.uploaded_stars = 0;
+            // A zero w marks the stars that aren't placed yet, the renderer skips them
+            extinction_cache.positions.clear();
+            extinction_cache.positions.resize(size, Vec4::ZERO);
+            extinction_cache.colours.clear();
+            extinction_cache.colours.resize(size, Vec4::ZERO);
+
+            if let Some(buffer) = buffers.get_mut(&extinction_cache.output_buffer) {
+                buffer.set_data(vec![Vec4::ZERO; size]);
+            }
+        }
+        if full || changed {
+            for (i, position) in extinction_cache.extra_positions.iter().enumerate() {
+                extinction_cache.positions[required_size + i] = position.extend(1.0);
+                extinction_cache.colours[required_size + i] = Vec4::ONE;
+            }
+        }
 
-    for (transform, star) in &query {
-        extinction_cache.positions[star.index as usize] = transform.translation.extend(1.0);
-        extinction_cache.colours[star.index as usize] = star.color().extend(1.0);
+        // Every position is stale once the stars move, otherwise only the ones placed since last frame are new
+        let moved = catalogue.moves() != extinction_cache.uploaded_moves;
+        let first_new = extinction_cache.uploaded_stars;
+        let first_position = if moved { 0 } else { first_new };
+        extinction_cache.positions[first_position..placed]
+            .par_iter_mut()
+            .zip(&catalogue.positions[first_position..placed])
+            .for_each(|(position, star)| *position = star.extend(1.0));
+        extinction_cache.colours[first_new..placed]
+            .iter_mut()
+            .zip(&catalogue.colors[first_new..placed])
+            .for_each(|(colour, star)| *colour = star.extend(1.0));
+        extinction_cache.uploaded_stars = placed;
+        extinction_cache.uploaded_moves = catalogue.moves();
+
+        if full {
+            if let Some(buffer) = buffers.get_mut(&extinction_cache.colours_buffer) {
+                buffer.set_data(extinction_cache.colours.as_slice());
+            }
+        }
+        if full || moved {
+            if let Some(buffer) = buffers.get_mut(&extinction_cache.positions_buffer) {
+                buffer.set_data(extinction_cache.positions.as_slice());
+            }
+        }
+        if !full {
+            let end = if changed { size } else { placed };
+            extinction_cache.written = Some(first_new..end).filter(|range| !range.is_empty());
+        }
     }
+}
 
-    if let Some(buffer) = buffers.get_mut(&extinction_cache.positions_buffer) {
-        buffer.set_data(extinction_cache.positions.as_slice());
-    }
-    if let Some(buffer) = buffers.get_mut(&extinction_cache.colours_buffer) {
-        buffer.set_data(extinction_cache.colours.as_slice());
-    }
+/// Everything the compute pass needs from one galaxy
+struct ExtractedExtinctionCache {
+    output_buffer: Handle<ShaderStorageBuffer>,
+    written: Option<ExtractedWrite>,
+    positions_buffer: Handle<ShaderStorageBuffer>,
+    colours_buffer: Handle<ShaderStorageBuffer>,
+    size: usize,
+    texture: GalaxyTexture,
+    galaxy_params: GalaxyParams,
+    bulge_params: BulgeParams,
+    disk_params: ComponentParams,
+    dust_params: ComponentParams,
+    local_from_world: Affine3A,
 }
 
-fn init_cache_resource(mut commands: Commands, mut buffers: ResMut<Assets<ShaderStorageBuffer>>) {
-    let size = 0;
-    commands.insert_resource(ExtinctionCache {
-        output_buffer: buffers.add(ShaderStorageBuffer::from(vec![Vec4::ZERO; size])),
-        positions: vec![Vec4::ZERO; size],
-        colours: vec![Vec4::ZERO; size],
-        positions_buffer: buffers.add(ShaderStorageBuffer::from(vec![Vec4::ZERO; size])),
-        colours_buffer: buffers.add(ShaderStorageBuffer::from(vec![Vec4::ZERO; size])),
-        required_size: size,
-        size,
-    });
+/// Changed part of the input buffers, starting at `offset` bytes
+struct ExtractedWrite {
+    offset: u64,
+    positions: Vec<Vec4>,
+    colours: Vec<Vec4>,
 }
 
 #[derive(Resource, Default)]
-struct ExtinctionCacheGalaxyUniforms {
+struct ExtractedExtinctionCaches(Vec<ExtractedExtinctionCache>);
+
+fn extract_extinction_caches(
+    mut extracted: ResMut<ExtractedExtinctionCaches>,
+    galaxy_render_settings: Extract<Res<GalaxyRenderConfig>>,
+    galaxies: Extract<
+        Query<(
+            &ExtinctionCache,
+            &GalaxyConfig,
+            &GalaxyTexture,
+            &GlobalTransform,
+        )>,
+    >,
+) {
+    extracted.0.clear();
+    for (cache, galaxy_config, texture, transform) in &galaxies {
+        extracted.0.push(ExtractedExtinctionCache {
+            output_buffer: cache.output_buffer.clone(),
+            written: cache.written.clone().map(|range| ExtractedWrite {
+                offset: (range.start * size_of::<Vec4>()) as u64,
+                positions: cache.positions[range.clone()].to_vec(),
+                colours: cache.colours[range].to_vec(),
+            }),
+            positions_buffer: cache.positions_buffer.clone(),
+            colours_buffer: cache.colours_buffer.clone(),
+            size: cache.size,
+            texture: texture.clone(),
+            galaxy_params: GalaxyParams::read(galaxy_config, &galaxy_render_settings),
+            bulge_params: BulgeParams::read(galaxy_config),
+            disk_params: ComponentParams::read(&galaxy_config.disk_params),
+            dust_params: ComponentParams::read(&galaxy_config.dust_params),
+            local_from_world: transform.affine().inverse(),
+        });
+    }
+}
+
+#[derive(Default)]
+struct GalaxyUniforms {
     galaxy_params: UniformBuffer<GalaxyParams>,
     bulge_params: UniformBuffer<BulgeParams>,
     disk_params: UniformBuffer<ComponentParams>,
@@ -131,100 +255,146 @@ struct ExtinctionCacheGalaxyUniforms {
     camera_uniform: UniformBuffer<Vec4>,
 }
 
+/// One set of uniforms per galaxy, in the same order as [`ExtractedExtinctionCaches`]
+#[derive(Resource, Default)]
+struct ExtinctionCacheGalaxyUniforms(Vec<GalaxyUniforms>);
+
 fn prepare_uniforms(
     render_device: Res<RenderDevice>,
     render_queue: Res<RenderQueue>,
-    galaxy_config: Res<GalaxyConfig>,
-    galaxy_render_settings: Res<GalaxyRenderConfig>,
+    extracted: Res<ExtractedExtinctionCaches>,
     mut uniforms: ResMut<ExtinctionCacheGalaxyUniforms>,
-    camera: Query<&CameraMain>,
+    extinction_origin: Res<ExtinctionOrigin>,
+    camera: Query<&ExtractedView, With<GalaxyCamera>>,
 ) {
+    let origin = extinction_origin.0.or_else(|| {
+        camera
+            .single()
+            .ok()
+            .map(|view| view.world_from_view.translation())
+    });
+
     uniforms
-        .galaxy_params
-        .set(GalaxyParams::read(&galaxy_config, &galaxy_render_settings));
-    uniforms.bulge_params.set(BulgeParams::read(&galaxy_config));
-    uniforms
-        .disk_params
-        .set(ComponentParams::read(&galaxy_config.disk_params));
-    uniforms
-        .dust_params
-        .set(ComponentParams::read(&galaxy_config.dust_params));
+        .0
+        .resize_with(extracted.0.len(), GalaxyUniforms::default);
+    for (galaxy, uniforms) in extracted.0.iter().zip(uniforms.0.iter_mut()) {
+        uniforms.galaxy_params.set(galaxy.galaxy_params);
+        uniforms.bulge_params.set(galaxy.bulge_params);
+        uniforms.disk_params.set(galaxy.disk_params);
+        uniforms.dust_params.set(galaxy.dust_params);
+        if let Some(origin) = origin {
+            // The compute shader works in the galaxy's local space
+            let local_origin = galaxy.local_from_world.transform_point3(origin);
+            uniforms.camera_uniform.set(local_origin.extend(1.0));
+        }
 
-    if let Ok(camera) = camera.single() {
-        uniforms.camera_uniform.set(camera.translation.extend(1.0));
+        uniforms
+            .galaxy_params
+            .write_buffer(&render_device, &render_queue);
+        uniforms
+            .bulge_params
+            .write_buffer(&render_device, &render_queue);
+        uniforms
+            .disk_params
+            .write_buffer(&render_device, &render_queue);
+        uniforms
+            .dust_params
+            .write_buffer(&render_device, &render_queue);
+        uniforms
+            .camera_uniform
+            .write_buffer(&render_device, &render_queue);
     }
+}
 
-    uniforms
-        .galaxy_params
-        .write_buffer(&render_device, &render_queue);
-    uniforms
-        .bulge_params
-        .write_buffer(&render_device, &render_queue);
-    uniforms
-        .disk_params
-        .write_buffer(&render_device, &render_queue);
-    uniforms
-        .dust_params
-        .write_buffer(&render_device, &render_queue);
-    uniforms
-        .camera_uniform
-        .write_buffer(&render_device, &render_queue);
+/// Writes the changed part of the input buffers, after any whole buffer upload of the same frame
+fn write_input_ranges(
+    render_queue: Res<RenderQueue>,
+    extracted: Res<ExtractedExtinctionCaches>,
+    ssbos: Res<RenderAssets<GpuShaderStorageBuffer>>,
+) {
+    for galaxy in &extracted.0 {
+        let Some(written) = &galaxy.written else {
+            continue;
+        };
+        for (handle, data) in [
+            (&galaxy.positions_buffer, &written.positions),
+            (&galaxy.colours_buffer, &written.colours),
+        ] {
+            let bytes: &[u8] = bytemuck::cast_slice(data);
+            // Whole buffer uploads are prepared earlier in the frame, this only guards against one that failed
+            if let Some(buffer) = ssbos.get(handle) {
+                if written.offset + bytes.len() as u64 <= buffer.buffer.size() {
+                    render_queue.write_buffer(&buffer.buffer, written.offset, bytes);
+                }
+            }
+        }
+    }
 }
 
+/// Bind group and star count of each galaxy
 #[derive(Resource)]
-struct ExtinctionCacheBindGroups([BindGroup; 1]);
+struct ExtinctionCacheBindGroups(Vec<(BindGroup, u32)>);
 
 #[allow(clippy::too_many_arguments)]
 fn prepare_bind_group(
     mut commands: Commands,
     pipeline: Res<ExtinctionCachePipeline>,
     gpu_images: Res<RenderAssets<GpuImage>>,
-    galaxy_texture: Res<GalaxyTexture>,
-    cache_image: Res<ExtinctionCache>,
-    uniforms_buffer: Res<ExtinctionCacheGalaxyUniforms>,
+    extracted: Res<ExtractedExtinctionCaches>,
+    uniforms: Res<ExtinctionCacheGalaxyUniforms>,
     render_device: Res<RenderDevice>,
     ssbos: Res<RenderAssets<GpuShaderStorageBuffer>>,
 ) {
-    let input_positions = ssbos.get(&cache_image.positions_buffer).unwrap();
-    let input_colours = ssbos.get(&cache_image.colours_buffer).unwrap();
-    let output_buffer = ssbos.get(&cache_image.output_buffer).unwrap();
-
-    let galaxy_uniform = uniforms_buffer.galaxy_params.binding().unwrap();
-    let bulge_params = uniforms_buffer.bulge_params.binding().unwrap();
-    let disk_params = uniforms_buffer.disk_params.binding().unwrap();
-    let dust_params = uniforms_buffer.dust_params.binding().unwrap();
-    let camera_uniform = uniforms_buffer.camera_uniform.binding().unwrap();
-
-    let galaxy_view = galaxy_texture
-        .tex
-        .as_ref()
-        .and_then(|x| gpu_images.get(x))
-        .unwrap();
-    let lut_view = galaxy_texture
-        .luts
-        .as_ref()
-        .and_then(|x| gpu_images.get(x))
-        .unwrap();
-
-    let bind_group = render_device.create_bind_group(
-        "extinction_cache_bind_group",
-        &pipeline.bind_group_layout,
-        &BindGroupEntries::sequential((
-            camera_uniform,
-            output_buffer.buffer.as_entire_buffer_binding(),
-            input_positions.buffer.as_entire_buffer_binding(),
-            input_colours.buffer.as_entire_buffer_binding(),
-            galaxy_uniform,
-            bulge_params,
-            disk_params,
-            dust_params,
-            &galaxy_view.texture_view,
-            &galaxy_view.sampler,
-            &lut_view.texture_view,
-            &lut_view.sampler,
-        )),
-    );
-    commands.insert_resource(ExtinctionCacheBindGroups([bind_group]));
+    let mut bind_groups = vec![];
+    for (galaxy, uniforms) in extracted.0.iter().zip(uniforms.0.iter()) {
+        // Skip galaxies whose buffers or textures aren't on the GPU yet
+        let (
+            Some(input_positions),
+            Some(input_colours),
+            Some(output_buffer),
+            Some(galaxy_view),
+            Some(lut_view),
+        ) = (
+            ssbos.get(&galaxy.positions_buffer),
+            ssbos.get(&galaxy.colours_buffer),
+            ssbos.get(&galaxy.output_buffer),
+            galaxy.texture.tex.as_ref().and_then(|x| gpu_images.get(x)),
+            galaxy.texture.luts.as_ref().and_then(|x| gpu_images.get(x)),
+        )
+        else {
+            continue;
+        };
+        if galaxy.size == 0 {
+            continue;
+        }
+
+        let galaxy_uniform = uniforms.galaxy_params.binding().unwrap();
+        let bulge_params = uniforms.bulge_params.binding().unwrap();
+        let disk_params = uniforms.disk_params.binding().unwrap();
+        let dust_params = uniforms.dust_params.binding().unwrap();
+        let camera_uniform = uniforms.camera_uniform.binding().unwrap();
+
+        let bind_group = render_device.create_bind_group(
+            "extinction_cache_bind_group",
+            &pipeline.bind_group_layout,
+            &BindGroupEntries::sequential((
+                camera_uniform,
+                output_buffer.buffer.as_entire_buffer_binding(),
+                input_positions.buffer.as_entire_buffer_binding(),
+                input_colours.buffer.as_entire_buffer_binding(),
+                galaxy_uniform,
+                bulge_params,
+                disk_params,
+                dust_params,
+                &galaxy_view.texture_view,
+                &galaxy_view.sampler,
+                &lut_view.texture_view,
+                &lut_view.sampler,
+            )),
+        );
+        bind_groups.push((bind_group, galaxy.size as u32));
+    }
+    commands.insert_resource(ExtinctionCacheBindGroups(bind_groups));
 }
 
 #[derive(Resource)]
@@ -330,7 +500,6 @@ impl render_graph::Node for ExtinctionCacheNode {
         let bind_groups = &world.resource::<ExtinctionCacheBindGroups>().0;
         let pipeline_cache = world.resource::<PipelineCache>();
         let pipeline: &ExtinctionCachePipeline = world.resource::<ExtinctionCachePipeline>();
-        let size = world.resource::<ExtinctionCache>().size;
 
         let mut pass = render_context
             .command_encoder()
@@ -343,9 +512,14 @@ impl render_graph::Node for ExtinctionCacheNode {
                 let pipeline = pipeline_cache
                     .get_compute_pipeline(pipeline.pipeline)
                     .unwrap();
-                pass.set_bind_group(0, &bind_groups[0], &[]);
                 pass.set_pipeline(pipeline);
-                pass.dispatch_workgroups(size as u32 / WORKGROUP_SIZE, 1, 1);
+                for (bind_group, size) in bind_groups {
+                    pass.set_bind_group(0, bind_group, &[]);
+                    // Millions of stars need a second axis
+                    let workgroups = size.div_ceil(WORKGROUP_SIZE);
+                    let x = workgroups.min(MAX_WORKGROUPS_PER_DIMENSION);
+                    pass.dispatch_workgroups(x, workgroups.div_ceil(x), 1);
+                }
             }
         }
 
@@ -0,0 +1,414 @@
+use super::galaxy_texture::get_texture;
+use crate::prelude::*;
+use crate::ui::CameraMain;
+use bevy::{
+    pbr::{MaterialPipeline, MaterialPipelineKey},
+    prelude::*,
+    reflect::TypePath,
+    render::{
+        mesh::{MeshTag, MeshVertexBufferLayoutRef},
+        render_asset::RenderAssetUsages,
+        render_resource::{
+            AsBindGroup, Extent3d, RenderPipelineDescriptor, ShaderRef, ShaderType,
+            SpecializedMeshPipelineError, TextureDimension, TextureFormat,
+        },
+        storage::ShaderStorageBuffer,
+        view::NoFrustumCulling,
+    },
+};
+use rand::prelude::*;
+use std::f32::consts::TAU;
+
+const GALAXIES_SHADER_ASSET_PATH: &str = "shaders/background_galaxies.wgsl";
+const COSMIC_SHADER_ASSET_PATH: &str = "shaders/cosmic_background.wgsl";
+
+/// Field of distant galaxies and a faint cosmic glow behind everything
+///
+/// The impostors are placed on a shell around the camera in the vertex shader, so they behave like a skybox:
+/// only the view direction matters and moving around the galaxy never brings them closer.
+pub struct GalaxyBackgroundPlugin;
+
+impl Plugin for GalaxyBackgroundPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_plugins((
+            MaterialPlugin::<BackgroundGalaxyMaterial>::default(),
+            MaterialPlugin::<CosmicBackgroundMaterial>::default(),
+        ))
+        .insert_resource(GalaxyBackgroundConfig::default())
+        .add_systems(Startup, setup_cosmic_background)
+        .add_systems(
+            Update,
+            (
+                rebuild_background_galaxies,
+                update_cosmic_background,
+                hide_background_in_map_view,
+            ),
+        );
+    }
+}
+
+#[derive(Resource, Clone, PartialEq)]
+pub struct GalaxyBackgroundConfig {
+    pub enabled: bool,
+    pub count: u32,
+    pub seed: u64,
+    /// Angular radius of the largest impostors, in degrees
+    pub max_angular_size: f32,
+    pub brightness: f32,
+    /// Some impostors are drawn from galaxies baked by the real generator instead of the analytic profiles
+    pub use_generator: bool,
+    /// Number of distinct baked galaxies, each at `generator_texture_size`
+    pub generator_variants: u32,
+    pub generator_texture_size: u32,
+    pub cosmic_background: bool,
+    pub cosmic_background_intensity: f32,
+}
+
+impl GalaxyBackgroundConfig {
+    /// Whether the impostors have to be regenerated, brightness and the cosmic background are just uniforms
+    fn needs_rebuild(&self, previous: &Self) -> bool {
+        self.enabled != previous.enabled
+            || self.count != previous.count
+            || self.seed != previous.seed
+            || self.max_angular_size != previous.max_angular_size
+            || self.use_generator != previous.use_generator
+            || self.generator_variants != previous.generator_variants
+            || self.generator_texture_size != previous.generator_texture_size
+    }
+}
+
+impl Default for GalaxyBackgroundConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            count: 4000,
+            seed: 0,
+            max_angular_size: 0.4,
+            brightness: 1.0,
+            use_generator: true,
+            generator_variants: 8,
+            generator_texture_size: 64,
+            cosmic_background: false,
+            cosmic_background_intensity: 1.0,
+        }
+    }
+}
+
+/// Morphology ids, matching background_galaxies.wgsl
+const ELLIPTICAL: f32 = 0.0;
+const SPIRAL: f32 = 1.0;
+const IRREGULAR: f32 = 2.0;
+const GENERATED: f32 = 3.0;
+
+// Duplicated in background_galaxies.wgsl
+#[derive(ShaderType, Clone, Copy, Debug, Default)]
+struct BackgroundGalaxyInstance {
+    /// xyz = unit direction, w = angular radius in radians
+    direction_size: Vec4,
+    /// Orientation of the disk as a quaternion
+    orientation: Vec4,
+    /// rgb = colour, w = brightness
+    colour: Vec4,
+    /// x = morphology, y = arms / ellipticity, z = pitch / texture layer, w = random phase
+    shape: Vec4,
+}
+
+#[derive(Asset, TypePath, AsBindGroup, Debug, Clone)]
+struct BackgroundGalaxyMaterial {
+    #[storage(0, read_only)]
+    instances: Handle<ShaderStorageBuffer>,
+    #[texture(1, dimension = "2d_array")]
+    #[sampler(2)]
+    generated: Handle<Image>,
+    /// Kept out of the instances so brightness changes don't need a rebuild
+    #[uniform(3)]
+    brightness: f32,
+}
+
+impl Material for BackgroundGalaxyMaterial {
+    fn vertex_shader() -> ShaderRef {
+        GALAXIES_SHADER_ASSET_PATH.into()
+    }
+    fn fragment_shader() -> ShaderRef {
+        GALAXIES_SHADER_ASSET_PATH.into()
+    }
+    fn alpha_mode(&self) -> AlphaMode {
+        AlphaMode::Add
+    }
+}
+
+#[derive(Asset, TypePath, AsBindGroup, Debug, Clone)]
+struct CosmicBackgroundMaterial {
+    #[uniform(0)]
+    intensity: f32,
+}
+
+impl Material for CosmicBackgroundMaterial {
+    fn vertex_shader() -> ShaderRef {
+        COSMIC_SHADER_ASSET_PATH.into()
+    }
+    fn fragment_shader() -> ShaderRef {
+        COSMIC_SHADER_ASSET_PATH.into()
+    }
+    fn alpha_mode(&self) -> AlphaMode {
+        AlphaMode::Add
+    }
+
+    // Seen from the inside
+    fn specialize(
+        _pipeline: &MaterialPipeline<Self>,
+        descriptor: &mut RenderPipelineDescriptor,
+        _layout: &MeshVertexBufferLayoutRef,
+        _key: MaterialPipelineKey<Self>,
+    ) -> Result<(), SpecializedMeshPipelineError> {
+        descriptor.primitive.cull_mode = None;
+        Ok(())
+    }
+}
+
+#[derive(Component)]
+struct BackgroundGalaxy;
+
+#[derive(Component)]
+struct CosmicBackground;
+
+fn random_instance(
+    rng: &mut StdRng,
+    config: &GalaxyBackgroundConfig,
+    generated_layers: u32,
+) -> BackgroundGalaxyInstance {
+    let z: f32 = rng.random_range(-1.0..1.0);
+    let phi = rng.random_range(0.0..TAU);
+    let r = (1.0 - z * z).sqrt();
+    let direction = vec3(r * phi.cos(), z, r * phi.sin());
+
+    // Mostly tiny smudges with the occasional resolved galaxy
+    let size = config.max_angular_size.to_radians() * rng.random::<f32>().powi(3).max(0.02);
+    let orientation = Quat::from_euler(
+        EulerRot::YXZ,
+        rng.random_range(0.0..TAU),
+        rng.random_range(0.0..TAU),
+        0.0,
+    );
+
+    let morphology = match rng.random::<f32>() {
+        x if generated_layers > 0 && x < 0.25 => GENERATED,
+        x if x < 0.5 => SPIRAL,
+        x if x < 0.85 => ELLIPTICAL,
+        _ => IRREGULAR,
+    };
+    let (colour, shape) = if morphology == ELLIPTICAL {
+        (
+            vec3(1.0, 0.82, 0.6),
+            // Axis ratio of the projected ellipse
+            vec2(rng.random_range(0.3..1.0), 0.0),
+        )
+    } else if morphology == GENERATED {
+        (
+            vec3(0.85, 0.9, 1.0),
+            vec2(0.0, rng.random_range(0..generated_layers) as f32),
+        )
+    } else {
+        (
+            vec3(0.75, 0.85, 1.0),
+            // Arm count and pitch angle
+            vec2(
+                rng.random_range(2..=4) as f32,
+                rng.random_range(10f32..30.0).to_radians(),
+            ),
+        )
+    };
+    // Farther galaxies are smaller, dimmer and redder
+    let distance_factor = size / config.max_angular_size.to_radians();
+    let colour = colour
+        * vec3(
+            1.0,
+            0.9 + 0.1 * distance_factor,
+            0.8 + 0.2 * distance_factor,
+        );
+
+    BackgroundGalaxyInstance {
+        direction_size: direction.extend(size),
+        orientation: Vec4::from(orientation),
+        colour: colour.extend(rng.random_range(0.5..1.5)),
+        shape: vec4(morphology, shape.x, shape.y, rng.random_range(0.0..TAU)),
+    }
+}
+
+/// Bakes a handful of random galaxies with the real generator into a texture array
+fn bake_generated_galaxies(config: &GalaxyBackgroundConfig, rng: &mut StdRng) -> Image {
+    let layers = if config.use_generator {
+        config.generator_variants.max(1)
+    } else {
+        1
+    };
+    let render_settings = GalaxyRenderConfig {
+        texture_dimension: config.generator_texture_size,
+        ..default()
+    };
+    let dimension = render_settings.texture_dimension.next_power_of_two();
+
+    let mut data = vec![];
+    for _ in 0..layers {
+        let mut galaxy_config = GalaxyConfig {
+            seed: rng.random(),
+            winding_b: rng.random_range(0.2..0.8),
+            winding_n: rng.random_range(2.0..8.0),
+            bulge_radius: rng.random_range(5.0..15.0),
+            ..default()
+        };
+        for arm in galaxy_config.arm_configs.iter_mut() {
+            arm.enabled = rng.random_bool(0.6);
+        }
+        galaxy_config.arm_configs[0].enabled = true;
+        galaxy_config.update_arms();
+
+        let image = get_texture(&galaxy_config, &render_settings);
+        data.extend(image.data.unwrap_or_default());
+    }
+
+    Image::new(
+        Extent3d {
+            width: dimension,
+            height: dimension,
+            depth_or_array_layers: layers,
+        },
+        TextureDimension::D2,
+        data,
+        TextureFormat::Rgba16Float,
+        RenderAssetUsages::RENDER_WORLD,
+    )
+}
+
+#[allow(clippy::too_many_arguments)]
+fn rebuild_background_galaxies(
+    mut commands: Commands,
+    config: Res<GalaxyBackgroundConfig>,
+    existing: Query<(Entity, &MeshMaterial3d<BackgroundGalaxyMaterial>), With<BackgroundGalaxy>>,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut materials: ResMut<Assets<BackgroundGalaxyMaterial>>,
+    mut buffers: ResMut<Assets<ShaderStorageBuffer>>,
+    mut images: ResMut<Assets<Image>>,
+    mut mesh: Local<Option<Handle<Mesh>>>,
+    mut built: Local<Option<GalaxyBackgroundConfig>>,
+) {
+    if !config.is_changed() {
+        return;
+    }
+    if built
+        .as_ref()
+        .is_some_and(|built| !config.needs_rebuild(built))
+    {
+        // All impostors share one material
+        if let Some(material) = existing
+            .iter()
+            .next()
+            .and_then(|(_, material)| materials.get_mut(&material.0))
+        {
+            material.brightness = config.brightness;
+        }
+        return;
+    }
+    *built = Some(config.clone());
+
+    for (entity, _) in &existing {
+        commands.entity(entity).despawn();
+    }
+    if !config.enabled {
+        return;
+    }
+
+    let mut rng = StdRng::seed_from_u64(config.seed);
+    let generated = bake_generated_galaxies(&config, &mut rng);
+    let generated_layers = if config.use_generator {
+        generated.texture_descriptor.size.depth_or_array_layers
+    } else {
+        0
+    };
+    let instances: Vec<BackgroundGalaxyInstance> = (0..config.count)
+        .map(|_| random_instance(&mut rng, &config, generated_layers))
+        .collect();
+
+    let material = materials.add(BackgroundGalaxyMaterial {
+        instances: buffers.add(ShaderStorageBuffer::from(instances)),
+        generated: images.add(generated),
+        brightness: config.brightness,
+    });
+    let mesh = mesh
+        .get_or_insert_with(|| meshes.add(Rectangle::from_size(Vec2::splat(2.0))))
+        .clone();
+
+    for index in 0..config.count {
+        commands.spawn((
+            // Same mesh and material for every impostor so they are drawn instanced
+            Mesh3d(mesh.clone()),
+            MeshMaterial3d(material.clone()),
+            MeshTag(index),
+            Transform::IDENTITY,
+            BackgroundGalaxy,
+            // Positioned in the vertex shader
+            NoFrustumCulling,
+        ));
+    }
+}
+
+fn setup_cosmic_background(
+    mut commands: Commands,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut materials: ResMut<Assets<CosmicBackgroundMaterial>>,
+    config: Res<GalaxyBackgroundConfig>,
+) {
+    commands.spawn((
+        Mesh3d(meshes.add(Sphere::new(1.0).mesh().ico(4).unwrap())),
+        MeshMaterial3d(materials.add(CosmicBackgroundMaterial {
+            intensity: config.cosmic_background_intensity,
+        })),
+        Transform::IDENTITY,
+        Visibility::Hidden,
+        CosmicBackground,
+        NoFrustumCulling,
+    ));
+}
+
+fn update_cosmic_background(
+    config: Res<GalaxyBackgroundConfig>,
+    background: Query<&MeshMaterial3d<CosmicBackgroundMaterial>, With<CosmicBackground>>,
+    mut materials: ResMut<Assets<CosmicBackgroundMaterial>>,
+) {
+    if !config.is_changed() {
+        return;
+    }
+    if let Some(material) = background
+        .single()
+        .ok()
+        .and_then(|material| materials.get_mut(&material.0))
+    {
+        material.intensity = config.cosmic_background_intensity;
+    }
+}
+
+/// Both live on a shell around a perspective camera, which the map view doesn't have
+fn hide_background_in_map_view(
+    camera: Query<&CameraMain>,
+    config: Res<GalaxyBackgroundConfig>,
+    mut galaxies: Query<&mut Visibility, (With<BackgroundGalaxy>, Without<CosmicBackground>)>,
+    mut cosmic: Query<&mut Visibility, With<CosmicBackground>>,
+) {
+    let map_view = camera
+        .single()
+        .is_ok_and(|camera| camera.map_blend() >= 1.0);
+    let visibility = |shown: bool| {
+        if shown && !map_view {
+            Visibility::Inherited
+        } else {
+            Visibility::Hidden
+        }
+    };
+
+    for mut galaxy_visibility in &mut galaxies {
+        galaxy_visibility.set_if_neq(visibility(true));
+    }
+    if let Ok(mut cosmic_visibility) = cosmic.single_mut() {
+        cosmic_visibility.set_if_neq(visibility(config.cosmic_background));
+    }
+}
@@ -0,0 +1,82 @@
+use super::GalaxyCamera;
+use crate::prelude::*;
+use bevy::{core_pipeline::bloom::Bloom, prelude::*};
+
+/// Bloom on every [`GalaxyCamera`], scaled by how much of the view the primary galaxy fills
+///
+/// From afar the bulge and star clusters are small bright knots that should glow well past their edges, close
+/// in the same bloom would wash the arms out, so it fades as the galaxy grows on screen.
+pub struct GalaxyBloomPlugin;
+
+impl Plugin for GalaxyBloomPlugin {
+    fn build(&self, app: &mut App) {
+        app.insert_resource(GalaxyBloomConfig::default())
+            .add_systems(PostUpdate, update_bloom);
+    }
+}
+
+#[derive(Resource, Clone, PartialEq)]
+pub struct GalaxyBloomConfig {
+    pub enabled: bool,
+    /// Bloom with the galaxy far away
+    pub intensity: f32,
+    /// Fraction of the intensity kept once the galaxy fills the view
+    pub close_up_factor: f32,
+    pub low_frequency_boost: f32,
+}
+
+impl Default for GalaxyBloomConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            intensity: 0.3,
+            close_up_factor: 0.3,
+            low_frequency_boost: 0.7,
+        }
+    }
+}
+
+fn update_bloom(
+    mut commands: Commands,
+    config: Res<GalaxyBloomConfig>,
+    mut cameras: Query<(Entity, &GlobalTransform, Option<&mut Bloom>), With<GalaxyCamera>>,
+    galaxies: Query<(&GalaxyConfig, &GlobalTransform), With<PrimaryGalaxy>>,
+) {
+    let galaxy = galaxies.single().ok();
+    for (entity, camera_transform, bloom) in &mut cameras {
+        if !config.enabled {
+            if bloom.is_some() {
+                commands.entity(entity).remove::<Bloom>();
+            }
+            continue;
+        }
+
+        // Radius over distance, about the half angle the disk covers
+        let angular_size = galaxy.map_or(0.0, |(galaxy_config, galaxy_transform)| {
+            let distance = camera_transform
+                .translation()
+                .distance(galaxy_transform.translation());
+            galaxy_config.radius / distance.max(1.0)
+        });
+        let close_up = smoothstep(0.3, 2.0, angular_size);
+        let intensity = config.intensity * f32::lerp(1.0, config.close_up_factor, close_up);
+
+        match bloom {
+            Some(mut bloom) => {
+                if (bloom.intensity - intensity).abs() > 1e-4
+                    || bloom.low_frequency_boost != config.low_frequency_boost
+                {
+                    bloom.intensity = intensity;
+                    bloom.low_frequency_boost = config.low_frequency_boost;
+                }
+            }
+            None => {
+                commands.entity(entity).insert(Bloom {
+                    intensity,
+                    low_frequency_boost: config.low_frequency_boost,
+                    ..Bloom::NATURAL
+                });
+            }
+        }
+    }
+}
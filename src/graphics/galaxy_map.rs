@@ -0,0 +1,125 @@
+use super::GalaxyTexture;
+use crate::prelude::*;
+use crate::ui::CameraMain;
+use bevy::{
+    prelude::*,
+    reflect::TypePath,
+    render::render_resource::{AsBindGroup, ShaderRef, ShaderType},
+};
+
+const SHADER_ASSET_PATH: &str = "shaders/galaxy_map.wgsl";
+
+/// Flat top-down rendering of the primary galaxy's baked texture, shown in the camera's map view
+pub struct GalaxyMapPlugin;
+
+impl Plugin for GalaxyMapPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_plugins(MaterialPlugin::<GalaxyMapMaterial>::default())
+            .add_systems(Startup, setup_galaxy_map)
+            .add_systems(Update, update_galaxy_map);
+    }
+}
+
+#[derive(Component)]
+struct GalaxyMap;
+
+#[derive(ShaderType, Clone, Copy, Debug, Default, PartialEq)]
+struct GalaxyMapParams {
+    radius: f32,
+    padding_coefficient: f32,
+    texture_dimension: f32,
+    opacity: f32,
+    intensity: f32,
+    /// The disk's inner colour, the map doesn't show gradients
+    disk_color: Vec3,
+}
+
+#[derive(Asset, TypePath, AsBindGroup, Debug, Clone, Default)]
+struct GalaxyMapMaterial {
+    #[uniform(0)]
+    params: GalaxyMapParams,
+    #[texture(1)]
+    #[sampler(2)]
+    xz_texture: Option<Handle<Image>>,
+}
+
+impl Material for GalaxyMapMaterial {
+    fn fragment_shader() -> ShaderRef {
+        SHADER_ASSET_PATH.into()
+    }
+    fn alpha_mode(&self) -> AlphaMode {
+        AlphaMode::Blend
+    }
+}
+
+fn setup_galaxy_map(
+    mut commands: Commands,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut materials: ResMut<Assets<GalaxyMapMaterial>>,
+) {
+    commands.spawn((
+        Mesh3d(meshes.add(Plane3d::new(Vec3::Y, Vec2::splat(0.5)))),
+        MeshMaterial3d(materials.add(GalaxyMapMaterial::default())),
+        Transform::IDENTITY,
+        Visibility::Hidden,
+        GalaxyMap,
+    ));
+}
+
+fn update_galaxy_map(
+    mut map: Query<
+        (
+            &mut Transform,
+            &mut Visibility,
+            &MeshMaterial3d<GalaxyMapMaterial>,
+        ),
+        With<GalaxyMap>,
+    >,
+    camera: Query<&CameraMain>,
+    galaxy: Query<(&GalaxyConfig, &GalaxyTexture, &GlobalTransform), With<PrimaryGalaxy>>,
+    galaxy_render_settings: Res<GalaxyRenderConfig>,
+    mut materials: ResMut<Assets<GalaxyMapMaterial>>,
+) {
+    let (Ok((mut transform, mut visibility, material)), Ok(camera), Ok(galaxy)) =
+        (map.single_mut(), camera.single(), galaxy.single())
+    else {
+        return;
+    };
+
+    let opacity = camera.map_blend();
+    visibility.set_if_neq(if opacity > 0.0 {
+        Visibility::Inherited
+    } else {
+        Visibility::Hidden
+    });
+    if opacity == 0.0 {
+        return;
+    }
+
+    let (galaxy_config, galaxy_texture, galaxy_transform) = galaxy;
+    let size = galaxy_config.radius * 2.0 * galaxy_render_settings.padding_coeff;
+    // Lies in the plane of the primary galaxy
+    let (_, rotation, translation) = galaxy_transform.to_scale_rotation_translation();
+    *transform = Transform::from_translation(translation)
+        .with_rotation(rotation)
+        .with_scale(vec3(size, 1.0, size));
+
+    let params = GalaxyMapParams {
+        radius: galaxy_config.radius,
+        padding_coefficient: galaxy_render_settings.padding_coeff,
+        texture_dimension: galaxy_render_settings.texture_dimension as f32,
+        opacity,
+        intensity: 10.0,
+        disk_color: galaxy_config.disk_params.color.inner,
+    };
+    let up_to_date = materials
+        .get(&material.0)
+        .is_some_and(|mat| mat.params == params && mat.xz_texture == galaxy_texture.tex);
+    if up_to_date {
+        return;
+    }
+    if let Some(mat) = materials.get_mut(&material.0) {
+        mat.params = params;
+        mat.xz_texture = galaxy_texture.tex.clone();
+    }
+}
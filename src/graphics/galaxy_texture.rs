@@ -1,21 +1,23 @@
 use crate::prelude::*;
 use bevy::prelude::*;
-use bevy::render::extract_resource::{ExtractResource, ExtractResourcePlugin};
 use rayon::prelude::*;
 pub struct GalaxyTexturePlugin;
 
-#[derive(Resource, Default, Clone, ExtractResource)]
+/// Baked textures of a galaxy, on the galaxy entity
+#[derive(Component, Default, Clone)]
 pub struct GalaxyTexture {
     pub tex: Option<Handle<Image>>,
     pub luts: Option<Handle<Image>>,
+    /// Maximum density mip grid for empty space skipping, see [`OccupancyGrid`]
+    pub occupancy: Option<Handle<Image>>,
     dimension: u32,
+    padding: f32,
     generation: i32,
 }
 
 impl Plugin for GalaxyTexturePlugin {
     fn build(&self, app: &mut App) {
-        app.add_plugins(ExtractResourcePlugin::<GalaxyTexture>::default())
-            .insert_resource(GalaxyTexture::default())
+        app.register_required_components::<GalaxyConfig, GalaxyTexture>()
             .add_systems(Update, update_texture);
     }
 }
@@ -117,23 +119,51 @@ pub fn get_texture(config: &GalaxyConfig, render_settings: &GalaxyRenderConfig)
     )
 }
 
+pub fn get_occupancy(config: &GalaxyConfig, render_settings: &GalaxyRenderConfig) -> Image {
+    let grid = OccupancyGrid::new(config, render_settings);
+    let dims = OccupancyGrid::dimensions(0);
+    let bytes = |level: &[f32]| -> Vec<u8> { level.iter().flat_map(|v| v.to_le_bytes()).collect() };
+
+    let mut image = Image::new(
+        Extent3d {
+            width: dims.x,
+            height: dims.y,
+            depth_or_array_layers: dims.z,
+        },
+        TextureDimension::D3,
+        bytes(&grid.levels[0]),
+        TextureFormat::R32Float,
+        RenderAssetUsages::RENDER_WORLD,
+    );
+    // Every level is baked here rather than generated on the GPU, as a mip of maxima
+    image.data = Some(grid.levels.iter().flat_map(|level| bytes(level)).collect());
+    image.texture_descriptor.mip_level_count = OCCUPANCY_LEVELS;
+    image
+}
+
 fn update_texture(
     mut images: ResMut<Assets<Image>>,
-    config: Res<GalaxyConfig>,
+    mut galaxies: Query<(&GalaxyConfig, &mut GalaxyTexture)>,
     render_settings: Res<GalaxyRenderConfig>,
-    mut tex_holder: ResMut<GalaxyTexture>,
 ) {
-    if config.generation != tex_holder.generation
-        || tex_holder.tex.is_none()
-        || tex_holder.dimension != render_settings.texture_dimension.next_power_of_two()
-    {
-        info!("Galaxy config updated, rebaking galaxy");
-        let handle = images.add(get_texture(&config, &render_settings));
-        tex_holder.tex = Some(handle);
-        tex_holder.dimension = render_settings.texture_dimension.next_power_of_two();
-
-        let lut_handle = images.add(get_lut(&config, &render_settings));
-        tex_holder.luts = Some(lut_handle);
-        tex_holder.generation = config.generation;
+    for (config, mut tex_holder) in &mut galaxies {
+        if config.generation != tex_holder.generation
+            || tex_holder.tex.is_none()
+            || tex_holder.dimension != render_settings.texture_dimension.next_power_of_two()
+            || tex_holder.padding != render_settings.padding_coeff
+        {
+            info!("Galaxy config updated, rebaking galaxy");
+            let handle = images.add(get_texture(config, &render_settings));
+            tex_holder.tex = Some(handle);
+            tex_holder.dimension = render_settings.texture_dimension.next_power_of_two();
+            tex_holder.padding = render_settings.padding_coeff;
+
+            let lut_handle = images.add(get_lut(config, &render_settings));
+            tex_holder.luts = Some(lut_handle);
+
+            let occupancy_handle = images.add(get_occupancy(config, &render_settings));
+            tex_holder.occupancy = Some(occupancy_handle);
+            tex_holder.generation = config.generation;
+        }
     }
 }
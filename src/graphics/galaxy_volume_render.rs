@@ -1,14 +1,12 @@
 use crate::prelude::*;
+use crate::ui::CameraMain;
 use bevy::{
     prelude::*,
     reflect::TypePath,
-    render::{
-        render_resource::{AsBindGroup, Face, ShaderRef},
-        view::RenderLayers,
-    },
+    render::render_resource::{AsBindGroup, Face, ShaderRef},
 };
 
-use super::shader_types::*;
+use super::{shader_types::*, volume_render_layers};
 
 pub struct GalaxyVolumePlugin;
 
@@ -16,35 +14,53 @@ impl Plugin for GalaxyVolumePlugin {
     fn build(&self, app: &mut App) {
         app.add_plugins(MaterialPlugin::<GalaxyVolumeMaterial>::default());
 
-        app.add_systems(Startup, setup_galaxy_volume)
-            .add_systems(Update, (update_volume_material, update_galaxy_volume));
+        app.add_systems(Startup, setup_galaxy_volume_mesh)
+            .add_systems(
+                Update,
+                (
+                    spawn_galaxy_volumes,
+                    update_volume_material,
+                    update_galaxy_volume,
+                    hide_volume_in_map_view,
+                ),
+            );
     }
 }
 
+/// Volume of a galaxy, a child of the galaxy entity so it follows its transform
 #[derive(Component)]
 struct GalaxyVolume;
 
-fn setup_galaxy_volume(
+#[derive(Resource)]
+struct GalaxyVolumeMesh(Handle<Mesh>);
+
+fn setup_galaxy_volume_mesh(mut commands: Commands, mut meshes: ResMut<Assets<Mesh>>) {
+    commands.insert_resource(GalaxyVolumeMesh(meshes.add(Sphere::new(1.0))));
+}
+
+fn spawn_galaxy_volumes(
     mut commands: Commands,
-    mut meshes: ResMut<Assets<Mesh>>,
+    galaxies: Query<(Entity, &GalaxyConfig), Added<GalaxyConfig>>,
+    galaxy_mesh: Res<GalaxyVolumeMesh>,
     mut galaxy_materials: ResMut<Assets<GalaxyVolumeMaterial>>,
-    galaxy_config: Res<GalaxyConfig>,
     galaxy_render_settings: Res<GalaxyRenderConfig>,
 ) {
-    let galaxy_mesh = meshes.add(Sphere::new(1.0));
-    let mat = galaxy_materials.add(GalaxyVolumeMaterial::new(
-        &galaxy_config,
-        &galaxy_render_settings,
-    ));
-    commands.spawn((
-        Mesh3d(galaxy_mesh),
-        Transform::IDENTITY,
-        Visibility::Inherited,
-        MeshMaterial3d(mat),
-        GalaxyVolume,
-        volume_upscaler::background_render_layer(),
-        bevy::render::view::NoFrustumCulling,
-    ));
+    for (galaxy, galaxy_config) in &galaxies {
+        let mat = galaxy_materials.add(GalaxyVolumeMaterial::new(
+            galaxy_config,
+            &galaxy_render_settings,
+        ));
+        commands.spawn((
+            Mesh3d(galaxy_mesh.0.clone()),
+            Transform::IDENTITY,
+            Visibility::Inherited,
+            MeshMaterial3d(mat),
+            GalaxyVolume,
+            ChildOf(galaxy),
+            volume_render_layers(&galaxy_render_settings),
+            bevy::render::view::NoFrustumCulling,
+        ));
+    }
 }
 
 fn update_galaxy_volume(
@@ -53,38 +69,63 @@ fn update_galaxy_volume(
     galaxy_render_settings: Res<GalaxyRenderConfig>,
 ) {
     if galaxy_render_settings.is_changed() {
-    if let Ok(entity) = query.single() {
-        commands
-            .entity(entity)
-            .insert(if galaxy_render_settings.draw_volume_to_background {
-                volume_upscaler::background_render_layer()
-            } else {
-                RenderLayers::layer(0)
-            });
+        for entity in &query {
+            commands
+                .entity(entity)
+                .insert(volume_render_layers(&galaxy_render_settings));
+        }
     }
 }
 
+/// The map view draws the baked texture instead, and the raymarch assumes a perspective camera
+/// Also hidden while the galaxy is merging, its stars no longer follow the analytic model
+fn hide_volume_in_map_view(
+    camera: Query<&CameraMain>,
+    mut volumes: Query<(&mut Visibility, &ChildOf), With<GalaxyVolume>>,
+    merging: Query<(), With<Merging>>,
+) {
+    let Ok(camera) = camera.single() else {
+        return;
+    };
+    for (mut visibility, child_of) in &mut volumes {
+        let hidden = camera.map_blend() >= 1.0 || merging.contains(child_of.parent());
+        visibility.set_if_neq(if hidden {
+            Visibility::Hidden
+        } else {
+            Visibility::Inherited
+        });
     }
+}
 
 fn update_volume_material(
-    galaxy_mat: Query<&MeshMaterial3d<GalaxyVolumeMaterial>, With<GalaxyVolume>>,
-    galaxy_texture: Res<super::GalaxyTexture>,
-    galaxy_config: Res<GalaxyConfig>,
+    volumes: Query<(&MeshMaterial3d<GalaxyVolumeMaterial>, &ChildOf), With<GalaxyVolume>>,
+    galaxies: Query<(&GalaxyConfig, Ref<super::GalaxyTexture>)>,
+    noise: Res<super::noise_texture::NoiseTexture>,
     galaxy_render_settings: Res<GalaxyRenderConfig>,
     mut galaxy_materials: ResMut<Assets<GalaxyVolumeMaterial>>,
 ) {
-    if galaxy_texture.is_changed() || galaxy_render_settings.is_changed() {
-        let Ok(galaxy) = galaxy_mat.single() else {
-            return;
+    for (volume, child_of) in &volumes {
+        let Ok((galaxy_config, galaxy_texture)) = galaxies.get(child_of.parent()) else {
+            continue;
         };
-        let Some(mat) = galaxy_materials.get_mut(&galaxy.0) else {
-            return;
+        if !galaxy_texture.is_changed()
+            && !galaxy_render_settings.is_changed()
+            && !noise.is_changed()
+        {
+            continue;
+        }
+        let Some(mat) = galaxy_materials.get_mut(&volume.0) else {
+            continue;
         };
 
-        mat.update(&galaxy_config, &galaxy_render_settings);
+        mat.update(galaxy_config, &galaxy_render_settings);
 
         mat.xz_texture = galaxy_texture.tex.clone();
         mat.lut = galaxy_texture.luts.clone();
+        mat.occupancy = galaxy_texture.occupancy.clone();
+        mat.noise = noise.tex.clone();
+        // Also covers the frames before the noise is baked
+        mat.runtime_noise = mat.noise.is_none();
     }
 }
 
@@ -107,8 +148,21 @@ pub struct GalaxyVolumeMaterial {
     #[texture(6, dimension = "2d_array")]
     #[sampler(7)]
     lut: Option<Handle<Image>>,
+    #[uniform(8)]
+    spectral_params: SpectralParams,
+    #[uniform(9)]
+    visualization_params: VisualizationParams,
+    #[texture(10, dimension = "3d", sample_type = "float", filterable = false)]
+    occupancy: Option<Handle<Image>>,
+    #[texture(11, dimension = "3d")]
+    #[sampler(12)]
+    noise: Option<Handle<Image>>,
     //alpha_mode: AlphaMode,
-    diagnostic_mode: bool,
+    visualization: bool,
+    dust_scattering: bool,
+    spectral: bool,
+    empty_space_skipping: bool,
+    runtime_noise: bool,
 }
 impl GalaxyVolumeMaterial {
     pub fn update(
@@ -120,7 +174,13 @@ impl GalaxyVolumeMaterial {
         self.bulge_params = BulgeParams::read(galaxy_config);
         self.disk_params = ComponentParams::read(&galaxy_config.disk_params);
         self.dust_params = ComponentParams::read(&galaxy_config.dust_params);
-        self.diagnostic_mode = galaxy_render_settings.diagnostic_mode;
+        self.visualization = galaxy_render_settings.visualization != VisualizationMode::Off;
+        self.dust_scattering = galaxy_render_settings.dust_scattering;
+        self.spectral_params = SpectralParams::read(galaxy_render_settings);
+        self.visualization_params =
+            VisualizationParams::read(galaxy_config, galaxy_render_settings);
+        self.spectral = galaxy_render_settings.spectral;
+        self.empty_space_skipping = galaxy_render_settings.empty_space_skipping;
     }
     pub fn new(galaxy_config: &GalaxyConfig, galaxy_render_settings: &GalaxyRenderConfig) -> Self {
         let mut ret = Self::default();
@@ -142,8 +202,9 @@ impl Material for GalaxyVolumeMaterial {
     fn vertex_shader() -> ShaderRef {
         "shaders/shader_galaxy_volume.wgsl".into()
     }
+    // Overlapping galaxies are summed, the shader writes zero alpha
     fn alpha_mode(&self) -> AlphaMode {
-        AlphaMode::Opaque
+        AlphaMode::Add
     }
 
     // prevents issues when rendering stars and galaxy volume together in the background camera
@@ -159,9 +220,25 @@ impl Material for GalaxyVolumeMaterial {
         key: MaterialPipelineKey<Self>,
     ) -> Result<(), SpecializedMeshPipelineError> {
         descriptor.primitive.cull_mode = Some(Face::Front);
-        if key.bind_group_data.diagnostic_mode {
+        if key.bind_group_data.visualization {
+            let fragment = descriptor.fragment.as_mut().unwrap();
+            fragment.shader_defs.push("VISUALIZATION".into());
+        }
+        if key.bind_group_data.dust_scattering {
+            let fragment = descriptor.fragment.as_mut().unwrap();
+            fragment.shader_defs.push("DUST_SCATTERING".into());
+        }
+        if key.bind_group_data.spectral {
+            let fragment = descriptor.fragment.as_mut().unwrap();
+            fragment.shader_defs.push("SPECTRAL".into());
+        }
+        if key.bind_group_data.empty_space_skipping {
+            let fragment = descriptor.fragment.as_mut().unwrap();
+            fragment.shader_defs.push("EMPTY_SPACE_SKIPPING".into());
+        }
+        if key.bind_group_data.runtime_noise {
             let fragment = descriptor.fragment.as_mut().unwrap();
-            fragment.shader_defs.push("DIAGNOSTIC".into());
+            fragment.shader_defs.push("RUNTIME_NOISE".into());
         }
         Ok(())
     }
@@ -172,13 +249,21 @@ impl Material for GalaxyVolumeMaterial {
 // as they will be used to look up the pipeline for each drawn entity with this material type.
 #[derive(Eq, PartialEq, Hash, Clone)]
 pub struct GalaxyMaterialKey {
-    diagnostic_mode: bool,
+    visualization: bool,
+    dust_scattering: bool,
+    spectral: bool,
+    empty_space_skipping: bool,
+    runtime_noise: bool,
 }
 
 impl From<&GalaxyVolumeMaterial> for GalaxyMaterialKey {
     fn from(material: &GalaxyVolumeMaterial) -> Self {
         Self {
-            diagnostic_mode: material.diagnostic_mode,
+            visualization: material.visualization,
+            dust_scattering: material.dust_scattering,
+            spectral: material.spectral,
+            empty_space_skipping: material.empty_space_skipping,
+            runtime_noise: material.runtime_noise,
         }
     }
 }
@@ -1,26 +1,73 @@
-use bevy::prelude::*;
+use crate::galaxy::GalaxyRenderConfig;
+use bevy::{
+    prelude::*,
+    render::{
+        extract_component::{ExtractComponent, ExtractComponentPlugin},
+        view::RenderLayers,
+    },
+};
 
+mod density_splat;
+mod exposure;
+mod galaxy_background;
+mod galaxy_bloom;
+mod galaxy_map;
 mod galaxy_texture;
 mod galaxy_volume_render;
+mod nebulae;
+mod noise_texture;
 
 mod extinction_cache;
+mod nucleus;
 mod shader_types;
 
 mod star_instancing;
-pub use star_instancing::{StarInstanceMarker, StarInstancingPlugin};
+mod territory_overlay;
+pub use star_instancing::{StarInstancingPlugin, StarPsfConfig};
 
-pub use extinction_cache::ExtinctionCache;
+pub use density_splat::DensitySplatConfig;
+pub use exposure::{ExposureConfig, TONEMAPPERS};
+pub use extinction_cache::{ExtinctionCache, ExtinctionOrigin};
+pub use galaxy_background::GalaxyBackgroundConfig;
+pub use galaxy_bloom::GalaxyBloomConfig;
+pub use nebulae::NebulaConfig;
+pub use nucleus::NucleusConfig;
 use galaxy_texture::GalaxyTexture;
 
+/// Layers of everything drawn alongside the galaxy volume, through the upscaler when the volume renders to the background
+fn volume_render_layers(galaxy_render_settings: &GalaxyRenderConfig) -> RenderLayers {
+    if galaxy_render_settings.draw_volume_to_background {
+        volume_upscaler::background_render_layer()
+    } else {
+        RenderLayers::layer(0)
+    }
+}
+
+/// Marks the camera the galaxy is rendered for
+/// The volume is drawn through its background upscaler and star extinction is evaluated toward it
+#[derive(Component, Default, Clone, ExtractComponent)]
+#[require(volume_upscaler::BackgroundCamera)]
+pub struct GalaxyCamera;
+
 pub struct GraphicsPlugin;
 
 impl Plugin for GraphicsPlugin {
     fn build(&self, app: &mut App) {
         app.add_plugins((
             galaxy_volume_render::GalaxyVolumePlugin,
+            galaxy_background::GalaxyBackgroundPlugin,
+            galaxy_bloom::GalaxyBloomPlugin,
+            density_splat::DensitySplatPlugin,
+            exposure::ExposurePlugin,
+            nucleus::NucleusPlugin,
+            nebulae::NebulaPlugin,
+            galaxy_map::GalaxyMapPlugin,
             galaxy_texture::GalaxyTexturePlugin,
+            noise_texture::NoiseTexturePlugin,
             extinction_cache::ExtinctionCachePlugin,
             volume_upscaler::BackgroundRenderingPlugin,
+            territory_overlay::TerritoryOverlayPlugin,
+            ExtractComponentPlugin::<GalaxyCamera>::default(),
         ));
     }
 }
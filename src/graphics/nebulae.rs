@@ -0,0 +1,372 @@
+use super::{volume_render_layers, ExtinctionCache};
+use crate::prelude::*;
+use crate::ui::CameraMain;
+use bevy::{
+    pbr::{MaterialPipeline, MaterialPipelineKey},
+    prelude::*,
+    reflect::TypePath,
+    render::{
+        mesh::{MeshTag, MeshVertexBufferLayoutRef},
+        render_resource::{
+            AsBindGroup, Face, RenderPipelineDescriptor, ShaderRef, ShaderType,
+            SpecializedMeshPipelineError,
+        },
+        storage::ShaderStorageBuffer,
+    },
+};
+use rand::prelude::*;
+use std::f32::consts::TAU;
+
+const SHADER_ASSET_PATH: &str = "shaders/nebulae.wgsl";
+
+/// Small volumetric nebulae scattered along the arms of every galaxy
+///
+/// Each nebula is raymarched with its own noise inside a small box, adding close up detail the galaxy scale
+/// noise can't. Their centres are appended to the galaxy's [`ExtinctionCache`], so they are dimmed and
+/// reddened by the dust in front of them like the stars are.
+pub struct NebulaPlugin;
+
+impl Plugin for NebulaPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_plugins(MaterialPlugin::<NebulaMaterial>::default())
+            .insert_resource(NebulaConfig::default())
+            .add_systems(
+                Update,
+                (
+                    (rebuild_nebulae, update_nebula_material).chain(),
+                    update_nebula_visibility,
+                ),
+            );
+    }
+}
+
+#[derive(Resource, Clone, PartialEq)]
+pub struct NebulaConfig {
+    pub enabled: bool,
+    /// Nebulae per galaxy
+    pub count: u32,
+    pub seed: u64,
+    /// Radius range of the nebulae, in parsecs
+    pub min_radius: f32,
+    pub max_radius: f32,
+    pub brightness: f32,
+    pub raymarch_steps: u32,
+    /// Dim the nebulae by the dust between them and the camera
+    pub extinction: bool,
+}
+
+impl Default for NebulaConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            count: 200,
+            seed: 0,
+            min_radius: 3.0,
+            max_radius: 15.0,
+            brightness: 1.0,
+            raymarch_steps: 32,
+            extinction: true,
+        }
+    }
+}
+
+impl NebulaConfig {
+    /// Whether the nebulae have to be placed again, the other fields only go to the material
+    fn needs_rebuild(&self, previous: &Self) -> bool {
+        self.enabled != previous.enabled
+            || self.count != previous.count
+            || self.seed != previous.seed
+            || self.min_radius != previous.min_radius
+            || self.max_radius != previous.max_radius
+    }
+}
+
+/// Kind ids, matching nebulae.wgsl
+const EMISSION: f32 = 0.0;
+const PLANETARY: f32 = 1.0;
+const SUPERNOVA_REMNANT: f32 = 2.0;
+
+// Duplicated in nebulae.wgsl
+#[derive(ShaderType, Clone, Copy, Debug, Default)]
+struct NebulaInstance {
+    /// rgb = colour, w = brightness
+    colour: Vec4,
+    /// Orientation of the nebula as a quaternion
+    orientation: Vec4,
+    /// x = kind, y = noise offset, z = shell radius, w = elongation
+    shape: Vec4,
+}
+
+#[derive(ShaderType, Clone, Copy, Debug, Default)]
+struct NebulaParams {
+    /// Index of the first nebula in the extinction buffer, after the galaxy's stars
+    extinction_offset: u32,
+    brightness: f32,
+    raymarch_steps: f32,
+    use_extinction: f32,
+}
+
+impl NebulaParams {
+    fn read(config: &NebulaConfig, extinction_offset: usize) -> Self {
+        Self {
+            extinction_offset: extinction_offset as u32,
+            brightness: config.brightness,
+            raymarch_steps: config.raymarch_steps as f32,
+            use_extinction: if config.extinction { 1.0 } else { 0.0 },
+        }
+    }
+}
+
+#[derive(Asset, TypePath, AsBindGroup, Debug, Clone)]
+struct NebulaMaterial {
+    #[storage(0, read_only)]
+    nebulae: Handle<ShaderStorageBuffer>,
+    #[storage(1, read_only)]
+    extinction: Handle<ShaderStorageBuffer>,
+    #[uniform(2)]
+    params: NebulaParams,
+}
+
+impl Material for NebulaMaterial {
+    fn vertex_shader() -> ShaderRef {
+        SHADER_ASSET_PATH.into()
+    }
+    fn fragment_shader() -> ShaderRef {
+        SHADER_ASSET_PATH.into()
+    }
+    fn alpha_mode(&self) -> AlphaMode {
+        AlphaMode::Add
+    }
+
+    // Back faces, so a nebula is still drawn with the camera inside it
+    fn specialize(
+        _pipeline: &MaterialPipeline<Self>,
+        descriptor: &mut RenderPipelineDescriptor,
+        _layout: &MeshVertexBufferLayoutRef,
+        _key: MaterialPipelineKey<Self>,
+    ) -> Result<(), SpecializedMeshPipelineError> {
+        descriptor.primitive.cull_mode = Some(Face::Front);
+        Ok(())
+    }
+}
+
+/// A single nebula, a child of its galaxy
+#[derive(Component)]
+struct Nebula;
+
+/// The nebulae material of a galaxy and the galaxy generation it was placed for
+#[derive(Component)]
+struct GalaxyNebulae {
+    material: Option<Handle<NebulaMaterial>>,
+    generation: i32,
+}
+
+struct PlacedNebula {
+    position: Vec3,
+    radius: f32,
+    instance: NebulaInstance,
+}
+
+/// Nebulae follow the disk's arms, sampled the same way the stars are
+fn place_nebulae(galaxy_config: &GalaxyConfig, config: &NebulaConfig) -> Vec<PlacedNebula> {
+    let mut rng = StdRng::seed_from_u64(galaxy_config.seed ^ config.seed);
+    let density = GalaxyComponentDensity::new(galaxy_config, &galaxy_config.disk_params);
+    let thickness = galaxy_config.disk_params.y_thickness * galaxy_config.radius;
+
+    (0..config.count)
+        .map(|_| {
+            let mut position = Vec3::ZERO;
+            let mut weight_sum = 0.0;
+            for _ in 0..64 {
+                let r = rng.random::<f32>().sqrt() * galaxy_config.radius;
+                let angle = rng.random_range(0.0..TAU);
+                let candidate = vec3(
+                    angle.cos() * r,
+                    rng.random_range(-1.0..1.0) * thickness,
+                    angle.sin() * r,
+                );
+                let weight = density.xyz_density(candidate) + 0.0001;
+                weight_sum += weight;
+                if rng.random::<f32>() < weight / weight_sum {
+                    position = candidate;
+                }
+            }
+
+            let size = f32::lerp(
+                config.min_radius,
+                config.max_radius,
+                rng.random::<f32>().powi(2),
+            );
+            let orientation = Quat::from_euler(
+                EulerRot::YXZ,
+                rng.random_range(0.0..TAU),
+                rng.random_range(0.0..TAU),
+                0.0,
+            );
+            let noise_offset = rng.random_range(0.0..100.0);
+
+            // Mostly star forming regions, with the odd dying star
+            let (kind, radius, colour, shell, elongation) = match rng.random_range(0..10) {
+                0 | 1 => (
+                    PLANETARY,
+                    size * 0.3,
+                    vec3(0.35, 0.9, 0.85),
+                    rng.random_range(0.45..0.7),
+                    rng.random_range(1.0..1.8),
+                ),
+                2 => (
+                    SUPERNOVA_REMNANT,
+                    size,
+                    vec3(1.0, 0.45, 0.35),
+                    rng.random_range(0.65..0.85),
+                    1.0,
+                ),
+                _ => (
+                    EMISSION,
+                    size,
+                    vec3(
+                        1.0,
+                        rng.random_range(0.25..0.4),
+                        rng.random_range(0.35..0.5),
+                    ),
+                    0.0,
+                    1.0,
+                ),
+            };
+
+            PlacedNebula {
+                position,
+                radius,
+                instance: NebulaInstance {
+                    colour: colour.extend(rng.random_range(0.5..1.5)),
+                    orientation: Vec4::from(orientation),
+                    shape: vec4(kind, noise_offset, shell, elongation),
+                },
+            }
+        })
+        .collect()
+}
+
+#[allow(clippy::too_many_arguments, clippy::type_complexity)]
+fn rebuild_nebulae(
+    mut commands: Commands,
+    config: Res<NebulaConfig>,
+    galaxy_render_settings: Res<GalaxyRenderConfig>,
+    mut galaxies: Query<(
+        Entity,
+        &GalaxyConfig,
+        &mut ExtinctionCache,
+        Option<&GalaxyNebulae>,
+    )>,
+    existing: Query<(Entity, &ChildOf), With<Nebula>>,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut materials: ResMut<Assets<NebulaMaterial>>,
+    mut buffers: ResMut<Assets<ShaderStorageBuffer>>,
+    mut mesh: Local<Option<Handle<Mesh>>>,
+    mut built: Local<Option<NebulaConfig>>,
+) {
+    let layout_changed = config.is_changed()
+        && built
+            .as_ref()
+            .is_none_or(|built| config.needs_rebuild(built));
+    if layout_changed {
+        *built = Some(config.clone());
+    }
+    for (galaxy, galaxy_config, mut extinction_cache, nebulae) in &mut galaxies {
+        let up_to_date =
+            nebulae.is_some_and(|nebulae| nebulae.generation == galaxy_config.generation);
+        if up_to_date && !layout_changed {
+            continue;
+        }
+        for (entity, child_of) in &existing {
+            if child_of.parent() == galaxy {
+                commands.entity(entity).despawn();
+            }
+        }
+
+        let placed = if config.enabled {
+            place_nebulae(galaxy_config, &config)
+        } else {
+            vec![]
+        };
+        extinction_cache.extra_positions = placed.iter().map(|nebula| nebula.position).collect();
+
+        let material = (!placed.is_empty()).then(|| {
+            let instances: Vec<NebulaInstance> =
+                placed.iter().map(|nebula| nebula.instance).collect();
+            materials.add(NebulaMaterial {
+                nebulae: buffers.add(ShaderStorageBuffer::from(instances)),
+                extinction: extinction_cache.output_buffer.clone(),
+                params: NebulaParams::read(&config, extinction_cache.required_size),
+            })
+        });
+        commands.entity(galaxy).insert(GalaxyNebulae {
+            material: material.clone(),
+            generation: galaxy_config.generation,
+        });
+        let Some(material) = material else {
+            continue;
+        };
+
+        let mesh = mesh
+            .get_or_insert_with(|| meshes.add(Cuboid::new(2.0, 2.0, 2.0)))
+            .clone();
+        for (index, nebula) in placed.iter().enumerate() {
+            commands.spawn((
+                // Same mesh and material for the whole galaxy so they are drawn instanced
+                Mesh3d(mesh.clone()),
+                MeshMaterial3d(material.clone()),
+                MeshTag(index as u32),
+                Transform::from_translation(nebula.position).with_scale(Vec3::splat(nebula.radius)),
+                Nebula,
+                ChildOf(galaxy),
+                volume_render_layers(&galaxy_render_settings),
+            ));
+        }
+    }
+}
+
+/// The nebulae's extinction follows the stars in the cache, so it moves whenever the star count changes
+fn update_nebula_material(
+    galaxies: Query<(Ref<ExtinctionCache>, &GalaxyNebulae)>,
+    config: Res<NebulaConfig>,
+    mut materials: ResMut<Assets<NebulaMaterial>>,
+) {
+    for (extinction_cache, nebulae) in &galaxies {
+        if !extinction_cache.is_changed() && !config.is_changed() {
+            continue;
+        }
+        let Some(mat) = nebulae
+            .material
+            .as_ref()
+            .and_then(|material| materials.get_mut(material))
+        else {
+            continue;
+        };
+        mat.extinction = extinction_cache.output_buffer.clone();
+        mat.params = NebulaParams::read(&config, extinction_cache.required_size);
+    }
+}
+
+fn update_nebula_visibility(
+    mut commands: Commands,
+    camera: Query<&CameraMain>,
+    mut nebulae: Query<(Entity, &mut Visibility), With<Nebula>>,
+    galaxy_render_settings: Res<GalaxyRenderConfig>,
+) {
+    let map_view = camera
+        .single()
+        .is_ok_and(|camera| camera.map_blend() >= 1.0);
+    for (entity, mut visibility) in &mut nebulae {
+        visibility.set_if_neq(if map_view {
+            Visibility::Hidden
+        } else {
+            Visibility::Inherited
+        });
+        if galaxy_render_settings.is_changed() {
+            commands
+                .entity(entity)
+                .insert(volume_render_layers(&galaxy_render_settings));
+        }
+    }
+}
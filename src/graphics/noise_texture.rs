@@ -0,0 +1,91 @@
+use crate::prelude::*;
+use bevy::{
+    image::{ImageAddressMode, ImageFilterMode, ImageSampler, ImageSamplerDescriptor},
+    prelude::*,
+    render::{
+        render_asset::RenderAssetUsages,
+        render_resource::{Extent3d, TextureDimension, TextureFormat},
+    },
+};
+use rayon::prelude::*;
+
+/// Bakes one period of `Perlin3D` into a tiling 3D texture
+///
+/// The volume shader samples it once per octave instead of hashing eight cell corners, unless
+/// `GalaxyRenderConfig::baked_noise` is off. The noise doesn't depend on the galaxy, so every galaxy shares it.
+pub struct NoiseTexturePlugin;
+
+impl Plugin for NoiseTexturePlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<NoiseTexture>()
+            .add_systems(Update, update_noise_texture);
+    }
+}
+
+#[derive(Resource, Default)]
+pub struct NoiseTexture {
+    pub tex: Option<Handle<Image>>,
+    texels_per_cell: u32,
+}
+
+pub fn get_noise_texture(texels_per_cell: u32) -> Image {
+    // Gradient noise is zero on the lattice, one texel per cell would bake nothing
+    let texels_per_cell = texels_per_cell.max(2);
+    let dimension = NOISE_PERIOD * texels_per_cell;
+
+    let mut texture_data = vec![0u8; (dimension * dimension * dimension * 2) as usize];
+    texture_data
+        .par_chunks_exact_mut(2)
+        .enumerate()
+        .for_each(|(i, chunk)| {
+            let i = i as u32;
+            let texel = uvec3(
+                i % dimension,
+                (i / dimension) % dimension,
+                i / (dimension * dimension),
+            );
+            // Texel centres sit on multiples of 1 / texels_per_cell, so the lattice points are sampled exactly
+            let p = texel.as_vec3() / texels_per_cell as f32;
+            chunk.copy_from_slice(&(perlin_3d(p) as f16).to_le_bytes());
+        });
+
+    let mut image = Image::new(
+        Extent3d {
+            width: dimension,
+            height: dimension,
+            depth_or_array_layers: dimension,
+        },
+        TextureDimension::D3,
+        texture_data,
+        TextureFormat::R16Float,
+        RenderAssetUsages::RENDER_WORLD,
+    );
+    image.sampler = ImageSampler::Descriptor(ImageSamplerDescriptor {
+        address_mode_u: ImageAddressMode::Repeat,
+        address_mode_v: ImageAddressMode::Repeat,
+        address_mode_w: ImageAddressMode::Repeat,
+        mag_filter: ImageFilterMode::Linear,
+        min_filter: ImageFilterMode::Linear,
+        ..default()
+    });
+    image
+}
+
+fn update_noise_texture(
+    mut images: ResMut<Assets<Image>>,
+    mut noise: ResMut<NoiseTexture>,
+    render_settings: Res<GalaxyRenderConfig>,
+) {
+    if !render_settings.baked_noise {
+        // Freed while the runtime noise is used
+        if noise.tex.is_some() {
+            noise.tex = None;
+        }
+        return;
+    }
+    if noise.tex.is_none() || noise.texels_per_cell != render_settings.noise_texels_per_cell {
+        info!("Baking noise texture");
+        noise.tex = Some(images.add(get_noise_texture(render_settings.noise_texels_per_cell)));
+        noise.texels_per_cell = render_settings.noise_texels_per_cell;
+    }
+}
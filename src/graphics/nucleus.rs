@@ -0,0 +1,471 @@
+use super::{volume_render_layers, GalaxyCamera};
+use crate::prelude::*;
+use crate::ui::CameraMain;
+use bevy::{
+    core_pipeline::{
+        core_3d::graph::{Core3d, Node3d},
+        fullscreen_vertex_shader::fullscreen_shader_vertex_state,
+    },
+    ecs::query::QueryItem,
+    pbr::{MaterialPipeline, MaterialPipelineKey},
+    prelude::*,
+    reflect::TypePath,
+    render::{
+        extract_component::{
+            ComponentUniforms, DynamicUniformIndex, ExtractComponent, ExtractComponentPlugin,
+            UniformComponentPlugin,
+        },
+        mesh::MeshVertexBufferLayoutRef,
+        render_graph::{
+            NodeRunError, RenderGraphApp, RenderGraphContext, RenderLabel, ViewNode, ViewNodeRunner,
+        },
+        render_resource::{
+            binding_types::{sampler, texture_2d, uniform_buffer},
+            *,
+        },
+        renderer::{RenderContext, RenderDevice},
+        view::{NoFrustumCulling, ViewTarget},
+        RenderApp,
+    },
+};
+
+const SHADER_ASSET_PATH: &str = "shaders/nucleus.wgsl";
+const LENSING_SHADER_ASSET_PATH: &str = "shaders/nucleus_lensing.wgsl";
+
+/// Central supermassive black hole of each galaxy
+///
+/// The accretion disk and the bipolar jets are an emissive volume drawn on top of the galaxy volume,
+/// the lensing is a screen space distortion around the primary galaxy's nucleus.
+pub struct NucleusPlugin;
+
+impl Plugin for NucleusPlugin {
+    fn build(&self, app: &mut App) {
+        app.register_required_components::<GalaxyConfig, NucleusConfig>()
+            .add_plugins((
+                MaterialPlugin::<NucleusMaterial>::default(),
+                ExtractComponentPlugin::<NucleusLensing>::default(),
+                UniformComponentPlugin::<NucleusLensing>::default(),
+            ))
+            .add_systems(Startup, setup_nucleus_mesh)
+            .add_systems(
+                Update,
+                (
+                    spawn_nuclei,
+                    update_nucleus_material,
+                    update_nucleus_visibility,
+                    update_lensing,
+                ),
+            );
+
+        let Some(render_app) = app.get_sub_app_mut(RenderApp) else {
+            return;
+        };
+        render_app
+            .add_render_graph_node::<ViewNodeRunner<NucleusLensingNode>>(
+                Core3d,
+                NucleusLensingLabel,
+            )
+            .add_render_graph_edges(
+                Core3d,
+                (Node3d::Bloom, NucleusLensingLabel, Node3d::Tonemapping),
+            );
+    }
+
+    fn finish(&self, app: &mut App) {
+        let Some(render_app) = app.get_sub_app_mut(RenderApp) else {
+            return;
+        };
+        render_app.init_resource::<NucleusLensingPipeline>();
+    }
+}
+
+/// Settings of a galaxy's nucleus, added to every galaxy
+/// Sizes are in parsecs and heavily exaggerated, a real accretion disk would be far smaller than a pixel
+#[derive(Component, Clone, PartialEq)]
+pub struct NucleusConfig {
+    pub enabled: bool,
+    pub disk_radius: f32,
+    pub disk_brightness: f32,
+    pub jets: bool,
+    pub jet_length: f32,
+    /// Half angle of the jet cones, in degrees
+    pub jet_opening_angle: f32,
+    pub jet_brightness: f32,
+    pub jet_noise_scale: f32,
+    /// Speed the jet's knots travel outwards, in parsecs per second
+    pub jet_speed: f32,
+    /// Only drawn for the primary galaxy
+    pub lensing: bool,
+    pub einstein_radius: f32,
+    /// Radius of the black disk in the middle, relative to the Einstein radius
+    pub shadow_radius: f32,
+}
+
+impl Default for NucleusConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            disk_radius: 8.0,
+            disk_brightness: 1.0,
+            jets: true,
+            jet_length: 150.0,
+            jet_opening_angle: 4.0,
+            jet_brightness: 1.0,
+            jet_noise_scale: 6.0,
+            jet_speed: 10.0,
+            lensing: false,
+            einstein_radius: 5.0,
+            shadow_radius: 0.4,
+        }
+    }
+}
+
+#[derive(ShaderType, Clone, Copy, Debug, Default, PartialEq)]
+struct NucleusParams {
+    half_size: Vec4,
+    disk_radius: f32,
+    disk_brightness: f32,
+    jet_length: f32,
+    /// Tangent of the cone half angle
+    jet_opening: f32,
+    jet_brightness: f32,
+    jet_noise_scale: f32,
+    jet_speed: f32,
+}
+
+impl NucleusParams {
+    fn read(config: &NucleusConfig) -> Self {
+        let jet_opening = config.jet_opening_angle.to_radians().tan();
+        let (jet_length, jet_brightness) = if config.jets {
+            (config.jet_length, config.jet_brightness)
+        } else {
+            (0.0, 0.0)
+        };
+        // Bounds of the raymarch, the disk is intersected analytically so only needs to fit inside
+        let half_width = config.disk_radius.max(jet_length * jet_opening) * 1.2;
+        let half_height = jet_length.max(config.disk_radius * 0.5) * 1.05;
+        Self {
+            half_size: vec4(half_width, half_height, half_width, 0.0),
+            disk_radius: config.disk_radius,
+            disk_brightness: config.disk_brightness,
+            jet_length,
+            jet_opening,
+            jet_brightness,
+            jet_noise_scale: config.jet_noise_scale,
+            jet_speed: config.jet_speed,
+        }
+    }
+}
+
+#[derive(Asset, TypePath, AsBindGroup, Debug, Clone, Default)]
+struct NucleusMaterial {
+    #[uniform(0)]
+    params: NucleusParams,
+}
+
+impl Material for NucleusMaterial {
+    fn vertex_shader() -> ShaderRef {
+        SHADER_ASSET_PATH.into()
+    }
+    fn fragment_shader() -> ShaderRef {
+        SHADER_ASSET_PATH.into()
+    }
+    fn alpha_mode(&self) -> AlphaMode {
+        AlphaMode::Add
+    }
+
+    // Back faces, so the volume is still drawn with the camera inside it
+    fn specialize(
+        _pipeline: &MaterialPipeline<Self>,
+        descriptor: &mut RenderPipelineDescriptor,
+        _layout: &MeshVertexBufferLayoutRef,
+        _key: MaterialPipelineKey<Self>,
+    ) -> Result<(), SpecializedMeshPipelineError> {
+        descriptor.primitive.cull_mode = Some(Face::Front);
+        Ok(())
+    }
+}
+
+/// Volume of a galaxy's nucleus, a child of the galaxy entity
+#[derive(Component)]
+struct Nucleus;
+
+#[derive(Resource)]
+struct NucleusMesh(Handle<Mesh>);
+
+fn setup_nucleus_mesh(mut commands: Commands, mut meshes: ResMut<Assets<Mesh>>) {
+    // Scaled to the bounds in the shader
+    commands.insert_resource(NucleusMesh(meshes.add(Cuboid::new(2.0, 2.0, 2.0))));
+}
+
+fn spawn_nuclei(
+    mut commands: Commands,
+    galaxies: Query<(Entity, &NucleusConfig), Added<GalaxyConfig>>,
+    nucleus_mesh: Res<NucleusMesh>,
+    mut materials: ResMut<Assets<NucleusMaterial>>,
+    galaxy_render_settings: Res<GalaxyRenderConfig>,
+) {
+    for (galaxy, config) in &galaxies {
+        commands.spawn((
+            Mesh3d(nucleus_mesh.0.clone()),
+            MeshMaterial3d(materials.add(NucleusMaterial {
+                params: NucleusParams::read(config),
+            })),
+            Transform::IDENTITY,
+            Visibility::Hidden,
+            Nucleus,
+            ChildOf(galaxy),
+            volume_render_layers(&galaxy_render_settings),
+            NoFrustumCulling,
+        ));
+    }
+}
+
+fn update_nucleus_material(
+    nuclei: Query<(&MeshMaterial3d<NucleusMaterial>, &ChildOf), With<Nucleus>>,
+    galaxies: Query<Ref<NucleusConfig>>,
+    mut materials: ResMut<Assets<NucleusMaterial>>,
+) {
+    for (material, child_of) in &nuclei {
+        let Ok(config) = galaxies.get(child_of.parent()) else {
+            continue;
+        };
+        if !config.is_changed() {
+            continue;
+        }
+        if let Some(mat) = materials.get_mut(&material.0) {
+            mat.params = NucleusParams::read(&config);
+        }
+    }
+}
+
+fn update_nucleus_visibility(
+    mut commands: Commands,
+    camera: Query<&CameraMain>,
+    mut nuclei: Query<(Entity, &mut Visibility, &ChildOf), With<Nucleus>>,
+    galaxies: Query<&NucleusConfig>,
+    galaxy_render_settings: Res<GalaxyRenderConfig>,
+) {
+    let map_view = camera
+        .single()
+        .is_ok_and(|camera| camera.map_blend() >= 1.0);
+    for (entity, mut visibility, child_of) in &mut nuclei {
+        let enabled = galaxies
+            .get(child_of.parent())
+            .is_ok_and(|config| config.enabled);
+        visibility.set_if_neq(if enabled && !map_view {
+            Visibility::Inherited
+        } else {
+            Visibility::Hidden
+        });
+        if galaxy_render_settings.is_changed() {
+            commands
+                .entity(entity)
+                .insert(volume_render_layers(&galaxy_render_settings));
+        }
+    }
+}
+
+/// Point lens around the nucleus, on the galaxy camera while it's in view
+/// Positions are in screen uv, the radii in units of the screen height
+#[derive(Component, Clone, Copy, Default, ExtractComponent, ShaderType)]
+struct NucleusLensing {
+    centre: Vec2,
+    einstein_radius: f32,
+    aspect: f32,
+    shadow_radius: f32,
+}
+
+fn update_lensing(
+    mut commands: Commands,
+    mut cameras: Query<
+        (
+            Entity,
+            &Camera,
+            &GlobalTransform,
+            Option<&mut NucleusLensing>,
+        ),
+        With<GalaxyCamera>,
+    >,
+    main_camera: Query<&CameraMain>,
+    galaxies: Query<(&NucleusConfig, &GlobalTransform), With<PrimaryGalaxy>>,
+) {
+    let map_view = main_camera
+        .single()
+        .is_ok_and(|camera| camera.map_blend() > 0.0);
+    let galaxy = galaxies
+        .single()
+        .ok()
+        .filter(|(config, _)| config.enabled && config.lensing && !map_view);
+
+    for (entity, camera, camera_transform, lensing) in &mut cameras {
+        let new_lensing = galaxy.and_then(|(config, transform)| {
+            let centre = transform.translation();
+            let to_centre = centre - camera_transform.translation();
+            // Behind the camera, or so close the whole screen would be inside the lens
+            if to_centre.dot(*camera_transform.forward()) <= 0.0
+                || to_centre.length() < config.einstein_radius * 4.0
+            {
+                return None;
+            }
+            let size = camera.logical_viewport_size()?;
+            let screen_centre = camera.world_to_viewport(camera_transform, centre).ok()?;
+            let edge = camera
+                .world_to_viewport(
+                    camera_transform,
+                    centre + camera_transform.right() * config.einstein_radius,
+                )
+                .ok()?;
+            Some(NucleusLensing {
+                centre: screen_centre / size,
+                einstein_radius: screen_centre.distance(edge) / size.y,
+                aspect: size.x / size.y,
+                shadow_radius: config.shadow_radius,
+            })
+        });
+
+        match (new_lensing, lensing) {
+            (Some(new_lensing), Some(mut lensing)) => *lensing = new_lensing,
+            (Some(new_lensing), None) => {
+                commands.entity(entity).insert(new_lensing);
+            }
+            (None, Some(_)) => {
+                commands.entity(entity).remove::<NucleusLensing>();
+            }
+            (None, None) => {}
+        }
+    }
+}
+
+#[derive(Debug, Hash, PartialEq, Eq, Clone, RenderLabel)]
+struct NucleusLensingLabel;
+
+#[derive(Default)]
+struct NucleusLensingNode;
+
+impl ViewNode for NucleusLensingNode {
+    type ViewQuery = (
+        &'static ViewTarget,
+        &'static DynamicUniformIndex<NucleusLensing>,
+    );
+
+    fn run(
+        &self,
+        _graph: &mut RenderGraphContext,
+        render_context: &mut RenderContext,
+        (view_target, lensing_index): QueryItem<Self::ViewQuery>,
+        world: &World,
+    ) -> Result<(), NodeRunError> {
+        let lensing_pipeline = world.resource::<NucleusLensingPipeline>();
+        let pipeline_cache = world.resource::<PipelineCache>();
+        let pipeline_id = if view_target.is_hdr() {
+            lensing_pipeline.hdr_pipeline_id
+        } else {
+            lensing_pipeline.pipeline_id
+        };
+        let Some(pipeline) = pipeline_cache.get_render_pipeline(pipeline_id) else {
+            return Ok(());
+        };
+
+        let lensing_uniforms = world.resource::<ComponentUniforms<NucleusLensing>>();
+        let Some(lensing_binding) = lensing_uniforms.uniforms().binding() else {
+            return Ok(());
+        };
+
+        // Reads the current main texture and writes the distorted image to the other one
+        let post_process = view_target.post_process_write();
+        let bind_group = render_context.render_device().create_bind_group(
+            "nucleus_lensing_bind_group",
+            &lensing_pipeline.layout,
+            &BindGroupEntries::sequential((
+                post_process.source,
+                &lensing_pipeline.sampler,
+                lensing_binding.clone(),
+            )),
+        );
+
+        let mut render_pass = render_context.begin_tracked_render_pass(RenderPassDescriptor {
+            label: Some("nucleus_lensing_pass"),
+            color_attachments: &[Some(RenderPassColorAttachment {
+                view: post_process.destination,
+                resolve_target: None,
+                ops: Operations::default(),
+            })],
+            depth_stencil_attachment: None,
+            timestamp_writes: None,
+            occlusion_query_set: None,
+        });
+        render_pass.set_render_pipeline(pipeline);
+        render_pass.set_bind_group(0, &bind_group, &[lensing_index.index()]);
+        render_pass.draw(0..3, 0..1);
+
+        Ok(())
+    }
+}
+
+#[derive(Resource)]
+struct NucleusLensingPipeline {
+    layout: BindGroupLayout,
+    sampler: Sampler,
+    pipeline_id: CachedRenderPipelineId,
+    hdr_pipeline_id: CachedRenderPipelineId,
+}
+
+impl FromWorld for NucleusLensingPipeline {
+    fn from_world(world: &mut World) -> Self {
+        let render_device = world.resource::<RenderDevice>();
+
+        let layout = render_device.create_bind_group_layout(
+            "nucleus_lensing_bind_group_layout",
+            &BindGroupLayoutEntries::sequential(
+                ShaderStages::FRAGMENT,
+                (
+                    texture_2d(TextureSampleType::Float { filterable: true }),
+                    sampler(SamplerBindingType::Filtering),
+                    uniform_buffer::<NucleusLensing>(true),
+                ),
+            ),
+        );
+        // Clamped, light deflected from outside the screen just repeats the edge
+        let sampler = render_device.create_sampler(&SamplerDescriptor {
+            label: Some("nucleus_lensing_sampler"),
+            mag_filter: FilterMode::Linear,
+            min_filter: FilterMode::Linear,
+            ..SamplerDescriptor::default()
+        });
+
+        let shader = world.load_asset(LENSING_SHADER_ASSET_PATH);
+        let descriptor = |format: TextureFormat| RenderPipelineDescriptor {
+            label: Some("nucleus_lensing_pipeline".into()),
+            layout: vec![layout.clone()],
+            vertex: fullscreen_shader_vertex_state(),
+            fragment: Some(FragmentState {
+                shader: shader.clone(),
+                shader_defs: vec![],
+                entry_point: "fragment".into(),
+                targets: vec![Some(ColorTargetState {
+                    format,
+                    blend: None,
+                    write_mask: ColorWrites::ALL,
+                })],
+            }),
+            primitive: PrimitiveState::default(),
+            depth_stencil: None,
+            multisample: MultisampleState::default(),
+            push_constant_ranges: vec![],
+            zero_initialize_workgroup_memory: false,
+        };
+        let pipeline_cache = world.resource_mut::<PipelineCache>();
+        let pipeline_id =
+            pipeline_cache.queue_render_pipeline(descriptor(TextureFormat::bevy_default()));
+        let hdr_pipeline_id =
+            pipeline_cache.queue_render_pipeline(descriptor(ViewTarget::TEXTURE_FORMAT_HDR));
+
+        Self {
+            layout,
+            sampler,
+            pipeline_id,
+            hdr_pipeline_id,
+        }
+    }
+}
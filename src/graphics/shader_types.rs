@@ -1,4 +1,5 @@
 use crate::prelude::*;
+use bevy::prelude::*;
 use bevy::render::render_resource::ShaderType;
 use bytemuck::{Pod, Zeroable};
 
@@ -15,6 +16,10 @@ pub struct GalaxyParams {
     exposure: f32,
     raymarch_steps: f32,
     texture_dimension: f32,
+    dust_albedo: f32,
+    dust_anisotropy: f32,
+    scattering_intensity: f32,
+    max_step_scale: f32,
 }
 
 impl GalaxyParams {
@@ -29,24 +34,54 @@ impl GalaxyParams {
             exposure: galaxy_render_settings.exposure,
             raymarch_steps: galaxy_render_settings.raymarch_steps as f32,
             texture_dimension: galaxy_render_settings.texture_dimension as f32,
+            dust_albedo: galaxy_render_settings.dust_albedo,
+            dust_anisotropy: galaxy_render_settings.dust_anisotropy,
+            scattering_intensity: galaxy_render_settings.scattering_intensity,
+            max_step_scale: galaxy_render_settings.max_step_scale,
         }
     }
 }
 
-#[derive(ShaderType, Pod, Zeroable, Clone, Copy, Debug, Default)]
-#[repr(C)]
+#[derive(ShaderType, Clone, Copy, Debug, Default)]
 pub struct BulgeParams {
     strength: f32,
     r0: f32, // (inverse) width
     intensity_mod: f32,
+    sersic_index: f32,
+    axis_ratio_y: f32,
+    axis_ratio_z: f32,
+    boxiness: f32,
+    peanut: f32,
+    major_axis: Vec2,
+    color: Vec4,
+    outer_color: Vec4,
+}
+
+/// Inner and outer colour, the outer colour's w is the gradient radius or zero without a gradient
+fn color_params(color: &ComponentColor) -> (Vec4, Vec4) {
+    let gradient_radius = if color.radial_gradient {
+        color.gradient_radius.max(0.0)
+    } else {
+        0.0
+    };
+    (color.inner.extend(0.0), color.outer.extend(gradient_radius))
 }
 
 impl BulgeParams {
     pub fn read(config: &GalaxyConfig) -> Self {
+        let (color, outer_color) = color_params(&config.bulge_color);
         Self {
             strength: config.bulge_strength,
             r0: config.bulge_radius,
             intensity_mod: config.bulge_intensity,
+            sersic_index: config.bulge_shape.sersic_index.max(0.1),
+            axis_ratio_y: config.bulge_shape.axis_ratio_y,
+            axis_ratio_z: config.bulge_shape.axis_ratio_z,
+            boxiness: config.bulge_shape.boxiness.max(2.0),
+            peanut: config.bulge_shape.peanut,
+            major_axis: config.bulge_shape.major_axis(),
+            color,
+            outer_color,
         }
     }
 }
@@ -66,10 +101,13 @@ pub struct ComponentParams {
     noise_tilt: f32,
     noise_persistence: f32,
     noise_octaves: f32,
+    color: Vec4,
+    outer_color: Vec4,
 }
 
 impl ComponentParams {
     pub fn read(component: &ComponentConfig) -> Self {
+        let (color, outer_color) = color_params(&component.color);
         Self {
             strength: if component.enabled {
                 component.strength
@@ -91,6 +129,73 @@ impl ComponentParams {
             } else {
                 0.0
             },
+            color,
+            outer_color,
+        }
+    }
+}
+
+// Duplicated in intensity_shared.wgsl
+#[derive(ShaderType, Clone, Copy, Debug, Default)]
+pub struct SpectralParams {
+    /// Dust optical depth per bin, relative to the B band
+    extinction_lo: Vec4,
+    extinction_hi: Vec4,
+    disk_lo: Vec4,
+    disk_hi: Vec4,
+    bulge_lo: Vec4,
+    bulge_hi: Vec4,
+    /// Linear sRGB contribution of each bin through the filter, w unused
+    response: [Vec4; 8],
+}
+
+impl SpectralParams {
+    pub fn read(galaxy_render_settings: &GalaxyRenderConfig) -> Self {
+        let split = |bins: [f32; 8]| (Vec4::from_slice(&bins[..4]), Vec4::from_slice(&bins[4..]));
+        let (extinction_lo, extinction_hi) =
+            split(galaxy_render_settings.reddening_law.extinction_curve());
+        let (disk_lo, disk_hi) = split(disk_spectrum());
+        let (bulge_lo, bulge_hi) = split(bulge_spectrum());
+        Self {
+            extinction_lo,
+            extinction_hi,
+            disk_lo,
+            disk_hi,
+            bulge_lo,
+            bulge_hi,
+            response: galaxy_render_settings
+                .spectral_filter
+                .response()
+                .map(|col| col.extend(0.0)),
+        }
+    }
+}
+
+// Duplicated in intensity_shared.wgsl
+#[derive(ShaderType, Clone, Copy, Debug, Default)]
+pub struct VisualizationParams {
+    colormap: [Vec4; 7],
+    mode: f32,
+    range: f32,
+    log_scale: f32,
+    stars_y_thickness: f32,
+}
+
+impl VisualizationParams {
+    pub fn read(config: &GalaxyConfig, galaxy_render_settings: &GalaxyRenderConfig) -> Self {
+        Self {
+            colormap: galaxy_render_settings
+                .colormap
+                .coefficients()
+                .map(|c| c.extend(0.0)),
+            mode: galaxy_render_settings.visualization.shader_id(),
+            range: galaxy_render_settings.visualization_range.max(1e-6),
+            log_scale: if galaxy_render_settings.visualization_log_scale {
+                1.0
+            } else {
+                0.0
+            },
+            stars_y_thickness: config.stars_params.y_thickness,
         }
     }
 }
@@ -1,136 +1,499 @@
 use crate::graphics::ExtinctionCache;
 use crate::prelude::*;
+use crate::ui::CameraMain;
 use bevy::{
+    core_pipeline::core_3d::{
+        graph::{Core3d, Node3d},
+        CORE_3D_DEPTH_FORMAT,
+    },
+    ecs::query::QueryItem,
+    image::BevyDefault as _,
     prelude::*,
-    reflect::TypePath,
     render::{
-        mesh::MeshTag,
-        render_resource::{AsBindGroup, ShaderRef},
-        storage::ShaderStorageBuffer,
+        render_asset::RenderAssets,
+        render_graph::{
+            NodeRunError, RenderGraphApp, RenderGraphContext, RenderLabel, ViewNode, ViewNodeRunner,
+        },
+        render_resource::{
+            binding_types::{storage_buffer_read_only, uniform_buffer},
+            *,
+        },
+        renderer::{RenderContext, RenderDevice, RenderQueue},
+        storage::{GpuShaderStorageBuffer, ShaderStorageBuffer},
+        sync_world::RenderEntity,
+        view::{
+            ExtractedView, Msaa, RenderLayers, ViewDepthTexture, ViewTarget, ViewUniform,
+            ViewUniformOffset, ViewUniforms,
+        },
+        Extract, ExtractSchedule, Render, RenderApp, RenderSet,
     },
 };
 
 const SHADER_ASSET_PATH: &str = "shaders/star_instancing.wgsl";
 
+/// Draws every star of each galaxy in a single instanced draw
+///
+/// Stars have no entities or meshes, the vertex shader reads their positions and colours after extinction
+/// straight from the galaxy's [`ExtinctionCache`] buffers, so a galaxy can hold millions of them.
+/// The stars are added on top of the main pass, after the transparent phase.
 pub struct StarInstancingPlugin;
 
 impl Plugin for StarInstancingPlugin {
     fn build(&self, app: &mut App) {
-        app.add_plugins(MaterialPlugin::<StarInstanceMaterial>::default())
-            .add_systems(Startup, init_resource)
-            .add_systems(PostUpdate, (manage_star_instances, update_material));
+        app.insert_resource(StarPsfConfig::default());
+
+        let Some(render_app) = app.get_sub_app_mut(RenderApp) else {
+            return;
+        };
+        render_app
+            .init_resource::<ExtractedStarBatches>()
+            .init_resource::<StarBatchUniforms>()
+            .add_systems(ExtractSchedule, extract_star_batches)
+            .add_systems(
+                Render,
+                (
+                    prepare_star_pipelines.in_set(RenderSet::Prepare),
+                    prepare_star_uniforms.in_set(RenderSet::PrepareResources),
+                    prepare_star_bind_groups.in_set(RenderSet::PrepareBindGroups),
+                ),
+            )
+            .add_render_graph_node::<ViewNodeRunner<StarRenderNode>>(Core3d, StarRenderLabel)
+            .add_render_graph_edges(
+                Core3d,
+                (
+                    Node3d::MainTransparentPass,
+                    StarRenderLabel,
+                    Node3d::EndMainPass,
+                ),
+            );
+    }
+
+    fn finish(&self, app: &mut App) {
+        let Some(render_app) = app.get_sub_app_mut(RenderApp) else {
+            return;
+        };
+        render_app
+            .init_resource::<StarRenderPipeline>()
+            .init_resource::<SpecializedRenderPipelines<StarRenderPipeline>>();
     }
 }
 
-#[derive(Resource)]
-struct StarInstancingControl {
-    mesh_handle: Handle<Mesh>,
-    material_handle: Handle<StarInstanceMaterial>,
+/// Point spread function of the brightest stars, the rest stay simple points
+#[derive(Resource, Clone, PartialEq)]
+pub struct StarPsfConfig {
+    pub enabled: bool,
+    /// Stars whose colour after extinction sums above this get the PSF
+    pub threshold: f32,
+    /// Billboard size of PSF stars relative to plain ones, the spikes are clipped to it
+    pub size: f32,
+    pub brightness: f32,
+    /// Inverse width of the Airy core, at 650nm
+    pub core_scale: f32,
+    pub halo_strength: f32,
+    pub halo_radius: f32,
+    pub spike_strength: f32,
+    pub spike_length: f32,
+    /// Secondary mirror support vanes, an even count gives as many spikes and an odd count twice as many
+    pub vane_count: u32,
+    /// Of the spikes, in degrees
+    pub rotation: f32,
+}
+
+impl Default for StarPsfConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            threshold: 8.0,
+            size: 4.0,
+            brightness: 64.0,
+            core_scale: 6.0,
+            halo_strength: 0.02,
+            halo_radius: 0.5,
+            spike_strength: 0.05,
+            spike_length: 2.0,
+            vane_count: 4,
+            rotation: 0.0,
+        }
+    }
+}
+
+// Duplicated in star_instancing.wgsl
+#[derive(ShaderType, Clone, Copy, Debug, Default)]
+struct StarPsfParams {
+    threshold: f32,
+    size: f32,
+    brightness: f32,
+    core_scale: f32,
+    halo_strength: f32,
+    halo_radius: f32,
+    spike_strength: f32,
+    spike_length: f32,
+    spike_lines: f32,
+    rotation: f32,
+}
+
+impl StarPsfParams {
+    fn read(config: &StarPsfConfig) -> Self {
+        let vanes = config.vane_count.max(1);
+        Self {
+            // Nothing passes an infinite threshold
+            threshold: if config.enabled {
+                config.threshold
+            } else {
+                f32::MAX
+            },
+            size: config.size.max(1.0),
+            brightness: config.brightness,
+            core_scale: config.core_scale,
+            halo_strength: config.halo_strength,
+            halo_radius: config.halo_radius.max(0.001),
+            spike_strength: config.spike_strength,
+            spike_length: config.spike_length.max(0.001),
+            // Opposite vanes share a spike
+            spike_lines: if vanes.is_multiple_of(2) {
+                vanes / 2
+            } else {
+                vanes
+            } as f32,
+            rotation: config.rotation.to_radians(),
+        }
+    }
+}
+
+// Duplicated in star_instancing.wgsl
+#[derive(ShaderType, Clone, Copy, Debug, Default)]
+struct StarBatchParams {
+    world_from_local: Mat4,
+    point_scale: f32,
+    supersampling_offset_scale: f32,
+}
+
+/// The stars of one galaxy
+struct ExtractedStarBatch {
+    positions: Handle<ShaderStorageBuffer>,
+    colours: Handle<ShaderStorageBuffer>,
+    count: u32,
+    params: StarBatchParams,
+}
+
+#[derive(Resource, Default)]
+struct ExtractedStarBatches {
+    batches: Vec<ExtractedStarBatch>,
+    psf: StarPsfParams,
+    /// Cameras that see these layers draw the stars
+    layers: RenderLayers,
+}
+
+/// Render layers of a camera, on its view in the render world
+#[derive(Component)]
+struct StarViewLayers(RenderLayers);
+
+fn supersampling_offset_scale(galaxy_render_settings: &GalaxyRenderConfig) -> f32 {
+    if galaxy_render_settings.draw_stars_to_background {
+        0.25
+    } else {
+        1.0
+    }
 }
 
-/// Sets up the star instancing resource with the shared material and mesh
-fn init_resource(
+#[allow(clippy::type_complexity)]
+fn extract_star_batches(
     mut commands: Commands,
-    mut meshes: ResMut<Assets<Mesh>>,
-    mut materials: ResMut<Assets<StarInstanceMaterial>>,
+    mut extracted: ResMut<ExtractedStarBatches>,
+    galaxy_render_settings: Extract<Res<GalaxyRenderConfig>>,
+    psf_config: Extract<Res<StarPsfConfig>>,
+    main_camera: Extract<Query<&CameraMain>>,
+    galaxies: Extract<Query<(&GalaxyConfig, &ExtinctionCache, &GlobalTransform)>>,
+    cameras: Extract<Query<(RenderEntity, Option<&RenderLayers>), With<Camera3d>>>,
 ) {
-    let mesh_handle = meshes.add(Rectangle::from_size(Vec2::splat(2.0)));
-
-    let material_handle = materials.add(StarInstanceMaterial {
-        alpha_mode: AlphaMode::Add,
-        colors: Handle::default(),
-        supersampling_offset_scale: 1.0,
+    // Stars are sized in world units, so they need to grow in the map view to stay visible as points
+    let point_scale = main_camera.single().map_or(1.0, |camera| {
+        f32::lerp(1.0, camera.visible_height() / 250.0, camera.map_blend()).max(1.0)
     });
 
-    commands.insert_resource(StarInstancingControl {
-        mesh_handle,
-        material_handle,
-    });
+    extracted.batches.clear();
+    for (galaxy_config, extinction, transform) in &galaxies {
+        if !galaxy_config.stars_params.enabled || extinction.required_size == 0 {
+            continue;
+        }
+        extracted.batches.push(ExtractedStarBatch {
+            positions: extinction.positions_buffer.clone(),
+            colours: extinction.output_buffer.clone(),
+            count: extinction.required_size as u32,
+            params: StarBatchParams {
+                world_from_local: transform.compute_matrix(),
+                point_scale,
+                supersampling_offset_scale: supersampling_offset_scale(&galaxy_render_settings),
+            },
+        });
+    }
+    extracted.psf = StarPsfParams::read(&psf_config);
+    extracted.layers = if galaxy_render_settings.draw_stars_to_background {
+        volume_upscaler::background_render_layer()
+    } else {
+        RenderLayers::layer(0)
+    };
+
+    for (entity, layers) in &cameras {
+        commands
+            .entity(entity)
+            .insert(StarViewLayers(layers.cloned().unwrap_or_default()));
+    }
 }
 
-fn update_material(
-    star_instancing: Res<StarInstancingControl>,
-    extinction: Res<crate::graphics::ExtinctionCache>,
-    mut materials: ResMut<Assets<StarInstanceMaterial>>,
+/// One set of uniforms per galaxy, in the same order as [`ExtractedStarBatches`]
+#[derive(Resource, Default)]
+struct StarBatchUniforms {
+    psf: UniformBuffer<StarPsfParams>,
+    batches: Vec<UniformBuffer<StarBatchParams>>,
+}
+
+fn prepare_star_uniforms(
+    render_device: Res<RenderDevice>,
+    render_queue: Res<RenderQueue>,
+    extracted: Res<ExtractedStarBatches>,
+    mut uniforms: ResMut<StarBatchUniforms>,
 ) {
-    if extinction.is_changed() {
-        if let Some(mat) = materials.get_mut(&star_instancing.material_handle) {
-            mat.colors = extinction.output_buffer.clone();
-        };
+    uniforms.psf.set(extracted.psf);
+    uniforms.psf.write_buffer(&render_device, &render_queue);
+
+    uniforms
+        .batches
+        .resize_with(extracted.batches.len(), UniformBuffer::default);
+    for (batch, uniform) in extracted.batches.iter().zip(uniforms.batches.iter_mut()) {
+        uniform.set(batch.params);
+        uniform.write_buffer(&render_device, &render_queue);
     }
 }
 
-#[derive(Component)]
-pub struct StarInstanceMarker;
+/// Bind group and star count of each galaxy
+#[derive(Resource, Default)]
+struct StarBatchBindGroups(Vec<(BindGroup, u32)>);
 
-/// Spawns or despawns star instances
-/// Spawns in fairly small batches to avoid stutter when galaxy config changes
-/// - Might be a flag active during game loading that causes the spawn to run to finish
-#[allow(clippy::too_many_arguments)]
-fn manage_star_instances(
+fn prepare_star_bind_groups(
     mut commands: Commands,
-    galaxy_config: Res<GalaxyConfig>,
-    star_query: Query<(Entity, &Star), Without<StarInstanceMarker>>,
-    star_count: Res<StarCount>,
-    star_instancing: Res<StarInstancingControl>,
-    mut materials: ResMut<Assets<StarInstanceMaterial>>,
-    mut extinction: ResMut<ExtinctionCache>,
-    galaxy_render_settings: Res<GalaxyRenderConfig>,
+    pipeline: Res<StarRenderPipeline>,
+    extracted: Res<ExtractedStarBatches>,
+    uniforms: Res<StarBatchUniforms>,
+    render_device: Res<RenderDevice>,
+    ssbos: Res<RenderAssets<GpuShaderStorageBuffer>>,
 ) {
-    extinction.required_size = star_count.count;
-
-    if !galaxy_config.stars_params.enabled {
+    let Some(psf_binding) = uniforms.psf.binding() else {
         return;
+    };
+    let mut bind_groups = vec![];
+    for (batch, uniform) in extracted.batches.iter().zip(uniforms.batches.iter()) {
+        // Skip galaxies whose buffers aren't on the GPU yet
+        let (Some(positions), Some(colours), Some(batch_binding)) = (
+            ssbos.get(&batch.positions),
+            ssbos.get(&batch.colours),
+            uniform.binding(),
+        ) else {
+            continue;
+        };
+
+        let bind_group = render_device.create_bind_group(
+            "star_batch_bind_group",
+            &pipeline.batch_layout,
+            &BindGroupEntries::sequential((
+                batch_binding,
+                psf_binding.clone(),
+                positions.buffer.as_entire_buffer_binding(),
+                colours.buffer.as_entire_buffer_binding(),
+            )),
+        );
+        bind_groups.push((bind_group, batch.count));
     }
+    commands.insert_resource(StarBatchBindGroups(bind_groups));
+}
 
-    // update supersampling offset
-    if galaxy_render_settings.is_changed() {
-        if let Some(mat) = materials.get_mut(&star_instancing.material_handle) {
-            mat.supersampling_offset_scale = if galaxy_render_settings.draw_stars_to_background {
-                0.25
-            } else {
-                1.0
-            };
+#[derive(Debug, Hash, PartialEq, Eq, Clone, RenderLabel)]
+struct StarRenderLabel;
+
+#[derive(Default)]
+struct StarRenderNode;
+
+impl ViewNode for StarRenderNode {
+    type ViewQuery = (
+        &'static ViewTarget,
+        &'static ViewDepthTexture,
+        &'static ViewUniformOffset,
+        &'static StarViewLayers,
+        &'static StarPipelineId,
+    );
+
+    fn run(
+        &self,
+        _graph: &mut RenderGraphContext,
+        render_context: &mut RenderContext,
+        (view_target, depth, view_uniform_offset, view_layers, pipeline_id): QueryItem<
+            Self::ViewQuery,
+        >,
+        world: &World,
+    ) -> Result<(), NodeRunError> {
+        let extracted = world.resource::<ExtractedStarBatches>();
+        if !view_layers.0.intersects(&extracted.layers) {
+            return Ok(());
+        }
+        let Some(bind_groups) = world.get_resource::<StarBatchBindGroups>() else {
+            return Ok(());
         };
-    }
+        if bind_groups.0.is_empty() {
+            return Ok(());
+        }
 
-    // add instancing components to stars that need them
-    for (entity, star) in star_query {
-        commands
-            .entity(entity)
-            .insert((
-                // For automatic instancing to take effect you need to
-                // use the same mesh handle and material handle for each instance
-                Mesh3d(star_instancing.mesh_handle.clone()),
-                MeshMaterial3d(star_instancing.material_handle.clone()),
-                // This is an optional component that can be used to help tie external data to a mesh instance
-                MeshTag(star.index),
-                StarInstanceMarker,
-            ))
-            .insert_if(volume_upscaler::background_render_layer(), || {
-                galaxy_render_settings.draw_stars_to_background
-            });
+        let star_pipeline = world.resource::<StarRenderPipeline>();
+        let pipeline_cache = world.resource::<PipelineCache>();
+        let Some(pipeline) = pipeline_cache.get_render_pipeline(pipeline_id.0) else {
+            return Ok(());
+        };
+        let Some(view_uniforms) = world.resource::<ViewUniforms>().uniforms.binding() else {
+            return Ok(());
+        };
+        let view_bind_group = render_context.render_device().create_bind_group(
+            "star_view_bind_group",
+            &star_pipeline.view_layout,
+            &BindGroupEntries::single(view_uniforms),
+        );
+
+        let mut render_pass = render_context.begin_tracked_render_pass(RenderPassDescriptor {
+            label: Some("star_render_pass"),
+            color_attachments: &[Some(view_target.get_color_attachment())],
+            // Tested against the scene but not written, the stars are additive
+            depth_stencil_attachment: Some(depth.get_attachment(StoreOp::Store)),
+            timestamp_writes: None,
+            occlusion_query_set: None,
+        });
+        render_pass.set_render_pipeline(pipeline);
+        render_pass.set_bind_group(0, &view_bind_group, &[view_uniform_offset.offset]);
+        for (bind_group, count) in &bind_groups.0 {
+            render_pass.set_bind_group(1, bind_group, &[]);
+            // A quad of two triangles per star
+            render_pass.draw(0..6, 0..*count);
+        }
+
+        Ok(())
     }
 }
 
-#[derive(Asset, TypePath, AsBindGroup, Debug, Clone)]
-struct StarInstanceMaterial {
-    #[storage(0, read_only)]
-    colors: Handle<ShaderStorageBuffer>,
-    #[uniform(1)]
-    supersampling_offset_scale: f32,
-    alpha_mode: AlphaMode,
+#[derive(Resource)]
+struct StarRenderPipeline {
+    view_layout: BindGroupLayout,
+    batch_layout: BindGroupLayout,
+    shader: Handle<Shader>,
 }
 
-impl Material for StarInstanceMaterial {
-    fn vertex_shader() -> ShaderRef {
-        SHADER_ASSET_PATH.into()
+impl FromWorld for StarRenderPipeline {
+    fn from_world(world: &mut World) -> Self {
+        let render_device = world.resource::<RenderDevice>();
+
+        let view_layout = render_device.create_bind_group_layout(
+            "star_view_bind_group_layout",
+            &BindGroupLayoutEntries::single(
+                ShaderStages::VERTEX,
+                uniform_buffer::<ViewUniform>(true),
+            ),
+        );
+        let batch_layout = render_device.create_bind_group_layout(
+            "star_batch_bind_group_layout",
+            &BindGroupLayoutEntries::sequential(
+                ShaderStages::VERTEX_FRAGMENT,
+                (
+                    uniform_buffer::<StarBatchParams>(false),
+                    uniform_buffer::<StarPsfParams>(false),
+                    // Positions in the galaxy's local space
+                    storage_buffer_read_only::<Vec4>(false),
+                    // Colours after extinction
+                    storage_buffer_read_only::<Vec4>(false),
+                ),
+            ),
+        );
+
+        Self {
+            view_layout,
+            batch_layout,
+            shader: world.load_asset(SHADER_ASSET_PATH),
+        }
     }
+}
+
+#[derive(Component)]
+struct StarPipelineId(CachedRenderPipelineId);
 
-    fn fragment_shader() -> ShaderRef {
-        SHADER_ASSET_PATH.into()
+#[derive(PartialEq, Eq, Hash, Clone, Copy)]
+struct StarPipelineKey {
+    hdr: bool,
+    samples: u32,
+}
+
+impl SpecializedRenderPipeline for StarRenderPipeline {
+    type Key = StarPipelineKey;
+
+    fn specialize(&self, key: Self::Key) -> RenderPipelineDescriptor {
+        RenderPipelineDescriptor {
+            label: Some("star_render_pipeline".into()),
+            layout: vec![self.view_layout.clone(), self.batch_layout.clone()],
+            // The quad corners come from the vertex index, there are no vertex buffers
+            vertex: VertexState {
+                shader: self.shader.clone(),
+                shader_defs: vec![],
+                entry_point: "vertex".into(),
+                buffers: vec![],
+            },
+            fragment: Some(FragmentState {
+                shader: self.shader.clone(),
+                shader_defs: vec![],
+                entry_point: "fragment".into(),
+                targets: vec![Some(ColorTargetState {
+                    format: if key.hdr {
+                        ViewTarget::TEXTURE_FORMAT_HDR
+                    } else {
+                        TextureFormat::bevy_default()
+                    },
+                    blend: Some(BlendState {
+                        color: BlendComponent {
+                            src_factor: BlendFactor::One,
+                            dst_factor: BlendFactor::One,
+                            operation: BlendOperation::Add,
+                        },
+                        alpha: BlendComponent::OVER,
+                    }),
+                    write_mask: ColorWrites::ALL,
+                })],
+            }),
+            primitive: PrimitiveState::default(),
+            depth_stencil: Some(DepthStencilState {
+                format: CORE_3D_DEPTH_FORMAT,
+                depth_write_enabled: false,
+                // Reversed z
+                depth_compare: CompareFunction::GreaterEqual,
+                stencil: StencilState::default(),
+                bias: DepthBiasState::default(),
+            }),
+            multisample: MultisampleState {
+                count: key.samples,
+                mask: !0,
+                alpha_to_coverage_enabled: false,
+            },
+            push_constant_ranges: vec![],
+            zero_initialize_workgroup_memory: false,
+        }
     }
-    fn alpha_mode(&self) -> AlphaMode {
-        self.alpha_mode
+}
+
+fn prepare_star_pipelines(
+    mut commands: Commands,
+    pipeline_cache: Res<PipelineCache>,
+    mut pipelines: ResMut<SpecializedRenderPipelines<StarRenderPipeline>>,
+    star_pipeline: Res<StarRenderPipeline>,
+    views: Query<(Entity, &ExtractedView, &Msaa), With<StarViewLayers>>,
+) {
+    for (entity, view, msaa) in &views {
+        let key = StarPipelineKey {
+            hdr: view.hdr,
+            samples: msaa.samples(),
+        };
+        let pipeline_id = pipelines.specialize(&pipeline_cache, &star_pipeline, key);
+        commands.entity(entity).insert(StarPipelineId(pipeline_id));
     }
 }
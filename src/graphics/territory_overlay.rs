@@ -0,0 +1,155 @@
+use crate::prelude::*;
+use crate::ui::CameraMain;
+use bevy::{
+    prelude::*,
+    render::{mesh::Indices, render_asset::RenderAssetUsages, render_resource::PrimitiveTopology},
+};
+
+pub struct TerritoryOverlayPlugin;
+
+impl Plugin for TerritoryOverlayPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_systems(
+            Update,
+            (
+                rebuild_overlay_meshes,
+                (update_overlay_visibility, draw_sector_borders),
+            )
+                .chain(),
+        );
+    }
+}
+
+/// Fill mesh for a single sector
+#[derive(Component)]
+struct TerritoryOverlay;
+
+// Slightly above the plane so the fill doesn't z-fight with anything drawn at y=0
+const OVERLAY_HEIGHT: f32 = 0.5;
+
+fn faction_color(owner: Option<u32>, opacity: f32) -> Color {
+    match owner {
+        // golden angle hue spacing keeps neighbouring faction ids distinct
+        Some(owner) => Color::hsla((owner as f32 * 137.5) % 360.0, 0.7, 0.5, opacity),
+        None => Color::srgba(1.0, 1.0, 1.0, opacity * 0.25),
+    }
+}
+
+fn sector_mesh(sector: &Sector) -> Mesh {
+    // Fan around the centroid, sectors are either convex or close enough for this
+    let centroid = sector.centroid();
+    let mut positions = vec![[centroid.x, OVERLAY_HEIGHT, centroid.y]];
+    positions.extend(sector.boundary.iter().map(|p| [p.x, OVERLAY_HEIGHT, p.y]));
+
+    let n = sector.boundary.len() as u32;
+    let mut indices = Vec::with_capacity(n as usize * 3);
+    for i in 0..n {
+        indices.extend([0, 1 + i, 1 + (i + 1) % n]);
+    }
+
+    Mesh::new(
+        PrimitiveTopology::TriangleList,
+        RenderAssetUsages::default(),
+    )
+    .with_inserted_attribute(Mesh::ATTRIBUTE_POSITION, positions.clone())
+    .with_inserted_attribute(
+        Mesh::ATTRIBUTE_NORMAL,
+        vec![[0.0, 1.0, 0.0]; positions.len()],
+    )
+    .with_inserted_indices(Indices::U32(indices))
+}
+
+#[allow(clippy::too_many_arguments)]
+fn rebuild_overlay_meshes(
+    mut commands: Commands,
+    territories: Res<Territories>,
+    config: Res<TerritoryConfig>,
+    existing: Query<Entity, With<TerritoryOverlay>>,
+    galaxy: Query<Entity, With<PrimaryGalaxy>>,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut materials: ResMut<Assets<StandardMaterial>>,
+    mut layout_version: Local<Option<u32>>,
+) {
+    // Membership updates while stars spawn don't move any boundaries
+    if *layout_version == Some(territories.layout_version()) && !config.is_changed() {
+        return;
+    }
+    *layout_version = Some(territories.layout_version());
+    for entity in &existing {
+        commands.entity(entity).despawn();
+    }
+    let Ok(galaxy) = galaxy.single() else {
+        return;
+    };
+    if !config.show_overlay {
+        return;
+    }
+
+    for sector in territories.sectors() {
+        if sector.boundary.len() < 3 {
+            continue;
+        }
+        commands.spawn((
+            Mesh3d(meshes.add(sector_mesh(sector))),
+            MeshMaterial3d(materials.add(StandardMaterial {
+                base_color: faction_color(sector.owner, config.overlay_opacity),
+                alpha_mode: AlphaMode::Blend,
+                unlit: true,
+                double_sided: true,
+                cull_mode: None,
+                ..default()
+            })),
+            Transform::IDENTITY,
+            TerritoryOverlay,
+            // Sectors are in the primary galaxy's local space
+            ChildOf(galaxy),
+        ));
+    }
+}
+
+/// The overlay is only meant to be read from above
+fn update_overlay_visibility(
+    camera: Query<&CameraMain>,
+    mut overlays: Query<&mut Visibility, With<TerritoryOverlay>>,
+) {
+    let Ok(camera) = camera.single() else {
+        return;
+    };
+    let visibility = if camera.side_view() {
+        Visibility::Hidden
+    } else {
+        Visibility::Inherited
+    };
+    for mut overlay_visibility in &mut overlays {
+        overlay_visibility.set_if_neq(visibility);
+    }
+}
+
+fn draw_sector_borders(
+    mut gizmos: Gizmos,
+    territories: Res<Territories>,
+    config: Res<TerritoryConfig>,
+    camera: Query<&CameraMain>,
+    galaxy: Query<&GlobalTransform, With<PrimaryGalaxy>>,
+) {
+    if !config.show_overlay || camera.single().is_ok_and(|camera| camera.side_view()) {
+        return;
+    }
+    let Ok(galaxy_transform) = galaxy.single() else {
+        return;
+    };
+    let color = Color::srgba(0.8, 0.9, 1.0, (config.overlay_opacity * 2.0).min(1.0));
+    for sector in territories.sectors() {
+        let Some(first) = sector.boundary.first() else {
+            continue;
+        };
+        gizmos.linestrip(
+            sector
+                .boundary
+                .iter()
+                .chain(std::iter::once(first))
+                .map(|p| galaxy_transform.transform_point(vec3(p.x, OVERLAY_HEIGHT, p.y))),
+            color,
+        );
+    }
+}
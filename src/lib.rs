@@ -0,0 +1,99 @@
+#![feature(f16)]
+use bevy::prelude::*;
+use bevy_egui::EguiPlugin;
+
+pub mod capture;
+pub mod galaxy;
+pub mod graphics;
+pub mod ui;
+
+pub mod prelude;
+
+/// Adds the galaxy simulation and rendering to an app
+///
+/// The shaders are loaded from `assets/shaders`, so that folder needs to be copied into the app's assets.
+///
+/// By default this includes the orbit camera and the egui windows, which can be turned off for embedding:
+/// ```ignore
+/// app.add_plugins(GalaxyPlugin::default().without_ui().with_custom_camera());
+/// ```
+/// A custom camera needs the [`graphics::GalaxyCamera`] component to render the galaxy volume.
+///
+/// One galaxy is spawned at the origin unless turned off, any entity with a [`galaxy::GalaxyConfig`] is a galaxy.
+pub struct GalaxyPlugin {
+    ui: bool,
+    fps_widget: bool,
+    builtin_camera: bool,
+    default_galaxy: bool,
+}
+
+impl Default for GalaxyPlugin {
+    fn default() -> Self {
+        Self {
+            ui: true,
+            fps_widget: true,
+            builtin_camera: true,
+            default_galaxy: true,
+        }
+    }
+}
+
+impl GalaxyPlugin {
+    /// No egui windows, overlays or capture controls, and no dependency on `EguiPlugin`
+    pub fn without_ui(mut self) -> Self {
+        self.ui = false;
+        self
+    }
+
+    pub fn without_fps_widget(mut self) -> Self {
+        self.fps_widget = false;
+        self
+    }
+
+    /// Skips spawning the orbit camera, the app provides its own camera with [`graphics::GalaxyCamera`]
+    pub fn with_custom_camera(mut self) -> Self {
+        self.builtin_camera = false;
+        self
+    }
+
+    /// Skips spawning the default galaxy, the app spawns its own [`galaxy::GalaxyConfig`] entities
+    pub fn without_default_galaxy(mut self) -> Self {
+        self.default_galaxy = false;
+        self
+    }
+}
+
+impl Plugin for GalaxyPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_plugins((
+            galaxy::SpawnStarsPlugin,
+            graphics::StarInstancingPlugin,
+            galaxy::GalaxyConfigPlugin,
+            galaxy::TerritoryPlugin,
+            galaxy::MergerPlugin,
+            graphics::GraphicsPlugin,
+        ));
+
+        if self.default_galaxy {
+            app.add_systems(Startup, galaxy::spawn_default_galaxy);
+        }
+
+        if self.builtin_camera {
+            app.add_plugins(ui::CameraPlugin);
+        }
+
+        if self.ui {
+            if !app.is_plugin_added::<EguiPlugin>() {
+                app.add_plugins(EguiPlugin {
+                    enable_multipass_for_primary_context: false,
+                });
+            }
+            app.add_plugins((
+                ui::UiPlugin {
+                    fps_widget: self.fps_widget,
+                },
+                capture::CapturePlugin,
+            ));
+        }
+    }
+}
@@ -1,13 +1,7 @@
-#![feature(f16)]
 use bevy::prelude::*;
 use bevy::window::{PresentMode, WindowTheme};
 use bevy_egui::EguiPlugin;
-
-mod galaxy;
-mod graphics;
-mod ui;
-
-mod prelude;
+use galaxy_tracer::GalaxyPlugin;
 
 fn main() {
     //std::env::set_var("RUST_BACKTRACE", "1");
@@ -28,12 +22,6 @@ fn main() {
         .add_plugins(EguiPlugin {
             enable_multipass_for_primary_context: false,
         })
-        .add_plugins((
-            galaxy::SpawnStarsPlugin,
-            graphics::StarInstancingPlugin,
-            galaxy::GalaxyConfigPlugin,
-            ui::UiPlugin,
-            graphics::GraphicsPlugin,
-        ))
+        .add_plugins(GalaxyPlugin::default())
         .run();
 }
@@ -1,8 +1,9 @@
+use super::camera_paths::{CameraBookmark, CameraViewMode};
+use crate::graphics::{ExposureConfig, GalaxyCamera};
 use crate::prelude::*;
 use bevy::{
-    input::mouse::MouseWheel,
+    input::mouse::{MouseMotion, MouseWheel},
     prelude::*,
-    render::extract_component::{ExtractComponent, ExtractComponentPlugin},
 };
 
 pub struct CameraPlugin;
@@ -10,8 +11,7 @@ pub struct CameraPlugin;
 impl Plugin for CameraPlugin {
     fn build(&self, app: &mut App) {
         app.add_systems(Startup, spawn_camera)
-            .add_systems(PostUpdate, camera_control_system)
-            .add_plugins(ExtractComponentPlugin::<CameraMain>::default());
+            .add_systems(PostUpdate, camera_control_system);
     }
 }
 
@@ -23,20 +23,34 @@ fn spawn_camera(mut commands: Commands, mut clearcolor: ResMut<ClearColor>) {
         Camera { ..default() },
         Transform::from_xyz(10.0, 12.0, 16.0).looking_at(Vec3::ZERO, Vec3::Y),
         CameraMain::default(),
-        volume_upscaler::BackgroundCamera,
+        GalaxyCamera,
     ));
 }
 
-#[derive(Component, Clone, ExtractComponent)]
+#[derive(Component, Clone)]
 pub struct CameraMain {
     target_pos: Vec3,
     galaxy_radius: f32,
     max_zoom_scale: f32,
     zoom: f32,
     side_view: bool,
+    // Vertical to horizontal offset ratio of the orbit view, higher looks more top-down
+    tilt: f32,
+    // Rotation of the orbit view around the target, in radians
+    yaw: f32,
     smooth_zoom_buffer: f32,
     far_view: bool,
+    map_view: bool,
+    // Animated towards map_view, 0 is the perspective view and 1 the orthographic map
+    map_blend: f32,
+    // Free-fly mode, the camera moves freely from `translation` instead of orbiting the target
+    fly_view: bool,
+    fly_rotation: Quat,
+    // log2 multiplier on the base fly speed, adjusted with the scroll wheel
+    fly_speed: f32,
     drag_origin: Option<Vec3>,
+    // Fixed view and vertical fov used by offline captures, bypasses all camera controls while set
+    capture_view: Option<(Transform, f32)>,
     pub translation: Vec3,
 }
 
@@ -48,15 +62,123 @@ impl Default for CameraMain {
             zoom: 1.0,
             max_zoom_scale: 4.0,
             side_view: false,
+            tilt: 1.0 / 0.6,
+            yaw: 0.0,
             smooth_zoom_buffer: 0.0,
             far_view: false,
+            map_view: false,
+            map_blend: 0.0,
+            fly_view: false,
+            fly_rotation: Quat::IDENTITY,
+            fly_speed: 0.0,
             drag_origin: None,
+            capture_view: None,
             translation: Vec3::ZERO,
         }
     }
 }
 
 impl CameraMain {
+    /// Point on the galactic plane the camera orbits
+    pub fn target(&self) -> Vec3 {
+        self.target_pos
+    }
+
+    /// Normalised zoom level, 0 is closest
+    pub fn zoom(&self) -> f32 {
+        self.zoom
+    }
+
+    pub fn side_view(&self) -> bool {
+        self.side_view
+    }
+
+    pub fn map_view(&self) -> bool {
+        self.map_view
+    }
+
+    /// Progress of the map view transition, 1 when fully in the orthographic map
+    pub fn map_blend(&self) -> f32 {
+        self.map_blend
+    }
+
+    pub fn fly_view(&self) -> bool {
+        self.fly_view
+    }
+
+    pub fn tilt(&self) -> f32 {
+        self.tilt
+    }
+
+    pub fn set_tilt(&mut self, tilt: f32) {
+        self.tilt = tilt.clamp(MIN_TILT, MAX_TILT);
+    }
+
+    pub fn yaw(&self) -> f32 {
+        self.yaw
+    }
+
+    pub fn set_yaw(&mut self, yaw: f32) {
+        self.yaw = yaw.rem_euclid(std::f32::consts::TAU);
+    }
+
+    /// Holds the camera at `transform` with a perspective projection of vertical `fov` until cleared
+    pub fn set_capture_view(&mut self, transform: Transform, fov: f32) {
+        self.capture_view = Some((transform, fov));
+    }
+
+    pub fn clear_capture_view(&mut self) {
+        self.capture_view = None;
+    }
+
+    pub fn view_mode(&self) -> CameraViewMode {
+        if self.fly_view {
+            CameraViewMode::Fly
+        } else if self.map_view {
+            CameraViewMode::Map
+        } else if self.far_view {
+            CameraViewMode::FarOrbit
+        } else {
+            CameraViewMode::Orbit
+        }
+    }
+
+    pub fn bookmark(&self, name: String, transform: &Transform) -> CameraBookmark {
+        CameraBookmark {
+            name,
+            view_mode: self.view_mode(),
+            target: self.target_pos,
+            zoom: self.zoom,
+            tilt: self.tilt,
+            yaw: self.yaw,
+            fly_translation: transform.translation,
+            fly_rotation: transform.rotation,
+        }
+    }
+
+    pub fn apply_bookmark(&mut self, bookmark: &CameraBookmark) {
+        self.target_pos = bookmark.target;
+        self.zoom = bookmark.zoom;
+        self.set_tilt(bookmark.tilt);
+        self.set_yaw(bookmark.yaw);
+        self.smooth_zoom_buffer = 0.0;
+        self.drag_origin = None;
+        self.far_view = bookmark.view_mode == CameraViewMode::FarOrbit;
+        self.map_view = bookmark.view_mode == CameraViewMode::Map;
+        self.fly_view = bookmark.view_mode == CameraViewMode::Fly;
+        if self.fly_view {
+            self.translation = bookmark.fly_translation;
+            self.fly_rotation = bookmark.fly_rotation;
+            self.map_blend = 0.0;
+        }
+    }
+
+    /// Height of the visible region at the target plane (in parsecs)
+    /// - Taken at the orbit distance, so the orthographic map keeps the scale of the perspective view
+    pub fn visible_height(&self) -> f32 {
+        2.0 * self.orbit_offset().length() * f32::tan(MAP_FOV * 0.5)
+    }
+
     fn adjusted_zoom(&self) -> f32 {
         let base_scale = if self.far_view { 10.0 } else { 1.0 };
         let min_zoom = 25.0 * base_scale;
@@ -68,18 +190,58 @@ impl CameraMain {
         10.0f32.powf(zoom_as_factor)
     }
 
-    fn translation(&self) -> Vec3 {
+    /// Camera offset from the target before the yaw and map tilt are applied
+    fn orbit_offset(&self) -> Vec3 {
         let adjusted_scale = self.adjusted_zoom();
 
         if self.side_view {
             let antitilt = 0.25;
-            self.target_pos + Vec3::new(0., adjusted_scale * antitilt, -adjusted_scale)
+            Vec3::new(0., adjusted_scale * antitilt, -adjusted_scale)
         } else {
-            let antitilt = 0.6;
-            self.target_pos + Vec3::new(0., adjusted_scale, -adjusted_scale * antitilt)
+            let antitilt = 1.0 / self.tilt;
+            Vec3::new(0., adjusted_scale, -adjusted_scale * antitilt)
+        }
+    }
+
+    fn translation(&self) -> Vec3 {
+        let offset = self.orbit_offset();
+        // Tilt to straight down as the map view blends in
+        let map_offset = Vec3::new(0., offset.length(), 0.);
+        self.target_pos + self.yaw_rotation() * offset.lerp(map_offset, self.map_tilt())
+    }
+
+    fn yaw_rotation(&self) -> Quat {
+        Quat::from_rotation_y(self.yaw)
+    }
+
+    fn map_tilt(&self) -> f32 {
+        let t = self.map_blend;
+        t * t * (3.0 - 2.0 * t)
+    }
+
+    /// Perspective while animating, switches to orthographic once the camera is looking straight down
+    fn projection(&self) -> Projection {
+        if self.map_blend >= 1.0 {
+            Projection::Orthographic(OrthographicProjection {
+                scaling_mode: ScalingMode::FixedVertical {
+                    viewport_height: self.visible_height(),
+                },
+                far: self.adjusted_zoom() * 4.0,
+                ..OrthographicProjection::default_3d()
+            })
+        } else {
+            Projection::Perspective(PerspectiveProjection {
+                fov: MAP_FOV,
+                ..default()
+            })
         }
     }
 
+    /// Applies [`Self::projection`] only where it differs, so the projection isn't marked changed every frame
+    fn apply_projection(&self, projection: &mut Mut<Projection>) {
+        set_projection_if_neq(projection, self.projection());
+    }
+
     fn look_pos(&self) -> Vec3 {
         self.target_pos
     }
@@ -96,27 +258,189 @@ impl CameraMain {
     fn set_transform(&mut self, transform: &mut Transform) {
         self.translation = self.translation();
         transform.translation = self.translation;
-        transform.look_at(self.look_pos(), Vec3::Y);
+        // Y up degenerates when looking straight down, so the map uses +Z as up instead
+        let up = Vec3::Y
+            .lerp(self.yaw_rotation() * Vec3::Z, self.map_tilt())
+            .normalize();
+        transform.look_at(self.look_pos(), up);
+    }
+}
+
+impl CameraMain {
+    fn toggle_fly_view(&mut self, transform: &Transform) {
+        self.fly_view = !self.fly_view;
+        if self.fly_view {
+            self.translation = transform.translation;
+            self.fly_rotation = transform.rotation;
+            self.map_view = false;
+            self.map_blend = 0.0;
+            self.side_view = false;
+        } else {
+            // Hand back to the orbit camera around the point below the fly position
+            self.target_pos = self.translation.with_y(0.0);
+        }
+    }
+
+    /// Star and gas density around the camera, scaled to roughly 0..1
+    fn local_density(&self, galaxy_config: &GalaxyConfig) -> f32 {
+        let p = self.translation;
+        let stars = GalaxyComponentDensity::new(galaxy_config, &galaxy_config.stars_params);
+        let disk = GalaxyComponentDensity::new(galaxy_config, &galaxy_config.disk_params);
+        // xz densities top out around 0.1 (see get_radial_intensity)
+        let arms = stars.xyz_density(p).max(disk.xyz_density(p)) * 10.0;
+        let bulge = f32::exp(-p.length() / galaxy_config.radius * galaxy_config.bulge_radius);
+        arms.max(bulge).clamp(0.0, 1.0)
+    }
+
+    fn fly_control(
+        &mut self,
+        keys: &ButtonInput<KeyCode>,
+        mouse_buttons: &ButtonInput<MouseButton>,
+        mouse_motion: Vec2,
+        scroll: f32,
+        dt: f32,
+        galaxy_config: Option<&GalaxyConfig>,
+    ) {
+        // Look around while holding the right mouse button, roll with Q/E
+        let mut look = Vec3::ZERO;
+        if mouse_buttons.pressed(MouseButton::Right) {
+            look.x = -mouse_motion.x * FLY_LOOK_SENSITIVITY;
+            look.y = -mouse_motion.y * FLY_LOOK_SENSITIVITY;
+        }
+        if keys.pressed(KeyCode::KeyQ) {
+            look.z += FLY_ROLL_SPEED * dt;
+        }
+        if keys.pressed(KeyCode::KeyE) {
+            look.z -= FLY_ROLL_SPEED * dt;
+        }
+        self.fly_rotation = (self.fly_rotation
+            * Quat::from_euler(EulerRot::YXZ, look.x, look.y, look.z))
+        .normalize();
+
+        let mut local_delta = Vec3::ZERO;
+        if keys.pressed(KeyCode::KeyW) {
+            local_delta.z -= 1.0;
+        }
+        if keys.pressed(KeyCode::KeyS) {
+            local_delta.z += 1.0;
+        }
+        if keys.pressed(KeyCode::KeyA) {
+            local_delta.x -= 1.0;
+        }
+        if keys.pressed(KeyCode::KeyD) {
+            local_delta.x += 1.0;
+        }
+        if keys.pressed(KeyCode::Space) {
+            local_delta.y += 1.0;
+        }
+        if keys.pressed(KeyCode::ControlLeft) {
+            local_delta.y -= 1.0;
+        }
+
+        self.fly_speed = (self.fly_speed + scroll * 0.25).clamp(-6.0, 4.0);
+
+        // Slow down in dense regions so there's time to take in the detail
+        let density_factor = galaxy_config.map_or(1.0, |galaxy_config| {
+            f32::lerp(1.0, 0.05, self.local_density(galaxy_config).sqrt())
+        });
+        let boost = if keys.pressed(KeyCode::ShiftLeft) {
+            4.0
+        } else {
+            1.0
+        };
+        let speed = self.galaxy_radius * 0.25 * self.fly_speed.exp2() * density_factor * boost * dt;
+
+        self.translation += self.fly_rotation * local_delta.normalize_or_zero() * speed;
+    }
+
+    fn set_fly_transform(&self, transform: &mut Transform) {
+        transform.translation = self.translation;
+        transform.rotation = self.fly_rotation;
     }
 }
 
 use bevy::input::mouse::MouseScrollUnit;
+use bevy::render::camera::ScalingMode;
 
+/// Only the fields the camera drives are compared, anything else set on the projection is kept
+fn set_projection_if_neq(projection: &mut Mut<Projection>, target: Projection) {
+    let differs = match (&**projection, &target) {
+        (Projection::Perspective(current), Projection::Perspective(target)) => {
+            current.fov != target.fov
+        }
+        (Projection::Orthographic(current), Projection::Orthographic(target)) => {
+            let same_scaling = matches!(
+                (current.scaling_mode, target.scaling_mode),
+                (
+                    ScalingMode::FixedVertical { viewport_height: a },
+                    ScalingMode::FixedVertical { viewport_height: b },
+                ) if a == b
+            );
+            !same_scaling || current.far != target.far
+        }
+        _ => true,
+    };
+    if !differs {
+        return;
+    }
+    match (&mut **projection, target) {
+        (Projection::Perspective(current), Projection::Perspective(target)) => {
+            current.fov = target.fov;
+        }
+        (Projection::Orthographic(current), Projection::Orthographic(target)) => {
+            current.scaling_mode = target.scaling_mode;
+            current.far = target.far;
+        }
+        (current, target) => *current = target,
+    }
+}
+
+// Matches the default perspective fov, so the orthographic map lines up with the perspective view it replaces
+const MAP_FOV: f32 = std::f32::consts::FRAC_PI_4;
+// Duration of the map transition, in seconds
+const MAP_TRANSITION_TIME: f32 = 0.6;
+pub const MIN_TILT: f32 = 0.5;
+pub const MAX_TILT: f32 = 10.0;
+// Radians per pixel of mouse motion
+const FLY_LOOK_SENSITIVITY: f32 = 0.003;
+// Radians per second
+const FLY_ROLL_SPEED: f32 = 1.5;
+
+#[allow(clippy::too_many_arguments)]
 pub fn camera_control_system(
-    mut query: Query<(&mut Camera, &mut Transform, &mut CameraMain)>,
+    mut query: Query<(
+        &mut Camera,
+        &mut Transform,
+        &mut Projection,
+        &mut CameraMain,
+    )>,
     windows: Query<&Window>,
     keys: Res<ButtonInput<KeyCode>>,
     mouse_buttons: Res<ButtonInput<MouseButton>>,
     time: Res<Time>,
-    galaxy_config: Res<GalaxyConfig>,
+    galaxy: Query<&GalaxyConfig, With<PrimaryGalaxy>>,
+    exposure_config: Res<ExposureConfig>,
     mut scroll_evr: EventReader<MouseWheel>,
+    mut motion_evr: EventReader<MouseMotion>,
     //mut gizmos : Gizmos,
 ) {
-    let galaxy_scale = galaxy_config.radius * 2.5;
-    let (mut cam, mut transform, mut camera_main) =
+    let (mut cam, mut transform, mut projection, mut camera_main) =
         query.single_mut().expect("Error: Require ONE camera");
 
-    camera_main.galaxy_radius = galaxy_config.radius;
+    let galaxy_config = galaxy.single().ok();
+    if let Some(galaxy_config) = galaxy_config {
+        camera_main.galaxy_radius = galaxy_config.radius;
+    }
+    let galaxy_scale = camera_main.galaxy_radius * 2.5;
+
+    if let Some((view, fov)) = camera_main.capture_view {
+        *transform = view;
+        set_projection_if_neq(
+            &mut projection,
+            Projection::Perspective(PerspectiveProjection { fov, ..default() }),
+        );
+        return;
+    }
 
     // HIDE CURSOR
     //windows.single_mut().cursor.visible = false;
@@ -159,14 +483,42 @@ pub fn camera_control_system(
         key_delta.x -= 1.0;
     }
 
-    if keys.just_pressed(KeyCode::KeyH) {
+    // Auto exposure keeps HDR on
+    if keys.just_pressed(KeyCode::KeyH) && !exposure_config.auto_exposure {
         cam.hdr = !cam.hdr;
     }
     if keys.just_pressed(KeyCode::KeyV) {
         camera_main.far_view = !camera_main.far_view;
     }
 
-    camera_main.side_view = keys.pressed(KeyCode::Space);
+    if keys.just_pressed(KeyCode::KeyF) {
+        camera_main.toggle_fly_view(&transform);
+    }
+    let mouse_motion: Vec2 = motion_evr.read().map(|ev| ev.delta).sum();
+    if camera_main.fly_view {
+        let scroll: f32 = scroll_evr.read().map(|ev| ev.y).sum();
+        camera_main.fly_control(
+            &keys,
+            &mouse_buttons,
+            mouse_motion,
+            scroll,
+            time.delta_secs(),
+            galaxy_config,
+        );
+        camera_main.set_fly_transform(&mut transform);
+        camera_main.apply_projection(&mut projection);
+        return;
+    }
+
+    if keys.just_pressed(KeyCode::KeyM) {
+        camera_main.map_view = !camera_main.map_view;
+    }
+
+    camera_main.side_view = keys.pressed(KeyCode::Space) && !camera_main.map_view;
+
+    let map_target = if camera_main.map_view { 1.0 } else { 0.0 };
+    let map_step = time.delta_secs() / MAP_TRANSITION_TIME;
+    camera_main.map_blend += (map_target - camera_main.map_blend).clamp(-map_step, map_step);
 
     let old_zoom = camera_main.zoom;
 
@@ -206,6 +558,8 @@ pub fn camera_control_system(
     camera_main.zoom = camera_main.zoom.clamp(0., 1.);
     let tzoom = camera_main.zoom * 0.85 + 0.15;
     let speed: f32 = (tzoom * galaxy_scale) * 0.5 * time.delta_secs();
+    // keys move relative to the view
+    let key_delta = camera_main.yaw_rotation() * key_delta;
     camera_main.target_pos += key_delta * speed;
 
     // Activate the mouse drag system while zooming
@@ -222,6 +576,7 @@ pub fn camera_control_system(
     }
 
     camera_main.set_transform(&mut transform);
+    camera_main.apply_projection(&mut projection);
     for _i in 0..2 {
         let Some(mouse_pos) = cursor
             .and_then(|cursor| {
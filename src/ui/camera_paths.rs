@@ -0,0 +1,559 @@
+use super::camera::{MAX_TILT, MIN_TILT};
+use super::CameraMain;
+use crate::galaxy::smoothstep;
+use bevy::prelude::*;
+use bevy_egui::{egui, EguiContexts};
+use std::f32::consts::{PI, TAU};
+use std::fmt::Write as _;
+
+/// Bookmarks and paths are saved together in a plain text file next to the executable
+pub const CAMERA_PATHS_FILE: &str = "camera_bookmarks.txt";
+/// Version 2 added the bookmark yaw, files without a version line are version 1
+const CAMERA_PATHS_VERSION: u32 = 2;
+
+pub struct CameraPathsPlugin;
+
+impl Plugin for CameraPathsPlugin {
+    fn build(&self, app: &mut App) {
+        app.insert_resource(CameraBookmarks::default())
+            .insert_resource(CameraPath::default())
+            // Runs before camera_control_system, which turns CameraMain into the actual transform
+            .add_systems(Update, (camera_paths_ui, play_camera_path).chain());
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum CameraViewMode {
+    Orbit,
+    FarOrbit,
+    Map,
+    Fly,
+}
+
+impl CameraViewMode {
+    fn as_str(&self) -> &'static str {
+        match self {
+            CameraViewMode::Orbit => "orbit",
+            CameraViewMode::FarOrbit => "far",
+            CameraViewMode::Map => "map",
+            CameraViewMode::Fly => "fly",
+        }
+    }
+
+    fn parse(s: &str) -> Option<Self> {
+        match s {
+            "orbit" => Some(CameraViewMode::Orbit),
+            "far" => Some(CameraViewMode::FarOrbit),
+            "map" => Some(CameraViewMode::Map),
+            "fly" => Some(CameraViewMode::Fly),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Clone, PartialEq, Debug)]
+pub struct CameraBookmark {
+    pub name: String,
+    pub view_mode: CameraViewMode,
+    pub target: Vec3,
+    pub zoom: f32,
+    pub tilt: f32,
+    /// Rotation of the orbit view around the target, in radians
+    pub yaw: f32,
+    /// Only used by the fly view
+    pub fly_translation: Vec3,
+    pub fly_rotation: Quat,
+}
+
+#[derive(Resource, Default)]
+pub struct CameraBookmarks {
+    pub bookmarks: Vec<CameraBookmark>,
+}
+
+impl CameraBookmarks {
+    pub fn get(&self, name: &str) -> Option<&CameraBookmark> {
+        self.bookmarks.iter().find(|b| b.name == name)
+    }
+
+    /// Adds a bookmark, replacing any existing bookmark with the same name
+    pub fn insert(&mut self, bookmark: CameraBookmark) {
+        if let Some(existing) = self.bookmarks.iter_mut().find(|b| b.name == bookmark.name) {
+            *existing = bookmark;
+        } else {
+            self.bookmarks.push(bookmark);
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum PathEasing {
+    Linear,
+    /// Eases in at the first keyframe and out at the last, keeping its pace through the ones between
+    Smooth,
+}
+
+#[derive(Clone, PartialEq, Debug)]
+pub struct Keyframe {
+    pub bookmark: String,
+    /// Time in seconds from the start of the path
+    pub time: f32,
+}
+
+#[derive(Resource, Clone, PartialEq, Debug)]
+pub struct CameraPath {
+    pub keyframes: Vec<Keyframe>,
+    pub easing: PathEasing,
+    pub looping: bool,
+    pub playing: bool,
+    pub time: f32,
+}
+
+impl Default for CameraPath {
+    fn default() -> Self {
+        Self {
+            keyframes: Vec::new(),
+            easing: PathEasing::Smooth,
+            looping: false,
+            playing: false,
+            time: 0.0,
+        }
+    }
+}
+
+fn catmull_rom<T>(p0: T, p1: T, p2: T, p3: T, t: f32) -> T
+where
+    T: std::ops::Add<Output = T>
+        + std::ops::Sub<Output = T>
+        + std::ops::Mul<f32, Output = T>
+        + Copy,
+{
+    let t2 = t * t;
+    let t3 = t2 * t;
+    (p1 * 2.0
+        + (p2 - p0) * t
+        + (p0 * 2.0 - p1 * 5.0 + p2 * 4.0 - p3) * t2
+        + (p1 * 3.0 - p0 - p2 * 3.0 + p3) * t3)
+        * 0.5
+}
+
+/// Interpolates angles in radians the short way around
+fn lerp_angle(a: f32, b: f32, t: f32) -> f32 {
+    let delta = (b - a + PI).rem_euclid(TAU) - PI;
+    a + delta * t
+}
+
+impl CameraPath {
+    pub fn duration(&self) -> f32 {
+        self.keyframes.iter().map(|k| k.time).fold(0.0, f32::max)
+    }
+
+    /// Samples the path at the given time, returns None if the path references missing bookmarks
+    pub fn sample(&self, bookmarks: &CameraBookmarks, time: f32) -> Option<CameraBookmark> {
+        let mut keys: Vec<(&Keyframe, &CameraBookmark)> = self
+            .keyframes
+            .iter()
+            .filter_map(|k| bookmarks.get(&k.bookmark).map(|b| (k, b)))
+            .collect();
+        if keys.is_empty() {
+            return None;
+        }
+        keys.sort_by(|a, b| a.0.time.total_cmp(&b.0.time));
+
+        let start = keys[0].0.time;
+        let end = keys[keys.len() - 1].0.time;
+        let time = if self.easing == PathEasing::Smooth && end > start {
+            start + smoothstep(start, end, time) * (end - start)
+        } else {
+            time
+        };
+
+        let segment = keys
+            .iter()
+            .rposition(|(k, _)| k.time <= time)
+            .unwrap_or(0)
+            .min(keys.len().saturating_sub(2));
+        if keys.len() == 1 {
+            return Some(keys[0].1.clone());
+        }
+
+        let (k1, b1) = keys[segment];
+        let (k2, b2) = keys[segment + 1];
+        let b0 = keys[segment.saturating_sub(1)].1;
+        let b3 = keys[(segment + 2).min(keys.len() - 1)].1;
+
+        let span = (k2.time - k1.time).max(f32::EPSILON);
+        let t = ((time - k1.time) / span).clamp(0.0, 1.0);
+
+        Some(CameraBookmark {
+            name: String::new(),
+            // Discrete view settings switch halfway through the segment
+            view_mode: if t < 0.5 { b1.view_mode } else { b2.view_mode },
+            target: catmull_rom(b0.target, b1.target, b2.target, b3.target, t),
+            zoom: catmull_rom(b0.zoom, b1.zoom, b2.zoom, b3.zoom, t).clamp(0.0, 1.0),
+            tilt: catmull_rom(b0.tilt, b1.tilt, b2.tilt, b3.tilt, t),
+            yaw: lerp_angle(b1.yaw, b2.yaw, t),
+            fly_translation: catmull_rom(
+                b0.fly_translation,
+                b1.fly_translation,
+                b2.fly_translation,
+                b3.fly_translation,
+                t,
+            ),
+            fly_rotation: b1.fly_rotation.slerp(b2.fly_rotation, t),
+        })
+    }
+}
+
+fn play_camera_path(
+    time: Res<Time>,
+    mut path: ResMut<CameraPath>,
+    bookmarks: Res<CameraBookmarks>,
+    mut camera: Query<&mut CameraMain>,
+) {
+    if !path.playing {
+        return;
+    }
+    let duration = path.duration();
+    path.time += time.delta_secs();
+    if path.time > duration {
+        if path.looping && duration > 0.0 {
+            path.time %= duration;
+        } else {
+            path.time = duration;
+            path.playing = false;
+        }
+    }
+
+    let (Some(sample), Ok(mut camera)) = (path.sample(&bookmarks, path.time), camera.single_mut())
+    else {
+        return;
+    };
+    camera.apply_bookmark(&sample);
+}
+
+/// One line per bookmark or keyframe after the version, names go last so they can contain spaces
+/// - version <version>
+/// - bookmark <mode> <target xyz> <zoom> <tilt> <yaw> <fly translation xyz> <fly rotation xyzw> <name>
+/// - keyframe <time> <bookmark name>
+pub fn serialize_camera_paths(bookmarks: &CameraBookmarks, path: &CameraPath) -> String {
+    let mut out = String::new();
+    let _ = writeln!(out, "version {CAMERA_PATHS_VERSION}");
+    for b in &bookmarks.bookmarks {
+        let t = b.target;
+        let f = b.fly_translation;
+        let q = b.fly_rotation;
+        let _ = writeln!(
+            out,
+            "bookmark {} {} {} {} {} {} {} {} {} {} {} {} {} {} {}",
+            b.view_mode.as_str(),
+            t.x,
+            t.y,
+            t.z,
+            b.zoom,
+            b.tilt,
+            b.yaw,
+            f.x,
+            f.y,
+            f.z,
+            q.x,
+            q.y,
+            q.z,
+            q.w,
+            b.name
+        );
+    }
+    for k in &path.keyframes {
+        let _ = writeln!(out, "keyframe {} {}", k.time, k.bookmark);
+    }
+    out
+}
+
+pub fn deserialize_camera_paths(text: &str) -> (CameraBookmarks, Vec<Keyframe>) {
+    let mut bookmarks = CameraBookmarks::default();
+    let mut keyframes = Vec::new();
+    let mut version: u32 = 1;
+
+    for line in text.lines() {
+        let mut parts = line.trim().splitn(2, ' ');
+        match (parts.next(), parts.next()) {
+            (Some("version"), Some(rest)) => {
+                let Ok(parsed) = rest.trim().parse() else {
+                    warn!("Skipping malformed camera paths version: {line}");
+                    continue;
+                };
+                version = parsed;
+            }
+            (Some("bookmark"), Some(rest)) => {
+                let Some((mode, mut rest)) = rest.split_once(' ') else {
+                    warn!("Skipping malformed camera bookmark: {line}");
+                    continue;
+                };
+                // Exactly as many numbers as the version wrote, so a name starting with a number stays whole
+                let count = if version >= 2 { 13 } else { 12 };
+                let mut nums = Vec::with_capacity(13);
+                while nums.len() < count {
+                    // An empty name leaves the last number at the end of the line
+                    let (field, tail) = rest.split_once(' ').unwrap_or((rest, ""));
+                    let Ok(num) = field.parse::<f32>() else {
+                        break;
+                    };
+                    nums.push(num);
+                    rest = tail;
+                }
+                // Version 1 bookmarks have no yaw
+                if version < 2 && nums.len() == 12 {
+                    nums.insert(5, 0.0);
+                }
+                let (Some(view_mode), 13) = (CameraViewMode::parse(mode), nums.len()) else {
+                    warn!("Skipping malformed camera bookmark: {line}");
+                    continue;
+                };
+                bookmarks.insert(CameraBookmark {
+                    name: rest.to_string(),
+                    view_mode,
+                    target: vec3(nums[0], nums[1], nums[2]),
+                    zoom: nums[3],
+                    tilt: nums[4],
+                    yaw: nums[5],
+                    fly_translation: vec3(nums[6], nums[7], nums[8]),
+                    fly_rotation: Quat::from_xyzw(nums[9], nums[10], nums[11], nums[12])
+                        .normalize(),
+                });
+            }
+            (Some("keyframe"), Some(rest)) => {
+                let mut fields = rest.splitn(2, ' ');
+                let (Some(Ok(time)), Some(name)) =
+                    (fields.next().map(str::parse::<f32>), fields.next())
+                else {
+                    warn!("Skipping malformed camera keyframe: {line}");
+                    continue;
+                };
+                keyframes.push(Keyframe {
+                    bookmark: name.to_string(),
+                    time,
+                });
+            }
+            _ => {}
+        }
+    }
+    (bookmarks, keyframes)
+}
+
+pub fn save_camera_paths(bookmarks: &CameraBookmarks, path: &CameraPath) {
+    match std::fs::write(CAMERA_PATHS_FILE, serialize_camera_paths(bookmarks, path)) {
+        Ok(()) => info!("Saved camera bookmarks to {CAMERA_PATHS_FILE}"),
+        Err(err) => error!("Failed to save camera bookmarks: {err}"),
+    }
+}
+
+pub fn load_camera_paths(bookmarks: &mut CameraBookmarks, path: &mut CameraPath) {
+    match std::fs::read_to_string(CAMERA_PATHS_FILE) {
+        Ok(text) => {
+            let (loaded, keyframes) = deserialize_camera_paths(&text);
+            *bookmarks = loaded;
+            path.keyframes = keyframes;
+            path.playing = false;
+            path.time = 0.0;
+        }
+        Err(err) => error!("Failed to load camera bookmarks: {err}"),
+    }
+}
+
+fn camera_paths_ui(
+    mut contexts: EguiContexts,
+    mut bookmarks: ResMut<CameraBookmarks>,
+    mut path: ResMut<CameraPath>,
+    mut camera: Query<(&Transform, &mut CameraMain)>,
+    mut new_bookmark_name: Local<String>,
+) {
+    let Ok((transform, mut camera)) = camera.single_mut() else {
+        return;
+    };
+    let ctx = contexts.ctx_mut();
+
+    egui::Window::new("Camera")
+        .default_open(false)
+        .resizable(false)
+        .show(ctx, |ui| {
+            let mut tilt = camera.tilt();
+            if ui
+                .add(egui::Slider::new(&mut tilt, MIN_TILT..=MAX_TILT).text("Tilt"))
+                .changed()
+            {
+                camera.set_tilt(tilt);
+            }
+
+            ui.separator();
+            ui.label("Bookmarks");
+            ui.horizontal(|ui| {
+                ui.text_edit_singleline(&mut *new_bookmark_name);
+                if ui.button("Add").clicked() && !new_bookmark_name.trim().is_empty() {
+                    let name = new_bookmark_name.trim().to_string();
+                    bookmarks.insert(camera.bookmark(name, transform));
+                    new_bookmark_name.clear();
+                }
+            });
+
+            let mut remove = None;
+            for (i, bookmark) in bookmarks.bookmarks.iter().enumerate() {
+                ui.horizontal(|ui| {
+                    ui.label(format!("{} ({:?})", bookmark.name, bookmark.view_mode));
+                    if ui.button("Go").clicked() {
+                        path.playing = false;
+                        camera.apply_bookmark(bookmark);
+                    }
+                    if ui.button("Keyframe").clicked() {
+                        let time = if path.keyframes.is_empty() {
+                            0.0
+                        } else {
+                            path.duration() + 2.0
+                        };
+                        path.keyframes.push(Keyframe {
+                            bookmark: bookmark.name.clone(),
+                            time,
+                        });
+                    }
+                    if ui.button("Delete").clicked() {
+                        remove = Some(i);
+                    }
+                });
+            }
+            if let Some(i) = remove {
+                bookmarks.bookmarks.remove(i);
+            }
+
+            ui.separator();
+            ui.label("Path");
+            let mut remove = None;
+            for (i, keyframe) in path.keyframes.iter_mut().enumerate() {
+                ui.horizontal(|ui| {
+                    ui.add(
+                        egui::DragValue::new(&mut keyframe.time)
+                            .speed(0.1)
+                            .range(0.0..=3600.0)
+                            .suffix(" s"),
+                    );
+                    ui.label(keyframe.bookmark.as_str());
+                    if ui.button("Delete").clicked() {
+                        remove = Some(i);
+                    }
+                });
+            }
+            if let Some(i) = remove {
+                path.keyframes.remove(i);
+            }
+
+            ui.horizontal(|ui| {
+                let mut smooth = path.easing == PathEasing::Smooth;
+                ui.checkbox(&mut smooth, "Ease");
+                path.easing = if smooth {
+                    PathEasing::Smooth
+                } else {
+                    PathEasing::Linear
+                };
+                ui.checkbox(&mut path.looping, "Loop");
+            });
+            ui.horizontal(|ui| {
+                if path.playing {
+                    if ui.button("Stop").clicked() {
+                        path.playing = false;
+                    }
+                } else if ui.button("Play").clicked() {
+                    path.playing = true;
+                    path.time = 0.0;
+                }
+                ui.label(format!("{:.1} / {:.1} s", path.time, path.duration()));
+            });
+
+            ui.separator();
+            ui.horizontal(|ui| {
+                if ui.button("Save").clicked() {
+                    save_camera_paths(&bookmarks, &path);
+                }
+                if ui.button("Load").clicked() {
+                    load_camera_paths(&mut bookmarks, &mut path);
+                }
+            });
+        });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn bookmark(name: &str, zoom: f32) -> CameraBookmark {
+        CameraBookmark {
+            name: name.to_string(),
+            view_mode: CameraViewMode::Orbit,
+            target: vec3(1.5, -2.25, 3.0),
+            zoom,
+            tilt: 2.5,
+            yaw: 0.75,
+            fly_translation: vec3(-10.0, 0.1, 42.0),
+            fly_rotation: Quat::IDENTITY,
+        }
+    }
+
+    #[test]
+    fn round_trip() {
+        let mut bookmarks = CameraBookmarks::default();
+        bookmarks.insert(bookmark("Core view", 0.25));
+        bookmarks.insert(bookmark("3 suns", 0.5));
+        bookmarks.insert(CameraBookmark {
+            view_mode: CameraViewMode::Fly,
+            ..bookmark("", 1.0)
+        });
+        let path = CameraPath {
+            keyframes: vec![
+                Keyframe {
+                    bookmark: "Core view".to_string(),
+                    time: 0.0,
+                },
+                Keyframe {
+                    bookmark: "3 suns".to_string(),
+                    time: 2.5,
+                },
+            ],
+            ..default()
+        };
+
+        let (loaded, keyframes) =
+            deserialize_camera_paths(&serialize_camera_paths(&bookmarks, &path));
+        assert_eq!(loaded.bookmarks, bookmarks.bookmarks);
+        assert_eq!(keyframes, path.keyframes);
+    }
+
+    #[test]
+    fn reads_bookmarks_without_yaw() {
+        let (loaded, _) = deserialize_camera_paths("bookmark map 1 2 3 0.5 2 4 5 6 0 0 0 1 3 suns");
+        let bookmark = loaded
+            .get("3 suns")
+            .expect("bookmark named after its numbers");
+        assert_eq!(bookmark.view_mode, CameraViewMode::Map);
+        assert_eq!(bookmark.target, vec3(1.0, 2.0, 3.0));
+        assert_eq!(bookmark.tilt, 2.0);
+        assert_eq!(bookmark.yaw, 0.0);
+        assert_eq!(bookmark.fly_translation, vec3(4.0, 5.0, 6.0));
+    }
+
+    #[test]
+    fn smooth_path_keeps_moving_through_keyframes() {
+        let mut bookmarks = CameraBookmarks::default();
+        let mut path = CameraPath::default();
+        for (i, zoom) in [0.0, 0.5, 1.0].into_iter().enumerate() {
+            let name = format!("key {i}");
+            bookmarks.insert(bookmark(&name, zoom));
+            path.keyframes.push(Keyframe {
+                bookmark: name,
+                time: i as f32,
+            });
+        }
+
+        let zoom_at = |time| path.sample(&bookmarks, time).unwrap().zoom;
+        assert_eq!(zoom_at(0.0), 0.0);
+        assert_eq!(zoom_at(2.0), 1.0);
+        // Easing every segment would stop the camera here
+        let speed = (zoom_at(1.01) - zoom_at(0.99)) / 0.02;
+        assert!(speed > 0.25, "{speed}");
+    }
+}
@@ -1,3 +1,8 @@
+use super::NameLabelSettings;
+use crate::graphics::{
+    DensitySplatConfig, ExposureConfig, GalaxyBackgroundConfig, GalaxyBloomConfig, NebulaConfig,
+    NucleusConfig, StarPsfConfig, TONEMAPPERS,
+};
 use crate::prelude::*;
 use bevy::prelude::*;
 use bevy_egui::{egui, EguiContexts};
@@ -27,6 +32,67 @@ fn arm_component_ui(id: i32, arm_config: &mut ArmConfig, ui: &mut egui::Ui) {
         });
 }
 
+fn color_ui(color: &mut ComponentColor, ui: &mut egui::Ui) {
+    ui.horizontal(|ui| {
+        let mut inner = color.inner.to_array();
+        egui::color_picker::color_edit_button_rgb(ui, &mut inner);
+        color.inner = Vec3::from_array(inner);
+        ui.label(if color.radial_gradient {
+            "Centre"
+        } else {
+            "Colour"
+        });
+    });
+    ui.checkbox(&mut color.radial_gradient, "Radial Gradient");
+    if color.radial_gradient {
+        ui.horizontal(|ui| {
+            let mut outer = color.outer.to_array();
+            egui::color_picker::color_edit_button_rgb(ui, &mut outer);
+            color.outer = Vec3::from_array(outer);
+            ui.label("Outer");
+        });
+        ui.add(
+            egui::Slider::new(&mut color.gradient_radius, 0.01..=1.5).text("Gradient Radius"),
+        );
+    }
+}
+
+fn bulge_shape_ui(shape: &mut BulgeShape, ui: &mut egui::Ui) {
+    ui.label("Shape");
+    ui.group(|ui| {
+        ui.add(
+            egui::Slider::new(&mut shape.sersic_index, 0.5..=8.0)
+                .text("Sérsic Index")
+                .logarithmic(true),
+        );
+        ui.add(egui::Slider::new(&mut shape.axis_ratio_y, 0.1..=1.0).text("Axis Ratio (Y)"));
+        ui.add(egui::Slider::new(&mut shape.axis_ratio_z, 0.1..=1.0).text("Axis Ratio (Z)"));
+        ui.add(egui::Slider::new(&mut shape.boxiness, 2.0..=6.0).text("Boxiness"));
+        ui.add(egui::Slider::new(&mut shape.peanut, 0.0..=3.0).text("Peanut"));
+        ui.add_enabled(
+            shape.axis_ratio_z < 1.0 || shape.peanut > 0.0 || shape.boxiness > 2.0,
+            egui::Slider::new(&mut shape.position_angle, -90.0..=90.0).text("Position Angle"),
+        );
+    });
+}
+
+/// Budget sliders with the number of stars each population currently has
+fn star_budget_ui(budget: &mut StarBudget, star_count: &StarCount, ui: &mut egui::Ui) {
+    ui.label("Star Budget");
+    ui.group(|ui| {
+        for population in StellarPopulation::ALL {
+            ui.add(
+                egui::Slider::new(budget.get_mut(population), 0.0..=5.0)
+                    .text(format!(
+                        "{} ({} stars)",
+                        population.name(),
+                        star_count.populations[population.index()]
+                    )),
+            );
+        }
+    });
+}
+
 fn component_ui(config: &mut ComponentConfig, has_noise: bool, ui: &mut egui::Ui) {
     let heading = match config.component_type {
         ComponentType::Disk => "Disk Config",
@@ -79,6 +145,14 @@ fn component_ui(config: &mut ComponentConfig, has_noise: bool, ui: &mut egui::Ui
                 .text("Angular Offset"),
             );
         });
+        if config.component_type != ComponentType::Stars {
+            ui.label(if config.component_type == ComponentType::Dust {
+                "Absorbed Colour"
+            } else {
+                "Colour"
+            });
+            ui.group(|ui| color_ui(&mut config.color, ui));
+        }
         if has_noise {
             ui.label("Noise");
 
@@ -145,15 +219,412 @@ fn component_ui(config: &mut ComponentConfig, has_noise: bool, ui: &mut egui::Ui
     ui.separator();
 }
 
+fn territory_ui(config: &mut TerritoryConfig, territories: &Territories, ui: &mut egui::Ui) {
+    ui.checkbox(&mut config.show_overlay, "Show Overlay");
+    ui.add(egui::Slider::new(&mut config.overlay_opacity, 0.0..=1.0).text("Overlay Opacity"));
+
+    ui.horizontal(|ui| {
+        let is_polar = matches!(config.mode, PartitionMode::Polar { .. });
+        if ui.radio(is_polar, "Polar Grid").clicked() && !is_polar {
+            config.mode = PartitionMode::Polar {
+                rings: 4,
+                slices: 12,
+            };
+        }
+        if ui.radio(!is_polar, "Voronoi").clicked() && is_polar {
+            config.mode = PartitionMode::Voronoi { seeds: 16 };
+        }
+    });
+
+    match &mut config.mode {
+        PartitionMode::Polar { rings, slices } => {
+            ui.add(egui::Slider::new(rings, 1..=16).text("Rings"));
+            ui.add(egui::Slider::new(slices, 1..=36).text("Slices"));
+        }
+        PartitionMode::Voronoi { seeds } => {
+            ui.add(egui::Slider::new(seeds, 2..=64).text("Seed Stars"));
+        }
+    }
+
+    let total_members: usize = territories.sectors().iter().map(|s| s.members.len()).sum();
+    ui.label(format!(
+        "{} sectors, {} stars assigned",
+        territories.sectors().len(),
+        total_members
+    ));
+}
+
+fn background_ui(config: &mut GalaxyBackgroundConfig, ui: &mut egui::Ui) {
+    ui.checkbox(&mut config.enabled, "Distant Galaxies");
+    ui.add_enabled_ui(config.enabled, |ui| {
+        ui.horizontal(|ui| {
+            ui.label("Seed");
+            ui.add(egui::DragValue::new(&mut config.seed));
+        });
+        ui.add(egui::Slider::new(&mut config.count, 0..=20000).text("Count"));
+        ui.add(
+            egui::Slider::new(&mut config.max_angular_size, 0.05..=2.0)
+                .text("Max Angular Size"),
+        );
+        ui.add(egui::Slider::new(&mut config.brightness, 0.0..=10.0).text("Brightness"));
+        ui.checkbox(&mut config.use_generator, "Use Galaxy Generator");
+        ui.add_enabled_ui(config.use_generator, |ui| {
+            ui.add(egui::Slider::new(&mut config.generator_variants, 1..=32).text("Variants"));
+            ui.add(
+                egui::Slider::new(&mut config.generator_texture_size, 16..=256)
+                    .text("Variant Texture Size"),
+            );
+        });
+    });
+    ui.checkbox(&mut config.cosmic_background, "Cosmic Background");
+    ui.add_enabled(
+        config.cosmic_background,
+        egui::Slider::new(&mut config.cosmic_background_intensity, 0.0..=10.0).text("Intensity"),
+    );
+}
+
+fn nebula_ui(config: &mut NebulaConfig, ui: &mut egui::Ui) {
+    ui.checkbox(&mut config.enabled, "Enabled");
+    ui.add_enabled_ui(config.enabled, |ui| {
+        ui.horizontal(|ui| {
+            ui.label("Seed");
+            ui.add(egui::DragValue::new(&mut config.seed));
+        });
+        ui.add(egui::Slider::new(&mut config.count, 0..=2000).text("Count per Galaxy"));
+        ui.add(egui::Slider::new(&mut config.min_radius, 0.5..=50.0).text("Min Radius"));
+        ui.add(egui::Slider::new(&mut config.max_radius, 0.5..=50.0).text("Max Radius"));
+        config.max_radius = config.max_radius.max(config.min_radius);
+        ui.add(egui::Slider::new(&mut config.brightness, 0.0..=10.0).text("Brightness"));
+        ui.add(egui::Slider::new(&mut config.raymarch_steps, 4..=128).text("Raymarch Steps"));
+        ui.checkbox(&mut config.extinction, "Dust Extinction");
+    });
+}
+
+fn exposure_ui(config: &mut ExposureConfig, ui: &mut egui::Ui) {
+    ui.checkbox(&mut config.auto_exposure, "Auto Exposure")
+        .on_hover_text("Keeps HDR on, the H key only toggles HDR with this off");
+    ui.add_enabled_ui(config.auto_exposure, |ui| {
+        ui.add(
+            egui::Slider::new(&mut config.adaptation_speed, 0.1..=10.0)
+                .logarithmic(true)
+                .text("Adaptation Speed (EV/s)"),
+        );
+        ui.add(egui::Slider::new(&mut config.compensation, -8.0..=8.0).text("Compensation (EV)"));
+        ui.add(egui::Slider::new(&mut config.min_ev, -16.0..=0.0).text("Min EV"));
+        ui.add(egui::Slider::new(&mut config.max_ev, 0.0..=16.0).text("Max EV"));
+    });
+    ui.label("Tonemapper");
+    for (tonemapping, name) in TONEMAPPERS {
+        ui.radio_value(&mut config.tonemapping, tonemapping, name);
+    }
+}
+
+fn star_psf_ui(psf: &mut StarPsfConfig, bloom: &mut GalaxyBloomConfig, ui: &mut egui::Ui) {
+    ui.checkbox(&mut psf.enabled, "Point Spread Function");
+    ui.add_enabled_ui(psf.enabled, |ui| {
+        ui.add(
+            egui::Slider::new(&mut psf.threshold, 0.0..=40.0).text("Brightness Threshold"),
+        );
+        ui.add(egui::Slider::new(&mut psf.size, 1.0..=16.0).text("Size"));
+        ui.add(
+            egui::Slider::new(&mut psf.brightness, 1.0..=1000.0)
+                .logarithmic(true)
+                .text("Brightness"),
+        );
+        ui.add(egui::Slider::new(&mut psf.core_scale, 0.5..=20.0).text("Airy Core Scale"));
+        ui.add(egui::Slider::new(&mut psf.halo_strength, 0.0..=0.2).text("Halo Strength"));
+        ui.add(egui::Slider::new(&mut psf.halo_radius, 0.05..=4.0).text("Halo Radius"));
+        ui.add(egui::Slider::new(&mut psf.spike_strength, 0.0..=0.5).text("Spike Strength"));
+        ui.add(egui::Slider::new(&mut psf.spike_length, 0.1..=10.0).text("Spike Length"));
+        ui.add(egui::Slider::new(&mut psf.vane_count, 1..=8).text("Vanes"));
+        ui.add(egui::Slider::new(&mut psf.rotation, 0.0..=180.0).text("Spike Rotation"));
+    });
+
+    ui.checkbox(&mut bloom.enabled, "Bloom");
+    ui.add_enabled_ui(bloom.enabled, |ui| {
+        ui.add(egui::Slider::new(&mut bloom.intensity, 0.0..=1.0).text("Intensity"));
+        ui.add(
+            egui::Slider::new(&mut bloom.close_up_factor, 0.0..=1.0).text("Close Up Factor"),
+        );
+        ui.add(
+            egui::Slider::new(&mut bloom.low_frequency_boost, 0.0..=1.0)
+                .text("Low Frequency Boost"),
+        );
+    });
+}
+
+fn nucleus_ui(config: &mut NucleusConfig, ui: &mut egui::Ui) {
+    ui.checkbox(&mut config.enabled, "Enabled");
+    ui.add_enabled_ui(config.enabled, |ui| {
+        ui.add(egui::Slider::new(&mut config.disk_radius, 0.5..=50.0).text("Disk Radius"));
+        ui.add(
+            egui::Slider::new(&mut config.disk_brightness, 0.0..=10.0).text("Disk Brightness"),
+        );
+        ui.checkbox(&mut config.jets, "Jets");
+        ui.add_enabled_ui(config.jets, |ui| {
+            ui.add(egui::Slider::new(&mut config.jet_length, 10.0..=1000.0).text("Jet Length"));
+            ui.add(
+                egui::Slider::new(&mut config.jet_opening_angle, 0.5..=20.0)
+                    .text("Opening Angle"),
+            );
+            ui.add(
+                egui::Slider::new(&mut config.jet_brightness, 0.0..=10.0).text("Jet Brightness"),
+            );
+            ui.add(egui::Slider::new(&mut config.jet_noise_scale, 1.0..=20.0).text("Noise Scale"));
+            ui.add(egui::Slider::new(&mut config.jet_speed, 0.0..=100.0).text("Jet Speed"));
+        });
+        ui.checkbox(&mut config.lensing, "Lensing");
+        ui.add_enabled_ui(config.lensing, |ui| {
+            ui.add(
+                egui::Slider::new(&mut config.einstein_radius, 0.5..=50.0)
+                    .text("Einstein Radius"),
+            );
+            ui.add(egui::Slider::new(&mut config.shadow_radius, 0.0..=1.0).text("Shadow Radius"));
+        });
+    });
+}
+
+/// Merger controls, returns a start or stop request
+fn merger_ui(
+    config: &mut MergerConfig,
+    splat: &mut DensitySplatConfig,
+    simulation: &MergerSimulation,
+    running: &mut bool,
+    ui: &mut egui::Ui,
+) -> Option<MergerRequest> {
+    let mut request = None;
+    ui.horizontal(|ui| {
+        if simulation.is_active() {
+            if ui.button("Stop").clicked() {
+                request = Some(MergerRequest::Stop);
+            }
+            let label = if *running { "Pause" } else { "Resume" };
+            if ui.button(label).clicked() {
+                *running = !*running;
+            }
+            ui.label(format!("t = {:.1}", simulation.time()));
+        } else if ui.button("Start").clicked() {
+            request = Some(MergerRequest::Start);
+        }
+    });
+    ui.label("Merges the primary galaxy with the next one");
+
+    // Initial conditions only apply on start
+    ui.add_enabled_ui(!simulation.is_active(), |ui| {
+        ui.add(egui::Slider::new(&mut config.mass_ratio, 0.05..=1.0).text("Mass Ratio"));
+        ui.add(
+            egui::Slider::new(&mut config.approach_speed, 0.0..=2.0).text("Approach Speed"),
+        );
+        ui.add(
+            egui::Slider::new(&mut config.approach_angle, 0.0..=90.0).text("Approach Angle"),
+        );
+        ui.add(
+            egui::Slider::new(&mut config.orbital_period, 5.0..=300.0).text("Orbital Period"),
+        );
+        ui.add(
+            egui::Slider::new(&mut config.core_softening, 0.01..=1.0).text("Core Softening"),
+        );
+        ui.checkbox(&mut config.retrograde[0], "Primary Retrograde");
+        ui.checkbox(&mut config.retrograde[1], "Secondary Retrograde");
+    });
+    ui.add(
+        egui::Slider::new(&mut config.dynamical_friction, 0.0..=2.0).text("Dynamical Friction"),
+    );
+    ui.add(egui::Slider::new(&mut config.time_step, 0.005..=0.5).text("Time Step"));
+    ui.add(egui::Slider::new(&mut config.speed, 0.0..=20.0).text("Speed"));
+
+    ui.separator();
+    ui.checkbox(&mut splat.enabled, "Density Volume");
+    ui.add_enabled_ui(splat.enabled, |ui| {
+        ui.add(egui::Slider::new(&mut splat.resolution, 16..=192).text("Resolution"));
+        ui.add(egui::Slider::new(&mut splat.intensity, 0.0..=50.0).text("Intensity"));
+        ui.add(egui::Slider::new(&mut splat.raymarch_steps, 16..=256).text("Raymarch Steps"));
+    });
+    request
+}
+
+enum GalaxyAction {
+    Select(Entity),
+    Add,
+    Remove,
+}
+
+/// Galaxy list and placement of the primary galaxy
+fn galaxies_ui(
+    galaxies: &[(Entity, bool)],
+    transform: &mut Transform,
+    ui: &mut egui::Ui,
+) -> Option<GalaxyAction> {
+    let mut action = None;
+    for (i, (entity, primary)) in galaxies.iter().enumerate() {
+        if ui.selectable_label(*primary, format!("Galaxy {i}")).clicked() && !primary {
+            action = Some(GalaxyAction::Select(*entity));
+        }
+    }
+    ui.horizontal(|ui| {
+        if ui.button("Add").clicked() {
+            action = Some(GalaxyAction::Add);
+        }
+        if ui
+            .add_enabled(galaxies.len() > 1, egui::Button::new("Remove"))
+            .clicked()
+        {
+            action = Some(GalaxyAction::Remove);
+        }
+    });
+
+    ui.horizontal(|ui| {
+        ui.label("Position");
+        ui.add(egui::DragValue::new(&mut transform.translation.x).speed(10.0));
+        ui.add(egui::DragValue::new(&mut transform.translation.y).speed(10.0));
+        ui.add(egui::DragValue::new(&mut transform.translation.z).speed(10.0));
+    });
+    // Position angle spins the galaxy in its own plane, inclination tilts that plane
+    let (mut position_angle, mut inclination, _) = transform.rotation.to_euler(EulerRot::YXZ);
+    position_angle = position_angle.to_degrees();
+    inclination = inclination.to_degrees();
+    let rotated = ui
+        .add(egui::Slider::new(&mut position_angle, -180.0..=180.0).text("Position Angle"))
+        .changed()
+        | ui.add(egui::Slider::new(&mut inclination, -90.0..=90.0).text("Inclination"))
+            .changed();
+    // Only rebuilt on change, the euler round trip isn't exact
+    if rotated {
+        transform.rotation = Quat::from_euler(
+            EulerRot::YXZ,
+            position_angle.to_radians(),
+            inclination.to_radians(),
+            0.0,
+        );
+    }
+    action
+}
+
+fn visualization_ui(config: &mut GalaxyRenderConfig, ui: &mut egui::Ui) {
+    let previous_mode = config.visualization;
+    for mode in VisualizationMode::ALL {
+        ui.radio_value(&mut config.visualization, mode, mode.name());
+    }
+    // Each mode's values live on a different scale
+    if config.visualization != previous_mode {
+        config.visualization_range = config.visualization.default_range();
+    }
+
+    ui.add_enabled_ui(config.visualization != VisualizationMode::Off, |ui| {
+        ui.horizontal(|ui| {
+            for colormap in Colormap::ALL {
+                ui.radio_value(&mut config.colormap, colormap, colormap.name());
+            }
+        });
+        ui.add(
+            egui::Slider::new(&mut config.visualization_range, 0.001..=1000.0)
+                .logarithmic(true)
+                .text("Range"),
+        );
+        ui.checkbox(&mut config.visualization_log_scale, "Log Scale");
+    });
+}
+
+/// Colour bar for the active visualization mode, in the bottom right corner
+fn visualization_legend(config: &GalaxyRenderConfig, ctx: &egui::Context) {
+    if config.visualization == VisualizationMode::Off {
+        return;
+    }
+    egui::Area::new(egui::Id::new("visualization_legend"))
+        .anchor(egui::Align2::RIGHT_BOTTOM, [-10.0, -10.0])
+        .show(ctx, |ui| {
+            egui::Frame::popup(ui.style()).show(ui, |ui| {
+                ui.label(config.visualization.name());
+
+                let (rect, _) =
+                    ui.allocate_exact_size(egui::vec2(200.0, 16.0), egui::Sense::hover());
+                const SEGMENTS: usize = 64;
+                for i in 0..SEGMENTS {
+                    let t = (i as f32 + 0.5) / SEGMENTS as f32;
+                    let col = config.colormap.sample(t);
+                    let x0 = egui::lerp(rect.left()..=rect.right(), i as f32 / SEGMENTS as f32);
+                    let x1 =
+                        egui::lerp(rect.left()..=rect.right(), (i + 1) as f32 / SEGMENTS as f32);
+                    ui.painter().rect_filled(
+                        egui::Rect::from_x_y_ranges(x0..=x1, rect.y_range()),
+                        0.0,
+                        egui::Color32::from_rgb(
+                            (col.x * 255.0) as u8,
+                            (col.y * 255.0) as u8,
+                            (col.z * 255.0) as u8,
+                        ),
+                    );
+                }
+
+                ui.horizontal(|ui| {
+                    ui.label("0");
+                    ui.with_layout(egui::Layout::right_to_left(egui::Align::Center), |ui| {
+                        ui.label(format!("{:.3}", config.visualization_range));
+                        if config.visualization_log_scale {
+                            ui.label("(log)");
+                        }
+                    });
+                });
+            });
+        });
+}
+
+#[allow(clippy::too_many_arguments)]
 fn ui_system(
+    mut commands: Commands,
     mut contexts: EguiContexts,
-    mut galaxy_config: ResMut<GalaxyConfig>,
+    mut galaxies: Query<(
+        Entity,
+        &mut GalaxyConfig,
+        &mut Transform,
+        &StarCount,
+        Has<PrimaryGalaxy>,
+    )>,
     mut rendering_config: ResMut<GalaxyRenderConfig>,
+    mut label_settings: ResMut<NameLabelSettings>,
+    mut territory_config: ResMut<TerritoryConfig>,
+    territories: Res<Territories>,
+    mut background_config: ResMut<GalaxyBackgroundConfig>,
+    mut merger_config: ResMut<MergerConfig>,
+    mut splat_config: ResMut<DensitySplatConfig>,
+    mut simulation: ResMut<MergerSimulation>,
+    mut nuclei: Query<&mut NucleusConfig, With<PrimaryGalaxy>>,
+    mut nebula_config: ResMut<NebulaConfig>,
+    mut exposure_config: ResMut<ExposureConfig>,
+    mut psf_config: ResMut<StarPsfConfig>,
+    mut bloom_config: ResMut<GalaxyBloomConfig>,
 ) {
     let ctx = contexts.ctx_mut();
 
+    let galaxy_list: Vec<(Entity, bool)> = galaxies
+        .iter()
+        .map(|(entity, _, _, _, primary)| (entity, primary))
+        .collect();
+    // The panel edits the primary galaxy
+    let Some((primary, mut galaxy_config, mut galaxy_transform, star_count, _)) = galaxies
+        .iter_mut()
+        .find(|(_, _, _, _, primary)| *primary)
+    else {
+        return;
+    };
+
     let mut new_galaxy_config = galaxy_config.clone();
+    let mut new_galaxy_transform = *galaxy_transform;
+    let mut galaxy_action = None;
     let mut new_rendering_config = rendering_config.clone();
+    let mut new_label_settings = label_settings.clone();
+    let mut new_territory_config = territory_config.clone();
+    let mut new_background_config = background_config.clone();
+    let mut new_nebula_config = nebula_config.clone();
+    let mut new_exposure_config = exposure_config.clone();
+    let mut new_psf_config = psf_config.clone();
+    let mut new_bloom_config = bloom_config.clone();
+    let mut new_merger_config = merger_config.clone();
+    let mut new_splat_config = splat_config.clone();
+    let mut nucleus_config = nuclei.single_mut().ok();
+    let mut new_nucleus_config = nucleus_config.as_deref().cloned();
+    let mut merger_running = simulation.running;
+    let mut merger_request = None;
 
     egui::SidePanel::left("side_panel")
         .default_width(200.0)
@@ -161,7 +632,16 @@ fn ui_system(
             egui::ScrollArea::vertical().show(ui, |ui| {
                 ui.heading("Configuration");
 
+                egui::CollapsingHeader::new("Galaxies").show(ui, |ui| {
+                    galaxy_action = galaxies_ui(&galaxy_list, &mut new_galaxy_transform, ui);
+                });
+                ui.separator();
+
                 egui::CollapsingHeader::new("Galaxy Parameters").show(ui, |ui| {
+                    ui.horizontal(|ui| {
+                        ui.label("Seed");
+                        ui.add(egui::DragValue::new(&mut new_galaxy_config.seed));
+                    });
                     ui.add(
                         egui::Slider::new(&mut new_galaxy_config.radius, 100.0..=1000.0).text("Radius"),
                     );
@@ -185,6 +665,21 @@ fn ui_system(
                         egui::Slider::new(&mut new_rendering_config.raymarch_steps, 1..=256)
                             .text("Raymarch Steps"),
                     );
+                    ui.checkbox(
+                        &mut new_rendering_config.empty_space_skipping,
+                        "Empty Space Skipping",
+                    );
+                    ui.add_enabled(
+                        new_rendering_config.empty_space_skipping,
+                        egui::Slider::new(&mut new_rendering_config.max_step_scale, 1.0..=16.0)
+                            .text("Max Step Scale"),
+                    );
+                    ui.checkbox(&mut new_rendering_config.baked_noise, "Baked Noise");
+                    ui.add_enabled(
+                        new_rendering_config.baked_noise,
+                        egui::Slider::new(&mut new_rendering_config.noise_texels_per_cell, 2..=5)
+                            .text("Noise Texels per Cell"),
+                    );
                     ui.checkbox(
                         &mut new_rendering_config.draw_volume_to_background,
                         "Draw volume to background layer",
@@ -206,9 +701,57 @@ fn ui_system(
                     );
 
                     ui.checkbox(
-                        &mut new_rendering_config.diagnostic_mode,
-                        "Performance Diagnostic",
+                        &mut new_rendering_config.dust_scattering,
+                        "Dust Scattering",
                     );
+                    ui.add_enabled_ui(new_rendering_config.dust_scattering, |ui| {
+                        ui.add(
+                            egui::Slider::new(&mut new_rendering_config.dust_albedo, 0.0..=1.0)
+                                .text("Dust Albedo"),
+                        );
+                        ui.add(
+                            egui::Slider::new(
+                                &mut new_rendering_config.dust_anisotropy,
+                                -0.9..=0.9,
+                            )
+                            .text("Dust Anisotropy"),
+                        );
+                        ui.add(
+                            egui::Slider::new(
+                                &mut new_rendering_config.scattering_intensity,
+                                0.0..=1.0,
+                            )
+                            .text("Scattering Intensity"),
+                        );
+                    });
+
+                    ui.checkbox(&mut new_rendering_config.spectral, "Spectral");
+                    ui.add_enabled_ui(new_rendering_config.spectral, |ui| {
+                        ui.horizontal(|ui| {
+                            for filter in SpectralFilter::ALL {
+                                ui.radio_value(&mut new_rendering_config.spectral_filter, filter, filter.name());
+                            }
+                        });
+                        ui.horizontal(|ui| {
+                            ui.radio_value(&mut new_rendering_config.reddening_law, ReddeningLaw::Cardelli, "Cardelli");
+                            ui.radio_value(&mut new_rendering_config.reddening_law, ReddeningLaw::Calzetti, "Calzetti");
+                        });
+                    });
+                });
+
+                ui.separator();
+                egui::CollapsingHeader::new("Visualization").show(ui, |ui| {
+                    visualization_ui(&mut new_rendering_config, ui);
+                });
+
+                ui.separator();
+                egui::CollapsingHeader::new("Exposure").show(ui, |ui| {
+                    exposure_ui(&mut new_exposure_config, ui);
+                });
+
+                ui.separator();
+                egui::CollapsingHeader::new("Star PSF & Bloom").show(ui, |ui| {
+                    star_psf_ui(&mut new_psf_config, &mut new_bloom_config, ui);
                 });
 
                 ui.separator();
@@ -229,31 +772,154 @@ fn ui_system(
                         egui::Slider::new(&mut new_galaxy_config.bulge_radius, 1.0..=20.0)
                             .text("Scale Factor"),
                     );
+                    ui.add(
+                        egui::Slider::new(&mut new_galaxy_config.bulge_intensity, 0.0..=5.0)
+                            .text("Brightness"),
+                    );
+                    bulge_shape_ui(&mut new_galaxy_config.bulge_shape, ui);
+                    color_ui(&mut new_galaxy_config.bulge_color, ui);
                 });
                 ui.separator();
 
+                if let Some(new_nucleus_config) = &mut new_nucleus_config {
+                    egui::CollapsingHeader::new("Nucleus").show(ui, |ui| {
+                        nucleus_ui(new_nucleus_config, ui);
+                    });
+                    ui.separator();
+                }
+
                 component_ui(&mut new_galaxy_config.disk_params, true, ui);
                 component_ui(&mut new_galaxy_config.dust_params, true, ui);
 
                 egui::CollapsingHeader::new("Stars Parameters").show(ui, |ui| {
                     ui.add(
-                        egui::Slider::new(&mut new_galaxy_config.stars_per_arm, 4096..=65536)
-                            .text("Stars per arm"),
+                        egui::Slider::new(&mut new_galaxy_config.stars_per_arm, 4096..=1_048_576)
+                            .text("Stars per arm")
+                            .logarithmic(true),
                     );
+                    star_budget_ui(&mut new_galaxy_config.star_budget, star_count, ui);
                     ui.checkbox(
                         &mut new_rendering_config.draw_stars_to_background,
                         "Draw stars to background",
                     );
                     component_ui(&mut new_galaxy_config.stars_params, false, ui);
                 });
+                ui.separator();
+
+                egui::CollapsingHeader::new("Labels").show(ui, |ui| {
+                    ui.checkbox(&mut new_label_settings.enabled, "Show Labels");
+                    ui.add(
+                        egui::Slider::new(&mut new_label_settings.max_star_labels, 0..=100)
+                            .text("Max Star Labels"),
+                    );
+                    ui.add(
+                        egui::Slider::new(&mut new_label_settings.star_zoom, 0.0..=1.0)
+                            .text("Star Label Zoom"),
+                    );
+                    ui.add(
+                        egui::Slider::new(&mut new_label_settings.cluster_zoom, 0.0..=1.0)
+                            .text("Cluster Label Zoom"),
+                    );
+                });
+                ui.separator();
+
+                egui::CollapsingHeader::new("Territories").show(ui, |ui| {
+                    territory_ui(&mut new_territory_config, &territories, ui);
+                });
+                ui.separator();
+
+                egui::CollapsingHeader::new("Nebulae").show(ui, |ui| {
+                    nebula_ui(&mut new_nebula_config, ui);
+                });
+                ui.separator();
+
+                egui::CollapsingHeader::new("Background").show(ui, |ui| {
+                    background_ui(&mut new_background_config, ui);
+                });
+                ui.separator();
+
+                egui::CollapsingHeader::new("Merger").show(ui, |ui| {
+                    merger_request = merger_ui(
+                        &mut new_merger_config,
+                        &mut new_splat_config,
+                        &simulation,
+                        &mut merger_running,
+                        ui,
+                    );
+                });
             });
         });
+    visualization_legend(&new_rendering_config, ctx);
 
     if new_galaxy_config != *galaxy_config {
         new_galaxy_config.update_arms();
         *galaxy_config = new_galaxy_config;
     }
+    if new_galaxy_transform != *galaxy_transform {
+        *galaxy_transform = new_galaxy_transform;
+    }
+    match galaxy_action {
+        Some(GalaxyAction::Select(entity)) => {
+            commands.entity(primary).remove::<PrimaryGalaxy>();
+            commands.entity(entity).insert(PrimaryGalaxy);
+        }
+        Some(GalaxyAction::Add) => {
+            // A differently seeded copy next to the current one
+            let mut config = galaxy_config.clone();
+            config.seed = config.seed.wrapping_add(1);
+            config.generation = 0;
+            let offset = galaxy_transform.rotation * Vec3::X * config.radius * 3.0;
+            let transform = galaxy_transform.with_translation(galaxy_transform.translation + offset);
+            commands.entity(primary).remove::<PrimaryGalaxy>();
+            commands.spawn((config, transform, PrimaryGalaxy));
+        }
+        Some(GalaxyAction::Remove) => {
+            // Stars and volume are children, they go with it
+            commands.entity(primary).despawn();
+        }
+        None => {}
+    }
     if new_rendering_config != *rendering_config {
         *rendering_config = new_rendering_config;
     }
+    if new_label_settings != *label_settings {
+        *label_settings = new_label_settings;
+    }
+    if new_territory_config != *territory_config {
+        *territory_config = new_territory_config;
+    }
+    if new_background_config != *background_config {
+        *background_config = new_background_config;
+    }
+    if let (Some(nucleus_config), Some(new_nucleus_config)) =
+        (&mut nucleus_config, new_nucleus_config)
+    {
+        if new_nucleus_config != **nucleus_config {
+            **nucleus_config = new_nucleus_config;
+        }
+    }
+    if new_nebula_config != *nebula_config {
+        *nebula_config = new_nebula_config;
+    }
+    if new_exposure_config != *exposure_config {
+        *exposure_config = new_exposure_config;
+    }
+    if new_psf_config != *psf_config {
+        *psf_config = new_psf_config;
+    }
+    if new_bloom_config != *bloom_config {
+        *bloom_config = new_bloom_config;
+    }
+    if new_merger_config != *merger_config {
+        *merger_config = new_merger_config;
+    }
+    if new_splat_config != *splat_config {
+        *splat_config = new_splat_config;
+    }
+    if merger_running != simulation.running {
+        simulation.running = merger_running;
+    }
+    if let Some(request) = merger_request {
+        simulation.request(request);
+    }
 }
@@ -0,0 +1,129 @@
+use super::CameraMain;
+use crate::prelude::*;
+use bevy::prelude::*;
+use bevy_egui::{egui, EguiContexts};
+
+/// Coordinate grid, scale bar and compass for the map view
+pub struct MapOverlayPlugin;
+
+impl Plugin for MapOverlayPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_systems(Update, (draw_map_grid, draw_map_widgets));
+    }
+}
+
+const GRID_LINES_ON_SCREEN: f32 = 8.0;
+
+/// Rounds down to 1, 2 or 5 times a power of ten
+fn nice_step(x: f32) -> f32 {
+    let magnitude = 10f32.powf(x.log10().floor());
+    let residual = x / magnitude;
+    let nice = if residual >= 5.0 {
+        5.0
+    } else if residual >= 2.0 {
+        2.0
+    } else {
+        1.0
+    };
+    nice * magnitude
+}
+
+fn draw_map_grid(
+    mut gizmos: Gizmos,
+    camera: Query<&CameraMain>,
+    galaxy: Query<&GalaxyConfig, With<PrimaryGalaxy>>,
+) {
+    let (Ok(camera), Ok(galaxy_config)) = (camera.single(), galaxy.single()) else {
+        return;
+    };
+    if camera.map_blend() == 0.0 {
+        return;
+    }
+
+    let step = nice_step(camera.visible_height() / GRID_LINES_ON_SCREEN);
+    let extent = galaxy_config.radius * 1.5;
+    let lines = (extent / step).ceil() as i32;
+    let color = Color::srgba(0.5, 0.6, 0.8, 0.25 * camera.map_blend());
+    let axis_color = Color::srgba(0.7, 0.8, 1.0, 0.5 * camera.map_blend());
+
+    for i in -lines..=lines {
+        let v = i as f32 * step;
+        let c = if i == 0 { axis_color } else { color };
+        gizmos.line(vec3(v, 0.0, -extent), vec3(v, 0.0, extent), c);
+        gizmos.line(vec3(-extent, 0.0, v), vec3(extent, 0.0, v), c);
+    }
+}
+
+fn draw_map_widgets(
+    mut contexts: EguiContexts,
+    camera: Query<(&Camera, &GlobalTransform, &CameraMain)>,
+) {
+    let Ok((camera, camera_transform, camera_main)) = camera.single() else {
+        return;
+    };
+    let blend = camera_main.map_blend();
+    if blend == 0.0 {
+        return;
+    }
+    let Some(viewport) = camera.logical_viewport_rect() else {
+        return;
+    };
+
+    let alpha = (blend * 255.0) as u8;
+    let color = egui::Color32::from_rgba_unmultiplied(220, 230, 255, alpha);
+    let stroke = egui::Stroke::new(2.0f32, color);
+
+    let ctx = contexts.ctx_mut();
+    let painter = ctx.layer_painter(egui::LayerId::new(
+        egui::Order::Background,
+        egui::Id::new("map_overlay"),
+    ));
+
+    // Scale bar
+    let parsecs_per_point = camera_main.visible_height() / viewport.height();
+    let bar_parsecs = nice_step(viewport.width() * 0.2 * parsecs_per_point);
+    let bar_length = bar_parsecs / parsecs_per_point;
+    let bar_end = egui::pos2(viewport.max.x - 24.0, viewport.max.y - 24.0);
+    let bar_start = bar_end - egui::vec2(bar_length, 0.0);
+
+    painter.line_segment([bar_start, bar_end], stroke);
+    for x in [bar_start.x, bar_end.x] {
+        painter.line_segment(
+            [
+                egui::pos2(x, bar_end.y - 5.0),
+                egui::pos2(x, bar_end.y + 5.0),
+            ],
+            stroke,
+        );
+    }
+    painter.text(
+        egui::pos2((bar_start.x + bar_end.x) * 0.5, bar_end.y - 8.0),
+        egui::Align2::CENTER_BOTTOM,
+        format!("{bar_parsecs} pc"),
+        egui::FontId::proportional(13.0),
+        color,
+    );
+
+    // Compass, points along +Z projected to the screen
+    let target = camera_main.target();
+    let (Ok(origin), Ok(north)) = (
+        camera.world_to_viewport(camera_transform, target),
+        camera.world_to_viewport(camera_transform, target + Vec3::Z),
+    ) else {
+        return;
+    };
+    let dir = (north - origin).normalize_or(Vec2::NEG_Y);
+    let dir = egui::vec2(dir.x, dir.y);
+    let center = egui::pos2(viewport.max.x - 48.0, viewport.max.y - 100.0);
+    let radius = 22.0;
+
+    painter.circle_stroke(center, radius, egui::Stroke::new(1.0f32, color));
+    painter.arrow(center - dir * radius * 0.7, dir * radius * 1.4, stroke);
+    painter.text(
+        center + dir * (radius + 8.0),
+        egui::Align2::CENTER_CENTER,
+        "N",
+        egui::FontId::proportional(13.0),
+        color,
+    );
+}
@@ -2,19 +2,38 @@ use bevy::input::mouse::MouseWheel;
 use bevy::prelude::*;
 
 mod camera;
+mod camera_paths;
 mod config_egui;
 mod fps_widget;
+mod map_overlay;
+mod name_labels;
 
-pub use camera::CameraMain;
+pub use camera::{CameraMain, CameraPlugin};
+pub use camera_paths::{CameraBookmarks, CameraPath};
+pub use name_labels::NameLabelSettings;
 
-pub struct UiPlugin;
+/// The egui windows and overlays, requires `EguiPlugin`
+/// The camera controls are added separately with [`CameraPlugin`]
+pub struct UiPlugin {
+    pub fps_widget: bool,
+}
+
+impl Default for UiPlugin {
+    fn default() -> Self {
+        Self { fps_widget: true }
+    }
+}
 
 impl Plugin for UiPlugin {
     fn build(&self, app: &mut App) {
+        if self.fps_widget {
+            app.add_plugins(fps_widget::FpsWidgetPlugin);
+        }
         app.add_plugins((
-            fps_widget::FpsWidgetPlugin,
             config_egui::ConfigEguiPlugin,
-            camera::CameraPlugin,
+            camera_paths::CameraPathsPlugin,
+            name_labels::NameLabelsPlugin,
+            map_overlay::MapOverlayPlugin,
         ))
         // Egui mouse input culling (see below)
         .add_systems(
@@ -0,0 +1,323 @@
+use super::CameraMain;
+use crate::prelude::*;
+use bevy::prelude::*;
+use bevy_egui::{egui, EguiContexts};
+use std::collections::HashMap;
+
+pub struct NameLabelsPlugin;
+
+impl Plugin for NameLabelsPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<NameGenerator>()
+            .insert_resource(NameLabelSettings::default())
+            .add_systems(Update, draw_name_labels);
+    }
+}
+
+#[derive(Resource, Clone, PartialEq)]
+pub struct NameLabelSettings {
+    pub enabled: bool,
+    pub max_star_labels: usize,
+    /// Zoom level below which individual stars are labelled
+    pub star_zoom: f32,
+    /// Zoom level below which clusters are labelled, regions are labelled above it
+    pub cluster_zoom: f32,
+}
+
+impl Default for NameLabelSettings {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            max_star_labels: 24,
+            star_zoom: 0.2,
+            cluster_zoom: 0.5,
+        }
+    }
+}
+
+// Clusters are just dense cells of a coarse grid over the galactic plane
+const CLUSTER_CELLS: f32 = 24.0;
+const CLUSTER_MIN_STARS: usize = 24;
+const REGION_LABELS_PER_ARM: usize = 2;
+// Star grid cells along each side of a cluster cell
+const GRID_SUBDIVISIONS: i32 = 4;
+
+struct Label {
+    pos: Vec3,
+    text: String,
+    size: f32,
+}
+
+#[derive(Default)]
+struct GridCell {
+    stars: Vec<u32>,
+    sum: Vec3,
+}
+
+/// The primary galaxy's stars bucketed by cell over the galactic plane, so labelling only looks at the stars
+/// around the focus
+/// Stars are added as they are placed, it's only rebuilt when the catalogue is respawned or its stars move
+#[derive(Default)]
+struct StarGrid {
+    cells: HashMap<IVec2, GridCell>,
+    cell_size: f32,
+    stars: usize,
+    generation: Option<i32>,
+    moves: u32,
+}
+
+impl StarGrid {
+    fn update(&mut self, catalogue: &StarCatalogue, generation: i32, cell_size: f32) {
+        if self.generation != Some(generation)
+            || self.moves != catalogue.moves()
+            || self.cell_size != cell_size
+            || catalogue.len() < self.stars
+        {
+            self.cells.clear();
+            self.cell_size = cell_size;
+            self.stars = 0;
+            self.generation = Some(generation);
+            self.moves = catalogue.moves();
+        }
+        for (index, position) in catalogue.positions.iter().enumerate().skip(self.stars) {
+            let cell = self.cells.entry(self.cell_of(*position)).or_default();
+            cell.stars.push(index as u32);
+            cell.sum += *position;
+        }
+        self.stars = catalogue.len();
+    }
+
+    fn cell_of(&self, position: Vec3) -> IVec2 {
+        (position.xz() / self.cell_size).floor().as_ivec2()
+    }
+
+    /// Up to `count` stars closest to `focus` within `max_distance`, nearest first
+    fn nearest(
+        &self,
+        catalogue: &StarCatalogue,
+        focus: Vec3,
+        max_distance: f32,
+        count: usize,
+    ) -> Vec<(f32, u32)> {
+        if count == 0 {
+            return vec![];
+        }
+        let centre = self.cell_of(focus);
+        let mut nearest = vec![];
+        // Searched outward one ring of cells at a time
+        for ring in 0..=(max_distance / self.cell_size).ceil() as i32 + 1 {
+            for offset in ring_offsets(ring) {
+                let Some(cell) = self.cells.get(&(centre + offset)) else {
+                    continue;
+                };
+                for &index in &cell.stars {
+                    let d2 = catalogue.positions[index as usize].distance_squared(focus);
+                    if d2 < max_distance * max_distance {
+                        nearest.push((d2, index));
+                    }
+                }
+            }
+            if nearest.len() >= count {
+                nearest.select_nth_unstable_by(count - 1, |a, b| a.0.total_cmp(&b.0));
+                nearest.truncate(count);
+                // Stars in the next ring are at least this far from the focus
+                let reached = ring as f32 * self.cell_size;
+                if nearest[count - 1].0 <= reached * reached {
+                    break;
+                }
+            }
+        }
+        nearest.sort_by(|a, b| a.0.total_cmp(&b.0));
+        nearest
+    }
+
+    /// Star count and summed position per cluster cell, over the grid cells whose centre is within `radius`
+    fn clusters(&self, focus: Vec3, radius: f32) -> HashMap<IVec2, (Vec3, usize)> {
+        let mut clusters: HashMap<IVec2, (Vec3, usize)> = HashMap::new();
+        let min = self.cell_of(focus - Vec3::splat(radius));
+        let max = self.cell_of(focus + Vec3::splat(radius));
+        for x in min.x..=max.x {
+            for y in min.y..=max.y {
+                let cell = ivec2(x, y);
+                let centre = (cell.as_vec2() + 0.5) * self.cell_size;
+                if centre.distance_squared(focus.xz()) > radius * radius {
+                    continue;
+                }
+                let Some(stars) = self.cells.get(&cell) else {
+                    continue;
+                };
+                let cluster = clusters
+                    .entry(cell.div_euclid(IVec2::splat(GRID_SUBDIVISIONS)))
+                    .or_insert((Vec3::ZERO, 0));
+                cluster.0 += stars.sum;
+                cluster.1 += stars.stars.len();
+            }
+        }
+        clusters
+    }
+}
+
+/// Offsets of the cells exactly `ring` cells away along either axis
+fn ring_offsets(ring: i32) -> impl Iterator<Item = IVec2> {
+    (-ring..=ring)
+        .flat_map(move |x| (-ring..=ring).map(move |y| ivec2(x, y)))
+        .filter(move |offset| offset.abs().max_element() == ring)
+}
+
+fn draw_name_labels(
+    mut contexts: EguiContexts,
+    settings: Res<NameLabelSettings>,
+    names: Res<NameGenerator>,
+    mut galaxy: Query<
+        (
+            &GalaxyConfig,
+            &GlobalTransform,
+            &StarCatalogue,
+            &mut StarSelection,
+        ),
+        With<PrimaryGalaxy>,
+    >,
+    camera: Query<(&Camera, &GlobalTransform, &CameraMain)>,
+    mut grid: Local<StarGrid>,
+) {
+    if !settings.enabled {
+        return;
+    }
+    let (
+        Ok((camera, camera_transform, camera_main)),
+        Ok((galaxy_config, galaxy_transform, catalogue, mut selection)),
+    ) = (camera.single(), galaxy.single_mut())
+    else {
+        return;
+    };
+    // Only the primary galaxy is named, labels are placed in its local space
+    let cluster_cell_size = galaxy_config.radius * 2.0 / CLUSTER_CELLS;
+    grid.update(
+        catalogue,
+        galaxy_config.generation,
+        cluster_cell_size / GRID_SUBDIVISIONS as f32,
+    );
+
+    let seed = galaxy_config.seed;
+    let focus = galaxy_transform
+        .affine()
+        .inverse()
+        .transform_point3(camera_main.target());
+    let zoom = camera_main.zoom();
+    // roughly the visible extent of the galactic plane around the focus
+    let focus_radius = galaxy_config.radius * (0.05 + zoom);
+
+    let mut labels: Vec<Label> = Vec::new();
+    let mut labelled_stars = vec![];
+
+    if zoom < settings.star_zoom {
+        let nearest = grid.nearest(catalogue, focus, focus_radius, settings.max_star_labels);
+        for (_, index) in nearest {
+            let pos = catalogue.positions[index as usize];
+            labelled_stars.push(index);
+            labels.push(Label {
+                pos,
+                text: format!(
+                    "{} ({})",
+                    names.star_name(seed, index),
+                    catalogue_designation(pos, galaxy_config.radius, index)
+                ),
+                size: 11.0,
+            });
+        }
+    } else if zoom < settings.cluster_zoom {
+        for (cell, (sum, count)) in grid.clusters(focus, focus_radius) {
+            if count < CLUSTER_MIN_STARS {
+                continue;
+            }
+            let cluster_id = ((cell.x as u32 as u64) << 32) | cell.y as u32 as u64;
+            labels.push(Label {
+                pos: sum / count as f32,
+                text: names.cluster_name(seed, cluster_id),
+                size: 13.0,
+            });
+        }
+    } else {
+        let arm_painter = GalaxyComponentDensity::new(galaxy_config, &galaxy_config.stars_params);
+        for arm_id in 0..galaxy_config.n_arms as usize {
+            for i in 0..REGION_LABELS_PER_ARM {
+                let d = (i + 1) as f32 / (REGION_LABELS_PER_ARM + 1) as f32 * 0.8;
+                let p = arm_painter.arm_position(arm_id, d);
+                labels.push(Label {
+                    pos: vec3(p.x, 0.0, p.y),
+                    text: names.region_name(seed, (arm_id * REGION_LABELS_PER_ARM + i) as u64),
+                    size: 16.0,
+                });
+            }
+        }
+    }
+
+    // Labelled stars are the ones worth an entity
+    if selection.indices != labelled_stars {
+        selection.indices = labelled_stars;
+    }
+
+    let ctx = contexts.ctx_mut();
+    let painter = ctx.layer_painter(egui::LayerId::new(
+        egui::Order::Background,
+        egui::Id::new("name_labels"),
+    ));
+
+    for label in labels {
+        let world_pos = galaxy_transform.transform_point(label.pos);
+        let Ok(viewport_pos) = camera.world_to_viewport(camera_transform, world_pos) else {
+            continue;
+        };
+        painter.text(
+            egui::pos2(viewport_pos.x, viewport_pos.y - 4.0),
+            egui::Align2::CENTER_BOTTOM,
+            label.text,
+            egui::FontId::proportional(label.size),
+            egui::Color32::from_rgba_unmultiplied(200, 220, 255, 200),
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::{rngs::StdRng, Rng, SeedableRng};
+
+    #[test]
+    fn nearest_matches_brute_force() {
+        let mut rng = StdRng::seed_from_u64(3);
+        let mut catalogue = StarCatalogue::default();
+        catalogue.positions = (0..20_000)
+            .map(|_| {
+                vec3(
+                    rng.random_range(-100.0..100.0),
+                    rng.random_range(-5.0..5.0),
+                    rng.random_range(-100.0..100.0),
+                )
+            })
+            .collect();
+        let mut grid = StarGrid::default();
+        grid.update(
+            &catalogue,
+            0,
+            200.0 / CLUSTER_CELLS / GRID_SUBDIVISIONS as f32,
+        );
+
+        for focus in [Vec3::ZERO, vec3(37.0, 2.0, -81.5), vec3(99.0, 0.0, 99.0)] {
+            for (max_distance, count) in [(40.0, 24), (3.0, 24), (40.0, 0)] {
+                let mut expected: Vec<(f32, u32)> = catalogue
+                    .positions
+                    .iter()
+                    .enumerate()
+                    .map(|(index, position)| (position.distance_squared(focus), index as u32))
+                    .filter(|(d2, _)| *d2 < max_distance * max_distance)
+                    .collect();
+                expected.sort_by(|a, b| a.0.total_cmp(&b.0));
+                expected.truncate(count);
+
+                let nearest = grid.nearest(&catalogue, focus, max_distance, count);
+                assert_eq!(nearest, expected, "{focus} {max_distance} {count}");
+            }
+        }
+    }
+}